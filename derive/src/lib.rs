@@ -0,0 +1,288 @@
+//! Derive macro of `tokio-prometheus-client`, use it through the `derive`
+//! feature of `tokio-prometheus-client` instead of depending on this crate
+//! directly.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::{
+    parse_macro_input, Attribute, Data, DeriveInput, Expr, Fields, Ident, Lit, LitStr, Meta, Type,
+};
+
+/// Derive `tokio_prometheus_client::collector::CollectorMetrics`, see there.
+#[proc_macro_derive(CollectorMetrics, attributes(collector_metrics, metric))]
+pub fn derive_collector_metrics(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// How a field is updated from the snapshot.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// Incremented by the value of the snapshot.
+    Counter,
+    /// Set to the value of the snapshot.
+    Gauge,
+}
+
+/// A field of the metrics struct and its `#[metric(..)]` attribute.
+struct Metric {
+    field: Ident,
+    kind: Kind,
+    /// The value is a `Duration`, exposed in seconds.
+    duration: bool,
+    name: LitStr,
+    unit: Option<LitStr>,
+    /// The field of the snapshot the metric is updated from.
+    from: Ident,
+    help: String,
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let ident = &input.ident;
+    let snapshot = snapshot_type(&input)?;
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "CollectorMetrics can only be derived for structs",
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "CollectorMetrics can only be derived for structs with named fields",
+        ));
+    };
+
+    let mut metrics = Vec::new();
+    for field in &fields.named {
+        if let Some(metric) = metric(field)? {
+            metrics.push(metric);
+        }
+    }
+
+    check_names(&metrics)?;
+
+    let updates = metrics.iter().map(|metric| {
+        let Metric { field, from, .. } = metric;
+        let value = if metric.duration {
+            quote!(snapshot.#from.as_secs_f64())
+        } else {
+            quote!(snapshot.#from as _)
+        };
+        match metric.kind {
+            Kind::Counter => quote!(self.#field.inc_by(#value);),
+            Kind::Gauge => quote!(self.#field.set(#value);),
+        }
+    });
+    let encodes = metrics.iter().map(|metric| {
+        let Metric {
+            field, name, help, ..
+        } = metric;
+        let unit = match &metric.unit {
+            Some(unit) => {
+                let unit = unit_expr(unit);
+                quote!(::core::option::Option::Some(&#unit))
+            }
+            None => quote!(::core::option::Option::None),
+        };
+        quote! {
+            let metric_encoder = encoder.encode_descriptor(
+                #name,
+                #help,
+                #unit,
+                self.#field.metric_type(),
+            )?;
+            self.#field.encode(metric_encoder)?;
+        }
+    });
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::tokio_prometheus_client::collector::CollectorMetrics
+            for #ident #ty_generics #where_clause
+        {
+            type Snapshot = #snapshot;
+
+            fn update(&self, snapshot: &Self::Snapshot) {
+                #(#updates)*
+            }
+
+            fn encode(
+                &self,
+                encoder: &mut ::tokio_prometheus_client::collector::__private::DescriptorEncoder,
+            ) -> ::core::result::Result<(), ::core::fmt::Error> {
+                use ::tokio_prometheus_client::collector::__private::EncodeMetric as _;
+                #(#encodes)*
+                ::core::result::Result::Ok(())
+            }
+        }
+    })
+}
+
+/// The type of `#[collector_metrics(snapshot = ..)]`.
+fn snapshot_type(input: &DeriveInput) -> syn::Result<Type> {
+    let mut snapshot = None;
+    for attr in &input.attrs {
+        if !attr.path().is_ident("collector_metrics") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("snapshot") {
+                snapshot = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("unknown collector_metrics attribute"))
+            }
+        })?;
+    }
+    snapshot.ok_or_else(|| {
+        syn::Error::new_spanned(
+            &input.ident,
+            "missing #[collector_metrics(snapshot = Type)] attribute",
+        )
+    })
+}
+
+/// The metric of `field`, if it has a `#[metric(..)]` attribute.
+fn metric(field: &syn::Field) -> syn::Result<Option<Metric>> {
+    let Some(attr) = field
+        .attrs
+        .iter()
+        .find(|attr| attr.path().is_ident("metric"))
+    else {
+        return Ok(None);
+    };
+    let ident = field
+        .ident
+        .clone()
+        .expect("fields of named structs should have a name");
+
+    let mut kind = None;
+    let mut duration = false;
+    let mut name = None;
+    let mut unit = None;
+    let mut from = None;
+    attr.parse_nested_meta(|meta| {
+        if meta.path.is_ident("counter") {
+            kind = Some(Kind::Counter);
+        } else if meta.path.is_ident("gauge") {
+            kind = Some(Kind::Gauge);
+        } else if meta.path.is_ident("duration") {
+            duration = true;
+        } else if meta.path.is_ident("name") {
+            name = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("unit") {
+            unit = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("from") {
+            from = Some(meta.value()?.parse()?);
+        } else {
+            return Err(meta.error("unknown metric attribute"));
+        }
+        Ok(())
+    })?;
+    let Some(kind) = kind else {
+        return Err(syn::Error::new_spanned(
+            attr,
+            "metric needs to be either a `counter` or a `gauge`",
+        ));
+    };
+
+    Ok(Some(Metric {
+        name: name.unwrap_or_else(|| LitStr::new(&ident.to_string(), ident.span())),
+        from: from.unwrap_or_else(|| ident.clone()),
+        help: help(&field.attrs),
+        field: ident,
+        kind,
+        duration,
+        unit,
+    }))
+}
+
+/// Fail unless the names `metrics` export are valid Prometheus metric names
+/// and no two of them are the same, so the build fails rather than the
+/// scrape.
+fn check_names(metrics: &[Metric]) -> syn::Result<()> {
+    let mut exported = Vec::new();
+    for metric in metrics {
+        let mut name = metric.name.value();
+        if let Some(unit) = &metric.unit {
+            name = format!("{name}_{}", unit.value());
+        }
+        if !is_valid(&name) {
+            return Err(syn::Error::new_spanned(
+                &metric.name,
+                format!(
+                    "`{name}` is not a valid metric name, it should match [a-zA-Z_:][a-zA-Z0-9_:]*"
+                ),
+            ));
+        }
+        let mut names = vec![name.clone()];
+        if metric.kind == Kind::Counter {
+            names.push(format!("{name}_total"));
+        }
+        for name in names {
+            if exported.contains(&name) {
+                return Err(syn::Error::new_spanned(
+                    &metric.name,
+                    format!("metric name `{name}` is exported more than once"),
+                ));
+            }
+            exported.push(name);
+        }
+    }
+    Ok(())
+}
+
+/// Whether `name` matches `[a-zA-Z_:][a-zA-Z0-9_:]*`.
+fn is_valid(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+/// The doc comment of a field, joined into a single line.
+fn help(attrs: &[Attribute]) -> String {
+    let mut lines = Vec::new();
+    for attr in attrs {
+        if let Meta::NameValue(meta) = &attr.meta {
+            if !meta.path.is_ident("doc") {
+                continue;
+            }
+            if let Expr::Lit(expr) = &meta.value {
+                if let Lit::Str(doc) = &expr.lit {
+                    lines.push(doc.value().trim().to_string());
+                }
+            }
+        }
+    }
+    lines.join(" ")
+}
+
+/// The `Unit` named `unit`, one of its variants or `Unit::Other`.
+fn unit_expr(unit: &LitStr) -> TokenStream2 {
+    let variant = match unit.value().as_str() {
+        "amperes" => "Amperes",
+        "bytes" => "Bytes",
+        "celsius" => "Celsius",
+        "grams" => "Grams",
+        "joules" => "Joules",
+        "meters" => "Meters",
+        "ratios" => "Ratios",
+        "seconds" => "Seconds",
+        "volts" => "Volts",
+        _ => {
+            return quote! {
+                ::tokio_prometheus_client::collector::__private::Unit::Other(
+                    ::std::string::String::from(#unit),
+                )
+            }
+        }
+    };
+    let variant = Ident::new(variant, Span::call_site());
+    quote!(::tokio_prometheus_client::collector::__private::Unit::#variant)
+}
@@ -0,0 +1,123 @@
+//! [actix-web](https://docs.rs/actix-web) integration.
+//!
+//! Enabled with the `actix` feature.
+
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse, Scope};
+
+use crate::tower::MetricsService;
+
+/// Create a [`Scope`] serving `metrics` on `GET /metrics`.
+///
+/// `metrics` is either an `Arc<Mutex<Registry>>` or a [`MetricsService`].
+///
+/// ## Example
+///
+/// ```
+/// # use std::sync::{Arc, Mutex};
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let handle = tokio::runtime::Handle::current();
+/// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// tokio_prometheus_client::register(runtime_monitor, registry.sub_registry_with_prefix("tokio"));
+///
+/// let registry = Arc::new(Mutex::new(registry));
+/// let app = actix_web::App::new()
+///     .service(tokio_prometheus_client::actix::metrics_scope(registry));
+/// # });
+/// ```
+pub fn metrics_scope(metrics: impl Into<MetricsService>) -> Scope {
+    let metrics: MetricsService = metrics.into();
+    web::scope("/metrics")
+        .app_data(web::Data::new(metrics))
+        .route("", web::get().to(metrics_handler))
+}
+
+/// Handler responding with the [`MetricsService`] stored as
+/// `web::Data<MetricsService>`.
+///
+/// Use this instead of [`metrics_scope`] to mount the metrics on a custom
+/// path.
+pub async fn metrics_handler(
+    metrics: web::Data<MetricsService>,
+    request: HttpRequest,
+) -> HttpResponse {
+    // actix-web uses its own version of the http types, convert the parts
+    // the service looks at.
+    let mut converted = http::Request::new(());
+    *converted.method_mut() = request
+        .method()
+        .as_str()
+        .parse()
+        .expect("method should be valid");
+    if let Some(uri) = request
+        .uri()
+        .path_and_query()
+        .and_then(|path| path.as_str().parse().ok())
+    {
+        *converted.uri_mut() = uri;
+    }
+    for (name, value) in request.headers() {
+        if let (Ok(name), Ok(value)) = (
+            http::HeaderName::from_bytes(name.as_ref()),
+            http::HeaderValue::from_bytes(value.as_bytes()),
+        ) {
+            converted.headers_mut().append(name, value);
+        }
+    }
+
+    let (parts, body) = metrics.respond(&converted).into_parts();
+    let mut response = HttpResponse::build(
+        StatusCode::from_u16(parts.status.as_u16()).expect("status should be valid"),
+    );
+    for (name, value) in &parts.headers {
+        response.append_header((name.as_str(), value.as_bytes()));
+    }
+    response.body(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use actix_web::{body::MessageBody, test::TestRequest};
+    use prometheus_client::{metrics::counter::Counter, registry::Registry};
+
+    use super::*;
+
+    fn registry(name: &str) -> Arc<Mutex<Registry>> {
+        let mut registry = Registry::default();
+        registry.register(name, "A counter", Counter::<u64>::default());
+        Arc::new(Mutex::new(registry))
+    }
+
+    async fn get(metrics: &web::Data<MetricsService>, uri: &str) -> (u16, String) {
+        let request = TestRequest::get().uri(uri).to_http_request();
+        let response = metrics_handler(metrics.clone(), request).await;
+        let status = response.status().as_u16();
+        let body = response.into_body().try_into_bytes().unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn selects_registries_by_query() {
+        let metrics = web::Data::new(
+            MetricsService::new(registry("default"))
+                .named_registry("a", registry("a"))
+                .named_registry("b", registry("b")),
+        );
+
+        let (status, body) = get(&metrics, "/metrics?collect[]=a").await;
+        assert_eq!(status, 200);
+        assert!(body.contains("\na_total 0\n"), "{body}");
+        assert!(!body.contains("b_total"), "{body}");
+        assert!(!body.contains("default_total"), "{body}");
+
+        let (_, body) = get(&metrics, "/metrics").await;
+        assert!(body.contains("default_total 0"), "{body}");
+        assert!(body.contains("b_total 0"), "{body}");
+
+        let (status, _) = get(&metrics, "/metrics?collect[]=unknown").await;
+        assert_eq!(status, 400);
+    }
+}
@@ -0,0 +1,69 @@
+//! [axum](https://docs.rs/axum) integration.
+//!
+//! Enabled with the `axum` feature.
+
+use axum::Router;
+
+use crate::tower::MetricsService;
+
+/// Create a [`Router`] serving `metrics` on `/metrics`.
+///
+/// The router does not use any state so it can be merged into an existing
+/// application with [`Router::merge`].
+///
+/// `metrics` is either an `Arc<Mutex<Registry>>` or a [`MetricsService`].
+///
+/// ## Example
+///
+/// ```
+/// # use std::sync::{Arc, Mutex};
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let handle = tokio::runtime::Handle::current();
+/// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// tokio_prometheus_client::register(runtime_monitor, registry.sub_registry_with_prefix("tokio"));
+///
+/// let app: axum::Router = axum::Router::new()
+///     .merge(tokio_prometheus_client::axum::metrics_router(Arc::new(Mutex::new(registry))));
+/// # });
+/// ```
+pub fn metrics_router<S>(metrics: impl Into<MetricsService>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Router::new().route_service("/metrics", metrics.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::body::Body;
+    use http::{Request, StatusCode};
+    use http_body_util::BodyExt;
+    use tower_service::Service;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn routes_metrics() {
+        let mut registry = prometheus_client::registry::Registry::default();
+        crate::RuntimeCollectorBuilder::noop().register(&mut registry);
+        let mut router = Router::new()
+            .merge(metrics_router(Arc::new(Mutex::new(registry))))
+            .with_state(());
+
+        let request = Request::get("/metrics").body(Body::empty()).unwrap();
+        let response = router.call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(std::str::from_utf8(&body)
+            .unwrap()
+            .contains("workers_count 0\n"));
+
+        let request = Request::get("/other").body(Body::empty()).unwrap();
+        let response = router.call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,66 @@
+//! Minimal base64 encoding, not worth pulling in a dependency for.
+
+#[cfg(any(feature = "remote-write", feature = "tower"))]
+const STANDARD: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+#[cfg(feature = "pushgateway")]
+const URL_SAFE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Encode `data` with the standard alphabet and padding.
+#[cfg(any(feature = "remote-write", feature = "tower"))]
+pub(crate) fn encode(data: &[u8]) -> String {
+    let mut encoded = encode_with(data, STANDARD);
+    for _ in 0..(3 - data.len() % 3) % 3 {
+        encoded.push('=');
+    }
+    encoded
+}
+
+/// Encode `data` with the URL safe alphabet and without padding.
+#[cfg(feature = "pushgateway")]
+pub(crate) fn encode_url_safe(data: &[u8]) -> String {
+    encode_with(data, URL_SAFE)
+}
+
+fn encode_with(data: &[u8], alphabet: &[u8; 64]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            encoded.push(alphabet[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(any(feature = "remote-write", feature = "tower"))]
+    #[test]
+    fn encodes_rfc_4648_vectors() {
+        let vectors = [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ];
+        for (data, encoded) in vectors {
+            assert_eq!(encode(data.as_bytes()), encoded);
+        }
+        assert_eq!(encode(&[0xfb, 0xff]), "+/8=");
+    }
+
+    #[cfg(feature = "pushgateway")]
+    #[test]
+    fn encodes_url_safe_without_padding() {
+        assert_eq!(encode_url_safe(b"fo"), "Zm8");
+        assert_eq!(encode_url_safe(&[0xfb, 0xff]), "-_8");
+    }
+}
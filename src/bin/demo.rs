@@ -0,0 +1,63 @@
+//! Serve the metrics of a runtime under synthetic load.
+//!
+//! Built with the `bin` feature, run with
+//! `cargo run --features bin -- [ADDR]`. Metrics are served on
+//! `http://{ADDR}/metrics`, `0.0.0.0:9090` by default.
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use prometheus_client::registry::Registry;
+
+/// Number of tasks generating load.
+const TASKS: u64 = 64;
+
+#[tokio::main]
+async fn main() {
+    let addr: SocketAddr = match std::env::args().nth(1) {
+        Some(addr) => addr.parse().expect("ADDR should be a socket address"),
+        None => SocketAddr::from(([0, 0, 0, 0], 9090)),
+    };
+
+    let handle = tokio::runtime::Handle::current();
+    let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+    let mut registry = Registry::default();
+    tokio_prometheus_client::register(runtime_monitor, registry.sub_registry_with_prefix("tokio"));
+
+    for task in 0..TASKS {
+        tokio::spawn(load(task));
+    }
+
+    println!("serving metrics on http://{addr}/metrics");
+    tokio_prometheus_client::server::serve_metrics(addr, Arc::new(Mutex::new(registry)))
+        .await
+        .expect("should be able to serve metrics");
+}
+
+/// Mix of short polls, long polls, yields, timers and spawns so every
+/// runtime metric moves.
+async fn load(task: u64) {
+    let mut round = 0u64;
+    loop {
+        round += 1;
+        match (task + round) % 5 {
+            0 => tokio::task::yield_now().await,
+            1 => tokio::time::sleep(Duration::from_millis(task % 10 + 1)).await,
+            2 => {
+                // Blocks the worker for a bit, causing steals and queueing.
+                std::thread::sleep(Duration::from_micros(200 * (task % 4)));
+            }
+            3 => {
+                let _ = tokio::spawn(async { tokio::task::yield_now().await }).await;
+            }
+            _ => {
+                let _ =
+                    tokio::task::spawn_blocking(|| std::thread::sleep(Duration::from_millis(1)))
+                        .await;
+            }
+        }
+    }
+}
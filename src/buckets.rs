@@ -0,0 +1,99 @@
+//! Named bucket presets for duration histograms.
+
+/// Buckets of a duration histogram, in seconds.
+///
+/// Using the same preset for all histograms of a fleet keeps them
+/// comparable and aggregatable.
+///
+/// ## Example
+///
+/// ```
+/// use prometheus_client::metrics::histogram::Histogram;
+/// use tokio_prometheus_client::buckets::BucketPreset;
+///
+/// let histogram = Histogram::new(BucketPreset::LatencyFine.buckets().iter().copied());
+/// histogram.observe(0.000_42);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum BucketPreset {
+    /// 10µs to 1s, for task polls and other short operations.
+    LatencyFine,
+    /// 1ms to 10s, for requests and IO. The default.
+    #[default]
+    LatencyCoarse,
+    /// 100ms to 1h, for jobs and long lived operations.
+    LongRunning,
+}
+
+impl BucketPreset {
+    /// The name of the preset, as deserialized.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            BucketPreset::LatencyFine => "latency_fine",
+            BucketPreset::LatencyCoarse => "latency_coarse",
+            BucketPreset::LongRunning => "long_running",
+        }
+    }
+
+    /// The upper bounds of the buckets, in seconds.
+    pub fn buckets(self) -> &'static [f64] {
+        match self {
+            BucketPreset::LatencyFine => &[
+                0.000_01, 0.000_025, 0.000_05, 0.000_1, 0.000_25, 0.000_5, 0.001, 0.002_5, 0.005,
+                0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+            ],
+            BucketPreset::LatencyCoarse => &[
+                0.001, 0.002_5, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+            ],
+            BucketPreset::LongRunning => &[
+                0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0,
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRESETS: [BucketPreset; 3] = [
+        BucketPreset::LatencyFine,
+        BucketPreset::LatencyCoarse,
+        BucketPreset::LongRunning,
+    ];
+
+    #[test]
+    fn buckets_ascend_over_the_documented_ranges() {
+        for preset in PRESETS {
+            let buckets = preset.buckets();
+            assert!(
+                buckets.windows(2).all(|pair| pair[0] < pair[1]),
+                "{preset:?}"
+            );
+        }
+        let range = |preset: BucketPreset| {
+            let buckets = preset.buckets();
+            (buckets[0], buckets[buckets.len() - 1])
+        };
+        assert_eq!(range(BucketPreset::LatencyFine), (0.000_01, 1.0));
+        assert_eq!(range(BucketPreset::LatencyCoarse), (0.001, 10.0));
+        assert_eq!(range(BucketPreset::LongRunning), (0.1, 3600.0));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn names_deserialize() {
+        for preset in PRESETS {
+            let name = serde_json::Value::from(preset.name());
+            assert_eq!(
+                serde_json::from_value::<BucketPreset>(name).unwrap(),
+                preset
+            );
+        }
+    }
+}
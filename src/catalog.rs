@@ -0,0 +1,70 @@
+//! Machine readable descriptions of the exported metrics.
+//!
+//! See [`RuntimeCollectorBuilder::catalog`](crate::RuntimeCollectorBuilder::catalog),
+//! e.g. to validate dashboards against the metrics or generate their
+//! documentation.
+
+use prometheus_client::metrics::MetricType;
+
+use crate::{names::Family, samples::MetricFamily};
+
+/// How likely a metric is to change or vanish.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stability {
+    /// Backed by stable Tokio APIs, or maintained by this crate, and kept
+    /// across Tokio upgrades.
+    Stable,
+    /// Backed by Tokio APIs only available with `--cfg tokio_unstable`,
+    /// which may change or be removed in any Tokio release.
+    Unstable,
+    /// New in this crate, its name or meaning may still change.
+    Experimental,
+}
+
+/// The description of an exported metric family.
+#[derive(Clone, Debug)]
+pub struct MetricDescription {
+    /// Name of the family, including the unit suffix.
+    pub name: String,
+    /// Type of the family.
+    pub metric_type: MetricType,
+    /// Unit of the family, if any.
+    pub unit: Option<String>,
+    /// Names of the labels of its samples.
+    pub labels: Vec<String>,
+    /// Help text of the family.
+    pub help: String,
+    /// Stability of the family, that of the counter for its `_created` and
+    /// `_per_second` families.
+    pub stability: Stability,
+}
+
+/// Describe the encoded `families` of a collector exporting `exporting`.
+pub(crate) fn describe(
+    exporting: &[Family],
+    families: Vec<MetricFamily>,
+) -> Vec<MetricDescription> {
+    families
+        .into_iter()
+        .map(|family| {
+            let mut labels: Vec<String> = Vec::new();
+            for sample in &family.samples {
+                for (label, _) in &sample.labels {
+                    if !labels.contains(label) {
+                        labels.push(label.clone());
+                    }
+                }
+            }
+            let stability = crate::names::exporting(exporting, &family.name)
+                .map_or(Stability::Experimental, |family| family.stability);
+            MetricDescription {
+                name: family.name,
+                metric_type: family.metric_type,
+                unit: family.unit,
+                labels,
+                help: family.help,
+                stability,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,324 @@
+//! Build collectors of metric structs updated from snapshots, the way the
+//! runtime metrics are collected.
+//!
+//! A struct implementing [`CollectorMetrics`] holds one metric per field
+//! and is updated from the fields of the same name of a snapshot type, like
+//! the intervals of a [`tokio_metrics::TaskMonitor`]. With the `derive`
+//! feature, `#[derive(CollectorMetrics)]` generates the implementation.
+//!
+//! ## Example
+//!
+#![cfg_attr(feature = "derive", doc = "```")]
+#![cfg_attr(not(feature = "derive"), doc = "```ignore")]
+//! use prometheus_client::metrics::{counter::Counter, gauge::Gauge};
+//! use tokio_prometheus_client::collector::{CollectorMetrics, SnapshotCollector};
+//!
+//! #[derive(Debug, Default, CollectorMetrics)]
+//! #[collector_metrics(snapshot = tokio_metrics::TaskMetrics)]
+//! struct TaskMetrics {
+//!     /// The number of tasks instrumented
+//!     #[metric(counter)]
+//!     instrumented_count: Counter,
+//!     /// The number of tasks dropped
+//!     #[metric(counter)]
+//!     dropped_count: Counter,
+//!     /// The time tasks were polled
+//!     #[metric(counter, duration, unit = "seconds", name = "poll_duration")]
+//!     total_poll_duration: Counter<f64>,
+//!     /// The number of times tasks idled during the last interval
+//!     #[metric(gauge, from = total_idled_count)]
+//!     idled_count: Gauge,
+//! }
+//!
+//! let monitor = tokio_metrics::TaskMonitor::new();
+//! let mut intervals = monitor.intervals();
+//! let mut registry = prometheus_client::registry::Registry::default();
+//! registry
+//!     .sub_registry_with_prefix("tasks")
+//!     .register_collector(Box::new(SnapshotCollector::<TaskMetrics, _>::new(
+//!         move || intervals.next(),
+//!     )));
+//!
+//! let mut text = String::new();
+//! prometheus_client::encoding::text::encode(&mut text, &registry).unwrap();
+//! assert!(text.contains("tasks_instrumented_count_total 0\n"));
+//! assert!(text.contains("tasks_poll_duration_seconds_total 0.0\n"));
+//! ```
+//!
+//! ## Attributes
+//!
+//! The snapshot type is set with `#[collector_metrics(snapshot = Type)]`
+//! on the struct. Only fields with a `#[metric(..)]` attribute are metrics,
+//! with the doc comment as help text:
+//!
+//! * `counter` or `gauge`: counters are incremented by the value of the
+//!   snapshot, gauges are set to it. The value is converted with `as`.
+//! * `duration`: the value is a [`Duration`](std::time::Duration), counted
+//!   in seconds.
+//! * `name = "..."`: the name of the metric, by default the field name.
+//! * `unit = "..."`: the unit of the metric, e.g. `seconds` or `bytes`.
+//! * `from = field`: the field of the snapshot, by default the field name.
+//!
+//! Invalid metric names, and names exported more than once, fail the build.
+//!
+//! ## Gauges of closures
+//!
+//! A value only known to the application, like the size of a pool, is
+//! exported with a [`GaugeFn`] evaluating a closure on every collection,
+//! without a collector of its own.
+
+use std::sync::Mutex;
+
+use prometheus_client::{
+    collector::Collector,
+    encoding::{DescriptorEncoder, EncodeGaugeValue, EncodeMetric},
+    metrics::{gauge::ConstGauge, MetricType},
+    registry::Unit,
+};
+
+#[cfg(feature = "derive")]
+pub use tokio_prometheus_client_derive::CollectorMetrics;
+
+/// Metrics updated from snapshots of type [`CollectorMetrics::Snapshot`].
+pub trait CollectorMetrics {
+    /// The snapshot the metrics are updated from.
+    type Snapshot;
+
+    /// Increment the counters and set the gauges from `snapshot`.
+    fn update(&self, snapshot: &Self::Snapshot);
+
+    /// Encode each metric as a family of its own.
+    fn encode(&self, encoder: &mut DescriptorEncoder) -> Result<(), std::fmt::Error>;
+}
+
+/// Collects [`CollectorMetrics`] updated from a snapshot taken on every
+/// collection.
+///
+/// Collections without a snapshot, when `snapshot` returns `None`, encode
+/// the metrics unchanged.
+pub struct SnapshotCollector<M, F> {
+    metrics: M,
+    snapshot: Mutex<F>,
+}
+
+impl<M, F> SnapshotCollector<M, F>
+where
+    M: CollectorMetrics + Default,
+    F: FnMut() -> Option<M::Snapshot>,
+{
+    /// Create a [`SnapshotCollector`] taking snapshots with `snapshot`.
+    pub fn new(snapshot: F) -> Self {
+        Self {
+            metrics: M::default(),
+            snapshot: Mutex::new(snapshot),
+        }
+    }
+}
+
+impl<M: std::fmt::Debug, F> std::fmt::Debug for SnapshotCollector<M, F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SnapshotCollector")
+            .field("metrics", &self.metrics)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<M, F> Collector for SnapshotCollector<M, F>
+where
+    M: CollectorMetrics + std::fmt::Debug + Send + Sync + 'static,
+    F: FnMut() -> Option<M::Snapshot> + Send + 'static,
+{
+    fn encode(&self, mut encoder: DescriptorEncoder) -> Result<(), std::fmt::Error> {
+        let mut take_snapshot = self
+            .snapshot
+            .lock()
+            .expect("should be able to lock snapshot");
+        if let Some(snapshot) = take_snapshot() {
+            self.metrics.update(&snapshot);
+        }
+        self.metrics.encode(&mut encoder)
+    }
+}
+
+/// Collects a gauge set to the value of a closure on every collection.
+///
+/// ## Example
+///
+/// ```
+/// use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+/// use tokio_prometheus_client::collector::GaugeFn;
+///
+/// let connections = Arc::new(AtomicUsize::new(3));
+/// let pool = connections.clone();
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// registry.register_collector(Box::new(GaugeFn::new(
+///     "pool_connections",
+///     "The number of connections of the pool",
+///     move || pool.load(Ordering::Relaxed) as i64,
+/// )));
+///
+/// let mut text = String::new();
+/// prometheus_client::encoding::text::encode(&mut text, &registry).unwrap();
+/// assert!(text.contains("pool_connections 3\n"));
+/// ```
+pub struct GaugeFn<F> {
+    name: String,
+    help: String,
+    unit: Option<Unit>,
+    value: F,
+}
+
+impl<F, N> GaugeFn<F>
+where
+    F: Fn() -> N,
+    N: EncodeGaugeValue,
+{
+    /// Create a [`GaugeFn`] named `name` set to the value of `value`.
+    ///
+    /// `value` is called while encoding, it should be quick and must not
+    /// encode the registry itself.
+    pub fn new(name: impl Into<String>, help: impl Into<String>, value: F) -> Self {
+        Self {
+            name: name.into(),
+            help: help.into(),
+            unit: None,
+            value,
+        }
+    }
+
+    /// Set the unit of the gauge, appended to its name.
+    pub fn unit(mut self, unit: Unit) -> Self {
+        self.unit = Some(unit);
+        self
+    }
+}
+
+impl<F> std::fmt::Debug for GaugeFn<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GaugeFn")
+            .field("name", &self.name)
+            .field("unit", &self.unit)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F, N> Collector for GaugeFn<F>
+where
+    F: Fn() -> N + Send + Sync + 'static,
+    N: EncodeGaugeValue,
+{
+    fn encode(&self, mut encoder: DescriptorEncoder) -> Result<(), std::fmt::Error> {
+        let gauge = ConstGauge::new((self.value)());
+        let metric = encoder.encode_descriptor(
+            &self.name,
+            &self.help,
+            self.unit.as_ref(),
+            MetricType::Gauge,
+        )?;
+        gauge.encode(metric)
+    }
+}
+
+#[doc(hidden)]
+pub mod __private {
+    pub use prometheus_client::{
+        encoding::{DescriptorEncoder, EncodeMetric},
+        registry::Unit,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "derive")]
+    use std::time::Duration;
+
+    use prometheus_client::registry::Registry;
+
+    use super::*;
+
+    /// A snapshot of what the metrics are updated from.
+    #[cfg(feature = "derive")]
+    #[derive(Debug)]
+    struct Snapshot {
+        requests: u32,
+        busy: Duration,
+        in_flight: usize,
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(Debug, Default, CollectorMetrics)]
+    #[collector_metrics(snapshot = Snapshot)]
+    struct Metrics {
+        /// Requests served
+        #[metric(counter)]
+        requests: prometheus_client::metrics::counter::Counter,
+        /// Time spent serving requests
+        #[metric(counter, duration, unit = "seconds", name = "busy_time", from = busy)]
+        busy_duration: prometheus_client::metrics::counter::Counter<f64>,
+        /// Requests in flight
+        #[metric(gauge)]
+        in_flight: prometheus_client::metrics::gauge::Gauge,
+        /// Not a metric, without an attribute.
+        #[allow(dead_code)]
+        ignored: u64,
+    }
+
+    fn encoded(registry: &Registry) -> String {
+        let mut text = String::new();
+        prometheus_client::encoding::text::encode(&mut text, registry).unwrap();
+        text
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn derived_metrics_update_from_snapshots() {
+        let mut snapshots = vec![
+            None,
+            Some(Snapshot {
+                requests: 2,
+                busy: Duration::from_millis(500),
+                in_flight: 1,
+            }),
+            Some(Snapshot {
+                requests: 3,
+                busy: Duration::from_millis(250),
+                in_flight: 4,
+            }),
+        ]
+        .into_iter();
+        let mut registry = Registry::default();
+        registry.register_collector(Box::new(SnapshotCollector::<Metrics, _>::new(move || {
+            snapshots.next().flatten()
+        })));
+
+        let text = encoded(&registry);
+        assert!(text.contains("# HELP requests Requests served\n"), "{text}");
+        assert!(text.contains("requests_total 0\n"), "{text}");
+        encoded(&registry);
+        let text = encoded(&registry);
+        assert!(text.contains("requests_total 5\n"), "{text}");
+        assert!(
+            text.contains("# UNIT busy_time_seconds seconds\n"),
+            "{text}"
+        );
+        assert!(text.contains("busy_time_seconds_total 0.75\n"), "{text}");
+        assert!(text.contains("in_flight 4\n"), "{text}");
+        assert!(!text.contains("ignored"), "{text}");
+    }
+
+    #[test]
+    fn gauge_fn_evaluates_on_collection() {
+        let value = std::sync::Arc::new(std::sync::atomic::AtomicI64::new(1));
+        let gauge = value.clone();
+        let mut registry = Registry::default();
+        registry.register_collector(Box::new(
+            GaugeFn::new("pool_size", "The size of the pool", move || {
+                gauge.load(std::sync::atomic::Ordering::Relaxed)
+            })
+            .unit(Unit::Bytes),
+        ));
+        assert!(encoded(&registry).contains("pool_size_bytes 1\n"));
+        value.store(7, std::sync::atomic::Ordering::Relaxed);
+        assert!(encoded(&registry).contains("pool_size_bytes 7\n"));
+    }
+}
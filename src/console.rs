@@ -0,0 +1,383 @@
+//! Collect the aggregates of a
+//! [console-subscriber](https://crates.io/crates/console-subscriber) into a
+//! [`Registry`].
+//!
+//! Enabled with the `console` feature. Applications already instrumented for
+//! `tokio-console` get summarized task and resource metrics without a second
+//! instrumentation.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use console_api::{
+    instrument::{instrument_client::InstrumentClient, InstrumentRequest, Update},
+    tasks,
+};
+use prometheus_client::{
+    collector::Collector,
+    encoding::{DescriptorEncoder, EncodeMetric},
+    metrics::{counter::ConstCounter, gauge::ConstGauge, MetricType},
+    registry::Registry,
+};
+use tokio::task::JoinHandle;
+
+use crate::labels::sanitize_value;
+
+/// Subscribes to the instrument server of a console-subscriber and collects
+/// its aggregates.
+///
+/// The following metrics are collected:
+///
+/// * `tasks`: the number of live tasks by `state`, one of `idle`,
+///   `scheduled` and `running`.
+/// * `tasks_completed`: the number of tasks that completed.
+/// * `task_wakes`, `task_self_wakes` and `task_polls`: the number of times
+///   tasks were woken, woke themselves and were polled.
+/// * `resources`: the number of live resources by `concrete_type`, e.g.
+///   `Sleep`, sanitized with [`sanitize_value`].
+///
+/// The console-subscriber resends its state on every new subscription, so
+/// the counters restart after reconnecting.
+///
+/// ## Example
+///
+/// ```no_run
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// tokio_prometheus_client::console::Console::new("http://127.0.0.1:6669")
+///     .register(registry.sub_registry_with_prefix("console"));
+/// # });
+/// ```
+#[derive(Debug)]
+pub struct Console {
+    target: String,
+    reconnect_interval: Duration,
+}
+
+impl Console {
+    /// Create a [`Console`] subscribing to the instrument server at
+    /// `target`, by default `http://127.0.0.1:6669`.
+    ///
+    /// Failed subscriptions are retried every 5 seconds.
+    pub fn new(target: impl Into<String>) -> Self {
+        Self {
+            target: target.into(),
+            reconnect_interval: Duration::from_secs(5),
+        }
+    }
+
+    /// Set the interval between attempts to subscribe.
+    pub fn reconnect_interval(mut self, interval: Duration) -> Self {
+        self.reconnect_interval = interval;
+        self
+    }
+
+    /// Register the collector of the aggregates with `registry` and spawn the
+    /// task subscribing to the instrument server.
+    ///
+    /// Failed subscriptions are logged.
+    pub fn register(self, registry: &mut Registry) -> JoinHandle<()> {
+        let aggregates = Arc::new(Mutex::new(Aggregates::default()));
+        registry.register_collector(Box::new(ConsoleCollector {
+            aggregates: aggregates.clone(),
+        }));
+        tokio::spawn(async move {
+            loop {
+                if let Err(err) = self.subscribe(&aggregates).await {
+                    tracing::warn!(%err, target = self.target, "failed to subscribe to console");
+                }
+                tokio::time::sleep(self.reconnect_interval).await;
+            }
+        })
+    }
+
+    /// Apply the updates of one subscription until it ends.
+    async fn subscribe(&self, aggregates: &Mutex<Aggregates>) -> Result<(), String> {
+        let mut client = InstrumentClient::connect(self.target.clone())
+            .await
+            .map_err(|err| err.to_string())?;
+        let mut updates = client
+            .watch_updates(InstrumentRequest {})
+            .await
+            .map_err(|err| err.to_string())?
+            .into_inner();
+        *aggregates
+            .lock()
+            .expect("should be able to lock aggregates") = Aggregates::default();
+        while let Some(update) = updates.message().await.map_err(|err| err.to_string())? {
+            aggregates
+                .lock()
+                .expect("should be able to lock aggregates")
+                .apply(update);
+        }
+        Ok(())
+    }
+}
+
+/// State of a task, as shown by `tokio-console`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TaskState {
+    Idle,
+    Scheduled,
+    Running,
+}
+
+#[derive(Debug)]
+struct Task {
+    state: TaskState,
+    wakes: u64,
+    self_wakes: u64,
+    polls: u64,
+}
+
+/// Aggregates of the current subscription.
+#[derive(Debug, Default)]
+struct Aggregates {
+    /// Live tasks by id.
+    tasks: HashMap<u64, Task>,
+    completed: u64,
+    /// The wakes, self wakes and polls of completed tasks.
+    completed_wakes: u64,
+    completed_self_wakes: u64,
+    completed_polls: u64,
+    /// Concrete types of live resources by id.
+    resources: HashMap<u64, String>,
+}
+
+impl Aggregates {
+    fn apply(&mut self, update: Update) {
+        if let Some(update) = update.task_update {
+            for (id, stats) in update.stats_update {
+                self.apply_task(id, &stats);
+            }
+        }
+        if let Some(update) = update.resource_update {
+            for resource in update.new_resources {
+                if let Some(id) = resource.id {
+                    let concrete_type = sanitize_value(&resource.concrete_type).into_owned();
+                    self.resources.insert(id.id, concrete_type);
+                }
+            }
+            for (id, stats) in update.stats_update {
+                if stats.dropped_at.is_some() {
+                    self.resources.remove(&id);
+                }
+            }
+        }
+    }
+
+    fn apply_task(&mut self, id: u64, stats: &tasks::Stats) {
+        let polls = stats.poll_stats.as_ref();
+        let task = Task {
+            state: task_state(stats),
+            wakes: stats.wakes,
+            self_wakes: stats.self_wakes,
+            polls: polls.map_or(0, |polls| polls.polls),
+        };
+        if stats.dropped_at.is_none() {
+            self.tasks.insert(id, task);
+            return;
+        }
+        // Completed tasks do not change anymore, only their totals are kept.
+        self.tasks.remove(&id);
+        self.completed += 1;
+        self.completed_wakes += task.wakes;
+        self.completed_self_wakes += task.self_wakes;
+        self.completed_polls += task.polls;
+    }
+}
+
+fn task_state(stats: &tasks::Stats) -> TaskState {
+    // Timestamps are comparable as seconds and nanoseconds.
+    macro_rules! at {
+        ($timestamp:expr) => {
+            $timestamp
+                .as_ref()
+                .map(|timestamp| (timestamp.seconds, timestamp.nanos))
+        };
+    }
+    let polls = stats.poll_stats.as_ref();
+    let poll_started = polls.and_then(|polls| at!(polls.last_poll_started));
+    let poll_ended = polls.and_then(|polls| at!(polls.last_poll_ended));
+    if poll_started.is_some() && poll_started > poll_ended {
+        TaskState::Running
+    } else if at!(stats.last_wake) > poll_started {
+        TaskState::Scheduled
+    } else {
+        TaskState::Idle
+    }
+}
+
+/// Collects the [`Aggregates`] of a [`Console`].
+#[derive(Debug)]
+struct ConsoleCollector {
+    aggregates: Arc<Mutex<Aggregates>>,
+}
+
+impl Collector for ConsoleCollector {
+    fn encode(&self, mut encoder: DescriptorEncoder) -> Result<(), std::fmt::Error> {
+        let aggregates = self
+            .aggregates
+            .lock()
+            .expect("should be able to lock aggregates");
+
+        let mut states = [
+            (TaskState::Idle, "idle", 0i64),
+            (TaskState::Scheduled, "scheduled", 0),
+            (TaskState::Running, "running", 0),
+        ];
+        let (mut wakes, mut self_wakes, mut polls) = (
+            aggregates.completed_wakes,
+            aggregates.completed_self_wakes,
+            aggregates.completed_polls,
+        );
+        for task in aggregates.tasks.values() {
+            for (state, _, count) in &mut states {
+                if *state == task.state {
+                    *count += 1;
+                }
+            }
+            wakes += task.wakes;
+            self_wakes += task.self_wakes;
+            polls += task.polls;
+        }
+
+        let mut family = encoder.encode_descriptor(
+            "tasks",
+            "The number of live tasks by state",
+            None,
+            MetricType::Gauge,
+        )?;
+        for (_, state, count) in states {
+            ConstGauge::new(count).encode(family.encode_family(&[("state", state)])?)?;
+        }
+
+        let counters = [
+            (
+                "tasks_completed",
+                "The number of tasks that completed",
+                aggregates.completed,
+            ),
+            ("task_wakes", "The number of times tasks were woken", wakes),
+            (
+                "task_self_wakes",
+                "The number of times tasks woke themselves",
+                self_wakes,
+            ),
+            ("task_polls", "The number of times tasks were polled", polls),
+        ];
+        for (name, help, value) in counters {
+            let counter = ConstCounter::new(value);
+            counter.encode(encoder.encode_descriptor(
+                name,
+                help,
+                None,
+                counter.metric_type(),
+            )?)?;
+        }
+
+        let mut resources: Vec<(&str, i64)> = Vec::new();
+        for concrete_type in aggregates.resources.values() {
+            match resources.iter_mut().find(|(name, _)| name == concrete_type) {
+                Some((_, count)) => *count += 1,
+                None => resources.push((concrete_type, 1)),
+            }
+        }
+        resources.sort_unstable();
+        let mut family = encoder.encode_descriptor(
+            "resources",
+            "The number of live resources by concrete type",
+            None,
+            MetricType::Gauge,
+        )?;
+        for (concrete_type, count) in resources {
+            ConstGauge::new(count)
+                .encode(family.encode_family(&[("concrete_type", concrete_type)])?)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use console_api::{
+        resources::{self, Resource, ResourceUpdate},
+        tasks::TaskUpdate,
+        Id,
+    };
+
+    use super::*;
+
+    fn resource(id: u64, concrete_type: &str) -> Resource {
+        Resource {
+            id: Some(Id { id }),
+            concrete_type: concrete_type.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn encode(aggregates: Aggregates) -> String {
+        let mut registry = Registry::default();
+        registry.register_collector(Box::new(ConsoleCollector {
+            aggregates: Arc::new(Mutex::new(aggregates)),
+        }));
+        let mut text = String::new();
+        prometheus_client::encoding::text::encode(&mut text, &registry).unwrap();
+        text
+    }
+
+    #[test]
+    fn aggregates_live_tasks_and_resources() {
+        let mut aggregates = Aggregates::default();
+        let stats = |polls, dropped: bool| tasks::Stats {
+            wakes: 2,
+            poll_stats: Some(console_api::PollStats {
+                polls,
+                ..Default::default()
+            }),
+            dropped_at: dropped.then(Default::default),
+            ..Default::default()
+        };
+        aggregates.apply(Update {
+            task_update: Some(TaskUpdate {
+                stats_update: [(1, stats(3, false)), (2, stats(4, true))].into(),
+                ..Default::default()
+            }),
+            resource_update: Some(ResourceUpdate {
+                new_resources: vec![
+                    resource(1, "Sleep"),
+                    resource(2, "Sleep"),
+                    resource(3, "Mutex<\"a\">"),
+                ],
+                stats_update: [(
+                    2,
+                    resources::Stats {
+                        dropped_at: Some(Default::default()),
+                        ..Default::default()
+                    },
+                )]
+                .into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+
+        let text = encode(aggregates);
+        assert!(text.contains("tasks{state=\"idle\"} 1\n"), "{text}");
+        assert!(text.contains("tasks_completed_total 1\n"), "{text}");
+        assert!(text.contains("task_polls_total 7\n"), "{text}");
+        assert!(text.contains("task_wakes_total 4\n"), "{text}");
+        assert!(
+            text.contains("resources{concrete_type=\"Mutex<_a_>\"} 1\n"),
+            "{text}"
+        );
+        assert!(
+            text.contains("resources{concrete_type=\"Sleep\"} 1\n"),
+            "{text}"
+        );
+    }
+}
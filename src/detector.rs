@@ -0,0 +1,329 @@
+//! Detect long polls and tasks that are never woken.
+//!
+//! Enabled with the `detector` feature. `tokio_metrics` counts polls but
+//! cannot tell which futures misbehave. Futures instrumented with a
+//! [`Detector`] report polls that block a worker for too long and pending
+//! futures whose waker is never called, which otherwise only show up as
+//! "my future is stuck" while debugging.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, Weak,
+    },
+    task::{Context, Poll, Wake, Waker},
+    time::{Duration, Instant},
+};
+
+use prometheus_client::{
+    collector::Collector,
+    encoding::{DescriptorEncoder, EncodeMetric},
+    metrics::{counter::ConstCounter, gauge::ConstGauge},
+    registry::Registry,
+};
+
+/// Instruments futures to detect long polls and tasks that are never woken.
+///
+/// Exposes:
+///
+/// * `detected_long_polls`: the number of polls that took longer than the
+///   long poll threshold.
+/// * `detected_stuck_tasks`: the number of times an instrumented future
+///   was found pending without being woken for longer than the stuck
+///   threshold, counted once per wait.
+/// * `stuck_tasks`: the number of instrumented futures currently stuck.
+///
+/// Stuck futures are looked for on every collection. Futures waiting on
+/// timers or other events longer than the stuck threshold look stuck as
+/// well, so it should be well above the longest expected wait.
+///
+/// ## Example
+///
+/// ```
+/// # use std::time::Duration;
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// let detector = tokio_prometheus_client::detector::Detector::new()
+///     .long_poll(Duration::from_millis(10))
+///     .stuck_after(Duration::from_secs(60));
+/// detector.register(registry.sub_registry_with_prefix("tokio"));
+///
+/// tokio::spawn(detector.instrument(async {
+///     tokio::time::sleep(Duration::from_millis(1)).await;
+/// }))
+/// .await
+/// .unwrap();
+/// # });
+/// ```
+#[derive(Clone, Debug)]
+pub struct Detector {
+    long_poll: Duration,
+    stuck_after: Duration,
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    long_polls: AtomicU64,
+    stuck_tasks: AtomicU64,
+    /// The instrumented futures, pruned on every collection.
+    tasks: Mutex<Vec<Weak<Task>>>,
+}
+
+impl Detector {
+    /// Create a [`Detector`] counting polls longer than 10ms and futures
+    /// pending without being woken for 5 minutes.
+    pub fn new() -> Self {
+        Self {
+            long_poll: Duration::from_millis(10),
+            stuck_after: Duration::from_secs(5 * 60),
+            shared: Arc::default(),
+        }
+    }
+
+    /// Set the duration above which a poll is a long poll.
+    pub fn long_poll(mut self, long_poll: Duration) -> Self {
+        self.long_poll = long_poll;
+        self
+    }
+
+    /// Set how long a future may stay pending without being woken before
+    /// it counts as stuck.
+    pub fn stuck_after(mut self, stuck_after: Duration) -> Self {
+        self.stuck_after = stuck_after;
+        self
+    }
+
+    /// Register the collector of the detections with `registry`.
+    pub fn register(&self, registry: &mut Registry) {
+        registry.register_collector(Box::new(DetectorCollector {
+            stuck_after: self.stuck_after,
+            shared: self.shared.clone(),
+        }));
+    }
+
+    /// Instrument `future`, e.g. before spawning it.
+    pub fn instrument<F: Future>(&self, future: F) -> Detected<F> {
+        let task = Arc::new(Task::default());
+        self.shared
+            .tasks
+            .lock()
+            .expect("should be able to lock detector tasks")
+            .push(Arc::downgrade(&task));
+        Detected {
+            future: Box::pin(future),
+            long_poll: self.long_poll,
+            shared: self.shared.clone(),
+            task,
+            waker: None,
+        }
+    }
+}
+
+impl Default for Detector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A future instrumented by [`Detector::instrument`].
+#[derive(Debug)]
+pub struct Detected<F> {
+    future: Pin<Box<F>>,
+    long_poll: Duration,
+    shared: Arc<Shared>,
+    task: Arc<Task>,
+    /// The waker of the last poll and the one wrapping it, reused while the
+    /// future is polled by the same task.
+    waker: Option<(Waker, Waker)>,
+}
+
+/// The state of an instrumented future.
+#[derive(Debug, Default)]
+struct Task {
+    /// When the future last returned pending, if it still is.
+    pending_since: Mutex<Option<Instant>>,
+    woken: AtomicBool,
+    /// Whether the current wait was counted as stuck already.
+    reported: AtomicBool,
+}
+
+/// Marks the task woken before waking it.
+struct DetectingWaker {
+    waker: Waker,
+    task: Weak<Task>,
+}
+
+impl Wake for DetectingWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if let Some(task) = self.task.upgrade() {
+            task.woken.store(true, Ordering::Release);
+        }
+        self.waker.wake_by_ref();
+    }
+}
+
+impl<F: Future> Future for Detected<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let waker = match &this.waker {
+            Some((waker, detecting)) if waker.will_wake(cx.waker()) => detecting.clone(),
+            _ => {
+                let detecting = Waker::from(Arc::new(DetectingWaker {
+                    waker: cx.waker().clone(),
+                    task: Arc::downgrade(&this.task),
+                }));
+                this.waker = Some((cx.waker().clone(), detecting.clone()));
+                detecting
+            }
+        };
+
+        this.task.woken.store(false, Ordering::Release);
+        let started = Instant::now();
+        let poll = this.future.as_mut().poll(&mut Context::from_waker(&waker));
+        let now = Instant::now();
+        if now.duration_since(started) > this.long_poll {
+            this.shared.long_polls.fetch_add(1, Ordering::Relaxed);
+        }
+
+        *this
+            .task
+            .pending_since
+            .lock()
+            .expect("should be able to lock detector task") = poll.is_pending().then_some(now);
+        this.task.reported.store(false, Ordering::Relaxed);
+        poll
+    }
+}
+
+/// Collects the detections of a [`Detector`].
+#[derive(Debug)]
+struct DetectorCollector {
+    stuck_after: Duration,
+    shared: Arc<Shared>,
+}
+
+impl Collector for DetectorCollector {
+    fn encode(&self, mut encoder: DescriptorEncoder) -> Result<(), std::fmt::Error> {
+        let mut stuck: i64 = 0;
+        self.shared
+            .tasks
+            .lock()
+            .expect("should be able to lock detector tasks")
+            .retain(|task| {
+                let Some(task) = task.upgrade() else {
+                    return false;
+                };
+                let pending_since = *task
+                    .pending_since
+                    .lock()
+                    .expect("should be able to lock detector task");
+                let is_stuck = pending_since
+                    .is_some_and(|pending_since| pending_since.elapsed() > self.stuck_after)
+                    && !task.woken.load(Ordering::Acquire);
+                if is_stuck {
+                    stuck += 1;
+                    if !task.reported.swap(true, Ordering::Relaxed) {
+                        self.shared.stuck_tasks.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                true
+            });
+
+        let long_polls = ConstCounter::new(self.shared.long_polls.load(Ordering::Relaxed));
+        long_polls.encode(encoder.encode_descriptor(
+            "detected_long_polls",
+            "The number of polls of instrumented futures longer than the long poll threshold",
+            None,
+            long_polls.metric_type(),
+        )?)?;
+
+        let stuck_tasks = ConstCounter::new(self.shared.stuck_tasks.load(Ordering::Relaxed));
+        stuck_tasks.encode(encoder.encode_descriptor(
+            "detected_stuck_tasks",
+            "The number of times instrumented futures were found pending without being woken for longer than the stuck threshold",
+            None,
+            stuck_tasks.metric_type(),
+        )?)?;
+
+        let stuck = ConstGauge::new(stuck);
+        stuck.encode(encoder.encode_descriptor(
+            "stuck_tasks",
+            "The number of instrumented futures pending without being woken for longer than the stuck threshold",
+            None,
+            stuck.metric_type(),
+        )?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(registry: &Registry, name: &str) -> f64 {
+        let mut text = String::new();
+        prometheus_client::encoding::text::encode(&mut text, registry).unwrap();
+        text.lines()
+            .find_map(|line| line.strip_prefix(&format!("{name} ")))
+            .unwrap()
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn counts_long_polls() {
+        let mut registry = Registry::default();
+        let detector = Detector::new().long_poll(Duration::from_millis(5));
+        detector.register(&mut registry);
+
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut fast = std::pin::pin!(detector.instrument(async {}));
+        assert!(fast.as_mut().poll(&mut cx).is_ready());
+        let mut slow = std::pin::pin!(detector.instrument(async {
+            std::thread::sleep(Duration::from_millis(10));
+        }));
+        assert!(slow.as_mut().poll(&mut cx).is_ready());
+
+        assert_eq!(sample(&registry, "detected_long_polls_total"), 1.0);
+    }
+
+    #[test]
+    fn counts_stuck_tasks_once_per_wait() {
+        let mut registry = Registry::default();
+        let detector = Detector::new().stuck_after(Duration::ZERO);
+        detector.register(&mut registry);
+
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut stuck = Box::pin(detector.instrument(std::future::pending::<()>()));
+        let mut woken = Box::pin(detector.instrument(std::future::poll_fn(|cx| {
+            cx.waker().wake_by_ref();
+            Poll::<()>::Pending
+        })));
+        assert!(stuck.as_mut().poll(&mut cx).is_pending());
+        assert!(woken.as_mut().poll(&mut cx).is_pending());
+        std::thread::sleep(Duration::from_millis(1));
+
+        assert_eq!(sample(&registry, "stuck_tasks"), 1.0);
+        assert_eq!(sample(&registry, "detected_stuck_tasks_total"), 1.0);
+        assert_eq!(sample(&registry, "stuck_tasks"), 1.0);
+        assert_eq!(sample(&registry, "detected_stuck_tasks_total"), 1.0);
+
+        // Another poll starts another wait, dropping the future ends it.
+        assert!(stuck.as_mut().poll(&mut cx).is_pending());
+        std::thread::sleep(Duration::from_millis(1));
+        assert_eq!(sample(&registry, "detected_stuck_tasks_total"), 2.0);
+        drop(stuck);
+        assert_eq!(sample(&registry, "stuck_tasks"), 0.0);
+        assert_eq!(detector.shared.tasks.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,387 @@
+//! Write a [`Registry`] in the CloudWatch
+//! [Embedded Metric Format](https://docs.aws.amazon.com/AmazonCloudWatch/latest/monitoring/CloudWatch_Embedded_Metric_Format_Specification.html).
+//!
+//! Enabled with the `emf` feature. Useful on Lambda and Fargate, where
+//! CloudWatch extracts metrics from the EMF lines of the logs.
+
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use prometheus_client::{metrics::MetricType, registry::Registry};
+use serde_json::{json, Map, Value};
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+
+use crate::samples::collect;
+
+/// CloudWatch accepts at most 100 metrics per EMF document.
+const MAX_METRICS: usize = 100;
+
+/// Periodically writes a [`Registry`] as EMF JSON lines.
+///
+/// Samples with the same labels are written as one document, with the label
+/// names as dimensions. Gauges and info metrics are written as their value.
+/// CloudWatch aggregates the values it receives, so counters and histograms
+/// are written as the increase since the previous emission. Values and the
+/// `_sum` samples of histograms are written in the unit of their metric,
+/// `_count` and `_bucket` samples as counts.
+///
+/// ## Example
+///
+/// ```no_run
+/// # use std::{sync::{Arc, Mutex}, time::Duration};
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let handle = tokio::runtime::Handle::current();
+/// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// tokio_prometheus_client::register(runtime_monitor, registry.sub_registry_with_prefix("tokio"));
+///
+/// tokio_prometheus_client::emf::Emf::stdout("MyService")
+///     .interval(Duration::from_secs(60))
+///     .spawn(Arc::new(Mutex::new(registry)));
+/// # });
+/// ```
+#[derive(Debug)]
+pub struct Emf {
+    namespace: String,
+    target: Target,
+    interval: Duration,
+    previous: Mutex<HashMap<String, f64>>,
+}
+
+#[derive(Debug)]
+enum Target {
+    Stdout,
+    File(PathBuf),
+}
+
+impl Emf {
+    /// Create an [`Emf`] writing to stdout, e.g. on Lambda.
+    ///
+    /// Metrics are written to `namespace` every 60 seconds.
+    pub fn stdout(namespace: impl Into<String>) -> Self {
+        Self::new(namespace.into(), Target::Stdout)
+    }
+
+    /// Create an [`Emf`] appending to the file at `path`, e.g. one tailed by
+    /// the CloudWatch agent.
+    ///
+    /// Metrics are written to `namespace` every 60 seconds.
+    pub fn file(namespace: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        Self::new(namespace.into(), Target::File(path.into()))
+    }
+
+    fn new(namespace: String, target: Target) -> Self {
+        Self {
+            namespace,
+            target,
+            interval: Duration::from_secs(60),
+            previous: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Set the interval between writes of the background task.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Encode `registry` and write all of its samples.
+    ///
+    /// The first emission establishes the baseline for counters and writes
+    /// their full value. The baseline only moves once the write succeeded, so
+    /// a failed emission is repeated in full by the next one. This performs
+    /// blocking IO.
+    pub fn emit(&self, registry: &Mutex<Registry>) -> io::Result<()> {
+        let families = collect(&registry.lock().expect("should be able to lock registry"))
+            .map_err(|_| io::Error::other("failed to encode metrics"))?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+
+        // In exposition order of their first sample.
+        let mut documents: Vec<Document<'_>> = Vec::new();
+        let mut previous = self
+            .previous
+            .lock()
+            .expect("should be able to lock previous values");
+        let mut baseline = Vec::new();
+        for family in &families {
+            let family_unit = match family.unit.as_deref() {
+                Some("seconds") => "Seconds",
+                Some("bytes") => "Bytes",
+                _ => "None",
+            };
+            for sample in &family.samples {
+                if !sample.value.is_finite() || sample.name.ends_with("_created") {
+                    continue;
+                }
+                let unit = match family.metric_type {
+                    MetricType::Histogram
+                        if sample.name.ends_with("_count") || sample.name.ends_with("_bucket") =>
+                    {
+                        "Count"
+                    }
+                    _ => family_unit,
+                };
+                let value = match family.metric_type {
+                    MetricType::Gauge | MetricType::Info | MetricType::Unknown => sample.value,
+                    MetricType::Counter | MetricType::Histogram => {
+                        let key = format!("{}{:?}", sample.name, sample.labels);
+                        let last = previous.get(&key).copied().unwrap_or(0.0);
+                        baseline.push((key, sample.value));
+                        // Counters only go down when they were reset.
+                        if sample.value >= last {
+                            sample.value - last
+                        } else {
+                            sample.value
+                        }
+                    }
+                };
+                let metric = Metric {
+                    name: &sample.name,
+                    unit,
+                    value,
+                };
+                match documents
+                    .iter_mut()
+                    .find(|document| document.labels == sample.labels.as_slice())
+                {
+                    Some(document) => document.metrics.push(metric),
+                    None => documents.push(Document {
+                        labels: &sample.labels,
+                        metrics: vec![metric],
+                    }),
+                }
+            }
+        }
+
+        let mut lines = Vec::new();
+        for document in &documents {
+            for metrics in document.metrics.chunks(MAX_METRICS) {
+                let line = self
+                    .document(document.labels, metrics, timestamp)
+                    .to_string();
+                lines.extend_from_slice(line.as_bytes());
+                lines.push(b'\n');
+            }
+        }
+        match &self.target {
+            Target::Stdout => io::stdout().lock().write_all(&lines)?,
+            Target::File(path) => std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?
+                .write_all(&lines)?,
+        }
+        previous.extend(baseline);
+        Ok(())
+    }
+
+    /// Spawn a task writing `registry` every interval.
+    ///
+    /// Failed writes are logged.
+    pub fn spawn(self, registry: Arc<Mutex<Registry>>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let emf = Arc::new(self);
+            let mut interval = tokio::time::interval(emf.interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let (emf, registry) = (emf.clone(), registry.clone());
+                let written = tokio::task::spawn_blocking(move || emf.emit(&registry))
+                    .await
+                    .unwrap_or_else(|err| Err(io::Error::other(err)));
+                if let Err(err) = written {
+                    tracing::warn!(%err, "failed to write EMF metrics");
+                }
+            }
+        })
+    }
+
+    fn document(
+        &self,
+        labels: &[(String, String)],
+        metrics: &[Metric<'_>],
+        timestamp: u64,
+    ) -> Value {
+        let dimensions: Vec<&str> = labels.iter().map(|(name, _)| name.as_str()).collect();
+        let definitions: Vec<Value> = metrics
+            .iter()
+            .map(|metric| json!({ "Name": metric.name, "Unit": metric.unit }))
+            .collect();
+        let mut document = Map::new();
+        document.insert(
+            "_aws".to_string(),
+            json!({
+                "Timestamp": timestamp,
+                "CloudWatchMetrics": [{
+                    "Namespace": self.namespace,
+                    "Dimensions": [dimensions],
+                    "Metrics": definitions,
+                }],
+            }),
+        );
+        for (name, value) in labels {
+            document.insert(name.clone(), Value::from(value.as_str()));
+        }
+        for metric in metrics {
+            document.insert(metric.name.to_string(), Value::from(metric.value));
+        }
+        Value::Object(document)
+    }
+}
+
+/// Metrics sharing the same labels.
+struct Document<'a> {
+    labels: &'a [(String, String)],
+    metrics: Vec<Metric<'a>>,
+}
+
+/// A metric of an EMF document.
+struct Metric<'a> {
+    name: &'a str,
+    unit: &'static str,
+    value: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus_client::{
+        metrics::{counter::Counter, family::Family, gauge::Gauge, histogram::Histogram},
+        registry::Unit,
+    };
+
+    use super::*;
+
+    fn emitted(emf: &Emf, registry: &Mutex<Registry>, path: &std::path::Path) -> Vec<Value> {
+        let _ = std::fs::remove_file(path);
+        emf.emit(registry).unwrap();
+        let lines = std::fs::read_to_string(path).unwrap();
+        lines
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn emits_documents_per_label_set() {
+        let requests = Family::<Vec<(String, String)>, Counter>::default();
+        let connections = Gauge::<i64>::default();
+        let mut registry = Registry::default();
+        registry.register("requests", "Requests", requests.clone());
+        registry.register("connections", "Connections", connections.clone());
+        let get = vec![("method".to_string(), "GET".to_string())];
+        requests.get_or_create(&get).inc_by(3);
+        connections.set(2);
+        let registry = Mutex::new(registry);
+        let path = std::env::temp_dir().join("tokio-prometheus-client-emf-test");
+        let emf = Emf::file("app", &path);
+
+        let documents = emitted(&emf, &registry, &path);
+        assert_eq!(documents.len(), 2, "{documents:?}");
+        let requests_document = &documents[0];
+        assert_eq!(requests_document["method"], "GET");
+        assert_eq!(requests_document["requests_total"], 3.0);
+        let metrics = &requests_document["_aws"]["CloudWatchMetrics"][0];
+        assert_eq!(metrics["Namespace"], "app");
+        assert_eq!(metrics["Dimensions"], json!([["method"]]));
+        assert_eq!(
+            metrics["Metrics"],
+            json!([{ "Name": "requests_total", "Unit": "None" }])
+        );
+        assert_eq!(documents[1]["connections"], 2.0);
+
+        // Counters write their increase since the previous emission, gauges
+        // their value.
+        requests.get_or_create(&get).inc_by(2);
+        let documents = emitted(&emf, &registry, &path);
+        assert_eq!(documents[0]["requests_total"], 2.0);
+        assert_eq!(documents[1]["connections"], 2.0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn splits_documents_of_many_metrics() {
+        let mut registry = Registry::default();
+        for i in 0..MAX_METRICS + 1 {
+            registry.register(format!("gauge_{i}"), "Gauge", Gauge::<i64>::default());
+        }
+        let registry = Mutex::new(registry);
+        let path = std::env::temp_dir().join("tokio-prometheus-client-emf-split-test");
+        let emf = Emf::file("app", &path);
+
+        let documents = emitted(&emf, &registry, &path);
+        let sizes: Vec<_> = documents
+            .iter()
+            .map(|document| {
+                document["_aws"]["CloudWatchMetrics"][0]["Metrics"]
+                    .as_array()
+                    .unwrap()
+                    .len()
+            })
+            .collect();
+        assert_eq!(sizes, [MAX_METRICS, 1]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn histograms_count_observations() {
+        let histogram = Histogram::new([1.0].into_iter());
+        histogram.observe(0.5);
+        let mut registry = Registry::default();
+        registry.register_with_unit("latency", "Latency", Unit::Seconds, histogram);
+        let registry = Mutex::new(registry);
+        let path = std::env::temp_dir().join("tokio-prometheus-client-emf-histogram-test");
+        let emf = Emf::file("app", &path);
+
+        let mut units = Vec::new();
+        for document in emitted(&emf, &registry, &path) {
+            for metric in document["_aws"]["CloudWatchMetrics"][0]["Metrics"]
+                .as_array()
+                .unwrap()
+            {
+                units.push((
+                    metric["Name"].as_str().unwrap().to_string(),
+                    metric["Unit"].as_str().unwrap().to_string(),
+                ));
+            }
+        }
+        units.sort();
+        units.dedup();
+        assert_eq!(
+            units,
+            [
+                ("latency_seconds_bucket".to_string(), "Count".to_string()),
+                ("latency_seconds_count".to_string(), "Count".to_string()),
+                ("latency_seconds_sum".to_string(), "Seconds".to_string()),
+            ]
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn failed_writes_keep_the_baseline() {
+        let requests = Counter::<u64>::default();
+        let mut registry = Registry::default();
+        registry.register("requests", "Requests", requests.clone());
+        let registry = Mutex::new(registry);
+        let directory = std::env::temp_dir().join("tokio-prometheus-client-emf-failed-test");
+        let _ = std::fs::remove_dir_all(&directory);
+        let path = directory.join("metrics.log");
+        let emf = Emf::file("app", &path);
+
+        requests.inc_by(3);
+        emf.emit(&registry).unwrap_err();
+        std::fs::create_dir_all(&directory).unwrap();
+        let documents = emitted(&emf, &registry, &path);
+        assert_eq!(documents[0]["requests_total"], 3.0);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,195 @@
+//! Emit the runtime metrics as [`tracing`] events.
+//!
+//! Enabled with the `events` feature. Useful when logs are shipped to a
+//! backend like Loki or Honeycomb and there is no Prometheus to scrape the
+//! metrics.
+
+use std::time::Duration;
+
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+use tokio_metrics::{RuntimeIntervals, RuntimeMonitor};
+use tracing::Level;
+
+/// Periodically emits the metrics of the last interval as a structured
+/// event.
+///
+/// Events have the target `tokio_prometheus_client::events` and one field
+/// per runtime metric, named like the metrics of
+/// [`register`](crate::register). Counters are the increase during the
+/// interval.
+///
+/// ## Example
+///
+/// ```
+/// # use std::time::Duration;
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let handle = tokio::runtime::Handle::current();
+/// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+///
+/// tokio_prometheus_client::events::RuntimeEvents::new(&runtime_monitor)
+///     .interval(Duration::from_secs(60))
+///     .level(tracing::Level::DEBUG)
+///     .spawn();
+/// # });
+/// ```
+#[derive(Debug)]
+pub struct RuntimeEvents {
+    intervals: RuntimeIntervals,
+    interval: Duration,
+    level: Level,
+}
+
+impl RuntimeEvents {
+    /// Create a [`RuntimeEvents`] emitting the metrics of the runtime of
+    /// `runtime_monitor` at [`Level::INFO`] every 10 seconds.
+    pub fn new(runtime_monitor: &RuntimeMonitor) -> Self {
+        Self {
+            intervals: runtime_monitor.intervals(),
+            interval: Duration::from_secs(10),
+            level: Level::INFO,
+        }
+    }
+
+    /// Set the interval between events.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Set the level of the events.
+    pub fn level(mut self, level: Level) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Spawn the task emitting the events.
+    ///
+    /// Must be called from within the monitored runtime.
+    pub fn spawn(mut self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            // The first tick completes immediately, the first interval
+            // starts now.
+            interval.tick().await;
+            self.intervals.next();
+            loop {
+                interval.tick().await;
+                let Some(metrics) = self.intervals.next() else {
+                    return;
+                };
+                emit(self.level, &metrics);
+            }
+        })
+    }
+}
+
+fn emit(level: Level, metrics: &tokio_metrics::RuntimeMetrics) {
+    // `tracing::event!` requires a constant level.
+    macro_rules! event {
+        ($level:expr) => {
+            tracing::event!(
+                $level,
+                workers_count = metrics.workers_count,
+                total_park_count = metrics.total_park_count,
+                total_noop_count = metrics.total_noop_count,
+                total_steal_count = metrics.total_steal_count,
+                total_steal_operations = metrics.total_steal_operations,
+                num_remote_schedules = metrics.num_remote_schedules,
+                total_local_schedule_count = metrics.total_local_schedule_count,
+                total_overflow_count = metrics.total_overflow_count,
+                total_polls_count = metrics.total_polls_count,
+                total_busy_duration = metrics.total_busy_duration.as_secs_f64(),
+                injection_queue_depth = metrics.injection_queue_depth,
+                total_local_queue_depth = metrics.total_local_queue_depth,
+                budget_forced_yield_count = metrics.budget_forced_yield_count,
+                io_driver_ready_count = metrics.io_driver_ready_count,
+                "runtime metrics"
+            )
+        };
+    }
+
+    match level {
+        Level::ERROR => event!(Level::ERROR),
+        Level::WARN => event!(Level::WARN),
+        Level::INFO => event!(Level::INFO),
+        Level::DEBUG => event!(Level::DEBUG),
+        Level::TRACE => event!(Level::TRACE),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use tracing::{field::Field, span, Event, Metadata, Subscriber};
+
+    use super::*;
+
+    /// Records the level and fields of events.
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl tracing::field::Visit for &Recorder {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{}={value:?}", field.name()));
+        }
+    }
+
+    impl Subscriber for &'static Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, _: &span::Attributes<'_>) -> span::Id {
+            span::Id::from_u64(1)
+        }
+
+        fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let metadata = event.metadata();
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", metadata.level(), metadata.target()));
+            event.record(&mut &**self);
+        }
+
+        fn enter(&self, _: &span::Id) {}
+
+        fn exit(&self, _: &span::Id) {}
+    }
+
+    #[test]
+    fn emits_metrics_at_level() {
+        let mut metrics = tokio_metrics::RuntimeMetrics::default();
+        metrics.workers_count = 2;
+        metrics.total_polls_count = 7;
+        metrics.total_busy_duration = Duration::from_millis(1500);
+
+        let recorder: &'static Recorder = Box::leak(Box::default());
+        tracing::subscriber::with_default(recorder, || emit(Level::DEBUG, &metrics));
+
+        let recorded = recorder.0.lock().unwrap();
+        assert_eq!(recorded[0], "DEBUG tokio_prometheus_client::events");
+        for expected in [
+            "message=runtime metrics",
+            "workers_count=2",
+            "total_polls_count=7",
+            "total_busy_duration=1.5",
+            "io_driver_ready_count=0",
+        ] {
+            assert!(
+                recorded.iter().any(|recorded| recorded == expected),
+                "{recorded:?}"
+            );
+        }
+    }
+}
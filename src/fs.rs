@@ -0,0 +1,282 @@
+//! Instrumented operations of `tokio::fs`.
+//!
+//! Enabled with the `fs` feature. File system operations run on the
+//! blocking pool, a backlog of it reported by the runtime metrics is often
+//! explained by them. The operations run through an [`FsMonitor`] export
+//! how many there were and how long they took, labeled with the operation.
+
+use std::{
+    future::Future,
+    io,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use prometheus_client::{
+    collector::Collector, encoding::DescriptorEncoder, metrics::histogram::Histogram,
+    registry::Registry,
+};
+use tokio::fs::{self, File};
+
+use crate::{
+    buckets::BucketPreset,
+    primitives::{all, encode_counters, encode_histograms, named, Primitive},
+};
+
+/// Runs file system operations and collects their metrics.
+///
+/// Each operation is exported with an `operation` label set to its name,
+/// e.g. `open` or `metadata`:
+///
+/// * `fs_operations`: the number of operations.
+/// * `fs_operation_errors`: the number of operations that failed.
+/// * `fs_operation_duration_seconds`: a histogram of the time operations
+///   took, including waiting for a thread of the blocking pool.
+///
+/// Reads and writes of an opened [`File`] are not counted, with the `io`
+/// feature wrap it with `io::IoMonitor::io` for those.
+///
+/// ## Example
+///
+/// ```
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// let monitor = tokio_prometheus_client::fs::FsMonitor::new();
+/// monitor.register(&mut registry);
+///
+/// let path = std::env::temp_dir().join("tokio-prometheus-client-fs-example");
+/// monitor.write(&path, "config").await.unwrap();
+/// assert_eq!(monitor.read_to_string(&path).await.unwrap(), "config");
+/// # std::fs::remove_file(&path).unwrap();
+///
+/// let mut text = String::new();
+/// prometheus_client::encoding::text::encode(&mut text, &registry).unwrap();
+/// assert!(text.contains("fs_operations_total{operation=\"write\"} 1\n"));
+/// assert!(text.contains("fs_operations_total{operation=\"read\"} 1\n"));
+/// # });
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct FsMonitor {
+    buckets: BucketPreset,
+    shared: Arc<Shared>,
+}
+
+/// The operations of an [`FsMonitor`].
+#[derive(Debug, Default)]
+struct Shared {
+    operations: Mutex<Vec<Arc<State>>>,
+}
+
+#[derive(Debug)]
+struct State {
+    name: String,
+    operations: AtomicU64,
+    errors: AtomicU64,
+    duration: Histogram,
+}
+
+impl FsMonitor {
+    /// Create an [`FsMonitor`] with [`BucketPreset::LatencyCoarse`]
+    /// histograms.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the buckets of the duration histograms of the operations run
+    /// for the first time from then on.
+    pub fn buckets(mut self, buckets: BucketPreset) -> Self {
+        self.buckets = buckets;
+        self
+    }
+
+    /// Register the collector of the operations with `registry`.
+    pub fn register(&self, registry: &mut Registry) {
+        registry.register_collector(Box::new(FsCollector {
+            shared: self.shared.clone(),
+        }));
+    }
+
+    /// Open the file at `path` for reading, see [`File::open`].
+    pub async fn open(&self, path: impl AsRef<Path>) -> io::Result<File> {
+        self.run("open", File::open(path)).await
+    }
+
+    /// Create or truncate the file at `path` for writing, see
+    /// [`File::create`].
+    pub async fn create(&self, path: impl AsRef<Path>) -> io::Result<File> {
+        self.run("create", File::create(path)).await
+    }
+
+    /// Read the file at `path`, see [`fs::read`].
+    pub async fn read(&self, path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+        self.run("read", fs::read(path)).await
+    }
+
+    /// Read the file at `path` as a string, see [`fs::read_to_string`].
+    ///
+    /// Counted as a `read`.
+    pub async fn read_to_string(&self, path: impl AsRef<Path>) -> io::Result<String> {
+        self.run("read", fs::read_to_string(path)).await
+    }
+
+    /// Write `contents` to the file at `path`, see [`fs::write`].
+    pub async fn write(
+        &self,
+        path: impl AsRef<Path>,
+        contents: impl AsRef<[u8]>,
+    ) -> io::Result<()> {
+        self.run("write", fs::write(path, contents)).await
+    }
+
+    /// The metadata of the file at `path`, see [`fs::metadata`].
+    pub async fn metadata(&self, path: impl AsRef<Path>) -> io::Result<std::fs::Metadata> {
+        self.run("metadata", fs::metadata(path)).await
+    }
+
+    /// Run `operation`, counted as `name`.
+    async fn run<T>(
+        &self,
+        name: &str,
+        operation: impl Future<Output = io::Result<T>>,
+    ) -> io::Result<T> {
+        let state = named(&self.shared.operations, name, |name| State {
+            name,
+            operations: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            duration: Histogram::new(self.buckets.buckets().iter().copied()),
+        });
+        let started = Instant::now();
+        let output = operation.await;
+        state.duration.observe(started.elapsed().as_secs_f64());
+        state.operations.fetch_add(1, Ordering::Relaxed);
+        if output.is_err() {
+            state.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        output
+    }
+}
+
+impl Primitive for State {
+    const LABEL: &'static str = "operation";
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Collects the operations of an [`FsMonitor`].
+#[derive(Debug)]
+struct FsCollector {
+    shared: Arc<Shared>,
+}
+
+impl Collector for FsCollector {
+    fn encode(&self, mut encoder: DescriptorEncoder) -> Result<(), std::fmt::Error> {
+        let operations = all(&self.shared.operations);
+        encode_counters(
+            &mut encoder,
+            &operations,
+            "fs_operations",
+            "The number of file system operations",
+            |operation| &operation.operations,
+        )?;
+        encode_counters(
+            &mut encoder,
+            &operations,
+            "fs_operation_errors",
+            "The number of file system operations that failed",
+            |operation| &operation.errors,
+        )?;
+        encode_histograms(
+            &mut encoder,
+            &operations,
+            "fs_operation_duration",
+            "The time file system operations took",
+            |operation| &operation.duration,
+        )
+    }
+}
+
+#[cfg(test)]
+impl FsMonitor {
+    /// The text exposition of the operations.
+    fn encoded(&self) -> String {
+        let mut registry = Registry::default();
+        self.register(&mut registry);
+        let mut text = String::new();
+        prometheus_client::encoding::text::encode(&mut text, &registry).unwrap();
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(text: &str, name: &str) -> f64 {
+        text.lines()
+            .find_map(|line| line.strip_prefix(&format!("{name} ")))
+            .unwrap_or_else(|| panic!("{name} should be exported: {text}"))
+            .parse()
+            .unwrap()
+    }
+
+    /// The operations, errors and observed durations of `operation`.
+    fn counts(monitor: &FsMonitor, operation: &str) -> [f64; 3] {
+        let text = monitor.encoded();
+        let labels = format!("{{operation=\"{operation}\"}}");
+        [
+            sample(&text, &format!("fs_operations_total{labels}")),
+            sample(&text, &format!("fs_operation_errors_total{labels}")),
+            sample(
+                &text,
+                &format!("fs_operation_duration_seconds_count{labels}"),
+            ),
+        ]
+    }
+
+    /// A fresh directory for the files of the test called `name`.
+    fn directory(name: &str) -> std::path::PathBuf {
+        let directory = std::env::temp_dir().join(format!("tokio-prometheus-client-{name}"));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[tokio::test]
+    async fn missing_files_count_errors() {
+        let monitor = FsMonitor::new();
+        let directory = directory("fs-missing");
+
+        let err = monitor.open(directory.join("missing")).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(counts(&monitor, "open"), [1.0, 1.0, 1.0]);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn operations_are_counted_separately() {
+        let monitor = FsMonitor::new();
+        let directory = directory("fs-operations");
+        let path = directory.join("config");
+
+        monitor.write(&path, "a").await.unwrap();
+        monitor.write(&path, "ab").await.unwrap();
+        assert_eq!(monitor.read(&path).await.unwrap(), b"ab");
+        assert_eq!(monitor.read_to_string(&path).await.unwrap(), "ab");
+        assert_eq!(monitor.metadata(&path).await.unwrap().len(), 2);
+        let _file = monitor.open(&path).await.unwrap();
+
+        assert_eq!(counts(&monitor, "write"), [2.0, 0.0, 2.0]);
+        assert_eq!(counts(&monitor, "read"), [2.0, 0.0, 2.0]);
+        assert_eq!(counts(&monitor, "metadata"), [1.0, 0.0, 1.0]);
+        assert_eq!(counts(&monitor, "open"), [1.0, 0.0, 1.0]);
+        assert!(!monitor.encoded().contains("operation=\"create\""));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
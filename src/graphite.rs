@@ -0,0 +1,246 @@
+//! Emit a [`Registry`] to [Graphite](https://graphite.readthedocs.io) using
+//! the plaintext protocol.
+//!
+//! Enabled with the `graphite` feature.
+
+use std::{
+    fmt::Write,
+    io,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use prometheus_client::registry::Registry;
+use tokio::{io::AsyncWriteExt, net::TcpStream, task::JoinHandle, time::MissedTickBehavior};
+
+use crate::samples::{collect, format_value, Sample};
+
+/// How labels are mapped into Graphite metric paths.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LabelScheme {
+    /// Append the label values to the path, e.g.
+    /// `tokio_worker_park_count_total.0`.
+    #[default]
+    Values,
+    /// Append label names and values to the path, e.g.
+    /// `tokio_worker_park_count_total.worker.0`.
+    NamesAndValues,
+    /// Send labels as Graphite 1.1 tags, e.g.
+    /// `tokio_worker_park_count_total;worker=0`.
+    Tags,
+}
+
+/// Emits a [`Registry`] to a Graphite carbon endpoint.
+///
+/// Every sample is sent as `metric.path value timestamp` line. The
+/// connection is reestablished on the next emission if writing fails.
+///
+/// ## Example
+///
+/// ```no_run
+/// # use std::{sync::{Arc, Mutex}, time::Duration};
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let handle = tokio::runtime::Handle::current();
+/// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// tokio_prometheus_client::register(runtime_monitor, registry.sub_registry_with_prefix("tokio"));
+///
+/// tokio_prometheus_client::graphite::Graphite::new("carbon:2003")
+///     .prefix("myapp.")
+///     .scheme(tokio_prometheus_client::graphite::LabelScheme::Tags)
+///     .interval(Duration::from_secs(60))
+///     .spawn(Arc::new(Mutex::new(registry)));
+/// # });
+/// ```
+#[derive(Debug)]
+pub struct Graphite {
+    addr: String,
+    prefix: String,
+    scheme: LabelScheme,
+    interval: Duration,
+    stream: Option<TcpStream>,
+}
+
+impl Graphite {
+    /// Create a [`Graphite`] sending to the carbon plaintext listener at
+    /// `addr`, e.g. `carbon:2003`.
+    ///
+    /// Emissions happen every 15 seconds.
+    pub fn new(addr: impl Into<String>) -> Self {
+        Self {
+            addr: addr.into(),
+            prefix: String::new(),
+            scheme: LabelScheme::default(),
+            interval: Duration::from_secs(15),
+            stream: None,
+        }
+    }
+
+    /// Prefix every metric path, e.g. with `myapp.`.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Set how labels are mapped into metric paths.
+    pub fn scheme(mut self, scheme: LabelScheme) -> Self {
+        self.scheme = scheme;
+        self
+    }
+
+    /// Set the interval between emissions of the background task.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Encode `registry` and send all of its samples.
+    ///
+    /// Infinite and NaN values cannot be represented and are skipped.
+    pub async fn emit(&mut self, registry: &Mutex<Registry>) -> io::Result<()> {
+        let families = collect(&registry.lock().expect("should be able to lock registry"))
+            .map_err(|_| io::Error::other("failed to encode metrics"))?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let mut lines = String::new();
+        for sample in families.iter().flat_map(|family| &family.samples) {
+            if sample.value.is_finite() {
+                let _ = writeln!(
+                    lines,
+                    "{} {} {timestamp}",
+                    self.path(sample),
+                    format_value(sample.value)
+                );
+            }
+        }
+
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => self.stream.insert(TcpStream::connect(&self.addr).await?),
+        };
+        if let Err(err) = stream.write_all(lines.as_bytes()).await {
+            self.stream = None;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Spawn a task emitting `registry` every interval.
+    ///
+    /// Failed emissions are logged.
+    pub fn spawn(mut self, registry: Arc<Mutex<Registry>>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                if let Err(err) = self.emit(&registry).await {
+                    tracing::warn!(%err, addr = self.addr, "failed to emit graphite metrics");
+                }
+            }
+        })
+    }
+
+    fn path(&self, sample: &Sample) -> String {
+        let mut path = self.prefix.clone();
+        path.push_str(&sample.name);
+        // Empty label values are the same as missing labels, and not
+        // allowed as tag values.
+        for (name, value) in sample.labels.iter().filter(|(_, value)| !value.is_empty()) {
+            match self.scheme {
+                LabelScheme::Values => {
+                    let _ = write!(path, ".{}", sanitize(value));
+                }
+                LabelScheme::NamesAndValues => {
+                    let _ = write!(path, ".{}.{}", sanitize(name), sanitize(value));
+                }
+                LabelScheme::Tags => {
+                    let _ = write!(path, ";{}={}", sanitize(name), sanitize(value));
+                }
+            }
+        }
+        path
+    }
+}
+
+/// Replace characters with a meaning in metric paths.
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | ':') => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus_client::metrics::{counter::Counter, family::Family, gauge::Gauge};
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+
+    use super::*;
+
+    fn sample() -> Sample {
+        Sample {
+            name: "requests_total".to_string(),
+            labels: vec![
+                ("path".to_string(), "/users/{id}".to_string()),
+                ("region".to_string(), String::new()),
+            ],
+            value: 1.0,
+        }
+    }
+
+    #[test]
+    fn paths_of_label_schemes() {
+        let path = |scheme| {
+            Graphite::new("")
+                .prefix("app.")
+                .scheme(scheme)
+                .path(&sample())
+        };
+        assert_eq!(path(LabelScheme::Values), "app.requests_total._users__id_");
+        assert_eq!(
+            path(LabelScheme::NamesAndValues),
+            "app.requests_total.path._users__id_"
+        );
+        assert_eq!(
+            path(LabelScheme::Tags),
+            "app.requests_total;path=_users__id_"
+        );
+    }
+
+    #[tokio::test]
+    async fn emits_finite_samples() {
+        let requests = Family::<Vec<(String, String)>, Counter>::default();
+        let ratio = Gauge::<f64, std::sync::atomic::AtomicU64>::default();
+        let mut registry = Registry::default();
+        registry.register("requests", "Requests", requests.clone());
+        registry.register("ratio", "Ratio", ratio.clone());
+        requests
+            .get_or_create(&vec![("method".to_string(), "GET".to_string())])
+            .inc_by(3);
+        ratio.set(f64::NAN);
+        let registry = Mutex::new(registry);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut graphite = Graphite::new(listener.local_addr().unwrap().to_string());
+        graphite.emit(&registry).await.unwrap();
+        drop(graphite);
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut lines = String::new();
+        stream.read_to_string(&mut lines).await.unwrap();
+
+        let lines: Vec<_> = lines.lines().collect();
+        assert_eq!(lines.len(), 1, "{lines:?}");
+        let (sample, timestamp) = lines[0].rsplit_once(' ').unwrap();
+        assert_eq!(sample, "requests_total.GET 3");
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        assert!(now.as_secs() - timestamp.parse::<u64>().unwrap() < 60);
+    }
+}
@@ -0,0 +1,287 @@
+//! Liveness and readiness derived from sampled runtime metrics.
+//!
+//! Enabled with the `health` feature. Combined with the `server` feature the
+//! built-in server answers `/healthz` and `/readyz`, see
+//! [`Server::health`](crate::server::Server::health).
+
+use std::{
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::time::MissedTickBehavior;
+use tokio_metrics::{RuntimeIntervals, RuntimeMetrics, RuntimeMonitor};
+
+use crate::signals::Signals;
+
+/// Thresholds on runtime metrics a runtime has to stay within to be ready.
+///
+/// The runtime is sampled in a task running on it, so a starved runtime also
+/// stops being live once that task no longer gets to run.
+///
+/// ## Example
+///
+/// ```
+/// # use std::time::Duration;
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let handle = tokio::runtime::Handle::current();
+/// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+///
+/// let health = tokio_prometheus_client::health::RuntimeHealth::new(&runtime_monitor)
+///     .max_mean_poll_duration(Duration::from_millis(10))
+///     .max_injection_queue_depth(1000)
+///     .spawn();
+/// assert!(health.is_live());
+/// # });
+/// ```
+#[derive(Debug)]
+pub struct RuntimeHealth {
+    intervals: RuntimeIntervals,
+    interval: Duration,
+    max_mean_poll_duration: Option<Duration>,
+    max_injection_queue_depth: Option<usize>,
+    max_local_queue_depth: Option<usize>,
+    max_forced_yields_per_second: Option<f64>,
+    max_busy_ratio: Option<f64>,
+}
+
+impl RuntimeHealth {
+    /// Create a [`RuntimeHealth`] without any thresholds, sampling the
+    /// runtime of `runtime_monitor` every second.
+    pub fn new(runtime_monitor: &RuntimeMonitor) -> Self {
+        Self {
+            intervals: runtime_monitor.intervals(),
+            interval: Duration::from_secs(1),
+            max_mean_poll_duration: None,
+            max_injection_queue_depth: None,
+            max_local_queue_depth: None,
+            max_forced_yields_per_second: None,
+            max_busy_ratio: None,
+        }
+    }
+
+    /// Set the interval between samples.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Require the mean duration of task polls to stay at or below `max`.
+    ///
+    /// Long polls block workers and delay the scheduling of all other tasks.
+    pub fn max_mean_poll_duration(mut self, max: Duration) -> Self {
+        self.max_mean_poll_duration = Some(max);
+        self
+    }
+
+    /// Require at most `max` tasks to wait in the injection queue.
+    pub fn max_injection_queue_depth(mut self, max: usize) -> Self {
+        self.max_injection_queue_depth = Some(max);
+        self
+    }
+
+    /// Require at most `max` tasks to wait in the local queues of all
+    /// workers combined.
+    pub fn max_local_queue_depth(mut self, max: usize) -> Self {
+        self.max_local_queue_depth = Some(max);
+        self
+    }
+
+    /// Require tasks to be forced to yield after exhausting their budget at
+    /// most `max` times per second.
+    pub fn max_forced_yields_per_second(mut self, max: f64) -> Self {
+        self.max_forced_yields_per_second = Some(max);
+        self
+    }
+
+    /// Require workers to be busy for at most `max`, between 0 and 1, of the
+    /// time.
+    pub fn max_busy_ratio(mut self, max: f64) -> Self {
+        self.max_busy_ratio = Some(max);
+        self
+    }
+
+    /// Spawn the task sampling the runtime and return a handle to its
+    /// results.
+    ///
+    /// Must be called from within the monitored runtime.
+    pub fn spawn(mut self) -> HealthCheck {
+        let check = HealthCheck {
+            state: Arc::new(Mutex::new(State {
+                sampled_at: Instant::now(),
+                unready: Some("runtime has not been sampled yet".to_string()),
+            })),
+            stale_after: self.interval * 3,
+        };
+        let state = Arc::downgrade(&check.state);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let Some(state) = state.upgrade() else {
+                    // All handles are gone.
+                    return;
+                };
+                let Some(metrics) = self.intervals.next() else {
+                    return;
+                };
+                let unready = self.violations(&metrics);
+                *state.lock().expect("should be able to lock health state") = State {
+                    sampled_at: Instant::now(),
+                    unready,
+                };
+            }
+        });
+        check
+    }
+
+    /// Describe the thresholds `metrics` exceeds, if any.
+    fn violations(&self, metrics: &RuntimeMetrics) -> Option<String> {
+        let mut violations = String::new();
+        let mut violation = |description: std::fmt::Arguments<'_>| {
+            if !violations.is_empty() {
+                violations.push_str(", ");
+            }
+            let _ = violations.write_fmt(description);
+        };
+        let signals = Signals::new(metrics);
+        if let Some(max) = self.max_mean_poll_duration {
+            if signals.mean_poll_duration > max {
+                violation(format_args!(
+                    "mean poll duration {:?} exceeds {max:?}",
+                    signals.mean_poll_duration
+                ));
+            }
+        }
+        if let Some(max) = self.max_injection_queue_depth {
+            if signals.injection_queue_depth > max {
+                violation(format_args!(
+                    "injection queue depth {} exceeds {max}",
+                    signals.injection_queue_depth
+                ));
+            }
+        }
+        if let Some(max) = self.max_local_queue_depth {
+            if signals.local_queue_depth > max {
+                violation(format_args!(
+                    "local queue depth {} exceeds {max}",
+                    signals.local_queue_depth
+                ));
+            }
+        }
+        if let Some(max) = self.max_forced_yields_per_second {
+            if signals.forced_yields_per_second > max {
+                violation(format_args!(
+                    "{:.1} forced yields per second exceed {max}",
+                    signals.forced_yields_per_second
+                ));
+            }
+        }
+        if let Some(max) = self.max_busy_ratio {
+            if signals.busy_ratio > max {
+                violation(format_args!(
+                    "busy ratio {:.2} exceeds {max}",
+                    signals.busy_ratio
+                ));
+            }
+        }
+        (!violations.is_empty()).then_some(violations)
+    }
+}
+
+/// Handle to the results of a [`RuntimeHealth`].
+///
+/// Sampling stops once all handles are dropped.
+#[derive(Clone, Debug)]
+pub struct HealthCheck {
+    state: Arc<Mutex<State>>,
+    stale_after: Duration,
+}
+
+#[derive(Debug)]
+struct State {
+    sampled_at: Instant,
+    unready: Option<String>,
+}
+
+impl HealthCheck {
+    /// Whether the runtime has been sampled within the last three
+    /// intervals.
+    pub fn is_live(&self) -> bool {
+        let state = self
+            .state
+            .lock()
+            .expect("should be able to lock health state");
+        state.sampled_at.elapsed() <= self.stale_after
+    }
+
+    /// Whether the runtime is live and stayed within all thresholds during
+    /// the last interval.
+    ///
+    /// Otherwise the reason is returned.
+    pub fn readiness(&self) -> Result<(), String> {
+        if !self.is_live() {
+            return Err("runtime has not been sampled recently".to_string());
+        }
+        let state = self
+            .state
+            .lock()
+            .expect("should be able to lock health state");
+        match &state.unready {
+            Some(reason) => Err(reason.clone()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn health() -> RuntimeHealth {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        RuntimeHealth::new(&RuntimeMonitor::new(runtime.handle()))
+    }
+
+    fn interval() -> RuntimeMetrics {
+        let mut interval = RuntimeMetrics::default();
+        interval.workers_count = 2;
+        interval.elapsed = Duration::from_secs(1);
+        interval.total_busy_duration = Duration::from_millis(1900);
+        interval.budget_forced_yield_count = 50;
+        interval.injection_queue_depth = 20;
+        interval.mean_poll_duration = Duration::from_millis(2);
+        interval
+    }
+
+    #[test]
+    fn within_thresholds_is_ready() {
+        let health = health()
+            .max_mean_poll_duration(Duration::from_millis(10))
+            .max_injection_queue_depth(100)
+            .max_busy_ratio(0.99);
+        assert_eq!(health.violations(&interval()), None);
+    }
+
+    #[test]
+    fn violations_list_exceeded_thresholds() {
+        let health = health()
+            .max_mean_poll_duration(Duration::from_millis(1))
+            .max_injection_queue_depth(10)
+            .max_local_queue_depth(10)
+            .max_forced_yields_per_second(10.0)
+            .max_busy_ratio(0.9);
+        assert_eq!(
+            health.violations(&interval()).as_deref(),
+            Some(
+                "mean poll duration 2ms exceeds 1ms, injection queue depth 20 exceeds 10, \
+                 50.0 forced yields per second exceed 10, busy ratio 0.95 exceeds 0.9"
+            ),
+        );
+    }
+}
@@ -0,0 +1,342 @@
+//! Write a [`Registry`] in the InfluxDB
+//! [line protocol](https://docs.influxdata.com/influxdb/v2/reference/syntax/line-protocol/),
+//! to any writer or to an InfluxDB or Telegraf HTTP endpoint.
+//!
+//! Enabled with the `influxdb` feature.
+
+use std::{
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use http::{
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    HeaderName, HeaderValue, Method, Request,
+};
+use http_body_util::Full;
+use prometheus_client::registry::Registry;
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+
+pub use crate::push::{PushError, RetryPolicy};
+use crate::{
+    push::PushClient,
+    samples::{collect, MetricFamily},
+};
+
+/// Encode `families` in the line protocol.
+///
+/// Every sample becomes one point of `measurement`, with the sample name as
+/// field key and the labels as tags, as Telegraf's Prometheus input does
+/// with `metric_version = 2`. Infinite and NaN values cannot be represented
+/// and are skipped.
+///
+/// ## Example
+///
+/// ```
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let handle = tokio::runtime::Handle::current();
+/// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// tokio_prometheus_client::register(runtime_monitor, registry.sub_registry_with_prefix("tokio"));
+///
+/// let families = tokio_prometheus_client::samples::collect(&registry).unwrap();
+/// let mut lines = String::new();
+/// tokio_prometheus_client::influxdb::encode(
+///     &mut lines,
+///     "prometheus",
+///     &families,
+///     std::time::SystemTime::now(),
+/// )
+/// .unwrap();
+/// assert!(lines.starts_with("prometheus tokio_workers_count="));
+/// # });
+/// ```
+pub fn encode(
+    writer: &mut impl Write,
+    measurement: &str,
+    families: &[MetricFamily],
+    timestamp: SystemTime,
+) -> std::fmt::Result {
+    let timestamp = timestamp
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    for sample in families.iter().flat_map(|family| &family.samples) {
+        if !sample.value.is_finite() {
+            continue;
+        }
+        write_escaped(writer, measurement, &[',', ' '])?;
+        for (key, value) in &sample.labels {
+            // Empty tag values are not allowed.
+            if value.is_empty() {
+                continue;
+            }
+            writer.write_char(',')?;
+            write_escaped(writer, key, &[',', '=', ' '])?;
+            writer.write_char('=')?;
+            write_escaped(writer, value, &[',', '=', ' '])?;
+        }
+        writer.write_char(' ')?;
+        write_escaped(writer, &sample.name, &[',', '=', ' '])?;
+        writeln!(writer, "={:?} {timestamp}", sample.value)?;
+    }
+    Ok(())
+}
+
+/// Write `value` escaping `special` characters with a backslash.
+fn write_escaped(writer: &mut impl Write, value: &str, special: &[char]) -> std::fmt::Result {
+    for c in value.chars() {
+        match c {
+            '\n' => writer.write_str("\\n")?,
+            '\\' => writer.write_str("\\\\")?,
+            c if special.contains(&c) => {
+                writer.write_char('\\')?;
+                writer.write_char(c)?;
+            }
+            c => writer.write_char(c)?,
+        }
+    }
+    Ok(())
+}
+
+/// Writes a [`Registry`] to an InfluxDB or Telegraf HTTP write endpoint.
+///
+/// ## Example
+///
+/// ```no_run
+/// # use std::{sync::{Arc, Mutex}, time::Duration};
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let handle = tokio::runtime::Handle::current();
+/// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// tokio_prometheus_client::register(runtime_monitor, registry.sub_registry_with_prefix("tokio"));
+///
+/// tokio_prometheus_client::influxdb::InfluxDb::new(
+///     "http://influxdb:8086/api/v2/write?org=my-org&bucket=my-bucket&precision=ns",
+/// )
+/// .token("secret")
+/// .interval(Duration::from_secs(10))
+/// .spawn(Arc::new(Mutex::new(registry)));
+/// # });
+/// ```
+#[derive(Clone, Debug)]
+pub struct InfluxDb {
+    url: String,
+    measurement: String,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    interval: Duration,
+    timeout: Duration,
+    client: PushClient,
+}
+
+impl InfluxDb {
+    /// Create an [`InfluxDb`] writing to `url`, including any query
+    /// parameters like the bucket. Timestamps are sent in nanoseconds, the
+    /// default precision.
+    ///
+    /// Points are written to the `prometheus` measurement every 15 seconds,
+    /// each write with a timeout of 5 seconds.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            measurement: "prometheus".to_string(),
+            headers: Vec::new(),
+            interval: Duration::from_secs(15),
+            timeout: Duration::from_secs(5),
+            client: PushClient::new(),
+        }
+    }
+
+    /// Set the measurement points are written to.
+    pub fn measurement(mut self, measurement: impl Into<String>) -> Self {
+        self.measurement = measurement.into();
+        self
+    }
+
+    /// Add a header to every request.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `name` or `value` are not valid header names and values.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((
+            HeaderName::try_from(name).expect("header name should be valid"),
+            HeaderValue::try_from(value).expect("header value should be valid"),
+        ));
+        self
+    }
+
+    /// Authenticate with an InfluxDB API token.
+    pub fn token(self, token: &str) -> Self {
+        self.header(AUTHORIZATION.as_str(), &format!("Token {token}"))
+    }
+
+    /// Retry failed writes according to `retry`, never retried by default.
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.client = self.client.retry(retry);
+        self
+    }
+
+    /// Register counters of the requests, retries and failures of the
+    /// writes with `registry`.
+    pub fn register_telemetry(&self, registry: &mut Registry) {
+        self.client.register(registry);
+    }
+
+    /// Set the interval between writes of the background task.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Set the timeout of a single write.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Encode `registry` and write all of its samples.
+    pub async fn write(&self, registry: &Mutex<Registry>) -> Result<(), PushError> {
+        let families = collect(&registry.lock().expect("should be able to lock registry"))
+            .map_err(|_| PushError::Encode)?;
+        let mut body = String::new();
+        encode(&mut body, &self.measurement, &families, SystemTime::now())
+            .map_err(|_| PushError::Encode)?;
+
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri(&self.url)
+            .header(CONTENT_TYPE, "text/plain; charset=utf-8");
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        let request = request
+            .body(Full::new(Bytes::from(body)))
+            .map_err(|err| PushError::Request(err.to_string()))?;
+        self.client.send(request, self.timeout).await
+    }
+
+    /// Spawn a task writing `registry` every interval.
+    ///
+    /// Failed writes are logged and the points are dropped.
+    pub fn spawn(self, registry: Arc<Mutex<Registry>>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                if let Err(err) = self.write(&registry).await {
+                    tracing::warn!(%err, url = self.url, "failed to write metrics");
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus_client::metrics::MetricType;
+
+    use super::*;
+    use crate::samples::Sample;
+
+    fn family(samples: Vec<Sample>) -> MetricFamily {
+        MetricFamily {
+            name: "family".to_string(),
+            help: String::new(),
+            unit: None,
+            metric_type: MetricType::Gauge,
+            samples,
+        }
+    }
+
+    fn sample(name: &str, labels: &[(&str, &str)], value: f64) -> Sample {
+        Sample {
+            name: name.to_string(),
+            labels: labels
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            value,
+        }
+    }
+
+    fn encoded(measurement: &str, samples: Vec<Sample>, timestamp: SystemTime) -> String {
+        let mut lines = String::new();
+        encode(&mut lines, measurement, &[family(samples)], timestamp).unwrap();
+        lines
+    }
+
+    #[test]
+    fn escapes_special_characters() {
+        let lines = encoded(
+            "my measurement,a=b",
+            vec![sample(
+                "requests total,a=b",
+                &[("the path,a=b", "/a b,c=d"), ("line", "a\nb\\c")],
+                1.0,
+            )],
+            UNIX_EPOCH,
+        );
+        assert_eq!(
+            lines,
+            "my\\ measurement\\,a=b,the\\ path\\,a\\=b=/a\\ b\\,c\\=d,line=a\\nb\\\\c \
+             requests\\ total\\,a\\=b=1.0 0\n"
+        );
+    }
+
+    #[test]
+    fn skips_values_without_representation() {
+        let lines = encoded(
+            "prometheus",
+            vec![
+                sample("nan", &[], f64::NAN),
+                sample("infinity", &[], f64::INFINITY),
+                sample("negative_infinity", &[], f64::NEG_INFINITY),
+                sample("finite", &[], -0.5),
+            ],
+            UNIX_EPOCH,
+        );
+        assert_eq!(lines, "prometheus finite=-0.5 0\n");
+    }
+
+    #[test]
+    fn omits_empty_tags() {
+        let lines = encoded(
+            "prometheus",
+            vec![sample(
+                "requests_total",
+                &[("method", ""), ("path", "/")],
+                3.0,
+            )],
+            UNIX_EPOCH,
+        );
+        assert_eq!(lines, "prometheus,path=/ requests_total=3.0 0\n");
+    }
+
+    #[test]
+    fn writes_timestamps_in_nanoseconds() {
+        let timestamp = UNIX_EPOCH + Duration::new(1_700_000_000, 123);
+        let lines = encoded(
+            "prometheus",
+            vec![sample("a", &[], 1.0), sample("b", &[], 2.0)],
+            timestamp,
+        );
+        assert_eq!(
+            lines,
+            "prometheus a=1.0 1700000000000000123\n\
+             prometheus b=2.0 1700000000000000123\n"
+        );
+        // Times before the epoch are written as the epoch.
+        let lines = encoded(
+            "prometheus",
+            vec![sample("a", &[], 1.0)],
+            UNIX_EPOCH - Duration::from_secs(1),
+        );
+        assert_eq!(lines, "prometheus a=1.0 0\n");
+    }
+}
@@ -0,0 +1,150 @@
+//! Instrumented IO of `tokio::io`.
+//!
+//! Enabled with the `io` feature. The runtime counts the readiness events
+//! of its IO driver, but not which streams they were for. The streams
+//! wrapped by an [`IoMonitor`] export their IO, labeled with the name of
+//! their class, e.g. upstream connections or client connections.
+
+use std::sync::{Arc, Mutex};
+
+use prometheus_client::{collector::Collector, encoding::DescriptorEncoder, registry::Registry};
+
+use crate::{buckets::BucketPreset, primitives::all};
+
+mod listener;
+mod stream;
+
+pub use listener::MonitoredTcpListener;
+pub use stream::MonitoredIo;
+
+/// Wraps IO to export it and collects its metrics.
+///
+/// IO is exported with a label of its kind set to the name of its class.
+/// Unlike the primitives of the `sync` module, the IO of a class is
+/// counted together and kept for the lifetime of the monitor.
+///
+/// Streams, wrapped with [`IoMonitor::io`], expose:
+///
+/// * `io_read_bytes` and `io_written_bytes`: the number of bytes read and
+///   written.
+/// * `io_reads` and `io_writes`: the number of reads and writes that
+///   completed, including those that failed.
+/// * `io_pending_reads` and `io_pending_writes`: the number of reads and
+///   writes that could not complete yet and waited for the stream to be
+///   ready. Their ratio to the completed ones shows how often a class of
+///   streams waits on the network or its peers.
+/// * `io_read_pending_seconds` and `io_write_pending_seconds`: histograms
+///   of the time reads and writes waited for the stream to be ready, with
+///   [`IoMonitor::pending_histograms`].
+///
+/// TCP listeners, wrapped with [`IoMonitor::tcp_listener`], count their
+/// accepted streams in the class of their name and expose:
+///
+/// * `tcp_listener_accepts`: the number of accepted connections.
+/// * `tcp_listener_accept_errors`: the number of accepts that failed, e.g.
+///   when running out of file descriptors.
+/// * `tcp_listener_open_connections`: the number of accepted connections
+///   whose streams were not dropped yet.
+/// * `tcp_listener_accept_processing_seconds`: a histogram of the time
+///   from accepts returning to waiting for the next one. An accept loop
+///   doing more than spawning a task per connection delays accepting the
+///   next ones.
+///
+/// ## Example
+///
+/// ```
+/// # use tokio::io::{AsyncReadExt, AsyncWriteExt};
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// let monitor = tokio_prometheus_client::io::IoMonitor::new();
+/// monitor.register(&mut registry);
+///
+/// let (client, server) = tokio::io::duplex(64);
+/// let mut client = monitor.io("client", client);
+/// let mut server = monitor.io("server", server);
+/// client.write_all(b"ping").await.unwrap();
+/// let mut ping = [0; 4];
+/// server.read_exact(&mut ping).await.unwrap();
+///
+/// let mut text = String::new();
+/// prometheus_client::encoding::text::encode(&mut text, &registry).unwrap();
+/// assert!(text.contains("io_written_bytes_total{stream=\"client\"} 4\n"));
+/// assert!(text.contains("io_read_bytes_total{stream=\"server\"} 4\n"));
+/// # });
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct IoMonitor {
+    buckets: BucketPreset,
+    pending_histograms: bool,
+    shared: Arc<Shared>,
+}
+
+/// The IO classes of an [`IoMonitor`].
+#[derive(Debug, Default)]
+struct Shared {
+    listeners: Mutex<Vec<Arc<listener::State>>>,
+    pending_times: Mutex<Vec<Arc<stream::PendingTimes>>>,
+    streams: Mutex<Vec<Arc<stream::State>>>,
+}
+
+impl IoMonitor {
+    /// Create an [`IoMonitor`] with [`BucketPreset::LatencyCoarse`]
+    /// histograms.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the buckets of the histograms of the IO wrapped from then on.
+    ///
+    /// The histograms of a class are created when it is first used.
+    pub fn buckets(mut self, buckets: BucketPreset) -> Self {
+        self.buckets = buckets;
+        self
+    }
+
+    /// Whether to export histograms of the time reads and writes of the
+    /// classes used from then on were pending, disabled by default.
+    ///
+    /// The time includes the delay of polling a woken stream. Long pending
+    /// times next to an idle runtime point to slow peers, next to long
+    /// scheduling delays to a slow runtime.
+    pub fn pending_histograms(mut self, enabled: bool) -> Self {
+        self.pending_histograms = enabled;
+        self
+    }
+
+    /// Register the collector of the IO with `registry`.
+    pub fn register(&self, registry: &mut Registry) {
+        registry.register_collector(Box::new(IoCollector {
+            shared: self.shared.clone(),
+        }));
+    }
+}
+
+/// Collects the IO of an [`IoMonitor`].
+#[derive(Debug)]
+struct IoCollector {
+    shared: Arc<Shared>,
+}
+
+impl Collector for IoCollector {
+    fn encode(&self, mut encoder: DescriptorEncoder) -> Result<(), std::fmt::Error> {
+        listener::encode(&all(&self.shared.listeners), &mut encoder)?;
+        stream::encode(&all(&self.shared.streams), &mut encoder)?;
+        stream::encode_pending_times(&all(&self.shared.pending_times), &mut encoder)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl IoMonitor {
+    /// The text exposition of the monitored IO.
+    fn encoded(&self) -> String {
+        let mut registry = Registry::default();
+        self.register(&mut registry);
+        let mut text = String::new();
+        prometheus_client::encoding::text::encode(&mut text, &registry).unwrap();
+        text
+    }
+}
@@ -0,0 +1,209 @@
+//! TCP listeners exporting their accepted and open connections.
+
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use prometheus_client::{encoding::DescriptorEncoder, metrics::histogram::Histogram};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::primitives::{encode_counters, encode_gauges, encode_histograms, named, Primitive};
+
+use super::{stream, IoMonitor, MonitoredIo};
+
+impl IoMonitor {
+    /// Wrap `listener` in a [`MonitoredTcpListener`] named `name`.
+    ///
+    /// The accepted streams are counted in the class `name`.
+    pub fn tcp_listener(
+        &self,
+        name: impl Into<String>,
+        listener: TcpListener,
+    ) -> MonitoredTcpListener {
+        let name = name.into();
+        let state = named(&self.shared.listeners, name.clone(), |name| State {
+            name,
+            accepts: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            open: AtomicI64::new(0),
+            processing: Histogram::new(self.buckets.buckets().iter().copied()),
+        });
+        MonitoredTcpListener {
+            listener,
+            state,
+            streams: self.stream_state(name),
+            last_accept: Mutex::new(None),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct State {
+    name: String,
+    accepts: AtomicU64,
+    errors: AtomicU64,
+    open: AtomicI64,
+    /// The time from accepts returning to waiting for the next one.
+    processing: Histogram,
+}
+
+/// An open connection of a listener, no longer open when dropped.
+#[derive(Debug)]
+pub(super) struct Connection(Arc<State>);
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.open.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A [`TcpListener`] exporting its accepted and open connections, see
+/// [`IoMonitor::tcp_listener`].
+#[derive(Debug)]
+pub struct MonitoredTcpListener {
+    listener: TcpListener,
+    state: Arc<State>,
+    streams: Arc<stream::State>,
+    /// When the last accept returned.
+    last_accept: Mutex<Option<Instant>>,
+}
+
+impl MonitoredTcpListener {
+    /// Accept a connection, see [`TcpListener::accept`].
+    ///
+    /// The connection is counted as open until the returned stream is
+    /// dropped.
+    pub async fn accept(&self) -> io::Result<(MonitoredIo<TcpStream>, SocketAddr)> {
+        if let Some(last_accept) = self.last_accept() {
+            self.state
+                .processing
+                .observe(last_accept.elapsed().as_secs_f64());
+        }
+        let accepted = self.listener.accept().await;
+        *self
+            .last_accept
+            .lock()
+            .expect("should be able to lock last accept") = Some(Instant::now());
+        match accepted {
+            Ok((stream, peer)) => {
+                self.state.accepts.fetch_add(1, Ordering::Relaxed);
+                self.state.open.fetch_add(1, Ordering::Relaxed);
+                let connection = Connection(self.state.clone());
+                let stream = MonitoredIo::new(stream, self.streams.clone(), Some(connection));
+                Ok((stream, peer))
+            }
+            Err(err) => {
+                self.state.errors.fetch_add(1, Ordering::Relaxed);
+                Err(err)
+            }
+        }
+    }
+
+    /// The listener, e.g. to set socket options.
+    ///
+    /// Connections accepted from it directly are not counted.
+    pub fn inner(&self) -> &TcpListener {
+        &self.listener
+    }
+
+    /// The local address of the listener, see [`TcpListener::local_addr`].
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    fn last_accept(&self) -> Option<Instant> {
+        self.last_accept
+            .lock()
+            .expect("should be able to lock last accept")
+            .take()
+    }
+}
+
+impl Primitive for State {
+    const LABEL: &'static str = "listener";
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Encode the metrics of `listeners`.
+pub(super) fn encode(
+    listeners: &[Arc<State>],
+    encoder: &mut DescriptorEncoder,
+) -> Result<(), std::fmt::Error> {
+    encode_counters(
+        encoder,
+        listeners,
+        "tcp_listener_accepts",
+        "The number of connections accepted by the listener",
+        |listener| &listener.accepts,
+    )?;
+    encode_counters(
+        encoder,
+        listeners,
+        "tcp_listener_accept_errors",
+        "The number of accepts of the listener that failed",
+        |listener| &listener.errors,
+    )?;
+    encode_gauges(
+        encoder,
+        listeners,
+        "tcp_listener_open_connections",
+        "The number of connections accepted by the listener that are still open",
+        |listener| listener.open.load(Ordering::Relaxed),
+    )?;
+    encode_histograms(
+        encoder,
+        listeners,
+        "tcp_listener_accept_processing",
+        "The time from accepts of the listener returning to waiting for the next one",
+        |listener| &listener.processing,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn tracks_accepted_and_open_connections() {
+        let monitor = IoMonitor::new();
+        let listener =
+            monitor.tcp_listener("clients", TcpListener::bind("127.0.0.1:0").await.unwrap());
+        let addr = listener.local_addr().unwrap();
+        let mut clients = Vec::new();
+        let mut accepted = Vec::new();
+        for _ in 0..2 {
+            clients.push(TcpStream::connect(addr).await.unwrap());
+            accepted.push(listener.accept().await.unwrap().0);
+        }
+        clients[0].write_all(b"ping").await.unwrap();
+        let mut ping = [0; 4];
+        accepted[0].read_exact(&mut ping).await.unwrap();
+
+        let text = monitor.encoded();
+        for expected in [
+            "tcp_listener_accepts_total{listener=\"clients\"} 2\n",
+            "tcp_listener_accept_errors_total{listener=\"clients\"} 0\n",
+            "tcp_listener_open_connections{listener=\"clients\"} 2\n",
+            "tcp_listener_accept_processing_seconds_count{listener=\"clients\"} 1\n",
+            "io_read_bytes_total{stream=\"clients\"} 4\n",
+        ] {
+            assert!(text.contains(expected), "{text}");
+        }
+
+        accepted.pop();
+        assert!(monitor
+            .encoded()
+            .contains("tcp_listener_open_connections{listener=\"clients\"} 1\n"));
+    }
+}
@@ -0,0 +1,354 @@
+//! Streams exporting their reads and writes.
+
+use std::{
+    io::{self, IoSlice},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use prometheus_client::{encoding::DescriptorEncoder, metrics::histogram::Histogram};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::primitives::{encode_counters, encode_histograms, named, Primitive};
+
+use super::{listener::Connection, IoMonitor};
+
+impl IoMonitor {
+    /// Wrap `io` in a [`MonitoredIo`] counted in the class `name`.
+    pub fn io<T>(&self, name: impl Into<String>, io: T) -> MonitoredIo<T> {
+        MonitoredIo::new(io, self.stream_state(name), None)
+    }
+
+    /// The state of the class `name`, created on first use.
+    pub(super) fn stream_state(&self, name: impl Into<String>) -> Arc<State> {
+        named(&self.shared.streams, name, |name| {
+            let pending_times = self.pending_histograms.then(|| {
+                let histogram = || Histogram::new(self.buckets.buckets().iter().copied());
+                named(&self.shared.pending_times, name.clone(), |name| {
+                    PendingTimes {
+                        name,
+                        read: histogram(),
+                        write: histogram(),
+                    }
+                })
+            });
+            State {
+                name,
+                read: Direction::default(),
+                write: Direction::default(),
+                pending_times,
+            }
+        })
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct State {
+    name: String,
+    read: Direction,
+    write: Direction,
+    pending_times: Option<Arc<PendingTimes>>,
+}
+
+/// The reads or writes of a class.
+#[derive(Debug, Default)]
+struct Direction {
+    bytes: AtomicU64,
+    completed: AtomicU64,
+    pending: AtomicU64,
+}
+
+impl Direction {
+    /// Count the outcome of polling a read or write, observing the time it
+    /// was pending since `pending_since` in `pending_time` once ready.
+    fn polled<T>(
+        &self,
+        poll: &Poll<io::Result<T>>,
+        pending_time: Option<&Histogram>,
+        pending_since: &mut Option<Instant>,
+    ) {
+        match poll {
+            Poll::Ready(_) => {
+                self.completed.fetch_add(1, Ordering::Relaxed);
+                if let (Some(histogram), Some(since)) = (pending_time, pending_since.take()) {
+                    histogram.observe(since.elapsed().as_secs_f64());
+                }
+            }
+            Poll::Pending => {
+                self.pending.fetch_add(1, Ordering::Relaxed);
+                if pending_time.is_some() && pending_since.is_none() {
+                    *pending_since = Some(Instant::now());
+                }
+            }
+        }
+    }
+}
+
+/// The histograms of the time reads and writes of a class were pending,
+/// see [`IoMonitor::pending_histograms`].
+#[derive(Debug)]
+pub(super) struct PendingTimes {
+    name: String,
+    read: Histogram,
+    write: Histogram,
+}
+
+/// An [`AsyncRead`] and [`AsyncWrite`] exporting its reads and writes, see
+/// [`IoMonitor::io`].
+#[derive(Debug)]
+pub struct MonitoredIo<T> {
+    io: T,
+    state: Arc<State>,
+    /// The connection counted as open while the IO is wrapped.
+    _connection: Option<Connection>,
+    /// When the current read started pending.
+    read_pending_since: Option<Instant>,
+    /// When the current write started pending.
+    write_pending_since: Option<Instant>,
+}
+
+impl<T> MonitoredIo<T> {
+    /// Wrap `io` counted in `state`, and as an open `connection` if
+    /// accepted by a listener.
+    pub(super) fn new(io: T, state: Arc<State>, connection: Option<Connection>) -> Self {
+        Self {
+            io,
+            state,
+            _connection: connection,
+            read_pending_since: None,
+            write_pending_since: None,
+        }
+    }
+
+    /// The wrapped IO.
+    pub fn get_ref(&self) -> &T {
+        &self.io
+    }
+
+    /// The wrapped IO, reads and writes through it are not counted.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.io
+    }
+
+    /// Consume the wrapper, returning the wrapped IO.
+    ///
+    /// The connection of an accepted stream is no longer counted as open.
+    pub fn into_inner(self) -> T {
+        self.io
+    }
+
+    /// Count the outcome of polling a write.
+    fn polled_write(&mut self, poll: &Poll<io::Result<usize>>) {
+        let write = &self.state.write;
+        let pending_time = self
+            .state
+            .pending_times
+            .as_deref()
+            .map(|times| &times.write);
+        write.polled(poll, pending_time, &mut self.write_pending_since);
+        if let Poll::Ready(Ok(written)) = poll {
+            write.bytes.fetch_add(*written as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for MonitoredIo<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut self.io).poll_read(cx, buf);
+        let this = &mut *self;
+        let read = &this.state.read;
+        let pending_time = this.state.pending_times.as_deref().map(|times| &times.read);
+        read.polled(&poll, pending_time, &mut this.read_pending_since);
+        if let Poll::Ready(Ok(())) = poll {
+            let bytes = buf.filled().len() - filled;
+            read.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        }
+        poll
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for MonitoredIo<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.io).poll_write(cx, buf);
+        self.polled_write(&poll);
+        poll
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.io).poll_write_vectored(cx, bufs);
+        self.polled_write(&poll);
+        poll
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.io.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
+
+impl Primitive for State {
+    const LABEL: &'static str = "stream";
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Primitive for PendingTimes {
+    const LABEL: &'static str = "stream";
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Encode the metrics of `streams`.
+pub(super) fn encode(
+    streams: &[Arc<State>],
+    encoder: &mut DescriptorEncoder,
+) -> Result<(), std::fmt::Error> {
+    encode_counters(
+        encoder,
+        streams,
+        "io_read_bytes",
+        "The number of bytes read from the streams",
+        |stream| &stream.read.bytes,
+    )?;
+    encode_counters(
+        encoder,
+        streams,
+        "io_written_bytes",
+        "The number of bytes written to the streams",
+        |stream| &stream.write.bytes,
+    )?;
+    encode_counters(
+        encoder,
+        streams,
+        "io_reads",
+        "The number of reads from the streams that completed",
+        |stream| &stream.read.completed,
+    )?;
+    encode_counters(
+        encoder,
+        streams,
+        "io_writes",
+        "The number of writes to the streams that completed",
+        |stream| &stream.write.completed,
+    )?;
+    encode_counters(
+        encoder,
+        streams,
+        "io_pending_reads",
+        "The number of reads from the streams that waited for them to be ready",
+        |stream| &stream.read.pending,
+    )?;
+    encode_counters(
+        encoder,
+        streams,
+        "io_pending_writes",
+        "The number of writes to the streams that waited for them to be ready",
+        |stream| &stream.write.pending,
+    )
+}
+
+/// Encode the histograms of `pending_times`.
+pub(super) fn encode_pending_times(
+    pending_times: &[Arc<PendingTimes>],
+    encoder: &mut DescriptorEncoder,
+) -> Result<(), std::fmt::Error> {
+    encode_histograms(
+        encoder,
+        pending_times,
+        "io_read_pending",
+        "The time reads from the streams waited for them to be ready",
+        |times| &times.read,
+    )?;
+    encode_histograms(
+        encoder,
+        pending_times,
+        "io_write_pending",
+        "The time writes to the streams waited for them to be ready",
+        |times| &times.write,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn counts_io_and_pending_times() {
+        let monitor = IoMonitor::new().pending_histograms(true);
+        let (client, server) = tokio::io::duplex(4);
+        let mut client = monitor.io("pipe", client);
+        let mut server = monitor.io("pipe", server);
+
+        // The reader starts late, so the write fills the buffer and waits
+        // for it to be read.
+        let reading = tokio::spawn(async move {
+            let mut read = [0; 8];
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            server.read_exact(&mut read).await.unwrap();
+            read
+        });
+        client.write_all(b"pingpong").await.unwrap();
+        assert_eq!(&reading.await.unwrap(), b"pingpong");
+
+        let text = monitor.encoded();
+        for expected in [
+            "io_read_bytes_total{stream=\"pipe\"} 8\n",
+            "io_written_bytes_total{stream=\"pipe\"} 8\n",
+            "io_writes_total{stream=\"pipe\"} 2\n",
+            "io_pending_writes_total{stream=\"pipe\"} 1\n",
+            "io_write_pending_seconds_count{stream=\"pipe\"} 1\n",
+        ] {
+            assert!(text.contains(expected), "{text}");
+        }
+        let write_pending = text
+            .lines()
+            .find_map(|line| line.strip_prefix("io_write_pending_seconds_sum{stream=\"pipe\"} "))
+            .unwrap();
+        assert!(write_pending.parse::<f64>().unwrap() >= 0.01, "{text}");
+    }
+
+    #[test]
+    fn pending_histograms_are_opt_in() {
+        let monitor = IoMonitor::new();
+        let _io = monitor.io("pipe", tokio::io::empty());
+        let text = monitor.encoded();
+        assert!(
+            text.contains("io_reads_total{stream=\"pipe\"} 0\n"),
+            "{text}"
+        );
+        assert!(!text.contains("io_read_pending_seconds"), "{text}");
+    }
+}
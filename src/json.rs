@@ -0,0 +1,68 @@
+//! JSON rendering of a [`Registry`] for tools that do not speak the
+//! Prometheus text formats.
+//!
+//! Enabled with the `json` feature.
+
+use prometheus_client::registry::Registry;
+use serde_json::{json, Map, Value};
+
+use crate::samples::{collect, MetricFamily};
+
+/// Content type of the JSON rendering.
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+
+/// Encode `registry` as a JSON array of metric families.
+///
+/// Every family is an object with `name`, `help`, `type`, `unit` and
+/// `samples` fields, every sample an object with `name`, `labels` and
+/// `value` fields. Non finite values are rendered as `null`.
+///
+/// ## Example
+///
+/// ```
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let handle = tokio::runtime::Handle::current();
+/// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// tokio_prometheus_client::register(runtime_monitor, registry.sub_registry_with_prefix("tokio"));
+///
+/// let json = tokio_prometheus_client::json::encode(&registry).unwrap();
+/// assert!(json.contains(r#""name":"tokio_workers_count""#));
+/// # });
+/// ```
+pub fn encode(registry: &Registry) -> Result<String, std::fmt::Error> {
+    Ok(to_value(&collect(registry)?).to_string())
+}
+
+/// Render `families` as a JSON value, see [`encode`] for the layout.
+pub fn to_value(families: &[MetricFamily]) -> Value {
+    families
+        .iter()
+        .map(|family| {
+            let samples: Vec<Value> = family
+                .samples
+                .iter()
+                .map(|sample| {
+                    let labels: Map<String, Value> = sample
+                        .labels
+                        .iter()
+                        .map(|(key, value)| (key.clone(), Value::from(value.as_str())))
+                        .collect();
+                    json!({
+                        "name": sample.name,
+                        "labels": labels,
+                        "value": sample.value,
+                    })
+                })
+                .collect();
+            json!({
+                "name": family.name,
+                "help": family.help,
+                "type": family.metric_type.as_str(),
+                "unit": family.unit,
+                "samples": samples,
+            })
+        })
+        .collect()
+}
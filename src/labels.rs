@@ -0,0 +1,206 @@
+//! Sanitization of label values from dynamic inputs.
+//!
+//! `prometheus_client` writes label values into the exposition as they are,
+//! so a value with a quote, backslash or line break, e.g. from a task
+//! monitor name or a route template, breaks the exposition for everyone
+//! scraping it. Values from such inputs should go through
+//! [`sanitize_value`] first.
+//!
+//! ## Policy
+//!
+//! * `"` and `\` are replaced with `_`, as are control characters,
+//!   including line breaks and tabs.
+//! * Values longer than [`MAX_VALUE_LEN`] bytes are truncated to it, on a
+//!   character boundary, to bound the size of series from unbounded
+//!   inputs.
+//! * Everything else, including non ASCII characters, is kept.
+//!
+//! Constant labels of the deployment, like the pod or region, are read
+//! from the environment with [`EnvLabels`].
+
+use std::borrow::Cow;
+
+use prometheus_client::registry::Registry;
+
+/// The maximum length of a sanitized label value, in bytes.
+pub const MAX_VALUE_LEN: usize = 128;
+
+/// Sanitize `value` according to the [policy](self#policy), only allocating
+/// when it needs changing.
+///
+/// ## Example
+///
+/// ```
+/// use tokio_prometheus_client::labels::sanitize_value;
+///
+/// assert_eq!(sanitize_value("/users/{id}"), "/users/{id}");
+/// assert_eq!(sanitize_value("say \"hi\"\n"), "say _hi__");
+/// assert_eq!(sanitize_value(&"x".repeat(200)).len(), 128);
+/// ```
+pub fn sanitize_value(value: &str) -> Cow<'_, str> {
+    let mut end = value.len().min(MAX_VALUE_LEN);
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    let value = &value[..end];
+    if value.chars().all(is_allowed) {
+        Cow::Borrowed(value)
+    } else {
+        Cow::Owned(
+            value
+                .chars()
+                .map(|c| if is_allowed(c) { c } else { '_' })
+                .collect(),
+        )
+    }
+}
+
+fn is_allowed(c: char) -> bool {
+    !matches!(c, '"' | '\\') && !c.is_control()
+}
+
+/// Constant labels read from environment variables, sanitized according to
+/// the [policy](self#policy).
+///
+/// Each label takes its value from the first of its variables that is set
+/// and not empty, or its fallback. Labels without either are left out.
+///
+/// ## Example
+///
+/// ```
+/// use tokio_prometheus_client::labels::EnvLabels;
+///
+/// let mut registry = EnvLabels::kubernetes()
+///     .label_or("cluster", ["CLUSTER"], "default")
+///     .registry();
+/// tokio_prometheus_client::RuntimeCollectorBuilder::noop()
+///     .register(registry.sub_registry_with_prefix("tokio"));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct EnvLabels {
+    labels: Vec<EnvLabel>,
+}
+
+#[derive(Clone, Debug)]
+struct EnvLabel {
+    name: Cow<'static, str>,
+    variables: Vec<String>,
+    fallback: Option<String>,
+}
+
+impl EnvLabels {
+    /// Create [`EnvLabels`] without any labels.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create [`EnvLabels`] with the labels of a Kubernetes pod, from the
+    /// variables commonly set through the downward API:
+    ///
+    /// * `pod` from `POD_NAME`, or `HOSTNAME`, which Kubernetes sets to the
+    ///   pod name.
+    /// * `namespace` from `POD_NAMESPACE`.
+    /// * `node` from `NODE_NAME`.
+    /// * `region` from `REGION`.
+    pub fn kubernetes() -> Self {
+        Self::new()
+            .label("pod", ["POD_NAME", "HOSTNAME"])
+            .label("namespace", ["POD_NAMESPACE"])
+            .label("node", ["NODE_NAME"])
+            .label("region", ["REGION"])
+    }
+
+    /// Add a label `name` taking its value from the first of `variables`
+    /// that is set.
+    pub fn label<V: Into<String>>(
+        mut self,
+        name: impl Into<Cow<'static, str>>,
+        variables: impl IntoIterator<Item = V>,
+    ) -> Self {
+        self.labels.push(EnvLabel {
+            name: name.into(),
+            variables: variables.into_iter().map(Into::into).collect(),
+            fallback: None,
+        });
+        self
+    }
+
+    /// Add a label `name` taking its value from the first of `variables`
+    /// that is set, or `fallback` if none is.
+    pub fn label_or<V: Into<String>>(
+        mut self,
+        name: impl Into<Cow<'static, str>>,
+        variables: impl IntoIterator<Item = V>,
+        fallback: impl Into<String>,
+    ) -> Self {
+        self = self.label(name, variables);
+        if let Some(label) = self.labels.last_mut() {
+            label.fallback = Some(fallback.into());
+        }
+        self
+    }
+
+    /// Read the labels from the environment.
+    pub fn resolve(&self) -> Vec<(Cow<'static, str>, Cow<'static, str>)> {
+        self.labels
+            .iter()
+            .filter_map(|label| {
+                let value = label
+                    .variables
+                    .iter()
+                    .filter_map(|variable| std::env::var(variable).ok())
+                    .find(|value| !value.is_empty())
+                    .or_else(|| label.fallback.clone())?;
+                let value = sanitize_value(&value).into_owned();
+                Some((label.name.clone(), Cow::Owned(value)))
+            })
+            .collect()
+    }
+
+    /// Create a [`Registry`] adding the labels read from the environment to
+    /// all of its metrics.
+    pub fn registry(&self) -> Registry {
+        Registry::with_labels(self.resolve().into_iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_value_replaces_and_truncates() {
+        assert!(matches!(sanitize_value("région"), Cow::Borrowed("région")));
+        assert_eq!(sanitize_value("a\\b\tc\u{7f}"), "a_b_c_");
+        // Truncated before the multi byte character crossing the limit.
+        let value = format!("{}é", "x".repeat(MAX_VALUE_LEN - 1));
+        assert_eq!(sanitize_value(&value), "x".repeat(MAX_VALUE_LEN - 1));
+        assert!(matches!(sanitize_value(&value), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn env_labels_resolve_first_set_variable() {
+        std::env::set_var("TOKIO_PROMETHEUS_CLIENT_TEST_EMPTY", "");
+        std::env::set_var("TOKIO_PROMETHEUS_CLIENT_TEST_POD", "web-\"1\"");
+        let labels = EnvLabels::new()
+            .label(
+                "pod",
+                [
+                    "TOKIO_PROMETHEUS_CLIENT_TEST_UNSET",
+                    "TOKIO_PROMETHEUS_CLIENT_TEST_EMPTY",
+                    "TOKIO_PROMETHEUS_CLIENT_TEST_POD",
+                ],
+            )
+            .label("node", ["TOKIO_PROMETHEUS_CLIENT_TEST_UNSET"])
+            .label_or("cluster", ["TOKIO_PROMETHEUS_CLIENT_TEST_EMPTY"], "default")
+            .resolve();
+
+        assert_eq!(
+            labels,
+            [
+                (Cow::from("pod"), Cow::from("web-_1_")),
+                (Cow::from("cluster"), Cow::from("default")),
+            ]
+        );
+    }
+}
@@ -1,15 +1,137 @@
-use std::sync::Mutex;
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    panic::AssertUnwindSafe,
+    sync::{
+        atomic::{AtomicU64, AtomicU8, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
+use arc_swap::ArcSwap;
 use prometheus_client::{
     collector::Collector,
-    encoding::EncodeMetric,
-    metrics::{counter::Counter, gauge::Gauge},
+    encoding::{DescriptorEncoder, EncodeCounterValue, EncodeMetric, MetricEncoder},
+    metrics::{
+        counter::ConstCounter, gauge::ConstGauge, histogram::Histogram, info::Info, MetricType,
+    },
     registry::{Registry, Unit},
 };
 use tokio_metrics::{RuntimeIntervals, RuntimeMonitor};
 
+use crate::{
+    buckets::BucketPreset,
+    catalog::{MetricDescription, Stability},
+    names::{runtime_family, Family, Renamed},
+};
+
+// The paths of the derive macro resolve in the tests of this crate too.
+#[cfg(all(test, feature = "derive"))]
+extern crate self as tokio_prometheus_client;
+
+#[cfg(feature = "actix")]
+pub mod actix;
+#[cfg(feature = "axum")]
+pub mod axum;
+#[cfg(any(feature = "pushgateway", feature = "remote-write", feature = "tower"))]
+mod base64;
+pub mod buckets;
+pub mod catalog;
+pub mod collector;
+#[cfg(feature = "console")]
+pub mod console;
+#[cfg(feature = "detector")]
+pub mod detector;
+#[cfg(feature = "emf")]
+pub mod emf;
+#[cfg(feature = "events")]
+pub mod events;
+#[cfg(feature = "fs")]
+pub mod fs;
+#[cfg(feature = "graphite")]
+pub mod graphite;
+#[cfg(feature = "health")]
+pub mod health;
+#[cfg(feature = "influxdb")]
+pub mod influxdb;
+#[cfg(feature = "io")]
+pub mod io;
+#[cfg(feature = "json")]
+pub mod json;
+pub mod labels;
+mod names;
+#[cfg(feature = "opentelemetry")]
+pub mod opentelemetry;
+#[cfg(feature = "otlp")]
+pub mod otlp;
+#[cfg(any(
+    feature = "fs",
+    feature = "io",
+    feature = "sync",
+    feature = "time",
+    feature = "util"
+))]
+mod primitives;
+#[cfg(feature = "process")]
+pub mod process;
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
+#[cfg(any(feature = "otlp", feature = "remote-write"))]
+mod protobuf;
+#[cfg(any(
+    feature = "influxdb",
+    feature = "otlp",
+    feature = "pushgateway",
+    feature = "remote-write"
+))]
+mod push;
+#[cfg(feature = "pushgateway")]
+pub mod pushgateway;
+#[cfg(feature = "remote-write")]
+pub mod remote_write;
+pub mod rules;
+pub mod samples;
+#[cfg(feature = "server")]
+pub mod server;
+mod signals;
+#[cfg(feature = "statsd")]
+pub mod statsd;
+#[cfg(feature = "summary")]
+pub mod summary;
+#[cfg(feature = "sync")]
+pub mod sync;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+#[cfg(feature = "textfile")]
+pub mod textfile;
+#[cfg(feature = "time")]
+pub mod time;
+#[cfg(feature = "tower")]
+pub mod tower;
+#[cfg(feature = "util")]
+pub mod util;
+#[cfg(feature = "warp")]
+pub mod warp;
+#[cfg(feature = "watchdog")]
+pub mod watchdog;
+
+/// Content type of the OpenMetrics text exposition format.
+pub const OPENMETRICS_CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Content type of the classic Prometheus text exposition format, see
+/// [`samples::encode_text`].
+pub const TEXT_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
 /// Register the Tokio Metrics collector with a Prometheus [`Registry`].
 ///
+/// The collector always encodes its metrics in the same order, and labeled
+/// series in the order of their label values as documented. Where the
+/// metrics end up among the other metrics of the registry depends on the
+/// order of registration, see [`samples::encode_sorted`] for an order
+/// independent of it.
+///
 /// ## Example
 ///
 /// ```
@@ -22,51 +144,1492 @@ use tokio_metrics::{RuntimeIntervals, RuntimeMonitor};
 /// # });
 /// ```
 pub fn register(monitor: RuntimeMonitor, registry: &mut Registry) {
-    registry.register_collector(Box::new(RuntimeCollector::new(monitor)))
+    RuntimeCollectorBuilder::new(monitor).register(registry)
+}
+
+/// Configures the Tokio Metrics collector before registering it.
+///
+/// All metrics of a collection, including rates and quantiles, are encoded
+/// from the same sample of the runtime. Expressions combining them, like
+/// the busy duration per poll, never mix two intervals.
+///
+/// ## Example
+///
+/// ```
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let handle = tokio::runtime::Handle::current();
+/// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// tokio_prometheus_client::RuntimeCollectorBuilder::new(runtime_monitor)
+///     .created(true)
+///     .register(registry.sub_registry_with_prefix("tokio"));
+/// # });
+/// ```
+#[derive(Debug)]
+pub struct RuntimeCollectorBuilder {
+    intervals: Intervals,
+    created: bool,
+    config: CollectorConfig,
+    #[cfg(feature = "reload")]
+    updates: Option<tokio::sync::watch::Receiver<CollectorConfig>>,
+    mean_poll_duration_window: Option<Duration>,
+    pause: PauseHandle,
+    scrape_labels: Option<ScrapeLabels>,
+    sample_gaps: Option<BucketPreset>,
+    config_info: bool,
+}
+
+impl RuntimeCollectorBuilder {
+    /// Create a [`RuntimeCollectorBuilder`] collecting the runtime of
+    /// `monitor`.
+    pub fn new(monitor: RuntimeMonitor) -> Self {
+        Self::with_intervals(Intervals::Runtime(monitor.intervals()))
+    }
+
+    /// Create a [`RuntimeCollectorBuilder`] that exposes the same metric
+    /// families without ever sampling a runtime.
+    ///
+    /// All values stay zero. Deploying it next to the real collector
+    /// measures the overhead that sampling adds to a runtime, while scrapes
+    /// stay the same size.
+    pub fn noop() -> Self {
+        Self::with_intervals(Intervals::Noop(Instant::now()))
+    }
+
+    /// Create a [`RuntimeCollectorBuilder`] collecting the scripted
+    /// `intervals` instead of a runtime, one per sample.
+    ///
+    /// Enabled with the `test-util` feature, to test dashboards and alerts
+    /// against known values. Once the script is exhausted, samples see empty
+    /// intervals.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// let mut interval = tokio_metrics::RuntimeMetrics::default();
+    /// interval.elapsed = Duration::from_secs(10);
+    /// interval.total_polls_count = 50;
+    ///
+    /// let mut registry = prometheus_client::registry::Registry::default();
+    /// tokio_prometheus_client::RuntimeCollectorBuilder::from_intervals([interval.clone(), interval])
+    ///     .rates(true)
+    ///     .register(&mut registry);
+    ///
+    /// let mut text = String::new();
+    /// prometheus_client::encoding::text::encode(&mut text, &registry).unwrap();
+    /// assert!(text.contains("total_polls_count_total 50\n"));
+    /// assert!(text.contains("total_polls_count_per_second 5.0\n"));
+    ///
+    /// text.clear();
+    /// prometheus_client::encoding::text::encode(&mut text, &registry).unwrap();
+    /// assert!(text.contains("total_polls_count_total 100\n"));
+    /// ```
+    #[cfg(feature = "test-util")]
+    pub fn from_intervals(
+        intervals: impl IntoIterator<Item = tokio_metrics::RuntimeMetrics>,
+    ) -> Self {
+        let intervals: Vec<_> = intervals.into_iter().collect();
+        Self::with_intervals(Intervals::Scripted(intervals.into_iter()))
+    }
+
+    fn with_intervals(intervals: Intervals) -> Self {
+        Self {
+            intervals,
+            created: false,
+            config: CollectorConfig::default(),
+            #[cfg(feature = "reload")]
+            updates: None,
+            mean_poll_duration_window: None,
+            pause: PauseHandle::default(),
+            scrape_labels: None,
+            sample_gaps: None,
+            config_info: false,
+        }
+    }
+
+    /// Whether to expose when each counter was created, disabled by default.
+    ///
+    /// Counters start when the collector is built. This lets
+    /// Prometheus tell a restarted process from a counter that did not
+    /// move. `prometheus-client` cannot encode the `_created` samples of
+    /// OpenMetrics counters, so they are exposed as `<counter>_created`
+    /// gauges holding the creation time in seconds since the Unix epoch,
+    /// e.g. `total_busy_duration_seconds_created`, the series Prometheus
+    /// stores `_created` samples as.
+    pub fn created(mut self, created: bool) -> Self {
+        self.created = created;
+        self
+    }
+
+    /// Whether to also expose the per-second rate of each counter during the
+    /// last interval, disabled by default.
+    ///
+    /// The rates are exposed as `<counter>_per_second` gauges, e.g.
+    /// `total_polls_count_per_second`, for backends that cannot compute
+    /// rates from counters, like Pushgateway snapshots, CloudWatch or statsd.
+    /// The rate of a counter is left out for intervals whose delta was
+    /// discarded as implausible.
+    pub fn rates(mut self, rates: bool) -> Self {
+        self.config.rates = rates;
+        self
+    }
+
+    /// Whether to expose the number of series the collector exports,
+    /// disabled by default.
+    ///
+    /// The count is exposed as the `exported_series` gauge, including
+    /// itself, to plan the capacity of Prometheus from within Prometheus.
+    pub fn series(mut self, series: bool) -> Self {
+        self.config.series = series;
+        self
+    }
+
+    /// Limit how long a collection may spend encoding the metrics, not
+    /// limited by default.
+    ///
+    /// Once `budget` is exceeded, the collection finishes the family being
+    /// encoded, skips the rest and increments the
+    /// `collector_truncated_scrapes` counter, which is always encoded. This
+    /// keeps a bloated collector from exceeding the scrape timeout of the
+    /// whole registry.
+    pub fn encode_budget(mut self, budget: Duration) -> Self {
+        self.config.encode_budget = Some(budget);
+        self
+    }
+
+    /// Only export the metrics at least as stable as `stability`, all of
+    /// them by default.
+    ///
+    /// With [`Stability::Stable`], dashboards and alerts can only rely on
+    /// series that do not vanish on a Tokio upgrade. See
+    /// [`catalog`](Self::catalog) for the stability of each metric.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use tokio_prometheus_client::catalog::Stability;
+    ///
+    /// let catalog = tokio_prometheus_client::RuntimeCollectorBuilder::noop()
+    ///     .stability(Stability::Stable)
+    ///     .catalog();
+    /// assert!(catalog.iter().all(|metric| metric.stability == Stability::Stable));
+    /// assert!(catalog.iter().any(|metric| metric.name == "workers_count"));
+    /// ```
+    pub fn stability(mut self, stability: Stability) -> Self {
+        self.config.stability = stability;
+        self
+    }
+
+    /// Expose a `health` gauge of the last interval, 0 when ok, 1 in
+    /// warning and 2 when critical, disabled by default.
+    ///
+    /// The level is the worst of the signals with `thresholds`, a single
+    /// traffic light per runtime for oncall, backed by the detailed series.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use tokio_prometheus_client::HealthThresholds;
+    ///
+    /// let mut registry = prometheus_client::registry::Registry::default();
+    /// tokio_prometheus_client::RuntimeCollectorBuilder::noop()
+    ///     .health(
+    ///         HealthThresholds::new()
+    ///             .injection_queue_depth(100, 1000)
+    ///             .busy_ratio(0.8, 0.95),
+    ///     )
+    ///     .register(&mut registry);
+    ///
+    /// let mut text = String::new();
+    /// prometheus_client::encoding::text::encode(&mut text, &registry).unwrap();
+    /// assert!(text.contains("\nhealth 0\n"));
+    /// ```
+    pub fn health(mut self, thresholds: HealthThresholds) -> Self {
+        self.config.health = Some(thresholds);
+        self
+    }
+
+    /// Set what collections do when sampling the runtime fails,
+    /// [`ErrorPolicy::Skip`] by default.
+    pub fn error_policy(mut self, error_policy: ErrorPolicy) -> Self {
+        self.config.error_policy = error_policy;
+        self
+    }
+
+    /// Replace the configuration set so far with `config`.
+    pub fn config(mut self, config: CollectorConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Take the configuration from `updates`, replacing the one set on the
+    /// builder.
+    ///
+    /// Enabled with the `reload` feature. Every collection encodes with the
+    /// latest configuration sent, e.g. from an admin endpoint, so metrics
+    /// can be enabled or thresholds changed without registering the
+    /// collector again. [`Sampler::spawn`] picks up the sampling interval
+    /// before its next sample. The creation times and the mean poll duration
+    /// window are fixed when the collector is built.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use tokio_prometheus_client::CollectorConfig;
+    ///
+    /// let (config, updates) = tokio::sync::watch::channel(CollectorConfig::default());
+    /// let mut registry = prometheus_client::registry::Registry::default();
+    /// tokio_prometheus_client::RuntimeCollectorBuilder::noop()
+    ///     .config_updates(updates)
+    ///     .register(&mut registry);
+    ///
+    /// let mut text = String::new();
+    /// prometheus_client::encoding::text::encode(&mut text, &registry).unwrap();
+    /// assert!(!text.contains("exported_series"));
+    ///
+    /// config.send_modify(|config| config.series = true);
+    /// text.clear();
+    /// prometheus_client::encoding::text::encode(&mut text, &registry).unwrap();
+    /// assert!(text.contains("exported_series"));
+    /// ```
+    #[cfg(feature = "reload")]
+    pub fn config_updates(
+        mut self,
+        updates: tokio::sync::watch::Receiver<CollectorConfig>,
+    ) -> Self {
+        self.updates = Some(updates);
+        self
+    }
+
+    /// Pause and resume the collector through `pause`, running by default.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use tokio_prometheus_client::{Pause, PauseHandle};
+    ///
+    /// let pause = PauseHandle::new();
+    /// let mut registry = prometheus_client::registry::Registry::default();
+    /// tokio_prometheus_client::RuntimeCollectorBuilder::noop()
+    ///     .pause_handle(pause.clone())
+    ///     .register(&mut registry);
+    ///
+    /// pause.pause(Pause::Silence);
+    /// let mut text = String::new();
+    /// prometheus_client::encoding::text::encode(&mut text, &registry).unwrap();
+    /// assert!(!text.contains("workers_count"));
+    ///
+    /// pause.resume();
+    /// text.clear();
+    /// prometheus_client::encoding::text::encode(&mut text, &registry).unwrap();
+    /// assert!(text.contains("workers_count"));
+    /// ```
+    pub fn pause_handle(mut self, pause: PauseHandle) -> Self {
+        self.pause = pause;
+        self
+    }
+
+    /// Add the labels returned by `labels` to every series, calling it on
+    /// every collection.
+    ///
+    /// Unlike the constant labels of a registry, the values may change over
+    /// the lifetime of the process, e.g. the deployment color read from a
+    /// configuration watch. The values are sanitized with
+    /// [`labels::sanitize_value`]. Collections with labels allocate them.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let color = Arc::new(Mutex::new("blue".to_string()));
+    /// let labels = color.clone();
+    /// let mut registry = prometheus_client::registry::Registry::default();
+    /// tokio_prometheus_client::RuntimeCollectorBuilder::noop()
+    ///     .scrape_labels(move || vec![("color".to_string(), labels.lock().unwrap().clone())])
+    ///     .register(&mut registry);
+    ///
+    /// *color.lock().unwrap() = "green".to_string();
+    /// let mut text = String::new();
+    /// prometheus_client::encoding::text::encode(&mut text, &registry).unwrap();
+    /// assert!(text.contains("workers_count{color=\"green\"} 0\n"));
+    /// ```
+    pub fn scrape_labels<F>(mut self, labels: F) -> Self
+    where
+        F: Fn() -> Vec<(String, String)> + Send + Sync + 'static,
+    {
+        self.scrape_labels = Some(ScrapeLabels(Arc::new(labels)));
+        self
+    }
+
+    /// Expose the 0.5, 0.95 and 0.99 quantiles of the mean poll duration of
+    /// the intervals within `window`, disabled by default.
+    ///
+    /// Every collection samples one interval, so the quantiles describe the
+    /// intervals between collections within the window. Intervals without
+    /// polls are left out. The quantiles are exposed as
+    /// `mean_poll_duration_seconds` gauges labeled with the `quantile`.
+    pub fn mean_poll_duration_quantiles(mut self, window: Duration) -> Self {
+        self.mean_poll_duration_window = Some(window);
+        self
+    }
+
+    /// Expose a `sample_gap_seconds` histogram of the time between
+    /// consecutive samples of the runtime, with `buckets`, disabled by
+    /// default.
+    ///
+    /// Without a [`Sampler`] every scrape samples, so irregular gaps point
+    /// at the scraper rather than the process when `rate()` graphs are
+    /// noisy. Collections waiting for a concurrent one share its sample.
+    pub fn sample_gaps(mut self, buckets: BucketPreset) -> Self {
+        self.sample_gaps = Some(buckets);
+        self
+    }
+
+    /// Expose a `collector_config_info` metric labeled with the effective
+    /// configuration, disabled by default.
+    ///
+    /// Comparing the labels of two processes tells at a glance whether
+    /// their graphs differ because they are configured differently. Settings
+    /// that are not set, like a missing encode budget, are left out.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// let mut registry = prometheus_client::registry::Registry::default();
+    /// tokio_prometheus_client::RuntimeCollectorBuilder::noop()
+    ///     .rates(true)
+    ///     .config_info(true)
+    ///     .register(&mut registry);
+    ///
+    /// let mut text = String::new();
+    /// prometheus_client::encoding::text::encode(&mut text, &registry).unwrap();
+    /// assert!(text.contains("collector_config_info{created=\"false\",rates=\"true\","));
+    /// ```
+    pub fn config_info(mut self, config_info: bool) -> Self {
+        self.config_info = config_info;
+        self
+    }
+
+    /// Describe every metric the collector will export as configured.
+    ///
+    /// Names are without the prefix the collector is registered with. The
+    /// description is taken from encoding the collector, so it always
+    /// matches the exposition, including the families only encoded once the
+    /// runtime has been sampled, like the rates.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// let catalog = tokio_prometheus_client::RuntimeCollectorBuilder::noop()
+    ///     .rates(true)
+    ///     .catalog();
+    /// let rate = catalog
+    ///     .iter()
+    ///     .find(|metric| metric.name == "total_polls_count_per_second")
+    ///     .unwrap();
+    /// assert_eq!(rate.stability, tokio_prometheus_client::catalog::Stability::Unstable);
+    /// ```
+    pub fn catalog(&self) -> Vec<MetricDescription> {
+        // Rates are only encoded for intervals that took time.
+        let mut interval = tokio_metrics::RuntimeMetrics::default();
+        interval.elapsed = Duration::from_secs(1);
+        let snapshot = Snapshot {
+            interval,
+            mean_poll_duration_quantiles: self
+                .mean_poll_duration_window
+                .map(|_| [("0.5", f64::NAN), ("0.95", f64::NAN), ("0.99", f64::NAN)]),
+            ..Snapshot::default()
+        };
+        let collector = self.collector(Source::Sampler(Arc::new(ArcSwap::from_pointee(snapshot))));
+        let config = collector.config().into_owned();
+        let collector = RuntimeCollector {
+            config: CollectorConfig {
+                // Describe the truncation counter without truncating.
+                encode_budget: config.encode_budget.map(|_| Duration::MAX),
+                ..config
+            },
+            #[cfg(feature = "reload")]
+            updates: None,
+            ..collector
+        };
+        let mut registry = Registry::default();
+        registry.register_collector(Box::new(collector));
+        let families =
+            samples::collect(&registry).expect("should be able to encode runtime metrics");
+        catalog::describe(RUNTIME_FAMILIES, families)
+    }
+
+    /// Register the collector with `registry`.
+    pub fn register(self, registry: &mut Registry) {
+        registry.register_collector(self.build())
+    }
+
+    /// Register the collector with `registry` under both `prefix` and
+    /// `alias`, e.g. the new and old prefixes while renaming them.
+    ///
+    /// Both encode the same samples, the collector under `alias` encodes
+    /// the last sample of the one under `prefix` rather than sampling again,
+    /// so dashboards on either see the same values during the migration.
+    /// With `deprecated`, the help of the families under `alias` points to
+    /// `prefix`.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// let mut registry = prometheus_client::registry::Registry::default();
+    /// tokio_prometheus_client::RuntimeCollectorBuilder::noop()
+    ///     .register_aliased(&mut registry, "runtime", "tokio", true);
+    ///
+    /// let mut text = String::new();
+    /// prometheus_client::encoding::text::encode(&mut text, &registry).unwrap();
+    /// assert!(text.contains("\nruntime_workers_count 0\n"));
+    /// assert!(text.contains("\ntokio_workers_count 0\n"));
+    /// assert!(text.contains(
+    ///     "# HELP tokio_workers_count Deprecated, use the runtime_ metrics instead: "
+    /// ));
+    /// ```
+    pub fn register_aliased(
+        self,
+        registry: &mut Registry,
+        prefix: &str,
+        alias: &str,
+        deprecated: bool,
+    ) {
+        let collector = Arc::new(self.build_collector());
+        registry
+            .sub_registry_with_prefix(prefix)
+            .register_collector(Box::new(AliasedCollector {
+                collector: collector.clone(),
+                alias: None,
+            }));
+        registry
+            .sub_registry_with_prefix(alias)
+            .register_collector(Box::new(AliasedCollector {
+                collector,
+                alias: Some(Alias {
+                    renamed: deprecated.then(|| prefix.to_string()),
+                }),
+            }));
+    }
+
+    /// Build the collector without registering it.
+    ///
+    /// The collector samples the runtime on every collection. Collections
+    /// running concurrently, e.g. of a pair of Prometheus servers, share a
+    /// single sample, so neither sees a shortened interval.
+    ///
+    /// For registries owned by other crates, the collector can be registered
+    /// once they hand out the registry, e.g. in a setup callback or behind a
+    /// lock.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use std::sync::{Arc, Mutex};
+    /// # let rt = tokio::runtime::Runtime::new().unwrap();
+    /// # rt.block_on(async {
+    /// let handle = tokio::runtime::Handle::current();
+    /// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+    /// let collector = tokio_prometheus_client::RuntimeCollectorBuilder::new(runtime_monitor).build();
+    ///
+    /// // Shared with other instrumentation, e.g. HTTP metrics.
+    /// let registry = Arc::new(Mutex::new(prometheus_client::registry::Registry::default()));
+    /// registry
+    ///     .lock()
+    ///     .unwrap()
+    ///     .sub_registry_with_prefix("tokio")
+    ///     .register_collector(collector);
+    /// # });
+    /// ```
+    pub fn build(self) -> Box<dyn Collector> {
+        Box::new(self.build_collector())
+    }
+
+    /// The collector, sampling on every collection.
+    fn build_collector(self) -> RuntimeCollector {
+        let (collector, sampling) = self.into_parts();
+        RuntimeCollector {
+            source: Source::Scrape(Box::new(Scrape {
+                sampling: Mutex::new(sampling),
+                samples: AtomicU64::new(0),
+            })),
+            ..collector
+        }
+    }
+
+    /// Build the collector together with the [`Sampler`] taking the samples
+    /// it encodes.
+    ///
+    /// Instead of sampling the runtime, scrapes encode the snapshot of the
+    /// last sample without taking a lock, so concurrent scrapes do not
+    /// contend with each other or with sampling, and a slow scrape cannot
+    /// delay the next sample. The counters, rates and quantiles only advance
+    /// with [`Sampler::sample`], which also takes the first sample.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # let rt = tokio::runtime::Runtime::new().unwrap();
+    /// # rt.block_on(async {
+    /// let handle = tokio::runtime::Handle::current();
+    /// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+    /// let (collector, mut sampler) =
+    ///     tokio_prometheus_client::RuntimeCollectorBuilder::new(runtime_monitor)
+    ///         .build_with_sampler();
+    /// let mut registry = prometheus_client::registry::Registry::default();
+    /// registry
+    ///     .sub_registry_with_prefix("tokio")
+    ///     .register_collector(collector);
+    ///
+    /// // E.g. on a timer, independent of scrapes.
+    /// sampler.sample();
+    /// # });
+    /// ```
+    ///
+    /// Or sample on a dedicated thread with [`Sampler::spawn`].
+    pub fn build_with_sampler(self) -> (Box<dyn Collector>, Sampler) {
+        let (collector, mut sampling) = self.into_parts();
+        sampling.sample();
+        let snapshot = Arc::new(ArcSwap::from_pointee(sampling.snapshot.clone()));
+        let missed_samples = Arc::new(AtomicU64::new(0));
+        let collector = Box::new(RuntimeCollector {
+            source: Source::Sampler(snapshot.clone()),
+            missed_samples: Some(missed_samples.clone()),
+            ..collector
+        });
+        let sampler = Sampler {
+            sampling,
+            snapshot,
+            missed_samples,
+            sample_interval: collector.config.sample_interval,
+            pause: collector.pause.clone(),
+            clock: SampleClock::default(),
+            #[cfg(feature = "reload")]
+            updates: collector.updates.clone(),
+        };
+        (collector, sampler)
+    }
+
+    /// The collector, without a source yet, and the sampling of the runtime.
+    fn into_parts(self) -> (RuntimeCollector, Sampling) {
+        let collector = self.collector(Source::Sampler(Arc::default()));
+        let sampling = Sampling {
+            intervals: self.intervals,
+            mean_poll_durations: self
+                .mean_poll_duration_window
+                .map(|window| MeanPollDurations {
+                    window,
+                    samples: VecDeque::new(),
+                    sorted: Vec::new(),
+                }),
+            gaps: collector
+                .sample_gaps
+                .as_ref()
+                .map(|(_, histogram)| SampleGaps {
+                    histogram: histogram.clone(),
+                    last: None,
+                }),
+            snapshot: Snapshot::default(),
+        };
+        (collector, sampling)
+    }
+
+    /// The collector encoding the samples of `source`.
+    fn collector(&self, source: Source) -> RuntimeCollector {
+        let created = self.created.then(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64()
+        });
+        RuntimeCollector {
+            source,
+            created,
+            config: self.config.clone(),
+            #[cfg(feature = "reload")]
+            updates: self.updates.clone(),
+            truncated_scrapes: AtomicU64::new(0),
+            missed_samples: None,
+            pause: self.pause.clone(),
+            scrape_labels: self.scrape_labels.clone(),
+            sample_gaps: self
+                .sample_gaps
+                .map(|buckets| (buckets, Histogram::new(buckets.buckets().iter().copied()))),
+            config_info: self.config_info.then(|| {
+                let mut fixed = vec![("created".to_string(), self.created.to_string())];
+                if let Some(window) = self.mean_poll_duration_window {
+                    fixed.push(seconds_label("mean_poll_duration_window", window));
+                }
+                if let Some(buckets) = self.sample_gaps {
+                    fixed.push(("sample_gaps".to_string(), buckets.name().to_string()));
+                }
+                ConfigInfo {
+                    built: Info::new(config_labels(&fixed, &self.config)),
+                    fixed,
+                }
+            }),
+        }
+    }
+}
+
+/// The labels of the `collector_config_info` metric with `config`.
+fn config_labels(fixed: &[(String, String)], config: &CollectorConfig) -> Vec<(String, String)> {
+    let mut labels = fixed.to_vec();
+    labels.push(("rates".to_string(), config.rates.to_string()));
+    labels.push(("series".to_string(), config.series.to_string()));
+    let stability = match config.stability {
+        Stability::Stable => "stable",
+        Stability::Unstable => "unstable",
+        Stability::Experimental => "experimental",
+    };
+    labels.push(("stability".to_string(), stability.to_string()));
+    let error_policy = match config.error_policy {
+        ErrorPolicy::Skip => "skip",
+        ErrorPolicy::StaleLast => "stale_last",
+        ErrorPolicy::Fail => "fail",
+    };
+    labels.push(("error_policy".to_string(), error_policy.to_string()));
+    if let Some(budget) = config.encode_budget {
+        labels.push(seconds_label("encode_budget", budget));
+    }
+    if let Some(interval) = config.sample_interval {
+        labels.push(seconds_label("sample_interval", interval));
+    }
+    if let Some(health) = &config.health {
+        let thresholds = [
+            ("mean_poll_duration_seconds", health.mean_poll_duration),
+            ("injection_queue_depth", health.injection_queue_depth),
+            ("local_queue_depth", health.local_queue_depth),
+            ("forced_yields_per_second", health.forced_yields_per_second),
+            ("busy_ratio", health.busy_ratio),
+        ];
+        for (signal, thresholds) in thresholds {
+            if let Some((warn, critical)) = thresholds {
+                labels.push((format!("health_{signal}"), format!("{warn},{critical}")));
+            }
+        }
+    }
+    labels
+}
+
+/// A label `name` holding `duration` in seconds.
+fn seconds_label(name: &str, duration: Duration) -> (String, String) {
+    (
+        format!("{name}_seconds"),
+        duration.as_secs_f64().to_string(),
+    )
+}
+
+/// The configuration of a collector that may change while it is
+/// registered, see [`RuntimeCollectorBuilder::config_updates`].
+///
+/// The defaults match those of [`RuntimeCollectorBuilder`].
+#[derive(Clone, Debug)]
+pub struct CollectorConfig {
+    /// Whether to expose the rates of counters, see
+    /// [`RuntimeCollectorBuilder::rates`].
+    pub rates: bool,
+    /// Whether to expose the number of series exported, see
+    /// [`RuntimeCollectorBuilder::series`].
+    pub series: bool,
+    /// How long encoding may take, see
+    /// [`RuntimeCollectorBuilder::encode_budget`].
+    pub encode_budget: Option<Duration>,
+    /// The least stable families exported, see
+    /// [`RuntimeCollectorBuilder::stability`].
+    pub stability: Stability,
+    /// The thresholds of the health gauge, see
+    /// [`RuntimeCollectorBuilder::health`].
+    pub health: Option<HealthThresholds>,
+    /// What collections do when sampling fails, see
+    /// [`RuntimeCollectorBuilder::error_policy`].
+    pub error_policy: ErrorPolicy,
+    /// The interval between samples of [`Sampler::spawn`], overriding the
+    /// one it is spawned with.
+    pub sample_interval: Option<Duration>,
+}
+
+impl Default for CollectorConfig {
+    fn default() -> Self {
+        Self {
+            rates: false,
+            series: false,
+            encode_budget: None,
+            stability: Stability::Experimental,
+            health: None,
+            error_policy: ErrorPolicy::default(),
+            sample_interval: None,
+        }
+    }
+}
+
+/// What a collection does when sampling the runtime fails.
+///
+/// Sampling fails when the runtime has no next interval or `tokio_metrics`
+/// panics while sampling it, e.g. on an overflow in debug builds. Failures
+/// are logged.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Encode none of the runtime metrics, so their series go stale in
+    /// Prometheus.
+    #[default]
+    Skip,
+    /// Encode the values of the last successful sample. A `stale` gauge is
+    /// exposed alongside, 1 when the values are from an earlier sample and
+    /// 0 otherwise.
+    StaleLast,
+    /// Return an error to the encoder, failing the whole collection.
+    Fail,
+}
+
+/// What a paused collector exports, see [`PauseHandle::pause`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pause {
+    /// Encode the values of the last sample, so gauges stay the same and
+    /// counters stop growing.
+    Freeze,
+    /// Encode none of the runtime metrics, so their series go stale in
+    /// Prometheus.
+    Silence,
+}
+
+/// Pauses and resumes sampling of collectors built with
+/// [`RuntimeCollectorBuilder::pause_handle`], e.g. to silence the runtime
+/// metrics of canaries during a load test without restarting them.
+///
+/// Clones pause and resume the same collectors. The first sample after
+/// resuming covers the whole pause, so counters catch up with the runtime.
+#[derive(Clone, Debug, Default)]
+pub struct PauseHandle {
+    /// 0 while running, 1 frozen and 2 silenced.
+    state: Arc<AtomicU8>,
+}
+
+impl PauseHandle {
+    /// Create a [`PauseHandle`] of a running collector.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop sampling the runtime, exporting as `pause` says until resumed.
+    pub fn pause(&self, pause: Pause) {
+        let state = match pause {
+            Pause::Freeze => 1,
+            Pause::Silence => 2,
+        };
+        self.state.store(state, Ordering::Relaxed);
+    }
+
+    /// Sample the runtime again.
+    pub fn resume(&self) {
+        self.state.store(0, Ordering::Relaxed);
+    }
+
+    /// How the collector is paused, if it is.
+    pub fn paused(&self) -> Option<Pause> {
+        match self.state.load(Ordering::Relaxed) {
+            0 => None,
+            1 => Some(Pause::Freeze),
+            _ => Some(Pause::Silence),
+        }
+    }
+}
+
+/// Thresholds of the `health` gauge, see
+/// [`RuntimeCollectorBuilder::health`].
+///
+/// Each threshold takes the value at which the runtime is in warning and
+/// the value at which it is critical. Signals without a threshold are
+/// ignored.
+#[derive(Clone, Debug, Default)]
+pub struct HealthThresholds {
+    mean_poll_duration: Option<(f64, f64)>,
+    injection_queue_depth: Option<(f64, f64)>,
+    local_queue_depth: Option<(f64, f64)>,
+    forced_yields_per_second: Option<(f64, f64)>,
+    busy_ratio: Option<(f64, f64)>,
+}
+
+impl HealthThresholds {
+    /// Create [`HealthThresholds`] without any thresholds.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the thresholds of the mean duration of task polls.
+    pub fn mean_poll_duration(mut self, warn: Duration, critical: Duration) -> Self {
+        self.mean_poll_duration = Some((warn.as_secs_f64(), critical.as_secs_f64()));
+        self
+    }
+
+    /// Set the thresholds of the number of tasks waiting in the injection
+    /// queue, scheduled from outside of the runtime.
+    pub fn injection_queue_depth(mut self, warn: usize, critical: usize) -> Self {
+        self.injection_queue_depth = Some((warn as f64, critical as f64));
+        self
+    }
+
+    /// Set the thresholds of the number of tasks waiting in the local
+    /// queues of all workers combined.
+    pub fn local_queue_depth(mut self, warn: usize, critical: usize) -> Self {
+        self.local_queue_depth = Some((warn as f64, critical as f64));
+        self
+    }
+
+    /// Set the thresholds of the number of times per second tasks were
+    /// forced to yield after exhausting their budget.
+    pub fn forced_yields_per_second(mut self, warn: f64, critical: f64) -> Self {
+        self.forced_yields_per_second = Some((warn, critical));
+        self
+    }
+
+    /// Set the thresholds of the fraction of the time, between 0 and 1, the
+    /// workers were busy.
+    pub fn busy_ratio(mut self, warn: f64, critical: f64) -> Self {
+        self.busy_ratio = Some((warn, critical));
+        self
+    }
+
+    /// The health of the runtime during `interval`: 0 when ok, 1 in warning
+    /// and 2 when critical.
+    fn level(&self, interval: &tokio_metrics::RuntimeMetrics) -> i64 {
+        let signals = signals::Signals::new(interval);
+        let signals = [
+            (
+                self.mean_poll_duration,
+                signals.mean_poll_duration.as_secs_f64(),
+            ),
+            (
+                self.injection_queue_depth,
+                signals.injection_queue_depth as f64,
+            ),
+            (self.local_queue_depth, signals.local_queue_depth as f64),
+            (
+                self.forced_yields_per_second,
+                signals.forced_yields_per_second,
+            ),
+            (self.busy_ratio, signals.busy_ratio),
+        ];
+        signals
+            .into_iter()
+            .filter_map(|(thresholds, value)| {
+                let (warn, critical) = thresholds?;
+                Some(if value >= critical {
+                    2
+                } else if value >= warn {
+                    1
+                } else {
+                    0
+                })
+            })
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Samples the runtime for a collector built with
+/// [`RuntimeCollectorBuilder::build_with_sampler`].
+#[derive(Debug)]
+pub struct Sampler {
+    sampling: Sampling,
+    snapshot: Arc<ArcSwap<Snapshot>>,
+    missed_samples: Arc<AtomicU64>,
+    sample_interval: Option<Duration>,
+    pause: PauseHandle,
+    clock: SampleClock,
+    #[cfg(feature = "reload")]
+    updates: Option<tokio::sync::watch::Receiver<CollectorConfig>>,
+}
+
+impl Sampler {
+    /// Sample the interval since the last sample and publish it to the
+    /// collector, unless the collector is paused.
+    pub fn sample(&mut self) {
+        if self.pause.paused().is_some() {
+            return;
+        }
+        self.sampling.sample();
+        if !self.sampling.snapshot.failed {
+            self.clock.tick();
+        }
+        self.snapshot
+            .store(Arc::new(self.sampling.snapshot.clone()));
+    }
+
+    /// The [`SampleClock`] telling when this sampler last sampled the
+    /// runtime, e.g. to stamp the exported samples with it.
+    pub fn clock(&self) -> SampleClock {
+        self.clock.clone()
+    }
+
+    /// Spawn a thread sampling every `interval`, or the
+    /// [`sample_interval`](CollectorConfig::sample_interval) of the latest
+    /// configuration if set.
+    ///
+    /// Sampling walks the state of every worker of the runtime. On its own
+    /// thread it neither runs on the scrape path nor competes with tasks
+    /// for the workers, so scrapes only encode the last snapshot. The
+    /// thread stops once the collector is dropped.
+    ///
+    /// Samples are taken on ticks every `interval`. Ticks that pass while
+    /// sampling takes too long or the thread is starved are skipped and
+    /// counted by the `collector_missed_samples` counter, so gaps in the
+    /// data explain themselves.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// # let rt = tokio::runtime::Runtime::new().unwrap();
+    /// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(rt.handle());
+    /// let (collector, sampler) =
+    ///     tokio_prometheus_client::RuntimeCollectorBuilder::new(runtime_monitor)
+    ///         .build_with_sampler();
+    /// let mut registry = prometheus_client::registry::Registry::default();
+    /// registry
+    ///     .sub_registry_with_prefix("tokio")
+    ///     .register_collector(collector);
+    /// sampler.spawn(Duration::from_secs(15)).unwrap();
+    /// ```
+    pub fn spawn(mut self, interval: Duration) -> std::io::Result<std::thread::JoinHandle<()>> {
+        std::thread::Builder::new()
+            .name("tokio-metrics-sampler".to_string())
+            .spawn(move || {
+                let mut tick = Instant::now();
+                loop {
+                    let interval = self.interval().unwrap_or(interval);
+                    tick += interval;
+                    std::thread::sleep(tick.saturating_duration_since(Instant::now()));
+                    // Only the sampler is left holding the snapshot.
+                    if Arc::strong_count(&self.snapshot) == 1 {
+                        return;
+                    }
+                    self.sample();
+
+                    let missed = missed_ticks(tick.elapsed(), interval);
+                    if missed > 0 {
+                        self.missed_samples.fetch_add(missed, Ordering::Relaxed);
+                        tracing::warn!(missed, "missed runtime metrics samples");
+                        // Start over rather than catching up.
+                        tick = Instant::now();
+                    }
+                }
+            })
+    }
+
+    /// The sampling interval of the latest configuration.
+    fn interval(&self) -> Option<Duration> {
+        #[cfg(feature = "reload")]
+        if let Some(updates) = &self.updates {
+            return updates.borrow().sample_interval;
+        }
+        self.sample_interval
+    }
+}
+
+/// The number of ticks every `interval` that passed while a sample was
+/// `late`.
+fn missed_ticks(late: Duration, interval: Duration) -> u64 {
+    if interval.is_zero() {
+        return 0;
+    }
+    (late.as_nanos() / interval.as_nanos()) as u64
+}
+
+/// When a [`Sampler`] last sampled the runtime successfully.
+///
+/// Scrapes of a collector with a sampler export the values of the last
+/// sample, whenever it was taken. Serving them with the time of the sample,
+/// e.g. with `tower::MetricsService::timestamped_registry`, keeps slow or
+/// batched scrapes from shifting the data in time.
+#[derive(Clone, Debug, Default)]
+pub struct SampleClock {
+    /// Milliseconds since the Unix epoch, 0 before the first sample.
+    sampled_at: Arc<AtomicU64>,
+}
+
+impl SampleClock {
+    /// When the runtime was last sampled, `None` before the first sample.
+    pub fn last_sample(&self) -> Option<SystemTime> {
+        match self.sampled_at.load(Ordering::Relaxed) {
+            0 => None,
+            millis => Some(UNIX_EPOCH + Duration::from_millis(millis)),
+        }
+    }
+
+    fn tick(&self) {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        self.sampled_at.store(millis.max(1), Ordering::Relaxed);
+    }
 }
 
 /// Collects tokio runtime metrics
+///
+/// Encoding reuses the buffers of the collector and does not allocate,
+/// except for the poll count histogram `tokio_metrics` allocates for each
+/// interval when it is enabled, the labels of
+/// [`RuntimeCollectorBuilder::scrape_labels`], the help texts of deprecated
+/// aliases and the configuration info of reloaded configurations.
 #[derive(Debug)]
 struct RuntimeCollector {
+    source: Source,
+    /// When the counters were created, if exposed.
+    created: Option<f64>,
+    config: CollectorConfig,
+    /// The configuration replacing `config`, if reloaded.
+    #[cfg(feature = "reload")]
+    updates: Option<tokio::sync::watch::Receiver<CollectorConfig>>,
+    /// The number of collections that skipped families.
+    truncated_scrapes: AtomicU64,
+    /// The number of ticks the spawned [`Sampler`] missed, if sampled by one.
+    missed_samples: Option<Arc<AtomicU64>>,
+    pause: PauseHandle,
+    scrape_labels: Option<ScrapeLabels>,
+    /// The histogram of the gaps between samples, shared with the sampling.
+    sample_gaps: Option<(BucketPreset, Histogram)>,
+    /// The `collector_config_info` metric, if the configuration is exposed.
+    config_info: Option<ConfigInfo>,
+}
+
+/// The `collector_config_info` metric of a [`RuntimeCollector`].
+#[derive(Debug)]
+struct ConfigInfo {
+    /// The labels of the configuration fixed when the collector was built.
+    fixed: Vec<(String, String)>,
+    /// The metric with the configuration the collector was built with,
+    /// formatted once as it is only replaced by reloads.
+    built: Info<Vec<(String, String)>>,
+}
+
+/// The labels of each collection, see
+/// [`RuntimeCollectorBuilder::scrape_labels`].
+#[derive(Clone)]
+struct ScrapeLabels(Arc<dyn Fn() -> Vec<(String, String)> + Send + Sync>);
+
+impl ScrapeLabels {
+    /// The labels of this collection, with sanitized values.
+    fn resolve(&self) -> Vec<(String, String)> {
+        let mut labels = (self.0)();
+        for (_, value) in &mut labels {
+            if let Cow::Owned(sanitized) = labels::sanitize_value(value) {
+                *value = sanitized;
+            }
+        }
+        labels
+    }
+}
+
+impl std::fmt::Debug for ScrapeLabels {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ScrapeLabels").finish_non_exhaustive()
+    }
+}
+
+/// Where a [`RuntimeCollector`] takes its samples from.
+#[derive(Debug)]
+enum Source {
+    /// Sample the runtime on every collection.
+    Scrape(Box<Scrape>),
+    /// Encode the latest snapshot of a [`Sampler`].
+    Sampler(Arc<ArcSwap<Snapshot>>),
+}
+
+/// Samples the runtime on collection, once for concurrent collections.
+#[derive(Debug)]
+struct Scrape {
+    sampling: Mutex<Sampling>,
+    /// The number of samples taken so far.
+    samples: AtomicU64,
+}
+
+/// The intervals a [`Sampling`] accumulates.
+#[derive(Debug)]
+enum Intervals {
+    Runtime(RuntimeIntervals),
+    /// Empty intervals, without sampling, since the last one ended.
+    Noop(Instant),
+    #[cfg(any(test, feature = "test-util"))]
+    Scripted(std::vec::IntoIter<tokio_metrics::RuntimeMetrics>),
+}
+
+impl Intervals {
+    fn next(&mut self) -> Option<tokio_metrics::RuntimeMetrics> {
+        match self {
+            Intervals::Runtime(intervals) => intervals.next(),
+            Intervals::Noop(ended) => {
+                // Elapsed like those of a runtime, so the rates are exported.
+                let now = Instant::now();
+                let mut interval = tokio_metrics::RuntimeMetrics::default();
+                interval.elapsed = now.duration_since(std::mem::replace(ended, now));
+                Some(interval)
+            }
+            #[cfg(any(test, feature = "test-util"))]
+            Intervals::Scripted(intervals) => Some(intervals.next().unwrap_or_default()),
+        }
+    }
+}
+
+/// Accumulates the intervals of a runtime.
+#[derive(Debug)]
+struct Sampling {
+    intervals: Intervals,
+    mean_poll_durations: Option<MeanPollDurations>,
+    gaps: Option<SampleGaps>,
+    /// The latest sample.
+    snapshot: Snapshot,
+}
+
+impl Sampling {
+    fn sample(&mut self) {
+        #[cfg(feature = "trace")]
+        let _span = tracing::debug_span!("sample_runtime_metrics").entered();
+        #[cfg(feature = "trace")]
+        let started = Instant::now();
+
+        if let Some(gaps) = &mut self.gaps {
+            let now = Instant::now();
+            if let Some(last) = gaps.last.replace(now) {
+                gaps.histogram
+                    .observe(now.duration_since(last).as_secs_f64());
+            }
+        }
+        let intervals = &mut self.intervals;
+        let Ok(Some(interval)) = std::panic::catch_unwind(AssertUnwindSafe(|| intervals.next()))
+        else {
+            tracing::warn!("failed to sample runtime metrics");
+            self.snapshot.failed = true;
+            return;
+        };
+        #[cfg(feature = "trace")]
+        tracing::debug!(
+            duration = ?started.elapsed(),
+            interval = ?interval.elapsed,
+            polls = interval.total_polls_count,
+            "sampled runtime metrics"
+        );
+        self.snapshot.failed = false;
+        self.snapshot.mean_poll_duration_quantiles = self
+            .mean_poll_durations
+            .as_mut()
+            .map(|durations| durations.quantiles(&interval));
+        self.snapshot
+            .metrics
+            .update(&interval, &mut self.snapshot.anomalies);
+        self.snapshot.interval = interval;
+    }
+}
+
+/// The gaps between the samples of a runtime.
+#[derive(Debug)]
+struct SampleGaps {
+    histogram: Histogram,
+    /// When the runtime was last sampled.
+    last: Option<Instant>,
+}
+
+/// The metrics of a runtime as of a sample.
+#[derive(Clone, Debug, Default)]
+struct Snapshot {
     metrics: RuntimeMetrics,
-    intervals: Mutex<RuntimeIntervals>,
+    /// The last interval, for the rates of counters.
+    interval: tokio_metrics::RuntimeMetrics,
+    mean_poll_duration_quantiles: Option<[(&'static str, f64); 3]>,
+    anomalies: Anomalies,
+    /// Whether the last attempt to sample failed, leaving the values of the
+    /// last successful sample.
+    failed: bool,
 }
 
-impl RuntimeCollector {
-    /// Create a [`RuntimeCollector`] in namespace.
-    pub fn new(monitor: RuntimeMonitor) -> Self {
-        let intervals = Mutex::new(monitor.intervals());
-        let metrics = RuntimeMetrics::default();
-        Self { metrics, intervals }
+/// Mean poll durations of the intervals within a window.
+#[derive(Debug)]
+struct MeanPollDurations {
+    window: Duration,
+    /// Mean poll durations by the time they were sampled at.
+    samples: VecDeque<(Instant, f64)>,
+    /// Buffer for sorting the durations, reused to avoid allocating on
+    /// every sample.
+    sorted: Vec<f64>,
+}
+
+impl MeanPollDurations {
+    /// Add the mean poll duration of an interval and return the quantiles
+    /// of the window.
+    fn quantiles(&mut self, interval: &tokio_metrics::RuntimeMetrics) -> [(&'static str, f64); 3] {
+        let now = Instant::now();
+        if interval.total_polls_count > 0 {
+            self.samples
+                .push_back((now, interval.mean_poll_duration.as_secs_f64()));
+        }
+        while let Some((sampled_at, _)) = self.samples.front() {
+            if now.duration_since(*sampled_at) <= self.window {
+                break;
+            }
+            self.samples.pop_front();
+        }
+        self.sorted.clear();
+        self.sorted
+            .extend(self.samples.iter().map(|(_, duration)| *duration));
+        self.sorted.sort_unstable_by(f64::total_cmp);
+        let durations = &self.sorted;
+        // Nearest rank, NaN without any samples.
+        let quantile = |quantile: f64| {
+            let rank = (quantile * durations.len() as f64).ceil() as usize;
+            durations
+                .get(rank.saturating_sub(1))
+                .copied()
+                .unwrap_or(f64::NAN)
+        };
+        [
+            ("0.5", quantile(0.5)),
+            ("0.95", quantile(0.95)),
+            ("0.99", quantile(0.99)),
+        ]
     }
 }
+
 impl Collector for RuntimeCollector {
-    fn encode(
+    fn encode(&self, encoder: DescriptorEncoder) -> Result<(), std::fmt::Error> {
+        self.encode_as(encoder, None)
+    }
+}
+
+/// A runtime collector registered under a prefix and an alias, see
+/// [`RuntimeCollectorBuilder::register_aliased`].
+#[derive(Debug)]
+struct AliasedCollector {
+    collector: Arc<RuntimeCollector>,
+    /// How the collector is encoded under the alias, if this is the alias.
+    alias: Option<Alias>,
+}
+
+#[derive(Debug)]
+struct Alias {
+    /// The prefix the alias is deprecated for, if deprecated.
+    renamed: Option<String>,
+}
+
+impl Collector for AliasedCollector {
+    fn encode(&self, encoder: DescriptorEncoder) -> Result<(), std::fmt::Error> {
+        self.collector.encode_as(encoder, self.alias.as_ref())
+    }
+}
+
+impl RuntimeCollector {
+    /// Encode the collector, or its `alias` encoding the last sample.
+    fn encode_as(
         &self,
-        mut encoder: prometheus_client::encoding::DescriptorEncoder,
+        encoder: DescriptorEncoder,
+        alias: Option<&Alias>,
     ) -> Result<(), std::fmt::Error> {
+        #[cfg(feature = "trace")]
+        let _span = tracing::debug_span!("encode_runtime_metrics").entered();
+        #[cfg(feature = "trace")]
+        let started = Instant::now();
+
+        let paused = self.pause.paused();
+        if paused == Some(Pause::Silence) {
+            return Ok(());
+        }
+        let config = self.config();
+        let renamed = alias.and_then(|alias| alias.renamed.as_deref());
+        let families = match &self.source {
+            Source::Scrape(scrape) => {
+                let seen = scrape.samples.load(Ordering::Acquire);
+                // Sampling catches panics, the state is intact.
+                let mut sampling = scrape
+                    .sampling
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner);
+                // A collection that sampled while this one waited for the
+                // lock covers the same interval. Aliases encode the sample of
+                // the collection under the prefix.
+                if paused.is_none()
+                    && alias.is_none()
+                    && scrape.samples.load(Ordering::Acquire) == seen
+                {
+                    sampling.sample();
+                    scrape.samples.fetch_add(1, Ordering::Release);
+                }
+                // Still locked, so no other collection replaces the sample
+                // while it is encoded.
+                self.encode_snapshot(&config, &sampling.snapshot, encoder, renamed)
+            }
+            Source::Sampler(snapshot) => {
+                self.encode_snapshot(&config, &snapshot.load(), encoder, renamed)
+            }
+        }?;
+
+        #[cfg(feature = "trace")]
+        tracing::debug!(
+            duration = ?started.elapsed(),
+            families,
+            "encoded runtime metrics"
+        );
+        #[cfg(not(feature = "trace"))]
+        let _ = families;
+        Ok(())
+    }
+
+    /// The latest configuration.
+    fn config(&self) -> Cow<'_, CollectorConfig> {
+        #[cfg(feature = "reload")]
+        if let Some(updates) = &self.updates {
+            return Cow::Owned(updates.borrow().clone());
+        }
+        Cow::Borrowed(&self.config)
+    }
+
+    fn encode_snapshot(
+        &self,
+        config: &CollectorConfig,
+        snapshot: &Snapshot,
+        mut encoder: DescriptorEncoder,
+        renamed: Option<&str>,
+    ) -> Result<usize, std::fmt::Error> {
+        // The number of families and series encoded.
+        let mut families = 0;
+        let mut series = 0;
+        if snapshot.failed {
+            match config.error_policy {
+                ErrorPolicy::Skip => return Ok(families),
+                ErrorPolicy::StaleLast => {}
+                ErrorPolicy::Fail => return Err(std::fmt::Error),
+            }
+        }
+        let rates_interval = config.rates.then_some(&snapshot.interval);
+        // Budgets too large for an instant do not limit encoding.
+        let deadline = config
+            .encode_budget
+            .and_then(|budget| Instant::now().checked_add(budget));
+        let mut truncated = false;
+        let labels = self
+            .scrape_labels
+            .as_ref()
+            .map_or_else(Vec::new, ScrapeLabels::resolve);
+
+        // Whether the budget allows encoding another family, skipping the
+        // rest once it is exceeded
+        macro_rules! within_budget {
+            () => {{
+                if !truncated && deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    truncated = true;
+                }
+                !truncated
+            }};
+        }
+        // Whether the family called `$name` is at least as stable as
+        // configured
+        macro_rules! included {
+            ($name:expr) => {{
+                const STABILITY: Stability = names::stability(RUNTIME_FAMILIES, $name);
+                STABILITY <= config.stability
+            }};
+        }
+        // Encode `$metric` with the labels of the scrape, if any
+        macro_rules! labeled {
+            ($metric:expr, $encoder:expr) => {{
+                let mut metric_encoder = $encoder;
+                if labels.is_empty() {
+                    $metric.encode(metric_encoder)
+                } else {
+                    $metric.encode(metric_encoder.encode_family(&labels)?)
+                }
+            }};
+        }
+        // The help text `$help`, pointing to the renamed families if
+        // deprecated
+        macro_rules! help {
+            ($help:expr) => {
+                match renamed {
+                    Some(prefix) => Cow::Owned(format!(
+                        "Deprecated, use the {prefix}_ metrics instead: {}",
+                        $help
+                    )),
+                    None => Cow::Borrowed($help),
+                }
+            };
+        }
         // Helper macros to ensure the metric name is consistent
         macro_rules! encode {
             ($name:ident, $description:expr, $unit:expr, $encoder:expr,) => {
-                let metric_encoder = $encoder.encode_descriptor(
-                    stringify!($name),
-                    $description,
-                    $unit,
-                    self.metrics.$name.metric_type(),
-                )?;
-                self.metrics.$name.encode(metric_encoder)?;
+                if included!(stringify!($name)) && within_budget!() {
+                    let metric_encoder = $encoder.encode_descriptor(
+                        runtime_family!(stringify!($name)),
+                        &help!($description),
+                        $unit,
+                        snapshot.metrics.$name.metric_type(),
+                    )?;
+                    labeled!(snapshot.metrics.$name, metric_encoder)?;
+                    families += 1;
+                    series += 1;
+                    const PREVIOUS: Option<&str> =
+                        names::previous(RENAMED_FAMILIES, stringify!($name));
+                    if let Some(previous) = PREVIOUS {
+                        let metric_encoder = $encoder.encode_descriptor(
+                            previous,
+                            &help!(concat!(
+                                "Deprecated, renamed to ",
+                                stringify!($name),
+                                ": ",
+                                $description
+                            )),
+                            $unit,
+                            snapshot.metrics.$name.metric_type(),
+                        )?;
+                        labeled!(snapshot.metrics.$name, metric_encoder)?;
+                        families += 1;
+                        series += 1;
+                    }
+                    if let (Some(created), MetricType::Counter) =
+                        (self.created, snapshot.metrics.$name.metric_type())
+                    {
+                        static CREATED: names::Name =
+                            names::created(RUNTIME_FAMILIES, stringify!($name));
+                        let metric_encoder = $encoder.encode_descriptor(
+                            CREATED.as_str(),
+                            &help!("When the counter was created, in seconds since the Unix epoch"),
+                            None,
+                            MetricType::Gauge,
+                        )?;
+                        labeled!(ConstGauge::new(created), metric_encoder)?;
+                        families += 1;
+                        series += 1;
+                    }
+                    if let (Some(interval), MetricType::Counter) =
+                        (rates_interval, snapshot.metrics.$name.metric_type())
+                    {
+                        let elapsed = interval.elapsed.as_secs_f64();
+                        if elapsed > 0.0
+                            && !snapshot.anomalies.discarded.contains(&stringify!($name))
+                        {
+                            let metric_encoder = $encoder.encode_descriptor(
+                                concat!(stringify!($name), "_per_second"),
+                                &help!(concat!(
+                                    "Per-second rate of ",
+                                    stringify!($name),
+                                    " during the last interval"
+                                )),
+                                None,
+                                MetricType::Gauge,
+                            )?;
+                            labeled!(
+                                ConstGauge::new(interval.$name.as_f64() / elapsed),
+                                metric_encoder
+                            )?;
+                            families += 1;
+                            series += 1;
+                        }
+                    }
+                }
             };
         }
 
-        let interval = self
-            .intervals
-            .lock()
-            .expect("should be able to lock intervals")
-            .next()
-            .expect("should always be another interval");
-
-        self.metrics.update(interval);
-
         encode!(
             workers_count,
             "The number of worker threads used by the runtime",
@@ -152,44 +1715,323 @@ impl Collector for RuntimeCollector {
             encoder,
         );
 
-        Ok(())
+        let anomalies = [
+            (
+                runtime_family!("counter_anomalies"),
+                included!("counter_anomalies"),
+                "The number of implausible interval deltas of counters that were discarded",
+                snapshot.anomalies.implausible,
+            ),
+            (
+                runtime_family!("counter_saturations"),
+                included!("counter_saturations"),
+                "The number of interval deltas added to counters at their maximum value",
+                snapshot.anomalies.saturated,
+            ),
+        ];
+        for (name, included, help, value) in anomalies {
+            if !included {
+                continue;
+            }
+            if !within_budget!() {
+                break;
+            }
+            let counter = ConstCounter::new(value);
+            labeled!(
+                counter,
+                encoder.encode_descriptor(name, &help!(help), None, counter.metric_type())?
+            )?;
+            families += 1;
+            series += 1;
+        }
+
+        if let Some(quantiles) = snapshot.mean_poll_duration_quantiles {
+            if included!("mean_poll_duration") && within_budget!() {
+                let mut family = encoder.encode_descriptor(
+                    runtime_family!("mean_poll_duration"),
+                    &help!("Quantiles of the mean duration of task polls of recent intervals"),
+                    Some(&Unit::Seconds),
+                    MetricType::Gauge,
+                )?;
+                for (quantile, value) in quantiles {
+                    let gauge = ConstGauge::new(value);
+                    if labels.is_empty() {
+                        gauge.encode(family.encode_family(&[("quantile", quantile)])?)?;
+                    } else {
+                        let mut labels = labels.clone();
+                        labels.push(("quantile".to_string(), quantile.to_string()));
+                        gauge.encode(family.encode_family(&labels)?)?;
+                    }
+                }
+                families += 1;
+                series += quantiles.len();
+            }
+        }
+
+        if let Some((buckets, gaps)) = &self.sample_gaps {
+            if included!("sample_gap") && within_budget!() {
+                labeled!(
+                    gaps,
+                    encoder.encode_descriptor(
+                        runtime_family!("sample_gap"),
+                        &help!("The time between consecutive samples of the runtime"),
+                        Some(&Unit::Seconds),
+                        gaps.metric_type(),
+                    )?
+                )?;
+                families += 1;
+                // The buckets, including +Inf, the sum and the count.
+                series += buckets.buckets().len() + 3;
+            }
+        }
+
+        if config.error_policy == ErrorPolicy::StaleLast && included!("stale") && within_budget!() {
+            let stale = ConstGauge::new(i64::from(snapshot.failed));
+            labeled!(
+                stale,
+                encoder.encode_descriptor(
+                    runtime_family!("stale"),
+                    &help!(
+                        "Whether the runtime metrics are from an earlier sample as sampling failed"
+                    ),
+                    None,
+                    stale.metric_type(),
+                )?
+            )?;
+            families += 1;
+            series += 1;
+        }
+
+        if let Some(thresholds) = &config.health {
+            if included!("health") && within_budget!() {
+                let health = ConstGauge::new(thresholds.level(&snapshot.interval));
+                labeled!(
+                    health,
+                    encoder.encode_descriptor(
+                        runtime_family!("health"),
+                        &help!("The health of the runtime during the last interval, 0 when ok, 1 in warning and 2 when critical"),
+                        None,
+                        health.metric_type(),
+                    )?
+                )?;
+                families += 1;
+                series += 1;
+            }
+        }
+
+        if let Some(missed_samples) = &self.missed_samples {
+            if included!("collector_missed_samples") && within_budget!() {
+                let missed_samples = ConstCounter::new(missed_samples.load(Ordering::Relaxed));
+                labeled!(
+                    missed_samples,
+                    encoder.encode_descriptor(
+                        runtime_family!("collector_missed_samples"),
+                        &help!("The number of sampling ticks the sampler missed as sampling took too long or its thread was starved"),
+                        None,
+                        missed_samples.metric_type(),
+                    )?
+                )?;
+                families += 1;
+                series += 1;
+            }
+        }
+
+        if let Some(config_info) = &self.config_info {
+            if included!("collector_config") && within_budget!() {
+                // A reloaded configuration is a copy, formatted on every
+                // collection.
+                let reloaded;
+                let info = if std::ptr::eq(config, &self.config) {
+                    &config_info.built
+                } else {
+                    reloaded = Info::new(config_labels(&config_info.fixed, config));
+                    &reloaded
+                };
+                labeled!(
+                    info,
+                    encoder.encode_descriptor(
+                        runtime_family!("collector_config"),
+                        &help!("The effective configuration of the runtime collector"),
+                        None,
+                        info.metric_type(),
+                    )?
+                )?;
+                families += 1;
+                series += 1;
+            }
+        }
+
+        let encode_series = config.series && included!("exported_series") && within_budget!();
+        if truncated {
+            self.truncated_scrapes.fetch_add(1, Ordering::Relaxed);
+            tracing::warn!(
+                budget = ?config.encode_budget,
+                "skipped runtime metrics exceeding the encode budget"
+            );
+        }
+        if config.encode_budget.is_some() && included!("collector_truncated_scrapes") {
+            let truncated_scrapes =
+                ConstCounter::new(self.truncated_scrapes.load(Ordering::Relaxed));
+            labeled!(
+                truncated_scrapes,
+                encoder.encode_descriptor(
+                    runtime_family!("collector_truncated_scrapes"),
+                    &help!("The number of collections that skipped runtime metrics exceeding the encode budget"),
+                    None,
+                    truncated_scrapes.metric_type(),
+                )?
+            )?;
+            families += 1;
+            series += 1;
+        }
+
+        if encode_series {
+            // Including this one.
+            series += 1;
+            let exported = ConstGauge::new(series as i64);
+            labeled!(
+                exported,
+                encoder.encode_descriptor(
+                    runtime_family!("exported_series"),
+                    &help!("The number of series the runtime collector exports"),
+                    None,
+                    exported.metric_type(),
+                )?
+            )?;
+            families += 1;
+        }
+
+        Ok(families)
     }
 }
 
+/// Every family the runtime collector exports, checked at compile time to
+/// have valid and unique names.
+const RUNTIME_FAMILIES: &[Family] = &[
+    Family::gauge("workers_count"),
+    Family::counter("total_park_count"),
+    Family::counter("total_noop_count").stability(Stability::Unstable),
+    Family::counter("total_steal_count").stability(Stability::Unstable),
+    Family::counter("total_steal_operations").stability(Stability::Unstable),
+    Family::counter("num_remote_schedules").stability(Stability::Unstable),
+    Family::counter("total_local_schedule_count").stability(Stability::Unstable),
+    Family::counter("total_overflow_count").stability(Stability::Unstable),
+    Family::counter("total_polls_count").stability(Stability::Unstable),
+    Family::counter("total_busy_duration").unit("seconds"),
+    Family::gauge("injection_queue_depth"),
+    Family::gauge("total_local_queue_depth").stability(Stability::Unstable),
+    Family::counter("budget_forced_yield_count").stability(Stability::Unstable),
+    Family::counter("io_driver_ready_count").stability(Stability::Unstable),
+    Family::counter("counter_anomalies"),
+    Family::counter("counter_saturations"),
+    Family::gauge("mean_poll_duration")
+        .unit("seconds")
+        .stability(Stability::Experimental),
+    // A histogram, exporting a single family like a gauge.
+    Family::gauge("sample_gap")
+        .unit("seconds")
+        .stability(Stability::Experimental),
+    Family::gauge("stale"),
+    Family::gauge("health").stability(Stability::Experimental),
+    Family::gauge("exported_series").stability(Stability::Experimental),
+    Family::counter("collector_truncated_scrapes").stability(Stability::Experimental),
+    Family::counter("collector_missed_samples").stability(Stability::Experimental),
+    // An info metric, exporting `collector_config_info` samples.
+    Family::gauge("collector_config").stability(Stability::Experimental),
+];
+const _: () = names::check(RUNTIME_FAMILIES);
+
+/// The families of `RUNTIME_FAMILIES` renamed in this release, exported under
+/// their previous names too, with help texts pointing to the new names, so
+/// alerts and dashboards on them keep working for a release while they are
+/// migrated. Emptied in the release after.
+const RENAMED_FAMILIES: &[Renamed] = &[];
+const _: () = names::check_renamed(RUNTIME_FAMILIES, RENAMED_FAMILIES);
+
 // Current RuntimeMetrics
 // https://docs.rs/tokio-metrics/latest/tokio_metrics/struct.RuntimeMetrics.html
-#[derive(Debug, Default)]
+#[derive(Clone, Copy, Debug, Default)]
 struct RuntimeMetrics {
-    workers_count: Gauge,
-    total_park_count: Counter,
-    total_noop_count: Counter,
-    total_steal_count: Counter,
-    total_steal_operations: Counter,
-    num_remote_schedules: Counter,
-    total_local_schedule_count: Counter,
-    total_overflow_count: Counter,
-    total_polls_count: Counter,
-    total_busy_duration: Counter<f64>,
-    injection_queue_depth: Gauge,
-    total_local_queue_depth: Gauge,
-    budget_forced_yield_count: Counter,
-    io_driver_ready_count: Counter,
+    workers_count: GaugeValue,
+    total_park_count: CounterValue<u64>,
+    total_noop_count: CounterValue<u64>,
+    total_steal_count: CounterValue<u64>,
+    total_steal_operations: CounterValue<u64>,
+    num_remote_schedules: CounterValue<u64>,
+    total_local_schedule_count: CounterValue<u64>,
+    total_overflow_count: CounterValue<u64>,
+    total_polls_count: CounterValue<u64>,
+    total_busy_duration: DurationValue,
+    injection_queue_depth: GaugeValue,
+    total_local_queue_depth: GaugeValue,
+    budget_forced_yield_count: CounterValue<u64>,
+    io_driver_ready_count: CounterValue<u64>,
 }
 
 impl RuntimeMetrics {
-    fn update(&self, data: tokio_metrics::RuntimeMetrics) {
+    /// Add the counters and set the gauges of interval `data`, counting the
+    /// deltas that could not be added in `anomalies`.
+    ///
+    /// `tokio_metrics` subtracts the previous from the current values of the
+    /// runtime, so a counter going backwards wraps around into a huge delta.
+    /// Discarding such deltas keeps the counters monotonic and free of
+    /// spikes. Counters reaching their maximum stay there instead of
+    /// wrapping around.
+    fn update(&mut self, data: &tokio_metrics::RuntimeMetrics, anomalies: &mut Anomalies) {
+        // Workers cannot be busy for much longer than the interval, allowing
+        // for the workers being probed one after the other.
+        let max_busy = 2.0 * data.elapsed.as_secs_f64() * data.workers_count as f64;
+        anomalies.discarded.clear();
+
         // macros to ensure we are using consistent metrics names
+        macro_rules! discard {
+            ( $field:ident, $delta:expr ) => {{
+                tracing::warn!(
+                    counter = stringify!($field),
+                    delta = $delta,
+                    "discarded implausible counter delta"
+                );
+                anomalies.implausible += 1;
+                anomalies.discarded.push(stringify!($field));
+            }};
+        }
+        macro_rules! add {
+            ( $field:ident, $delta:expr ) => {{
+                match self.$field.0.checked_add($delta) {
+                    Some(total) => self.$field.0 = total,
+                    None => {
+                        if self.$field.0 < u64::MAX {
+                            tracing::warn!(counter = stringify!($field), "counter saturated");
+                            self.$field.0 = u64::MAX;
+                        }
+                        anomalies.saturated += 1;
+                    }
+                }
+            }};
+        }
         macro_rules! inc_by {
             ( $field:ident, "int" ) => {{
-                self.$field.inc_by(data.$field as u64);
+                let delta = u64::try_from(data.$field).unwrap_or(u64::MAX);
+                if delta > i64::MAX as u64 {
+                    discard!($field, delta);
+                } else {
+                    add!($field, delta);
+                }
             }};
             ( $field:ident, "duration" ) => {{
-                self.$field.inc_by(data.$field.as_secs_f64());
+                let delta = data.$field;
+                if delta.as_secs_f64() > max_busy {
+                    discard!($field, delta.as_secs_f64());
+                } else {
+                    // Rounded to avoid drifting below the actual duration.
+                    let micros = (delta.as_nanos() + 500) / 1000;
+                    add!($field, u64::try_from(micros).unwrap_or(u64::MAX));
+                }
             }};
         }
         macro_rules! set {
             ( $field:ident) => {{
-                self.$field.set(data.$field as i64);
+                self.$field.0 = i64::try_from(data.$field).unwrap_or(i64::MAX);
             }};
         }
 
@@ -209,3 +2051,695 @@ impl RuntimeMetrics {
         inc_by!(io_driver_ready_count, "int");
     }
 }
+
+/// Counts of the deltas [`RuntimeMetrics::update`] could not add.
+#[derive(Clone, Debug, Default)]
+struct Anomalies {
+    /// Deltas discarded as implausible.
+    implausible: u64,
+    /// Deltas added to counters at their maximum.
+    saturated: u64,
+    /// The counters whose delta of the last interval was discarded, so
+    /// their rates are not exported for it.
+    discarded: Vec<&'static str>,
+}
+
+/// Total of a counter of [`RuntimeMetrics`].
+#[derive(Clone, Copy, Debug, Default)]
+struct CounterValue<N>(N);
+
+impl<N: Copy> CounterValue<N> {
+    fn get(&self) -> N {
+        self.0
+    }
+}
+
+impl<N: EncodeCounterValue + Copy> EncodeMetric for CounterValue<N> {
+    fn encode(&self, encoder: MetricEncoder) -> Result<(), std::fmt::Error> {
+        ConstCounter::new(self.get()).encode(encoder)
+    }
+
+    fn metric_type(&self) -> MetricType {
+        MetricType::Counter
+    }
+}
+
+/// Current value of a gauge of [`RuntimeMetrics`].
+#[derive(Clone, Copy, Debug, Default)]
+struct GaugeValue(i64);
+
+impl GaugeValue {
+    fn get(&self) -> i64 {
+        self.0
+    }
+}
+
+impl EncodeMetric for GaugeValue {
+    fn encode(&self, encoder: MetricEncoder) -> Result<(), std::fmt::Error> {
+        ConstGauge::new(self.get()).encode(encoder)
+    }
+
+    fn metric_type(&self) -> MetricType {
+        MetricType::Gauge
+    }
+}
+
+/// Total of a duration counter of [`RuntimeMetrics`], in microseconds.
+///
+/// Adding up integers does not drift like adding up `f64` seconds, which
+/// lose precision as the total grows. The total is exposed in seconds.
+#[derive(Clone, Copy, Debug, Default)]
+struct DurationValue(u64);
+
+impl DurationValue {
+    /// The total in seconds.
+    fn get(&self) -> f64 {
+        self.0 as f64 / 1e6
+    }
+}
+
+impl EncodeMetric for DurationValue {
+    fn encode(&self, encoder: MetricEncoder) -> Result<(), std::fmt::Error> {
+        ConstCounter::new(self.get()).encode(encoder)
+    }
+
+    fn metric_type(&self) -> MetricType {
+        MetricType::Counter
+    }
+}
+
+/// Values of [`tokio_metrics::RuntimeMetrics`] as exposed.
+trait IntervalValue {
+    fn as_f64(&self) -> f64;
+}
+
+impl IntervalValue for u64 {
+    fn as_f64(&self) -> f64 {
+        *self as f64
+    }
+}
+
+impl IntervalValue for usize {
+    fn as_f64(&self) -> f64 {
+        *self as f64
+    }
+}
+
+impl IntervalValue for Duration {
+    fn as_f64(&self) -> f64 {
+        self.as_secs_f64()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        cell::Cell,
+    };
+
+    use super::*;
+
+    /// Counts the allocations of each thread.
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    /// The number of allocations of the current thread while running `f`.
+    fn allocations(f: impl FnOnce()) -> usize {
+        let before = ALLOCATIONS.with(Cell::get);
+        f();
+        ALLOCATIONS.with(Cell::get) - before
+    }
+
+    /// Encode `registry` into `buffer`, which is cleared first.
+    fn encode(buffer: &mut String, registry: &Registry) {
+        buffer.clear();
+        prometheus_client::encoding::text::encode(buffer, registry).unwrap();
+    }
+
+    /// The allocations of encoding `registry` again, into a buffer with
+    /// enough capacity.
+    fn encode_allocations(registry: &Registry) -> usize {
+        let mut buffer = String::new();
+        encode(&mut buffer, registry);
+        buffer.reserve(buffer.len());
+        allocations(|| encode(&mut buffer, registry))
+    }
+
+    /// A builder collecting the scripted `intervals`.
+    fn scripted(
+        intervals: impl IntoIterator<Item = tokio_metrics::RuntimeMetrics>,
+    ) -> RuntimeCollectorBuilder {
+        let intervals: Vec<_> = intervals.into_iter().collect();
+        RuntimeCollectorBuilder::with_intervals(Intervals::Scripted(intervals.into_iter()))
+    }
+
+    /// The text encoding of `registry`.
+    fn encoded(registry: &Registry) -> String {
+        let mut text = String::new();
+        encode(&mut text, registry);
+        text
+    }
+
+    #[test]
+    fn encoding_does_not_allocate() {
+        let mut registry = Registry::default();
+        RuntimeCollectorBuilder::noop().register(registry.sub_registry_with_prefix("tokio"));
+        assert_eq!(encode_allocations(&registry), 0);
+
+        let mut registry = Registry::default();
+        RuntimeCollectorBuilder::noop()
+            .created(true)
+            .rates(true)
+            .series(true)
+            .encode_budget(Duration::from_secs(1))
+            .health(HealthThresholds::new().busy_ratio(0.8, 0.95))
+            .error_policy(ErrorPolicy::StaleLast)
+            .mean_poll_duration_quantiles(Duration::from_secs(60))
+            .sample_gaps(BucketPreset::default())
+            .config_info(true)
+            .register(registry.sub_registry_with_prefix("tokio"));
+        assert_eq!(encode_allocations(&registry), 0);
+
+        let mut registry = Registry::default();
+        RuntimeCollectorBuilder::noop().register_aliased(&mut registry, "runtime", "tokio", false);
+        assert_eq!(encode_allocations(&registry), 0);
+    }
+
+    #[cfg(feature = "reload")]
+    #[test]
+    fn encoding_reloaded_config_does_not_allocate() {
+        let (_updates, receiver) = tokio::sync::watch::channel(CollectorConfig {
+            rates: true,
+            ..Default::default()
+        });
+        let mut registry = Registry::default();
+        RuntimeCollectorBuilder::noop()
+            .config_updates(receiver)
+            .register(registry.sub_registry_with_prefix("tokio"));
+        assert_eq!(encode_allocations(&registry), 0);
+    }
+
+    #[test]
+    fn health_level_is_worst_signal() {
+        let mut interval = tokio_metrics::RuntimeMetrics::default();
+        interval.workers_count = 2;
+        interval.elapsed = Duration::from_secs(1);
+        interval.total_busy_duration = Duration::from_millis(1700);
+        interval.injection_queue_depth = 50;
+        interval.mean_poll_duration = Duration::from_millis(2);
+        assert_eq!(HealthThresholds::new().level(&interval), 0);
+        let thresholds = HealthThresholds::new().injection_queue_depth(100, 1000);
+        assert_eq!(thresholds.level(&interval), 0);
+        let thresholds = thresholds.busy_ratio(0.8, 0.95);
+        assert_eq!(thresholds.level(&interval), 1);
+        let thresholds =
+            thresholds.mean_poll_duration(Duration::from_micros(500), Duration::from_millis(1));
+        assert_eq!(thresholds.level(&interval), 2);
+    }
+
+    fn interval() -> tokio_metrics::RuntimeMetrics {
+        let mut interval = tokio_metrics::RuntimeMetrics::default();
+        interval.workers_count = 2;
+        interval.elapsed = Duration::from_secs(1);
+        interval.total_park_count = 3;
+        interval.total_busy_duration = Duration::from_millis(1500);
+        interval
+    }
+
+    #[test]
+    fn update_discards_implausible_deltas() {
+        let mut metrics = RuntimeMetrics::default();
+        let mut anomalies = Anomalies::default();
+        metrics.update(&interval(), &mut anomalies);
+
+        let mut wrapped = interval();
+        wrapped.total_park_count = u64::MAX - 1;
+        wrapped.total_busy_duration = Duration::from_secs(5);
+        metrics.update(&wrapped, &mut anomalies);
+        assert_eq!(metrics.total_park_count.get(), 3);
+        assert_eq!(metrics.total_busy_duration.0, 1_500_000);
+        assert_eq!(anomalies.implausible, 2);
+        assert_eq!(
+            anomalies.discarded,
+            ["total_park_count", "total_busy_duration"]
+        );
+
+        metrics.update(&interval(), &mut anomalies);
+        assert_eq!(metrics.total_park_count.get(), 6);
+        assert!(anomalies.discarded.is_empty());
+    }
+
+    #[test]
+    fn update_saturates_counters() {
+        let mut metrics = RuntimeMetrics::default();
+        metrics.total_park_count.0 = u64::MAX - 1;
+        let mut anomalies = Anomalies::default();
+        metrics.update(&interval(), &mut anomalies);
+        metrics.update(&interval(), &mut anomalies);
+        assert_eq!(metrics.total_park_count.get(), u64::MAX);
+        assert_eq!(anomalies.saturated, 2);
+        assert_eq!(anomalies.implausible, 0);
+        assert!(anomalies.discarded.is_empty());
+    }
+
+    #[test]
+    fn created_gauges_follow_counters() {
+        let mut registry = Registry::default();
+        RuntimeCollectorBuilder::noop()
+            .created(true)
+            .register(&mut registry);
+        let text = encoded(&registry);
+        let created = text
+            .lines()
+            .find_map(|line| line.strip_prefix("total_park_count_created "))
+            .expect("counters should have a created gauge");
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let created: f64 = created.parse().unwrap();
+        assert!((now.as_secs_f64() - created).abs() < 60.0, "{created}");
+        assert!(text.contains("# TYPE total_park_count_created gauge\n"));
+        assert!(!text.contains("workers_count_created"));
+        // Named after the series, including the unit.
+        assert!(text.contains("# TYPE total_busy_duration_seconds_created gauge\n"));
+        assert!(text.contains("\ntotal_busy_duration_seconds_created "));
+        assert!(!text.contains("total_busy_duration_created"));
+
+        let mut registry = Registry::default();
+        RuntimeCollectorBuilder::noop().register(&mut registry);
+        assert!(!encoded(&registry).contains("_created"));
+    }
+
+    #[test]
+    fn built_collector_encodes_like_registered() {
+        let mut registered = Registry::default();
+        RuntimeCollectorBuilder::noop().register(registered.sub_registry_with_prefix("tokio"));
+        let mut built = Registry::default();
+        built
+            .sub_registry_with_prefix("tokio")
+            .register_collector(RuntimeCollectorBuilder::noop().build());
+        let text = encoded(&built);
+        assert!(text.contains("tokio_workers_count 0\n"), "{text}");
+        assert_eq!(text, encoded(&registered));
+    }
+
+    #[test]
+    fn mean_poll_duration_quantiles_of_window() {
+        let mut durations = MeanPollDurations {
+            window: Duration::from_secs(60),
+            samples: VecDeque::new(),
+            sorted: Vec::new(),
+        };
+        let mut interval = tokio_metrics::RuntimeMetrics::default();
+        let [(_, median), ..] = durations.quantiles(&interval);
+        assert!(median.is_nan());
+
+        interval.total_polls_count = 1;
+        for millis in (1..=10).rev() {
+            interval.mean_poll_duration = Duration::from_millis(millis);
+            durations.quantiles(&interval);
+        }
+        // Intervals without polls have no mean poll duration.
+        interval.total_polls_count = 0;
+        assert_eq!(
+            durations.quantiles(&interval),
+            [("0.5", 0.005), ("0.95", 0.01), ("0.99", 0.01)]
+        );
+
+        durations.window = Duration::ZERO;
+        std::thread::sleep(Duration::from_millis(1));
+        interval.total_polls_count = 1;
+        interval.mean_poll_duration = Duration::from_millis(20);
+        assert_eq!(
+            durations.quantiles(&interval),
+            [("0.5", 0.02), ("0.95", 0.02), ("0.99", 0.02)]
+        );
+    }
+
+    #[test]
+    fn rates_of_the_last_interval() {
+        let mut busy = tokio_metrics::RuntimeMetrics::default();
+        busy.workers_count = 1;
+        busy.elapsed = Duration::from_secs(10);
+        busy.total_polls_count = 50;
+        busy.total_busy_duration = Duration::from_secs(5);
+        let mut implausible = busy.clone();
+        implausible.total_polls_count = u64::MAX;
+        let mut registry = Registry::default();
+        scripted([busy, implausible, Default::default()])
+            .rates(true)
+            .register(&mut registry);
+
+        let text = encoded(&registry);
+        assert!(
+            text.contains("total_polls_count_per_second 5.0\n"),
+            "{text}"
+        );
+        assert!(
+            text.contains("total_busy_duration_per_second 0.5\n"),
+            "{text}"
+        );
+        assert!(text.contains("total_park_count_per_second 0.0\n"), "{text}");
+        assert!(!text.contains("workers_count_per_second"), "{text}");
+
+        let text = encoded(&registry);
+        assert!(!text.contains("total_polls_count_per_second"), "{text}");
+        assert!(
+            text.contains("total_busy_duration_per_second 0.5\n"),
+            "{text}"
+        );
+
+        // Intervals without elapsed time have no rates.
+        assert!(!encoded(&registry).contains("_per_second"));
+    }
+
+    /// An interval of `polls` polls.
+    fn polls(polls: u64) -> tokio_metrics::RuntimeMetrics {
+        let mut interval = tokio_metrics::RuntimeMetrics::default();
+        interval.total_polls_count = polls;
+        interval
+    }
+
+    #[test]
+    fn sampler_publishes_snapshots() {
+        let (collector, mut sampler) = scripted([polls(1), polls(2)]).build_with_sampler();
+        let mut registry = Registry::default();
+        registry.register_collector(collector);
+
+        // Building takes the first sample, scrapes do not take any.
+        assert!(encoded(&registry).contains("total_polls_count_total 1\n"));
+        assert!(encoded(&registry).contains("total_polls_count_total 1\n"));
+        sampler.sample();
+        assert!(encoded(&registry).contains("total_polls_count_total 3\n"));
+    }
+
+    #[test]
+    fn spawned_sampler_samples_until_collector_is_dropped() {
+        let (collector, sampler) =
+            scripted(std::iter::repeat_n(polls(1), 1000)).build_with_sampler();
+        let mut registry = Registry::default();
+        registry.register_collector(collector);
+        let sampler = sampler.spawn(Duration::from_millis(1)).unwrap();
+
+        let started = Instant::now();
+        while encoded(&registry).contains("total_polls_count_total 1\n") {
+            assert!(started.elapsed() < Duration::from_secs(5), "not sampled");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        drop(registry);
+        sampler.join().unwrap();
+    }
+
+    #[test]
+    fn concurrent_scrapes_share_a_sample() {
+        #[derive(Debug)]
+        struct Shared(Arc<RuntimeCollector>);
+
+        impl Collector for Shared {
+            fn encode(&self, encoder: DescriptorEncoder) -> Result<(), std::fmt::Error> {
+                self.0.encode(encoder)
+            }
+        }
+
+        let collector = Arc::new(scripted([polls(1), polls(2), polls(4)]).build_collector());
+        let Source::Scrape(scrape) = &collector.source else {
+            unreachable!("collectors without a sampler sample on scrapes");
+        };
+        let scrapes: Vec<_> = {
+            let _sampling = scrape.sampling.lock().unwrap();
+            let scrapes = (0..2)
+                .map(|_| {
+                    let collector = collector.clone();
+                    std::thread::spawn(move || {
+                        let mut registry = Registry::default();
+                        registry.register_collector(Box::new(Shared(collector)));
+                        encoded(&registry)
+                    })
+                })
+                .collect();
+            // Both scrapes wait for the lock.
+            std::thread::sleep(Duration::from_millis(100));
+            scrapes
+        };
+        for scrape in scrapes {
+            assert!(scrape
+                .join()
+                .unwrap()
+                .contains("total_polls_count_total 1\n"));
+        }
+        assert_eq!(scrape.samples.load(Ordering::Acquire), 1);
+
+        let mut registry = Registry::default();
+        registry.register_collector(Box::new(Shared(collector.clone())));
+        assert!(encoded(&registry).contains("total_polls_count_total 3\n"));
+    }
+
+    #[test]
+    fn busy_duration_accumulates_rounded_micros() {
+        let mut metrics = RuntimeMetrics::default();
+        let mut anomalies = Anomalies::default();
+        let mut interval = interval();
+        for nanos in [1_499, 1_500, 999_999_500] {
+            interval.total_busy_duration = Duration::from_nanos(nanos);
+            metrics.update(&interval, &mut anomalies);
+        }
+        assert_eq!(metrics.total_busy_duration.0, 1_000_003);
+        assert_eq!(metrics.total_busy_duration.get(), 1.000_003);
+    }
+
+    /// A registry with a collector whose last sample failed after one
+    /// interval of one poll.
+    fn failed(error_policy: ErrorPolicy) -> Registry {
+        let (collector, mut sampler) = scripted([polls(1)])
+            .error_policy(error_policy)
+            .build_with_sampler();
+        sampler.sampling.snapshot.failed = true;
+        sampler
+            .snapshot
+            .store(Arc::new(sampler.sampling.snapshot.clone()));
+        let mut registry = Registry::default();
+        registry.register_collector(collector);
+        registry
+    }
+
+    #[test]
+    fn error_policies_of_failed_samples() {
+        assert_eq!(encoded(&failed(ErrorPolicy::Skip)), "# EOF\n");
+
+        let text = encoded(&failed(ErrorPolicy::StaleLast));
+        assert!(text.contains("total_polls_count_total 1\n"), "{text}");
+        assert!(text.contains("\nstale 1\n"), "{text}");
+
+        let mut text = String::new();
+        let registry = failed(ErrorPolicy::Fail);
+        assert!(prometheus_client::encoding::text::encode(&mut text, &registry).is_err());
+
+        let mut registry = Registry::default();
+        scripted([])
+            .error_policy(ErrorPolicy::StaleLast)
+            .register(&mut registry);
+        assert!(encoded(&registry).contains("\nstale 0\n"));
+    }
+
+    #[cfg(feature = "trace")]
+    #[test]
+    fn trace_spans_sampling_and_encoding() {
+        use tracing::{field::Field, span, Event, Metadata, Subscriber};
+
+        /// Records the names of spans and the fields of events.
+        #[derive(Default)]
+        struct Recorder(Mutex<Vec<String>>);
+
+        impl tracing::field::Visit for &Recorder {
+            fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("{}={value:?}", field.name()));
+            }
+        }
+
+        impl Subscriber for &'static Recorder {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(span.metadata().name().to_string());
+                span::Id::from_u64(1)
+            }
+
+            fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+            fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+            fn event(&self, event: &Event<'_>) {
+                event.record(&mut &**self);
+            }
+
+            fn enter(&self, _: &span::Id) {}
+
+            fn exit(&self, _: &span::Id) {}
+        }
+
+        let recorder: &'static Recorder = Box::leak(Box::default());
+        let mut registry = Registry::default();
+        RuntimeCollectorBuilder::noop().register(&mut registry);
+        tracing::subscriber::with_default(recorder, || encoded(&registry));
+
+        let recorded = recorder.0.lock().unwrap();
+        for expected in [
+            "encode_runtime_metrics",
+            "sample_runtime_metrics",
+            "message=sampled runtime metrics",
+            "polls=0",
+            "message=encoded runtime metrics",
+            "families=16",
+        ] {
+            assert!(
+                recorded.iter().any(|recorded| recorded == expected),
+                "{recorded:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn noop_exports_the_families_of_a_runtime_as_zero() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let families = |builder: RuntimeCollectorBuilder| {
+            let mut registry = Registry::default();
+            builder.rates(true).register(&mut registry);
+            encoded(&registry);
+            let text = encoded(&registry);
+            let types: Vec<_> = text
+                .lines()
+                .filter(|line| line.starts_with("# TYPE "))
+                .map(String::from)
+                .collect();
+            (text, types)
+        };
+        let (text, noop) = families(RuntimeCollectorBuilder::noop());
+        let (_, runtime) = families(RuntimeCollectorBuilder::new(RuntimeMonitor::new(
+            runtime.handle(),
+        )));
+        assert_eq!(noop, runtime);
+        for line in text.lines().filter(|line| !line.starts_with('#')) {
+            let (_, value) = line.rsplit_once(' ').unwrap();
+            assert_eq!(value.parse::<f64>().unwrap(), 0.0, "{line}");
+        }
+    }
+
+    #[test]
+    fn exported_series_counts_every_series() {
+        let builders = [
+            RuntimeCollectorBuilder::noop(),
+            RuntimeCollectorBuilder::noop()
+                .created(true)
+                .rates(true)
+                .error_policy(ErrorPolicy::StaleLast)
+                .encode_budget(Duration::from_secs(1))
+                .health(HealthThresholds::new().busy_ratio(0.8, 0.95))
+                .mean_poll_duration_quantiles(Duration::from_secs(60))
+                .sample_gaps(BucketPreset::LatencyFine)
+                .config_info(true),
+            RuntimeCollectorBuilder::noop()
+                .mean_poll_duration_quantiles(Duration::from_secs(60))
+                .scrape_labels(|| vec![("scraper".to_string(), "a".to_string())]),
+        ];
+        for builder in builders {
+            let mut registry = Registry::default();
+            builder.series(true).register(&mut registry);
+            let text = encoded(&registry);
+            let series: Vec<_> = text.lines().filter(|line| !line.starts_with('#')).collect();
+            let exported = series
+                .iter()
+                .find(|line| line.starts_with("exported_series"))
+                .and_then(|line| line.rsplit_once(' '))
+                .map(|(_, value)| value);
+            assert_eq!(exported, Some(series.len().to_string().as_str()), "{text}");
+        }
+    }
+
+    #[test]
+    fn encode_budget_skips_families_once_exceeded() {
+        let mut registry = Registry::default();
+        RuntimeCollectorBuilder::noop()
+            .encode_budget(Duration::ZERO)
+            .register(&mut registry);
+        let text = encoded(&registry);
+        assert!(!text.contains("workers_count"), "{text}");
+        assert!(
+            text.contains("collector_truncated_scrapes_total 1\n"),
+            "{text}"
+        );
+        assert!(encoded(&registry).contains("collector_truncated_scrapes_total 2\n"));
+
+        let mut registry = Registry::default();
+        RuntimeCollectorBuilder::noop()
+            .encode_budget(Duration::MAX)
+            .register(&mut registry);
+        let text = encoded(&registry);
+        assert!(text.contains("workers_count 0\n"), "{text}");
+        assert!(
+            text.contains("collector_truncated_scrapes_total 0\n"),
+            "{text}"
+        );
+    }
+
+    #[test]
+    fn sample_gaps_between_samples() {
+        let mut registry = Registry::default();
+        RuntimeCollectorBuilder::noop()
+            .sample_gaps(BucketPreset::LongRunning)
+            .register(&mut registry);
+        let text = encoded(&registry);
+        assert!(text.contains("sample_gap_seconds_count 0\n"), "{text}");
+        encoded(&registry);
+        let text = encoded(&registry);
+        assert!(text.contains("sample_gap_seconds_count 2\n"), "{text}");
+        assert!(
+            text.contains("sample_gap_seconds_bucket{le=\"0.1\"} 2\n"),
+            "{text}"
+        );
+    }
+
+    #[test]
+    fn missed_samples_count_passed_ticks() {
+        let interval = Duration::from_secs(10);
+        assert_eq!(missed_ticks(Duration::from_secs(9), interval), 0);
+        assert_eq!(missed_ticks(interval, interval), 1);
+        assert_eq!(missed_ticks(Duration::from_secs(35), interval), 3);
+        assert_eq!(missed_ticks(Duration::from_secs(35), Duration::ZERO), 0);
+
+        let (collector, sampler) = RuntimeCollectorBuilder::noop().build_with_sampler();
+        let mut registry = Registry::default();
+        registry.register_collector(collector);
+        sampler.missed_samples.fetch_add(2, Ordering::Relaxed);
+        assert!(encoded(&registry).contains("collector_missed_samples_total 2\n"));
+
+        let mut registry = Registry::default();
+        RuntimeCollectorBuilder::noop().register(&mut registry);
+        assert!(!encoded(&registry).contains("collector_missed_samples"));
+    }
+}
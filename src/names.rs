@@ -0,0 +1,416 @@
+//! Compile time checks of the names of exported metrics.
+//!
+//! Everything is `const fn`, so a collector listing its families in a
+//! `const` and calling [`check`] fails to build, rather than exposing names
+//! Prometheus rejects.
+
+use crate::catalog::Stability;
+
+/// The name `$name` of a family of the runtime collector, failing the build
+/// unless it is listed in `RUNTIME_FAMILIES`, so it is checked too.
+macro_rules! runtime_family {
+    ($name:expr) => {{
+        const _: () = assert!(
+            $crate::names::contains($crate::RUNTIME_FAMILIES, $name),
+            "metric is missing from RUNTIME_FAMILIES"
+        );
+        $name
+    }};
+}
+pub(crate) use runtime_family;
+
+/// The name of the value samples of the family `$name` of the runtime
+/// collector, e.g. with `_seconds_total` for a counter of seconds, failing
+/// the build unless it is listed in `RUNTIME_FAMILIES`.
+macro_rules! runtime_samples {
+    ($name:expr) => {{
+        static SAMPLES: $crate::names::Name =
+            $crate::names::samples($crate::RUNTIME_FAMILIES, $name);
+        SAMPLES.as_str()
+    }};
+}
+pub(crate) use runtime_samples;
+
+/// A metric family as exported by a collector.
+pub(crate) struct Family {
+    /// The name of the family, without prefix or unit.
+    pub(crate) name: &'static str,
+    /// The unit appended to the name, if any.
+    pub(crate) unit: Option<&'static str>,
+    /// Whether the family is a counter, exporting `_total` samples as well
+    /// as the `_created` and `_per_second` families.
+    pub(crate) counter: bool,
+    pub(crate) stability: Stability,
+}
+
+impl Family {
+    /// A stable counter called `name`.
+    pub(crate) const fn counter(name: &'static str) -> Self {
+        Self {
+            name,
+            unit: None,
+            counter: true,
+            stability: Stability::Stable,
+        }
+    }
+
+    /// A stable gauge called `name`.
+    pub(crate) const fn gauge(name: &'static str) -> Self {
+        Self {
+            counter: false,
+            ..Self::counter(name)
+        }
+    }
+
+    /// Set the unit of the family.
+    pub(crate) const fn unit(self, unit: &'static str) -> Self {
+        Self {
+            unit: Some(unit),
+            ..self
+        }
+    }
+
+    /// Set the stability of the family.
+    pub(crate) const fn stability(self, stability: Stability) -> Self {
+        Self { stability, ..self }
+    }
+}
+
+/// A family renamed from `previous` to `name`, exported under both names
+/// while the previous name is kept for compatibility.
+pub(crate) struct Renamed {
+    pub(crate) previous: &'static str,
+    pub(crate) name: &'static str,
+}
+
+/// The parts of a name, concatenated.
+type Parts = [&'static str; 4];
+
+/// The number of names `family` exports.
+const fn variants(family: &Family) -> usize {
+    if family.counter {
+        4
+    } else {
+        1
+    }
+}
+
+/// The parts of the `variant`th name of `family`.
+const fn parts(family: &Family, variant: usize) -> Parts {
+    let (separator, unit) = match family.unit {
+        Some(unit) => ("_", unit),
+        None => ("", ""),
+    };
+    match variant {
+        0 => [family.name, separator, unit, ""],
+        1 => [family.name, separator, unit, "_total"],
+        2 => [family.name, separator, unit, "_created"],
+        _ => [family.name, "", "", "_per_second"],
+    }
+}
+
+const fn len(parts: &Parts) -> usize {
+    parts[0].len() + parts[1].len() + parts[2].len() + parts[3].len()
+}
+
+/// The `i`th byte of the concatenation of `parts`.
+const fn byte(parts: &Parts, mut i: usize) -> u8 {
+    let mut part = 0;
+    while i >= parts[part].len() {
+        i -= parts[part].len();
+        part += 1;
+    }
+    parts[part].as_bytes()[i]
+}
+
+/// Whether `parts` match `[a-zA-Z_:][a-zA-Z0-9_:]*`.
+const fn is_valid(parts: &Parts) -> bool {
+    let len = len(parts);
+    if len == 0 {
+        return false;
+    }
+    let mut i = 0;
+    while i < len {
+        let valid = match byte(parts, i) {
+            b'a'..=b'z' | b'A'..=b'Z' | b'_' | b':' => true,
+            b'0'..=b'9' => i > 0,
+            _ => false,
+        };
+        if !valid {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+const fn eq(a: &Parts, b: &Parts) -> bool {
+    let len = len(a);
+    if len != self::len(b) {
+        return false;
+    }
+    let mut i = 0;
+    while i < len {
+        if byte(a, i) != byte(b, i) {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// The longest name a [`Name`] holds.
+const MAX_NAME_LEN: usize = 128;
+
+/// A name concatenated at compile time, so encoding it does not allocate.
+pub(crate) struct Name {
+    bytes: [u8; MAX_NAME_LEN],
+    len: usize,
+}
+
+impl Name {
+    /// Concatenate `parts`.
+    const fn new(parts: &Parts) -> Self {
+        let len = len(parts);
+        assert!(len <= MAX_NAME_LEN, "metric name is too long");
+        let mut bytes = [0; MAX_NAME_LEN];
+        let mut i = 0;
+        while i < len {
+            bytes[i] = byte(parts, i);
+            i += 1;
+        }
+        Self { bytes, len }
+    }
+
+    pub(crate) const fn as_str(&self) -> &str {
+        match std::str::from_utf8(self.bytes.split_at(self.len).0) {
+            Ok(name) => name,
+            Err(_) => panic!("metric name is not UTF-8"),
+        }
+    }
+}
+
+/// The name of the `_created` gauge of the counter called `name` in
+/// `families`, following its unit like the `_created` samples of
+/// OpenMetrics do.
+pub(crate) const fn created(families: &[Family], name: &str) -> Name {
+    match position(families, name) {
+        Some(i) => Name::new(&parts(&families[i], 2)),
+        None => panic!("metric is missing from the families"),
+    }
+}
+
+/// The name of the value samples of the family called `name` in
+/// `families`, the `_total` samples of counters.
+pub(crate) const fn samples(families: &[Family], name: &str) -> Name {
+    match position(families, name) {
+        Some(i) if families[i].counter => Name::new(&parts(&families[i], 1)),
+        Some(i) => Name::new(&parts(&families[i], 0)),
+        None => panic!("metric is missing from the families"),
+    }
+}
+
+/// The family of `families` exporting the family called `exported`, e.g. a
+/// counter for its `_created` family.
+pub(crate) fn exporting<'a>(families: &'a [Family], exported: &str) -> Option<&'a Family> {
+    families.iter().find(|family| {
+        (0..variants(family))
+            // Leave out the `_total` samples, they are not families.
+            .filter(|variant| *variant != 1)
+            .any(|variant| parts(family, variant).concat() == exported)
+    })
+}
+
+/// Whether `a` and `b` are the same.
+const fn same(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() && a[i] == b[i] {
+        i += 1;
+    }
+    i == a.len()
+}
+
+/// The index of the family called `name` in `families`.
+const fn position(families: &[Family], name: &str) -> Option<usize> {
+    let mut i = 0;
+    while i < families.len() {
+        if same(families[i].name, name) {
+            return Some(i);
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Whether `families` has a family called `name`.
+pub(crate) const fn contains(families: &[Family], name: &str) -> bool {
+    position(families, name).is_some()
+}
+
+/// The stability of the family called `name` in `families`.
+pub(crate) const fn stability(families: &[Family], name: &str) -> Stability {
+    match position(families, name) {
+        Some(i) => families[i].stability,
+        None => panic!("metric is missing from the families"),
+    }
+}
+
+/// Panic unless every name `families` export is a valid Prometheus metric
+/// name and no two of them are the same.
+pub(crate) const fn check(families: &[Family]) {
+    let mut i = 0;
+    while i < families.len() {
+        let mut variant = 0;
+        while variant < variants(&families[i]) {
+            let name = parts(&families[i], variant);
+            assert!(is_valid(&name), "invalid metric name");
+
+            // Compare with the names of this and the following families not
+            // compared yet.
+            let mut j = i;
+            let mut other = variant + 1;
+            while j < families.len() {
+                while other < variants(&families[j]) {
+                    assert!(
+                        !eq(&name, &parts(&families[j], other)),
+                        "duplicate metric name"
+                    );
+                    other += 1;
+                }
+                j += 1;
+                other = 0;
+            }
+            variant += 1;
+        }
+        i += 1;
+    }
+}
+
+/// The previous name of the family called `name`, if `renamed` has it.
+pub(crate) const fn previous(renamed: &[Renamed], name: &str) -> Option<&'static str> {
+    let mut i = 0;
+    while i < renamed.len() {
+        if same(renamed[i].name, name) {
+            return Some(renamed[i].previous);
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Panic unless every family of `renamed` is in `families` and its previous
+/// names are valid and not exported by any of `families`.
+pub(crate) const fn check_renamed(families: &[Family], renamed: &[Renamed]) {
+    let mut i = 0;
+    while i < renamed.len() {
+        let Some(position) = position(families, renamed[i].name) else {
+            panic!("renamed metric is missing from the families");
+        };
+        let family = &families[position];
+        let previous = Family {
+            name: renamed[i].previous,
+            unit: family.unit,
+            counter: family.counter,
+            stability: family.stability,
+        };
+        let mut variant = 0;
+        while variant < variants(&previous) {
+            let name = parts(&previous, variant);
+            assert!(is_valid(&name), "invalid metric name");
+            let mut j = 0;
+            while j < families.len() {
+                let mut other = 0;
+                while other < variants(&families[j]) {
+                    assert!(
+                        !eq(&name, &parts(&families[j], other)),
+                        "duplicate metric name"
+                    );
+                    other += 1;
+                }
+                j += 1;
+            }
+            variant += 1;
+        }
+        i += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAMILIES: &[Family] = &[
+        Family::counter("polls"),
+        Family::gauge("busy_duration").unit("seconds"),
+    ];
+
+    const RENAMED: &[Renamed] = &[Renamed {
+        previous: "total_polls",
+        name: "polls",
+    }];
+
+    #[test]
+    fn previous_names_renamed_families() {
+        assert_eq!(previous(RENAMED, "polls"), Some("total_polls"));
+        assert_eq!(previous(RENAMED, "busy_duration"), None);
+        check_renamed(FAMILIES, RENAMED);
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate metric name")]
+    fn previous_names_must_not_be_exported() {
+        check_renamed(
+            FAMILIES,
+            &[Renamed {
+                previous: "busy_duration_seconds",
+                name: "polls",
+            }],
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "renamed metric is missing from the families")]
+    fn renamed_families_must_be_listed() {
+        check_renamed(
+            FAMILIES,
+            &[Renamed {
+                previous: "total_steals",
+                name: "steals",
+            }],
+        );
+    }
+
+    #[test]
+    fn families_export_their_variants() {
+        check(FAMILIES);
+        check(crate::RUNTIME_FAMILIES);
+        assert!(contains(FAMILIES, "polls"));
+        assert!(!contains(FAMILIES, "polls_total"));
+        assert_eq!(
+            exporting(FAMILIES, "polls_created").map(|family| family.name),
+            Some("polls")
+        );
+        assert_eq!(
+            exporting(FAMILIES, "busy_duration_seconds").map(|family| family.name),
+            Some("busy_duration")
+        );
+        // Samples, not families.
+        assert!(exporting(FAMILIES, "polls_total").is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid metric name")]
+    fn names_must_be_valid() {
+        check(&[Family::gauge("9lives")]);
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate metric name")]
+    fn names_must_be_unique() {
+        check(&[Family::counter("polls"), Family::gauge("polls_per_second")]);
+    }
+}
@@ -0,0 +1,196 @@
+//! Register the runtime metrics with an [OpenTelemetry](https://opentelemetry.io)
+//! [`Meter`].
+//!
+//! Enabled with the `opentelemetry` feature. The metrics flow through the
+//! SDK pipeline of the meter, including its resource, views and exporters.
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+
+use ::opentelemetry::metrics::Meter;
+use tokio_metrics::{RuntimeIntervals, RuntimeMonitor};
+
+use crate::{Anomalies, RuntimeMetrics};
+
+/// Register the runtime metrics of `monitor` as asynchronous instruments of
+/// `meter`, with instrument names prefixed with `prefix` and `_`.
+///
+/// Counters are registered as observable counters, the current state of the
+/// runtime as observable gauges. The runtime is sampled once per collection
+/// of the meter.
+///
+/// ## Example
+///
+/// ```
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let handle = tokio::runtime::Handle::current();
+/// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+///
+/// let meter = opentelemetry::global::meter("myapp");
+/// tokio_prometheus_client::opentelemetry::register(runtime_monitor, &meter, "tokio");
+/// # });
+/// ```
+pub fn register(monitor: RuntimeMonitor, meter: &Meter, prefix: &str) {
+    let sampler = Arc::new(Sampler {
+        state: Mutex::new(State {
+            intervals: monitor.intervals(),
+            samples: 0,
+            metrics: RuntimeMetrics::default(),
+        }),
+    });
+
+    // Helper macros to ensure the instrument name is consistent
+    macro_rules! observe {
+        ($field:ident, $instrument:ident, $description:expr $(, $unit:expr)?) => {{
+            let sampler = sampler.clone();
+            let seen = AtomicU64::new(0);
+            meter
+                .$instrument(format!("{prefix}_{}", stringify!($field)))
+                .with_description($description)
+                $(.with_unit($unit))?
+                .with_callback(move |observer| {
+                    observer.observe(sampler.sample(&seen).$field.get(), &[])
+                })
+                .build();
+        }};
+    }
+
+    observe!(
+        workers_count,
+        i64_observable_gauge,
+        "The number of worker threads used by the runtime"
+    );
+    observe!(
+        total_park_count,
+        u64_observable_counter,
+        "The number of times worker threads parked"
+    );
+    observe!(
+        total_noop_count,
+        u64_observable_counter,
+        "The number of times worker threads unparked but performed no work before parking again"
+    );
+    observe!(
+        total_steal_count,
+        u64_observable_counter,
+        "The number of tasks worker threads stole from another worker thread"
+    );
+    observe!(
+        total_steal_operations,
+        u64_observable_counter,
+        "The number of times worker threads stole tasks from another worker thread"
+    );
+    observe!(
+        num_remote_schedules,
+        u64_observable_counter,
+        "The number of tasks scheduled from **outside** of the runtime"
+    );
+    observe!(
+        total_local_schedule_count,
+        u64_observable_counter,
+        "The number of tasks scheduled from worker threads"
+    );
+    observe!(
+        total_overflow_count,
+        u64_observable_counter,
+        "The number of times worker threads saturated their local queues"
+    );
+    observe!(
+        total_polls_count,
+        u64_observable_counter,
+        "The number of tasks that have been polled across all worker threads"
+    );
+    observe!(
+        total_busy_duration,
+        f64_observable_counter,
+        "The amount of time worker threads were busy",
+        "s"
+    );
+    observe!(
+        injection_queue_depth,
+        i64_observable_gauge,
+        "The number of tasks currently scheduled in the runtime's injection queue"
+    );
+    observe!(
+        total_local_queue_depth,
+        i64_observable_gauge,
+        "The total number of tasks currently scheduled in workers' local queues"
+    );
+    observe!(
+        budget_forced_yield_count,
+        u64_observable_counter,
+        "Returns the number of times that tasks have been forced to yield back to the scheduler after exhausting their task budgets"
+    );
+    observe!(
+        io_driver_ready_count,
+        u64_observable_counter,
+        "Returns the number of ready events processed by the runtime’s I/O driver"
+    );
+}
+
+/// Samples the runtime on behalf of all instruments.
+#[derive(Debug)]
+struct Sampler {
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    intervals: RuntimeIntervals,
+    /// The number of samples taken so far.
+    samples: u64,
+    metrics: RuntimeMetrics,
+}
+
+impl Sampler {
+    /// The metrics of the current collection, for an instrument that last
+    /// observed sample `seen`.
+    ///
+    /// The callbacks of a collection run in no particular order, so a new
+    /// sample is taken by the first instrument observing again.
+    fn sample(&self, seen: &AtomicU64) -> RuntimeMetrics {
+        let mut state = self.state.lock().expect("should be able to lock state");
+        if seen.load(Ordering::Relaxed) == state.samples {
+            if let Some(interval) = state.intervals.next() {
+                // Anomalies are only logged, there are no instruments for
+                // them.
+                state.metrics.update(&interval, &mut Anomalies::default());
+            }
+            state.samples += 1;
+        }
+        seen.store(state.samples, Ordering::Relaxed);
+        state.metrics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn instruments_share_the_sample_of_a_collection() {
+        let monitor = RuntimeMonitor::new(&tokio::runtime::Handle::current());
+        let sampler = Sampler {
+            state: Mutex::new(State {
+                intervals: monitor.intervals(),
+                samples: 0,
+                metrics: RuntimeMetrics::default(),
+            }),
+        };
+        let samples = || sampler.state.lock().unwrap().samples;
+        let (workers, polls) = (AtomicU64::new(0), AtomicU64::new(0));
+
+        assert_eq!(sampler.sample(&workers).workers_count.get(), 1);
+        sampler.sample(&polls);
+        assert_eq!(samples(), 1);
+
+        // The first instrument of the next collection samples again.
+        sampler.sample(&polls);
+        assert_eq!(samples(), 2);
+        sampler.sample(&workers);
+        assert_eq!(samples(), 2);
+    }
+}
@@ -0,0 +1,740 @@
+//! Export a [`Registry`] to an [OpenTelemetry](https://opentelemetry.io)
+//! collector using OTLP over HTTP.
+//!
+//! Enabled with the `otlp` feature.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use http::{header::CONTENT_TYPE, HeaderName, HeaderValue, Method, Request};
+use http_body_util::Full;
+use prometheus_client::{metrics::MetricType, registry::Registry};
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+
+pub use crate::push::{PushError, RetryPolicy};
+use crate::{
+    protobuf::{encode_bytes, encode_double, encode_fixed64, encode_uint},
+    push::PushClient,
+    samples::{collect, histogram_points, HistogramPoint, MetricFamily, Sample},
+};
+
+/// Aggregation temporality of exported sums and histograms.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Temporality {
+    /// Export the values accumulated since the exporter was created.
+    #[default]
+    Cumulative,
+    /// Export the increase since the previous export, as required by some
+    /// backends.
+    Delta,
+}
+
+impl Temporality {
+    /// `AggregationTemporality` enum value.
+    fn value(self) -> u64 {
+        match self {
+            Temporality::Delta => 1,
+            Temporality::Cumulative => 2,
+        }
+    }
+}
+
+/// Exports a [`Registry`] as OTLP metrics.
+///
+/// Counters are exported as monotonic sums, gauges, info and unknown metrics
+/// as gauges and histograms as explicit bucket histograms. Sums and
+/// histograms are cumulative unless configured otherwise with
+/// [`Otlp::temporality`].
+///
+/// Constant labels, e.g. those of [`Registry::with_labels`] or
+/// [`EnvLabels`](crate::labels::EnvLabels), describe the process rather
+/// than the data points. Map them to resource attributes with
+/// [`Otlp::resource_label`], or [`Otlp::semantic_resource_labels`] for the
+/// labels of the OpenTelemetry semantic conventions.
+///
+/// ## Example
+///
+/// ```no_run
+/// # use std::{sync::{Arc, Mutex}, time::Duration};
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let handle = tokio::runtime::Handle::current();
+/// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// tokio_prometheus_client::register(runtime_monitor, registry.sub_registry_with_prefix("tokio"));
+///
+/// tokio_prometheus_client::otlp::Otlp::new("http://otel-collector:4318/v1/metrics")
+///     .resource_attribute("service.name", "my-service")
+///     .semantic_resource_labels()
+///     .interval(Duration::from_secs(30))
+///     .spawn(Arc::new(Mutex::new(registry)));
+/// # });
+/// ```
+#[derive(Clone, Debug)]
+pub struct Otlp {
+    url: String,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    resource: Vec<(String, String)>,
+    resource_labels: Vec<(String, String)>,
+    interval: Duration,
+    timeout: Duration,
+    start_time: u64,
+    temporality: Temporality,
+    deltas: Deltas,
+    client: PushClient,
+}
+
+impl Otlp {
+    /// Create an [`Otlp`] exporter sending to the OTLP/HTTP metrics `url`,
+    /// usually ending in `/v1/metrics`.
+    ///
+    /// Exports happen every 15 seconds, each with a timeout of 5 seconds.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            headers: Vec::new(),
+            resource: Vec::new(),
+            resource_labels: Vec::new(),
+            interval: Duration::from_secs(15),
+            timeout: Duration::from_secs(5),
+            start_time: unix_nanos(),
+            temporality: Temporality::default(),
+            deltas: Deltas::default(),
+            client: PushClient::new(),
+        }
+    }
+
+    /// Add a header to every request.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `name` or `value` are not valid header names and values.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((
+            HeaderName::try_from(name).expect("header name should be valid"),
+            HeaderValue::try_from(value).expect("header value should be valid"),
+        ));
+        self
+    }
+
+    /// Add an attribute to the exported resource, e.g. `service.name`.
+    pub fn resource_attribute(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.resource.push((key.into(), value.into()));
+        self
+    }
+
+    /// Export the label `label` as the resource attribute `attribute`
+    /// rather than an attribute of every data point.
+    ///
+    /// The label is only moved while all samples have it with the same
+    /// value, as a constant label does, so data points stay distinct.
+    /// Attributes added with [`Otlp::resource_attribute`] take precedence.
+    pub fn resource_label(
+        mut self,
+        label: impl Into<String>,
+        attribute: impl Into<String>,
+    ) -> Self {
+        self.resource_labels.push((label.into(), attribute.into()));
+        self
+    }
+
+    /// Export the labels with an OpenTelemetry semantic convention as
+    /// resource attributes, see [`Otlp::resource_label`]:
+    ///
+    /// * `job` as `service.name` and `instance` as `service.instance.id`,
+    ///   as OpenTelemetry maps them from Prometheus.
+    /// * `pod`, `namespace` and `node`, as set by
+    ///   [`EnvLabels::kubernetes`](crate::labels::EnvLabels::kubernetes),
+    ///   as `k8s.pod.name`, `k8s.namespace.name` and `k8s.node.name`.
+    /// * `region` as `cloud.region`.
+    pub fn semantic_resource_labels(self) -> Self {
+        self.resource_label("job", "service.name")
+            .resource_label("instance", "service.instance.id")
+            .resource_label("pod", "k8s.pod.name")
+            .resource_label("namespace", "k8s.namespace.name")
+            .resource_label("node", "k8s.node.name")
+            .resource_label("region", "cloud.region")
+    }
+
+    /// Retry failed exports according to `retry`, never retried by default.
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.client = self.client.retry(retry);
+        self
+    }
+
+    /// Register counters of the requests, retries and failures of the
+    /// exports with `registry`.
+    pub fn register_telemetry(&self, registry: &mut Registry) {
+        self.client.register(registry);
+    }
+
+    /// Set the interval between exports of the background task.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Set the timeout of a single export.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set the aggregation temporality of sums and histograms.
+    ///
+    /// With [`Temporality::Delta`] the first export establishes the baseline
+    /// and exports the full values. Counters that were reset are exported
+    /// with their value after the reset. The increase of a failed export is
+    /// included in the next one.
+    pub fn temporality(mut self, temporality: Temporality) -> Self {
+        self.temporality = temporality;
+        self
+    }
+
+    /// Encode `registry` and export all of its metrics.
+    pub async fn export(&self, registry: &Mutex<Registry>) -> Result<(), PushError> {
+        let mut families = collect(&registry.lock().expect("should be able to lock registry"))
+            .map_err(|_| PushError::Encode)?;
+        let time = unix_nanos();
+        let (start_time, baseline) = match self.temporality {
+            Temporality::Cumulative => (self.start_time, None),
+            Temporality::Delta => {
+                let (exported_at, baseline) = self.deltas.apply(&mut families, time);
+                (exported_at.unwrap_or(self.start_time), Some(baseline))
+            }
+        };
+        let resource = self.resource(&mut families);
+        let body = self.export_request(&resource, &families, start_time, time);
+
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri(&self.url)
+            .header(CONTENT_TYPE, "application/x-protobuf");
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        let request = request
+            .body(Full::new(Bytes::from(body)))
+            .map_err(|err| PushError::Request(err.to_string()))?;
+        self.client.send(request, self.timeout).await?;
+        if let Some(baseline) = baseline {
+            self.deltas.commit(baseline);
+        }
+        Ok(())
+    }
+
+    /// Spawn a task exporting `registry` every interval.
+    ///
+    /// Failed exports are logged.
+    pub fn spawn(self, registry: Arc<Mutex<Registry>>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                if let Err(err) = self.export(&registry).await {
+                    tracing::warn!(%err, url = self.url, "failed to export metrics");
+                }
+            }
+        })
+    }
+
+    /// The attributes of the exported resource, moving the labels mapped to
+    /// resource attributes out of the samples of `families`.
+    fn resource(&self, families: &mut [MetricFamily]) -> Vec<(String, String)> {
+        let mut resource = self.resource.clone();
+        for (label, attribute) in &self.resource_labels {
+            if resource.iter().any(|(key, _)| key == attribute) {
+                continue;
+            }
+            let mut values = families
+                .iter()
+                .flat_map(|family| &family.samples)
+                .map(|sample| sample.labels.iter().find(|(key, _)| key == label));
+            let Some(Some((_, value))) = values.next() else {
+                continue;
+            };
+            if !values.all(|other| other.is_some_and(|(_, other)| other == value)) {
+                continue;
+            }
+            resource.push((attribute.clone(), value.clone()));
+            for sample in families.iter_mut().flat_map(|family| &mut family.samples) {
+                sample.labels.retain(|(key, _)| key != label);
+            }
+        }
+        resource
+    }
+
+    /// Encode an `ExportMetricsServiceRequest` protobuf message.
+    fn export_request(
+        &self,
+        attributes: &[(String, String)],
+        families: &[MetricFamily],
+        start_time: u64,
+        time: u64,
+    ) -> Vec<u8> {
+        let mut scope_metrics = Vec::new();
+        let mut scope = Vec::new();
+        encode_bytes(&mut scope, 1, env!("CARGO_PKG_NAME").as_bytes());
+        encode_bytes(&mut scope, 2, env!("CARGO_PKG_VERSION").as_bytes());
+        encode_bytes(&mut scope_metrics, 1, &scope);
+        for family in families {
+            encode_bytes(
+                &mut scope_metrics,
+                2,
+                &self.metric(family, start_time, time),
+            );
+        }
+
+        let mut resource = Vec::new();
+        for (key, value) in attributes {
+            encode_bytes(&mut resource, 1, &key_value(key, value));
+        }
+        let mut resource_metrics = Vec::new();
+        encode_bytes(&mut resource_metrics, 1, &resource);
+        encode_bytes(&mut resource_metrics, 2, &scope_metrics);
+
+        let mut request = Vec::new();
+        encode_bytes(&mut request, 1, &resource_metrics);
+        request
+    }
+
+    /// Encode a `Metric` protobuf message.
+    fn metric(&self, family: &MetricFamily, start_time: u64, time: u64) -> Vec<u8> {
+        let mut metric = Vec::new();
+        encode_bytes(&mut metric, 1, family.name.as_bytes());
+        encode_bytes(&mut metric, 2, family.help.as_bytes());
+        if let Some(unit) = &family.unit {
+            encode_bytes(&mut metric, 3, ucum_unit(unit).as_bytes());
+        }
+
+        let mut data = Vec::new();
+        match family.metric_type {
+            MetricType::Counter => {
+                for sample in family.samples.iter().filter(|s| s.name.ends_with("_total")) {
+                    encode_bytes(
+                        &mut data,
+                        1,
+                        &self.number_data_point(sample, start_time, time),
+                    );
+                }
+                encode_uint(&mut data, 2, self.temporality.value());
+                encode_uint(&mut data, 3, 1);
+                // Sum
+                encode_bytes(&mut metric, 7, &data);
+            }
+            MetricType::Histogram => {
+                for point in histogram_points(&family.samples) {
+                    encode_bytes(
+                        &mut data,
+                        1,
+                        &self.histogram_data_point(&point, start_time, time),
+                    );
+                }
+                encode_uint(&mut data, 2, self.temporality.value());
+                // Histogram
+                encode_bytes(&mut metric, 9, &data);
+            }
+            MetricType::Gauge | MetricType::Info | MetricType::Unknown => {
+                for sample in &family.samples {
+                    encode_bytes(
+                        &mut data,
+                        1,
+                        &self.number_data_point(sample, start_time, time),
+                    );
+                }
+                // Gauge
+                encode_bytes(&mut metric, 5, &data);
+            }
+        }
+        metric
+    }
+
+    /// Encode a `NumberDataPoint` protobuf message.
+    fn number_data_point(&self, sample: &Sample, start_time: u64, time: u64) -> Vec<u8> {
+        let mut point = Vec::new();
+        for (key, value) in &sample.labels {
+            encode_bytes(&mut point, 7, &key_value(key, value));
+        }
+        encode_fixed64(&mut point, 2, start_time);
+        encode_fixed64(&mut point, 3, time);
+        encode_double(&mut point, 4, sample.value);
+        point
+    }
+
+    /// Encode a `HistogramDataPoint` protobuf message.
+    fn histogram_data_point(
+        &self,
+        histogram: &HistogramPoint<'_>,
+        start_time: u64,
+        time: u64,
+    ) -> Vec<u8> {
+        let mut point = Vec::new();
+        for (key, value) in &histogram.labels {
+            encode_bytes(&mut point, 9, &key_value(key, value));
+        }
+        encode_fixed64(&mut point, 2, start_time);
+        encode_fixed64(&mut point, 3, time);
+        encode_fixed64(&mut point, 4, histogram.count as u64);
+        encode_double(&mut point, 5, histogram.sum);
+
+        // OTLP buckets are not cumulative and the +Inf bound is implicit.
+        let mut counts = Vec::new();
+        let mut bounds = Vec::new();
+        let mut previous = 0.0;
+        for (bound, cumulative) in &histogram.buckets {
+            counts.extend_from_slice(&((cumulative - previous).max(0.0) as u64).to_le_bytes());
+            previous = *cumulative;
+            if bound.is_finite() {
+                bounds.extend_from_slice(&bound.to_le_bytes());
+            }
+        }
+        encode_bytes(&mut point, 6, &counts);
+        encode_bytes(&mut point, 7, &bounds);
+        point
+    }
+}
+
+/// Encode a `KeyValue` protobuf message with a string value.
+fn key_value(key: &str, value: &str) -> Vec<u8> {
+    let mut any_value = Vec::new();
+    encode_bytes(&mut any_value, 1, value.as_bytes());
+    let mut key_value = Vec::new();
+    encode_bytes(&mut key_value, 1, key.as_bytes());
+    encode_bytes(&mut key_value, 2, &any_value);
+    key_value
+}
+
+/// Map OpenMetrics units to the UCUM units OpenTelemetry uses.
+fn ucum_unit(unit: &str) -> &str {
+    match unit {
+        "seconds" => "s",
+        "bytes" => "By",
+        "ratio" => "1",
+        "celsius" => "Cel",
+        "grams" => "g",
+        "joules" => "J",
+        "meters" => "m",
+        "volts" => "V",
+        "amperes" => "A",
+        unit => unit,
+    }
+}
+
+fn unix_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+/// State of [`Temporality::Delta`].
+///
+/// Clones of an exporter start with their own baseline.
+#[derive(Debug, Default)]
+struct Deltas(Mutex<DeltaState>);
+
+#[derive(Debug, Default)]
+struct DeltaState {
+    /// Time of the previous export.
+    exported_at: Option<u64>,
+    /// Previous values of sum and histogram samples.
+    previous: HashMap<String, f64>,
+}
+
+impl Clone for Deltas {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+/// The values of an export, the baseline of the next one once the export
+/// succeeded.
+#[derive(Debug)]
+struct Baseline {
+    exported_at: u64,
+    values: Vec<(String, f64)>,
+}
+
+impl Deltas {
+    /// Replace the values of counter and histogram samples with their
+    /// increase since the previous export, returning the time of the
+    /// previous export and the baseline of an export at `time`.
+    fn apply(&self, families: &mut [MetricFamily], time: u64) -> (Option<u64>, Baseline) {
+        let state = self.0.lock().expect("should be able to lock deltas");
+        let mut values = Vec::new();
+        for family in families {
+            if !matches!(
+                family.metric_type,
+                MetricType::Counter | MetricType::Histogram
+            ) {
+                continue;
+            }
+            for sample in &mut family.samples {
+                let key = format!("{}{:?}", sample.name, sample.labels);
+                let last = state.previous.get(&key).copied().unwrap_or(0.0);
+                values.push((key, sample.value));
+                // Counters only go down when they were reset.
+                if sample.value >= last {
+                    sample.value -= last;
+                }
+            }
+        }
+        let baseline = Baseline {
+            exported_at: time,
+            values,
+        };
+        (state.exported_at, baseline)
+    }
+
+    /// Make `baseline` the baseline of the next export.
+    fn commit(&self, baseline: Baseline) {
+        let mut state = self.0.lock().expect("should be able to lock deltas");
+        state.exported_at = Some(baseline.exported_at);
+        state.previous.extend(baseline.values);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry_proto::tonic::{
+        collector::metrics::v1::ExportMetricsServiceRequest,
+        common::v1::{any_value::Value, KeyValue},
+        metrics::v1::{metric::Data, number_data_point::Value as NumberValue, Metric},
+    };
+    use prometheus_client::{
+        metrics::{counter::Counter, family::Family, gauge::Gauge, histogram::Histogram},
+        registry::Unit,
+    };
+    use prost::Message;
+
+    use super::*;
+
+    fn attributes(attributes: &[KeyValue]) -> Vec<(&str, &str)> {
+        attributes
+            .iter()
+            .map(|attribute| {
+                let Some(Value::StringValue(value)) = attribute
+                    .value
+                    .as_ref()
+                    .and_then(|value| value.value.as_ref())
+                else {
+                    panic!("attribute {} should be a string", attribute.key);
+                };
+                (attribute.key.as_str(), value.as_str())
+            })
+            .collect()
+    }
+
+    /// Encode `registry` as exported by `otlp` and decode it again.
+    fn decode(otlp: &Otlp, registry: &Registry) -> ExportMetricsServiceRequest {
+        let mut families = collect(registry).unwrap();
+        let resource = otlp.resource(&mut families);
+        let body = otlp.export_request(&resource, &families, 10, 20);
+        ExportMetricsServiceRequest::decode(body.as_slice()).expect("request should decode")
+    }
+
+    fn metric<'a>(request: &'a ExportMetricsServiceRequest, name: &str) -> &'a Metric {
+        request.resource_metrics[0].scope_metrics[0]
+            .metrics
+            .iter()
+            .find(|metric| metric.name == name)
+            .unwrap_or_else(|| panic!("{name} should be exported"))
+    }
+
+    fn example_registry() -> Registry {
+        let mut registry = Registry::with_labels(
+            [("job".into(), "api".into()), ("zone".into(), "a".into())].into_iter(),
+        );
+        let requests = Family::<Vec<(String, String)>, Counter>::default();
+        requests
+            .get_or_create(&vec![("method".into(), "GET".into())])
+            .inc_by(3);
+        registry.register("requests", "Handled requests", requests);
+        let workers = Gauge::<i64>::default();
+        workers.set(4);
+        registry.register("workers", "Worker threads", workers);
+        let latency = Histogram::new([0.1, 1.0].into_iter());
+        latency.observe(0.05);
+        latency.observe(0.5);
+        latency.observe(5.0);
+        registry.register_with_unit("latency", "Request latency", Unit::Seconds, latency);
+        registry
+    }
+
+    #[test]
+    fn export_request_decodes() {
+        let otlp = Otlp::new("http://localhost:4318/v1/metrics")
+            .resource_attribute("service.version", "1.0")
+            .semantic_resource_labels();
+        let request = decode(&otlp, &example_registry());
+
+        let resource = request.resource_metrics[0].resource.as_ref().unwrap();
+        assert_eq!(
+            attributes(&resource.attributes),
+            [("service.version", "1.0"), ("service.name", "api")],
+        );
+        let scope = request.resource_metrics[0].scope_metrics[0]
+            .scope
+            .as_ref()
+            .unwrap();
+        assert_eq!(scope.name, env!("CARGO_PKG_NAME"));
+
+        let requests = metric(&request, "requests");
+        assert_eq!(requests.description, "Handled requests.");
+        let Some(Data::Sum(sum)) = &requests.data else {
+            panic!("requests should be a sum");
+        };
+        assert!(sum.is_monotonic);
+        assert_eq!(sum.aggregation_temporality, 2);
+        let [point] = sum.data_points.as_slice() else {
+            panic!("requests should have one data point");
+        };
+        assert_eq!(
+            attributes(&point.attributes),
+            [("zone", "a"), ("method", "GET")],
+        );
+        assert_eq!((point.start_time_unix_nano, point.time_unix_nano), (10, 20));
+        assert_eq!(point.value, Some(NumberValue::AsDouble(3.0)));
+
+        let workers = metric(&request, "workers");
+        let Some(Data::Gauge(gauge)) = &workers.data else {
+            panic!("workers should be a gauge");
+        };
+        assert_eq!(gauge.data_points[0].value, Some(NumberValue::AsDouble(4.0)));
+
+        let latency = metric(&request, "latency_seconds");
+        assert_eq!(latency.unit, "s");
+        let Some(Data::Histogram(histogram)) = &latency.data else {
+            panic!("latency should be a histogram");
+        };
+        assert_eq!(histogram.aggregation_temporality, 2);
+        let point = &histogram.data_points[0];
+        assert_eq!(attributes(&point.attributes), [("zone", "a")]);
+        assert_eq!(point.count, 3);
+        assert_eq!(point.sum, Some(5.55));
+        assert_eq!(point.explicit_bounds, [0.1, 1.0]);
+        assert_eq!(point.bucket_counts, [1, 1, 1]);
+    }
+
+    #[test]
+    fn export_request_encodes_delta_temporality() {
+        let otlp = Otlp::new("http://localhost:4318/v1/metrics").temporality(Temporality::Delta);
+        let request = decode(&otlp, &example_registry());
+        let Some(Data::Sum(sum)) = &metric(&request, "requests").data else {
+            panic!("requests should be a sum");
+        };
+        assert_eq!(sum.aggregation_temporality, 1);
+        let Some(Data::Histogram(histogram)) = &metric(&request, "latency_seconds").data else {
+            panic!("latency should be a histogram");
+        };
+        assert_eq!(histogram.aggregation_temporality, 1);
+    }
+
+    fn counter_registry() -> (Counter, Mutex<Registry>) {
+        let counter = Counter::<u64>::default();
+        let mut registry = Registry::default();
+        registry.register("requests", "Handled requests", counter.clone());
+        (counter, Mutex::new(registry))
+    }
+
+    fn requests(deltas: &Deltas, registry: &Mutex<Registry>, time: u64) -> (f64, Baseline) {
+        let mut families = collect(&registry.lock().unwrap()).unwrap();
+        let (_, baseline) = deltas.apply(&mut families, time);
+        (families[0].samples[0].value, baseline)
+    }
+
+    #[test]
+    fn deltas_advance_only_when_committed() {
+        let (counter, registry) = counter_registry();
+        let deltas = Deltas::default();
+        counter.inc_by(5);
+
+        let (value, baseline) = requests(&deltas, &registry, 1);
+        assert_eq!(value, 5.0);
+        assert_eq!(baseline.exported_at, 1);
+        // The export failed, the next one still covers the increase.
+        let (value, baseline) = requests(&deltas, &registry, 2);
+        assert_eq!(value, 5.0);
+        deltas.commit(baseline);
+
+        counter.inc_by(3);
+        let mut families = collect(&registry.lock().unwrap()).unwrap();
+        let (exported_at, _) = deltas.apply(&mut families, 3);
+        assert_eq!(exported_at, Some(2));
+        assert_eq!(families[0].samples[0].value, 3.0);
+    }
+
+    #[test]
+    fn deltas_export_reset_counters_in_full() {
+        let deltas = Deltas::default();
+        let (counter, registry) = counter_registry();
+        counter.inc_by(5);
+        let (_, baseline) = requests(&deltas, &registry, 1);
+        deltas.commit(baseline);
+
+        let (counter, registry) = counter_registry();
+        counter.inc_by(2);
+        let (value, _) = requests(&deltas, &registry, 2);
+        assert_eq!(value, 2.0);
+    }
+
+    #[tokio::test]
+    async fn failed_export_keeps_baseline() {
+        let (counter, registry) = counter_registry();
+        counter.inc_by(5);
+        let otlp = Otlp::new("http://127.0.0.1:1/v1/metrics").temporality(Temporality::Delta);
+        assert!(otlp.export(&registry).await.is_err());
+        let state = otlp.deltas.0.lock().unwrap();
+        assert_eq!(state.exported_at, None);
+        assert!(state.previous.is_empty());
+    }
+
+    #[test]
+    fn resource_labels_move_only_shared_values() {
+        let mut registry = example_registry();
+        let regions = Family::<Vec<(String, String)>, Gauge>::default();
+        for region in ["eu", "us"] {
+            regions
+                .get_or_create(&vec![("region".into(), region.into())])
+                .set(1);
+        }
+        registry.register("regions", "Regions", regions);
+        let otlp = Otlp::new("http://localhost:4318/v1/metrics")
+            .resource_attribute("service.name", "my-service")
+            .semantic_resource_labels()
+            .resource_label("zone", "cloud.availability_zone");
+        let request = decode(&otlp, &registry);
+
+        // `job` is kept as the attribute takes precedence, `region` as its
+        // values differ.
+        let resource = request.resource_metrics[0].resource.as_ref().unwrap();
+        assert_eq!(
+            attributes(&resource.attributes),
+            [
+                ("service.name", "my-service"),
+                ("cloud.availability_zone", "a"),
+            ],
+        );
+        let Some(Data::Gauge(gauge)) = &metric(&request, "regions").data else {
+            panic!("regions should be a gauge");
+        };
+        let mut points: Vec<_> = gauge
+            .data_points
+            .iter()
+            .map(|point| attributes(&point.attributes))
+            .collect();
+        points.sort();
+        assert_eq!(
+            points,
+            [
+                [("job", "api"), ("region", "eu")],
+                [("job", "api"), ("region", "us")],
+            ],
+        );
+    }
+}
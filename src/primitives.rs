@@ -0,0 +1,174 @@
+//! Tracking and encoding of the instrumented primitives of the `fs`,
+//! `io`, `sync`, `time` and `util` modules.
+
+#[cfg(any(feature = "sync", feature = "util"))]
+use std::sync::atomic::AtomicI64;
+#[cfg(any(feature = "sync", feature = "time", feature = "util"))]
+use std::sync::Weak;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+
+#[cfg(any(feature = "io", feature = "sync", feature = "util"))]
+use prometheus_client::metrics::gauge::ConstGauge;
+use prometheus_client::{
+    encoding::{DescriptorEncoder, EncodeMetric},
+    metrics::{counter::ConstCounter, histogram::Histogram},
+    registry::Unit,
+};
+
+use crate::labels::sanitize_value;
+
+/// Track `state` in `states`, returning it.
+#[cfg(any(feature = "sync", feature = "time", feature = "util"))]
+pub(crate) fn track<T>(states: &Mutex<Vec<Weak<T>>>, state: T) -> Arc<T> {
+    let state = Arc::new(state);
+    states
+        .lock()
+        .expect("should be able to lock monitored primitives")
+        .push(Arc::downgrade(&state));
+    state
+}
+
+/// The live states of `states`, pruning dropped ones.
+#[cfg(any(feature = "sync", feature = "time", feature = "util"))]
+pub(crate) fn live<T>(states: &Mutex<Vec<Weak<T>>>) -> Vec<Arc<T>> {
+    let mut states = states
+        .lock()
+        .expect("should be able to lock monitored primitives");
+    states.retain(|state| state.strong_count() > 0);
+    states.iter().filter_map(Weak::upgrade).collect()
+}
+
+/// The state named `name` in `states`, created with `create` from the
+/// label value of `name` on first use.
+///
+/// For primitives counted together per name, e.g. per call site, and kept
+/// for the lifetime of their monitor.
+#[cfg(any(feature = "fs", feature = "io", feature = "time"))]
+pub(crate) fn named<T: Primitive>(
+    states: &Mutex<Vec<Arc<T>>>,
+    name: impl Into<String>,
+    create: impl FnOnce(String) -> T,
+) -> Arc<T> {
+    let name = label_value(name);
+    let mut states = states
+        .lock()
+        .expect("should be able to lock monitored primitives");
+    if let Some(state) = states.iter().find(|state| state.name() == name) {
+        return state.clone();
+    }
+    let state = Arc::new(create(name));
+    states.push(state.clone());
+    state
+}
+
+/// The states of `states` created with [`named`].
+#[cfg(any(feature = "fs", feature = "io", feature = "time"))]
+pub(crate) fn all<T>(states: &Mutex<Vec<Arc<T>>>) -> Vec<Arc<T>> {
+    states
+        .lock()
+        .expect("should be able to lock monitored primitives")
+        .clone()
+}
+
+/// Counts a waiting task in a gauge until dropped, also if its wait is
+/// cancelled.
+#[cfg(any(feature = "sync", feature = "util"))]
+pub(crate) struct Waiting<'a>(&'a AtomicI64);
+
+#[cfg(any(feature = "sync", feature = "util"))]
+impl<'a> Waiting<'a> {
+    pub(crate) fn new(waiters: &'a AtomicI64) -> Self {
+        waiters.fetch_add(1, Ordering::Relaxed);
+        Self(waiters)
+    }
+}
+
+#[cfg(any(feature = "sync", feature = "util"))]
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The label value of the primitive `name`.
+pub(crate) fn label_value(name: impl Into<String>) -> String {
+    let name = name.into();
+    sanitize_value(&name).into_owned()
+}
+
+/// The state of an instrumented primitive.
+pub(crate) trait Primitive {
+    /// The label of the name, the kind of primitive.
+    const LABEL: &'static str;
+
+    fn name(&self) -> &str;
+}
+
+/// Encode a family with the metric `metric` of each of `primitives`,
+/// labeled with its name, unless there are none.
+pub(crate) fn encode_family<P: Primitive, M: EncodeMetric>(
+    encoder: &mut DescriptorEncoder,
+    primitives: &[Arc<P>],
+    name: &str,
+    help: &str,
+    unit: Option<&Unit>,
+    metric: impl Fn(&P) -> M,
+) -> Result<(), std::fmt::Error> {
+    let Some(first) = primitives.first() else {
+        return Ok(());
+    };
+    let metric_type = metric(first).metric_type();
+    let mut family = encoder.encode_descriptor(name, help, unit, metric_type)?;
+    for primitive in primitives {
+        metric(primitive).encode(family.encode_family(&[(P::LABEL, primitive.name())])?)?;
+    }
+    Ok(())
+}
+
+/// Encode a gauge family, see [`encode_family`].
+#[cfg(any(feature = "io", feature = "sync", feature = "util"))]
+pub(crate) fn encode_gauges<P: Primitive>(
+    encoder: &mut DescriptorEncoder,
+    primitives: &[Arc<P>],
+    name: &str,
+    help: &str,
+    value: impl Fn(&P) -> i64,
+) -> Result<(), std::fmt::Error> {
+    encode_family(encoder, primitives, name, help, None, |primitive| {
+        ConstGauge::new(value(primitive))
+    })
+}
+
+/// Encode a counter family, see [`encode_family`].
+pub(crate) fn encode_counters<P: Primitive>(
+    encoder: &mut DescriptorEncoder,
+    primitives: &[Arc<P>],
+    name: &str,
+    help: &str,
+    value: impl Fn(&P) -> &AtomicU64,
+) -> Result<(), std::fmt::Error> {
+    encode_family(encoder, primitives, name, help, None, |primitive| {
+        ConstCounter::new(value(primitive).load(Ordering::Relaxed))
+    })
+}
+
+/// Encode a histogram family of durations, see [`encode_family`].
+pub(crate) fn encode_histograms<P: Primitive>(
+    encoder: &mut DescriptorEncoder,
+    primitives: &[Arc<P>],
+    name: &str,
+    help: &str,
+    histogram: impl Fn(&P) -> &Histogram,
+) -> Result<(), std::fmt::Error> {
+    encode_family(
+        encoder,
+        primitives,
+        name,
+        help,
+        Some(&Unit::Seconds),
+        |primitive| histogram(primitive).clone(),
+    )
+}
@@ -0,0 +1,396 @@
+//! Collect the CPU, memory and file descriptor usage of the process and the
+//! CPU time of runtime workers.
+//!
+//! Enabled with the `process` feature. The metrics are read from `/proc`, so
+//! only Linux is supported. On other platforms nothing is collected.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    thread::ThreadId,
+};
+
+use prometheus_client::{
+    collector::Collector,
+    encoding::{DescriptorEncoder, EncodeMetric},
+    metrics::{counter::ConstCounter, gauge::ConstGauge, MetricType},
+    registry::{Registry, Unit},
+};
+use tokio::runtime::Handle;
+
+/// Register the process collector with a Prometheus [`Registry`].
+///
+/// Registered with the `process` prefix it exposes the standard process
+/// metrics of Prometheus client libraries, `process_cpu_seconds_total`,
+/// `process_resident_memory_bytes`, `process_virtual_memory_bytes`,
+/// `process_open_fds`, `process_max_fds` and `process_start_time_seconds`.
+///
+/// ## Example
+///
+/// ```
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let handle = tokio::runtime::Handle::current();
+/// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// tokio_prometheus_client::register(runtime_monitor, registry.sub_registry_with_prefix("tokio"));
+/// tokio_prometheus_client::process::register(registry.sub_registry_with_prefix("process"));
+/// # });
+/// ```
+pub fn register(registry: &mut Registry) {
+    registry.register_collector(Box::new(ProcessCollector))
+}
+
+/// Collects process metrics from `/proc/self`.
+#[derive(Debug)]
+struct ProcessCollector;
+
+impl Collector for ProcessCollector {
+    fn encode(&self, mut encoder: DescriptorEncoder) -> Result<(), std::fmt::Error> {
+        let Some(process) = Process::read() else {
+            return Ok(());
+        };
+
+        let cpu = ConstCounter::new(process.cpu_seconds);
+        cpu.encode(encoder.encode_descriptor(
+            "cpu",
+            "Total user and system CPU time spent",
+            Some(&Unit::Seconds),
+            cpu.metric_type(),
+        )?)?;
+        let gauges = [
+            (
+                "resident_memory",
+                "Resident memory size",
+                Some(&Unit::Bytes),
+                process.resident_memory_bytes,
+            ),
+            (
+                "virtual_memory",
+                "Virtual memory size",
+                Some(&Unit::Bytes),
+                process.virtual_memory_bytes,
+            ),
+            (
+                "open_fds",
+                "Number of open file descriptors",
+                None,
+                process.open_fds,
+            ),
+            (
+                "max_fds",
+                "Maximum number of open file descriptors",
+                None,
+                process.max_fds,
+            ),
+            (
+                "start_time",
+                "Start time of the process since the Unix epoch",
+                Some(&Unit::Seconds),
+                process.start_time_seconds,
+            ),
+        ];
+        for (name, help, unit, value) in gauges {
+            let Some(value) = value else {
+                continue;
+            };
+            let gauge = ConstGauge::new(value);
+            gauge.encode(encoder.encode_descriptor(name, help, unit, gauge.metric_type())?)?;
+        }
+        Ok(())
+    }
+}
+
+/// Usage of the process, as far as it could be read.
+#[derive(Debug)]
+struct Process {
+    cpu_seconds: f64,
+    resident_memory_bytes: Option<f64>,
+    virtual_memory_bytes: Option<f64>,
+    open_fds: Option<f64>,
+    max_fds: Option<f64>,
+    start_time_seconds: Option<f64>,
+}
+
+impl Process {
+    #[cfg(target_os = "linux")]
+    fn read() -> Option<Self> {
+        let stat = Stat::read("/proc/self/stat")?;
+        let ticks = clock_ticks();
+        // SAFETY: `sysconf` has no preconditions.
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as f64;
+
+        let boot_time = std::fs::read_to_string("/proc/stat").ok().and_then(|stat| {
+            stat.lines()
+                .find_map(|line| line.strip_prefix("btime "))
+                .and_then(|btime| btime.trim().parse::<f64>().ok())
+        });
+        let open_fds = std::fs::read_dir("/proc/self/fd")
+            .ok()
+            .map(|fds| fds.count() as f64);
+        let max_fds = std::fs::read_to_string("/proc/self/limits")
+            .ok()
+            .and_then(|limits| {
+                limits
+                    .lines()
+                    .find_map(|line| line.strip_prefix("Max open files"))
+                    .and_then(|limit| limit.split_whitespace().next()?.parse().ok())
+            });
+
+        Some(Self {
+            cpu_seconds: (stat.field(14)? + stat.field(15)?) / ticks,
+            resident_memory_bytes: stat.field(24).map(|pages| pages * page_size),
+            virtual_memory_bytes: stat.field(23),
+            open_fds,
+            max_fds,
+            start_time_seconds: boot_time
+                .zip(stat.field(22))
+                .map(|(boot, start)| boot + start / ticks),
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn read() -> Option<Self> {
+        None
+    }
+}
+
+/// Tracks the threads of a runtime to collect the CPU time of its workers.
+///
+/// The runtime has to be built with the hooks of
+/// [`WorkerThreads::on_thread_start`] and [`WorkerThreads::on_thread_stop`].
+/// Comparing the CPU time of a worker with its
+/// `worker_busy_duration` tells whether busy workers were computing or
+/// blocked in system calls.
+///
+/// ## Example
+///
+/// ```
+/// let workers = tokio_prometheus_client::process::WorkerThreads::new();
+/// let rt = tokio::runtime::Builder::new_multi_thread()
+///     .on_thread_start(workers.on_thread_start())
+///     .on_thread_stop(workers.on_thread_stop())
+///     .build()
+///     .unwrap();
+///
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// workers.register(rt.handle().clone(), registry.sub_registry_with_prefix("tokio"));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct WorkerThreads {
+    /// Kernel thread ids of the threads of the runtime.
+    threads: Arc<Mutex<HashMap<ThreadId, i32>>>,
+}
+
+impl WorkerThreads {
+    /// Create a [`WorkerThreads`] without any threads.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hook for [`Builder::on_thread_start`](tokio::runtime::Builder::on_thread_start)
+    /// recording the started thread.
+    pub fn on_thread_start(&self) -> impl Fn() + Send + Sync + 'static {
+        let threads = self.threads.clone();
+        move || {
+            #[cfg(target_os = "linux")]
+            threads
+                .lock()
+                .expect("should be able to lock threads")
+                // SAFETY: `gettid` has no preconditions.
+                .insert(std::thread::current().id(), unsafe { libc::gettid() });
+            #[cfg(not(target_os = "linux"))]
+            let _ = &threads;
+        }
+    }
+
+    /// Hook for [`Builder::on_thread_stop`](tokio::runtime::Builder::on_thread_stop)
+    /// forgetting the stopped thread.
+    pub fn on_thread_stop(&self) -> impl Fn() + Send + Sync + 'static {
+        let threads = self.threads.clone();
+        move || {
+            threads
+                .lock()
+                .expect("should be able to lock threads")
+                .remove(&std::thread::current().id());
+        }
+    }
+
+    /// Register the collector of the worker CPU times of the runtime of
+    /// `handle` with `registry`.
+    ///
+    /// Exposes `worker_cpu_seconds_total` labeled with the `worker` index and
+    /// the `mode`, `user` or `system`. Blocking threads of the runtime are not
+    /// collected.
+    pub fn register(&self, handle: Handle, registry: &mut Registry) {
+        registry.register_collector(Box::new(WorkerCpuCollector {
+            threads: self.threads.clone(),
+            handle,
+        }))
+    }
+}
+
+/// Collects the CPU time of the workers in [`WorkerThreads`].
+#[derive(Debug)]
+struct WorkerCpuCollector {
+    threads: Arc<Mutex<HashMap<ThreadId, i32>>>,
+    handle: Handle,
+}
+
+impl Collector for WorkerCpuCollector {
+    fn encode(&self, mut encoder: DescriptorEncoder) -> Result<(), std::fmt::Error> {
+        let metrics = self.handle.metrics();
+        let threads = self.threads.lock().expect("should be able to lock threads");
+        let mut workers = Vec::new();
+        for worker in 0..metrics.num_workers() {
+            let Some(tid) = metrics
+                .worker_thread_id(worker)
+                .and_then(|thread| threads.get(&thread))
+            else {
+                continue;
+            };
+            if let Some((user, system)) = thread_cpu_seconds(*tid) {
+                workers.push((worker.to_string(), user, system));
+            }
+        }
+        drop(threads);
+
+        let mut family = encoder.encode_descriptor(
+            "worker_cpu",
+            "CPU time spent by worker threads",
+            Some(&Unit::Seconds),
+            MetricType::Counter,
+        )?;
+        for (worker, user, system) in &workers {
+            for (mode, seconds) in [("user", user), ("system", system)] {
+                let labels = [("worker", worker.as_str()), ("mode", mode)];
+                ConstCounter::new(*seconds).encode(family.encode_family(&labels)?)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// User and system CPU time of the thread with the kernel thread id `tid`.
+#[cfg(target_os = "linux")]
+fn thread_cpu_seconds(tid: i32) -> Option<(f64, f64)> {
+    let stat = Stat::read(&format!("/proc/self/task/{tid}/stat"))?;
+    let ticks = clock_ticks();
+    Some((stat.field(14)? / ticks, stat.field(15)? / ticks))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn thread_cpu_seconds(_tid: i32) -> Option<(f64, f64)> {
+    None
+}
+
+/// Fields of a `/proc` `stat` file.
+#[cfg(target_os = "linux")]
+struct Stat(Vec<String>);
+
+#[cfg(target_os = "linux")]
+impl Stat {
+    fn read(path: &str) -> Option<Self> {
+        Self::parse(&std::fs::read_to_string(path).ok()?)
+    }
+
+    fn parse(stat: &str) -> Option<Self> {
+        // The command name may contain spaces and parentheses, the fields
+        // following it start with the state.
+        let fields = stat.get(stat.rfind(')')? + 2..)?;
+        Some(Self(fields.split(' ').map(str::to_string).collect()))
+    }
+
+    /// The numeric field `number`, counting from 1 as in `proc(5)`.
+    fn field(&self, number: usize) -> Option<f64> {
+        self.0.get(number.checked_sub(3)?)?.trim().parse().ok()
+    }
+}
+
+/// Clock ticks per second of the times in `stat` files.
+#[cfg(target_os = "linux")]
+fn clock_ticks() -> f64 {
+    // SAFETY: `sysconf` has no preconditions.
+    unsafe { libc::sysconf(libc::_SC_CLK_TCK) as f64 }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    fn encoded(registry: &Registry) -> String {
+        let mut text = String::new();
+        prometheus_client::encoding::text::encode(&mut text, registry).unwrap();
+        text
+    }
+
+    #[test]
+    fn stat_fields_follow_the_command() {
+        let stat =
+            Stat::parse("42 (tokio (worker) 1) S 1 42 42 0 -1 4194560 7 0 0 0 25 12\n").unwrap();
+        assert_eq!(stat.field(3), None);
+        assert_eq!(stat.field(4), Some(1.0));
+        assert_eq!(stat.field(14), Some(25.0));
+        assert_eq!(stat.field(15), Some(12.0));
+        assert_eq!(stat.field(16), None);
+        assert!(Stat::parse("42 tokio").is_none());
+    }
+
+    #[test]
+    fn collects_the_process() {
+        let mut registry = Registry::default();
+        register(registry.sub_registry_with_prefix("process"));
+
+        let text = encoded(&registry);
+        for family in [
+            "process_cpu_seconds_total",
+            "process_resident_memory_bytes",
+            "process_virtual_memory_bytes",
+            "process_open_fds",
+            "process_max_fds",
+            "process_start_time_seconds",
+        ] {
+            assert!(text.contains(&format!("\n{family} ")), "{text}");
+        }
+        let open_fds = text
+            .lines()
+            .find_map(|line| line.strip_prefix("process_open_fds "))
+            .unwrap();
+        assert!(open_fds.parse::<f64>().unwrap() > 0.0);
+    }
+
+    #[test]
+    fn collects_cpu_time_of_workers() {
+        let workers = WorkerThreads::new();
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .on_thread_start(workers.on_thread_start())
+            .on_thread_stop(workers.on_thread_stop())
+            .build()
+            .unwrap();
+        // The thread ids of workers are known once they run.
+        runtime.block_on(async {
+            for _ in 0..2 {
+                tokio::spawn(async {}).await.unwrap();
+            }
+        });
+        let mut registry = Registry::default();
+        workers.register(
+            runtime.handle().clone(),
+            registry.sub_registry_with_prefix("tokio"),
+        );
+
+        let text = encoded(&registry);
+        for worker in ["0", "1"] {
+            for mode in ["user", "system"] {
+                let series = format!(
+                    "tokio_worker_cpu_seconds_total{{worker=\"{worker}\",mode=\"{mode}\"}} "
+                );
+                assert!(text.contains(&series), "{text}");
+            }
+        }
+
+        drop(runtime);
+        assert!(workers.threads.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,328 @@
+//! Expose a [`Registry`] through the [prometheus](https://crates.io/crates/prometheus)
+//! crate.
+//!
+//! Enabled with the `prometheus` feature. Lets applications built on the
+//! `prometheus` crate export the runtime metrics without migrating to
+//! `prometheus-client`.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use ::prometheus::{
+    core::{Collector, Desc},
+    proto,
+};
+use prometheus_client::{metrics::MetricType, registry::Registry};
+use tokio_metrics::RuntimeMonitor;
+
+use crate::samples::{collect, histogram_points, MetricFamily};
+
+/// A [`Collector`] of the `prometheus` crate collecting a `prometheus-client`
+/// [`Registry`].
+///
+/// Counters are named after their `_total` samples and info metrics after
+/// their `_info` samples, so the exposed series match the ones
+/// `prometheus-client` exposes. Unknown metrics are collected as gauges.
+///
+/// ## Example
+///
+/// ```
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let handle = tokio::runtime::Handle::current();
+/// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+///
+/// let registry = prometheus::Registry::new();
+/// registry
+///     .register(Box::new(
+///         tokio_prometheus_client::prometheus::PrometheusCollector::runtime(runtime_monitor, "tokio"),
+///     ))
+///     .unwrap();
+/// let families = registry.gather();
+/// assert_eq!(families[0].name(), "tokio_budget_forced_yield_count_total");
+/// # });
+/// ```
+#[derive(Debug)]
+pub struct PrometheusCollector {
+    registry: Arc<Mutex<Registry>>,
+    descs: Vec<Desc>,
+}
+
+impl PrometheusCollector {
+    /// Create a [`PrometheusCollector`] collecting `registry`.
+    ///
+    /// The descriptors the `prometheus` crate requires are taken from the
+    /// metrics registered at this point, which samples any registered
+    /// collectors once. The `prometheus` crate only uses them to check for
+    /// conflicts when registering the collector: metrics registered to
+    /// `registry` afterwards, and label names that were not sampled yet,
+    /// are still collected, but are not checked against the metrics of other
+    /// collectors.
+    pub fn new(registry: Arc<Mutex<Registry>>) -> Self {
+        let families =
+            collect(&registry.lock().expect("should be able to lock registry")).unwrap_or_default();
+        let descs = families
+            .iter()
+            .filter_map(|family| {
+                let (name, _) = name_and_type(family);
+                let mut labels: Vec<String> = Vec::new();
+                for sample in &family.samples {
+                    for (label, _) in &sample.labels {
+                        if !labels.contains(label) && label != "le" {
+                            labels.push(label.clone());
+                        }
+                    }
+                }
+                let help = match family.help.as_str() {
+                    // Descriptors require a help text.
+                    "" => name.clone(),
+                    help => help.to_string(),
+                };
+                Desc::new(name, help, labels, HashMap::new())
+                    .inspect_err(|err| tracing::warn!(%err, "invalid metric descriptor"))
+                    .ok()
+            })
+            .collect();
+        Self { registry, descs }
+    }
+
+    /// Create a [`PrometheusCollector`] collecting the runtime metrics of
+    /// `monitor`, with metric names prefixed with `prefix`.
+    pub fn runtime(monitor: RuntimeMonitor, prefix: &str) -> Self {
+        let mut registry = Registry::with_prefix(prefix);
+        crate::register(monitor, &mut registry);
+        Self::new(Arc::new(Mutex::new(registry)))
+    }
+}
+
+impl Collector for PrometheusCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<proto::MetricFamily> {
+        let registry = self
+            .registry
+            .lock()
+            .expect("should be able to lock registry");
+        let families = match collect(&registry) {
+            Ok(families) => families,
+            Err(err) => {
+                tracing::error!(%err, "failed to encode metrics");
+                return Vec::new();
+            }
+        };
+        drop(registry);
+        families.iter().map(metric_family).collect()
+    }
+}
+
+/// Name and type of `family` in the `prometheus` crate.
+fn name_and_type(family: &MetricFamily) -> (String, proto::MetricType) {
+    match family.metric_type {
+        MetricType::Counter => (format!("{}_total", family.name), proto::MetricType::COUNTER),
+        MetricType::Histogram => (family.name.clone(), proto::MetricType::HISTOGRAM),
+        MetricType::Info => (format!("{}_info", family.name), proto::MetricType::GAUGE),
+        MetricType::Gauge | MetricType::Unknown => (family.name.clone(), proto::MetricType::GAUGE),
+    }
+}
+
+fn metric_family(family: &MetricFamily) -> proto::MetricFamily {
+    let (name, metric_type) = name_and_type(family);
+    let mut metrics = Vec::new();
+    match family.metric_type {
+        MetricType::Histogram => {
+            for point in histogram_points(&family.samples) {
+                let mut histogram = proto::Histogram::default();
+                histogram.set_sample_count(point.count as u64);
+                histogram.set_sample_sum(point.sum);
+                // The `+Inf` bucket is implicit, as in histograms of the
+                // `prometheus` crate.
+                let buckets = point
+                    .buckets
+                    .iter()
+                    .filter(|(bound, _)| bound.is_finite())
+                    .map(|(bound, count)| {
+                        let mut bucket = proto::Bucket::default();
+                        bucket.set_upper_bound(*bound);
+                        bucket.set_cumulative_count(*count as u64);
+                        bucket
+                    })
+                    .collect();
+                histogram.set_bucket(buckets);
+                let mut metric = proto::Metric::from_label(label_pairs(point.labels));
+                metric.set_histogram(histogram);
+                metrics.push(metric);
+            }
+        }
+        MetricType::Counter => {
+            for sample in family.samples.iter().filter(|s| s.name.ends_with("_total")) {
+                let mut counter = proto::Counter::default();
+                counter.set_value(sample.value);
+                let mut metric = proto::Metric::from_label(label_pairs(&sample.labels));
+                metric.set_counter(counter);
+                metrics.push(metric);
+            }
+        }
+        MetricType::Gauge | MetricType::Info | MetricType::Unknown => {
+            for sample in &family.samples {
+                let mut gauge = proto::Gauge::default();
+                gauge.set_value(sample.value);
+                let mut metric = proto::Metric::from_label(label_pairs(&sample.labels));
+                metric.set_gauge(gauge);
+                metrics.push(metric);
+            }
+        }
+    }
+
+    let mut metric_family = proto::MetricFamily::default();
+    metric_family.set_name(name);
+    metric_family.set_help(family.help.clone());
+    metric_family.set_field_type(metric_type);
+    metric_family.set_metric(metrics);
+    metric_family
+}
+
+fn label_pairs<'a>(
+    labels: impl IntoIterator<Item = &'a (String, String)>,
+) -> Vec<proto::LabelPair> {
+    labels
+        .into_iter()
+        .map(|(name, value)| {
+            let mut pair = proto::LabelPair::default();
+            pair.set_name(name.clone());
+            pair.set_value(value.clone());
+            pair
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus_client::metrics::{
+        counter::Counter, family::Family, gauge::Gauge, histogram::Histogram, info::Info,
+    };
+
+    use super::*;
+
+    fn labels(metric: &proto::Metric) -> Vec<(&str, &str)> {
+        metric
+            .get_label()
+            .iter()
+            .map(|pair| (pair.name(), pair.value()))
+            .collect()
+    }
+
+    /// Gather `registry` through a `prometheus` registry.
+    fn gather(registry: Registry) -> Vec<proto::MetricFamily> {
+        let collector = PrometheusCollector::new(Arc::new(Mutex::new(registry)));
+        let registry = ::prometheus::Registry::new();
+        registry.register(Box::new(collector)).unwrap();
+        registry.gather()
+    }
+
+    #[test]
+    fn gathers_counters() {
+        let requests = Family::<Vec<(String, String)>, Counter>::default();
+        requests
+            .get_or_create(&vec![("method".to_string(), "GET".to_string())])
+            .inc_by(3);
+        requests
+            .get_or_create(&vec![("method".to_string(), "PUT".to_string())])
+            .inc();
+        let mut registry = Registry::default();
+        registry.register("requests", "Handled requests", requests);
+
+        let families = gather(registry);
+        assert_eq!(families.len(), 1);
+        assert_eq!(families[0].name(), "requests_total");
+        assert_eq!(families[0].help(), "Handled requests.");
+        assert_eq!(families[0].get_field_type(), proto::MetricType::COUNTER);
+        let metrics = families[0].get_metric();
+        assert_eq!(metrics.len(), 2);
+        assert_eq!(labels(&metrics[0]), [("method", "GET")]);
+        assert_eq!(metrics[0].get_counter().get_value(), 3.0);
+        assert_eq!(labels(&metrics[1]), [("method", "PUT")]);
+        assert_eq!(metrics[1].get_counter().get_value(), 1.0);
+    }
+
+    #[test]
+    fn gathers_gauges() {
+        let gauge = Gauge::<i64>::default();
+        gauge.set(-4);
+        let mut registry = Registry::default();
+        registry.register("queued", "Queued requests", gauge);
+
+        let families = gather(registry);
+        assert_eq!(families.len(), 1);
+        assert_eq!(families[0].name(), "queued");
+        assert_eq!(families[0].get_field_type(), proto::MetricType::GAUGE);
+        let metrics = families[0].get_metric();
+        assert_eq!(metrics.len(), 1);
+        assert!(labels(&metrics[0]).is_empty());
+        assert_eq!(metrics[0].get_gauge().get_value(), -4.0);
+    }
+
+    #[test]
+    fn gathers_histograms() {
+        let histogram = Histogram::new([0.1, 1.0].into_iter());
+        histogram.observe(0.05);
+        histogram.observe(0.5);
+        histogram.observe(5.0);
+        let mut registry = Registry::default();
+        registry.register("latency", "Request latency", histogram);
+
+        let families = gather(registry);
+        assert_eq!(families.len(), 1);
+        assert_eq!(families[0].name(), "latency");
+        assert_eq!(families[0].get_field_type(), proto::MetricType::HISTOGRAM);
+        let metrics = families[0].get_metric();
+        assert_eq!(metrics.len(), 1);
+        assert!(labels(&metrics[0]).is_empty());
+        let histogram = metrics[0].get_histogram();
+        assert_eq!(histogram.get_sample_count(), 3);
+        assert_eq!(histogram.get_sample_sum(), 5.55);
+        let buckets: Vec<_> = histogram
+            .get_bucket()
+            .iter()
+            .map(|bucket| (bucket.upper_bound(), bucket.cumulative_count()))
+            .collect();
+        assert_eq!(buckets, [(0.1, 1), (1.0, 2)]);
+    }
+
+    #[test]
+    fn gathers_info() {
+        let info = Info::new(vec![("version".to_string(), "1.2.3".to_string())]);
+        let mut registry = Registry::default();
+        registry.register("build", "Build information", info);
+
+        let families = gather(registry);
+        assert_eq!(families.len(), 1);
+        assert_eq!(families[0].name(), "build_info");
+        assert_eq!(families[0].get_field_type(), proto::MetricType::GAUGE);
+        let metrics = families[0].get_metric();
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(labels(&metrics[0]), [("version", "1.2.3")]);
+        assert_eq!(metrics[0].get_gauge().get_value(), 1.0);
+    }
+
+    #[test]
+    fn gathers_metrics_registered_later() {
+        let registry = Arc::new(Mutex::new(Registry::default()));
+        let collector = PrometheusCollector::new(registry.clone());
+        assert!(collector.desc().is_empty());
+        let gauge = Gauge::<i64>::default();
+        gauge.set(1);
+        registry
+            .lock()
+            .unwrap()
+            .register("late", "Registered late", gauge);
+
+        let families = collector.collect();
+        assert_eq!(families.len(), 1);
+        assert_eq!(families[0].name(), "late");
+        assert_eq!(families[0].get_metric()[0].get_gauge().get_value(), 1.0);
+    }
+}
@@ -0,0 +1,44 @@
+//! Minimal protobuf wire format encoding for the handful of messages the
+//! push based exporters send.
+
+/// Append a field key.
+pub(crate) fn encode_key(buf: &mut Vec<u8>, field: u64, wire_type: u64) {
+    encode_varint(buf, field << 3 | wire_type);
+}
+
+/// Append a length delimited field, used for strings, bytes, embedded
+/// messages and packed repeated fields.
+pub(crate) fn encode_bytes(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    encode_key(buf, field, 2);
+    encode_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+/// Append a `double` field.
+pub(crate) fn encode_double(buf: &mut Vec<u8>, field: u64, value: f64) {
+    encode_key(buf, field, 1);
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Append a `fixed64` field.
+#[cfg(feature = "otlp")]
+pub(crate) fn encode_fixed64(buf: &mut Vec<u8>, field: u64, value: u64) {
+    encode_key(buf, field, 1);
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Append a varint encoded field, used for `int64`, `uint64`, `bool` and
+/// enums.
+pub(crate) fn encode_uint(buf: &mut Vec<u8>, field: u64, value: u64) {
+    encode_key(buf, field, 0);
+    encode_varint(buf, value);
+}
+
+/// Append a raw varint.
+pub(crate) fn encode_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
@@ -0,0 +1,499 @@
+//! Plumbing shared by the push based exporters.
+
+use std::{
+    collections::hash_map::RandomState,
+    fmt,
+    hash::{BuildHasher, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use bytes::Bytes;
+use http::Request;
+use http_body_util::Full;
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client},
+    rt::TokioExecutor,
+};
+use prometheus_client::{
+    collector::Collector,
+    encoding::{DescriptorEncoder, EncodeMetric},
+    metrics::counter::ConstCounter,
+    registry::Registry,
+};
+
+#[cfg(any(feature = "pushgateway", feature = "remote-write"))]
+use crate::samples::MetricFamily;
+
+/// Add `labels` to every sample of `families`.
+///
+/// Like a Prometheus scrape without `honor_labels`, labels the samples
+/// already have are renamed to `exported_<name>` so the added labels win.
+/// With `honor_labels` the labels the samples already have are kept
+/// instead.
+#[cfg(any(feature = "pushgateway", feature = "remote-write"))]
+pub(crate) fn add_labels(
+    families: &mut [MetricFamily],
+    labels: &[(String, String)],
+    honor_labels: bool,
+) {
+    if labels.is_empty() {
+        return;
+    }
+    for sample in families.iter_mut().flat_map(|family| &mut family.samples) {
+        for (name, value) in labels {
+            match sample.labels.iter_mut().find(|(label, _)| label == name) {
+                Some(_) if honor_labels => {}
+                Some((label, _)) => {
+                    *label = format!("exported_{label}");
+                    sample.labels.push((name.clone(), value.clone()));
+                }
+                None => sample.labels.push((name.clone(), value.clone())),
+            }
+        }
+    }
+}
+
+/// How failed pushes are retried.
+///
+/// Pushes failing to connect, timing out or answered with a `429` or `5xx`
+/// status are retried after an exponential backoff, other failures are
+/// not. A transient outage of the receiver then does not drop the only
+/// metrics a short lived job ever pushes.
+///
+/// ## Example
+///
+/// ```
+/// # use std::time::Duration;
+/// use tokio_prometheus_client::pushgateway::{Pushgateway, RetryPolicy};
+///
+/// let pushgateway = Pushgateway::new("http://pushgateway:9091", "batch").retry(
+///     RetryPolicy::new()
+///         .max_attempts(5)
+///         .backoff(Duration::from_millis(200), Duration::from_secs(5))
+///         .on_failure(|err| eprintln!("giving up pushing metrics: {err}")),
+/// );
+/// ```
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    jitter: f64,
+    on_failure: Option<OnFailure>,
+}
+
+/// Called with the error of a push that failed for good.
+type OnFailure = Arc<dyn Fn(&PushError) + Send + Sync>;
+
+impl RetryPolicy {
+    /// Create a [`RetryPolicy`] making up to 3 attempts, backing off from
+    /// 100ms, doubling up to 10s, with half of each backoff jittered.
+    pub fn new() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            jitter: 0.5,
+            on_failure: None,
+        }
+    }
+
+    /// Create a [`RetryPolicy`] making a single attempt, the default of the
+    /// exporters.
+    pub fn none() -> Self {
+        Self::new().max_attempts(1)
+    }
+
+    /// Set the number of attempts of a push, including the first one.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Set the backoff before the first retry, doubled for every following
+    /// one up to `max`.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Set the fraction of each backoff, between 0 and 1, that is randomly
+    /// cut, so replicas failing together do not retry together.
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Call `on_failure` with the error of every push that failed for good.
+    pub fn on_failure(mut self, on_failure: impl Fn(&PushError) + Send + Sync + 'static) -> Self {
+        self.on_failure = Some(Arc::new(on_failure));
+        self
+    }
+
+    /// The backoff before the `retry`th retry, counting from 1.
+    fn backoff_before(&self, retry: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry - 1))
+            .min(self.max_backoff);
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u32(retry);
+        // Uniform in [0, 1).
+        let random = (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64;
+        backoff.mul_f64(1.0 - self.jitter * random)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("initial_backoff", &self.initial_backoff)
+            .field("max_backoff", &self.max_backoff)
+            .field("jitter", &self.jitter)
+            .finish_non_exhaustive()
+    }
+}
+
+/// HTTP(S) client used to send pushes.
+#[derive(Clone, Debug)]
+pub(crate) struct PushClient {
+    client: Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+    retry: RetryPolicy,
+    stats: Arc<PushStats>,
+}
+
+/// Counts of the pushes of a [`PushClient`].
+#[derive(Debug, Default)]
+struct PushStats {
+    requests: AtomicU64,
+    retries: AtomicU64,
+    failures: AtomicU64,
+}
+
+impl PushClient {
+    pub(crate) fn new() -> Self {
+        let connector = HttpsConnectorBuilder::new()
+            .with_webpki_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+        Self {
+            client: Client::builder(TokioExecutor::new()).build(connector),
+            retry: RetryPolicy::none(),
+            stats: Arc::default(),
+        }
+    }
+
+    /// Retry failed pushes according to `retry`.
+    pub(crate) fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Register the counters of the pushes with `registry`.
+    pub(crate) fn register(&self, registry: &mut Registry) {
+        registry.register_collector(Box::new(PushCollector {
+            stats: self.stats.clone(),
+        }));
+    }
+
+    /// Send `request`, retrying it according to the retry policy, failing
+    /// unless a success status is returned within `timeout`.
+    pub(crate) async fn send(
+        &self,
+        request: Request<Full<Bytes>>,
+        timeout: Duration,
+    ) -> Result<(), PushError> {
+        let mut retries = 0;
+        loop {
+            self.stats.requests.fetch_add(1, Ordering::Relaxed);
+            let err = match self.send_once(copy(&request), timeout).await {
+                Ok(()) => return Ok(()),
+                Err(err) => err,
+            };
+            if retries + 1 < self.retry.max_attempts && err.is_transient() {
+                retries += 1;
+                self.stats.retries.fetch_add(1, Ordering::Relaxed);
+                let backoff = self.retry.backoff_before(retries);
+                tracing::debug!(%err, retries, ?backoff, "retrying push");
+                tokio::time::sleep(backoff).await;
+                continue;
+            }
+            self.stats.failures.fetch_add(1, Ordering::Relaxed);
+            if let Some(on_failure) = &self.retry.on_failure {
+                on_failure(&err);
+            }
+            return Err(err);
+        }
+    }
+
+    async fn send_once(
+        &self,
+        request: Request<Full<Bytes>>,
+        timeout: Duration,
+    ) -> Result<(), PushError> {
+        let response = tokio::time::timeout(timeout, self.client.request(request))
+            .await
+            .map_err(|_| PushError::Timeout)?
+            .map_err(|err| PushError::Request(err.to_string()))?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(PushError::Status(response.status().as_u16()))
+        }
+    }
+}
+
+/// A copy of `request`, to send it again.
+fn copy(request: &Request<Full<Bytes>>) -> Request<Full<Bytes>> {
+    let mut copy = Request::new(request.body().clone());
+    *copy.method_mut() = request.method().clone();
+    *copy.uri_mut() = request.uri().clone();
+    *copy.version_mut() = request.version();
+    *copy.headers_mut() = request.headers().clone();
+    copy
+}
+
+/// Collects the counts of the pushes of a [`PushClient`].
+#[derive(Debug)]
+struct PushCollector {
+    stats: Arc<PushStats>,
+}
+
+impl Collector for PushCollector {
+    fn encode(&self, mut encoder: DescriptorEncoder) -> Result<(), std::fmt::Error> {
+        let counters = [
+            (
+                "push_requests",
+                "The number of push requests sent, including retries",
+                &self.stats.requests,
+            ),
+            (
+                "push_retries",
+                "The number of push requests retried after a transient failure",
+                &self.stats.retries,
+            ),
+            (
+                "push_failures",
+                "The number of pushes that failed after all attempts",
+                &self.stats.failures,
+            ),
+        ];
+        for (name, help, count) in counters {
+            let counter = ConstCounter::new(count.load(Ordering::Relaxed));
+            counter.encode(encoder.encode_descriptor(
+                name,
+                help,
+                None,
+                counter.metric_type(),
+            )?)?;
+        }
+        Ok(())
+    }
+}
+
+/// Error returned when pushing metrics fails.
+#[derive(Debug)]
+pub enum PushError {
+    /// The registry could not be encoded.
+    Encode,
+    /// The request could not be sent.
+    Request(String),
+    /// The push did not complete within the timeout.
+    Timeout,
+    /// The receiver responded with a non success status code.
+    Status(u16),
+}
+
+impl fmt::Display for PushError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PushError::Encode => write!(f, "failed to encode metrics"),
+            PushError::Request(err) => write!(f, "failed to send push request: {err}"),
+            PushError::Timeout => write!(f, "push timed out"),
+            PushError::Status(status) => write!(f, "push failed with status {status}"),
+        }
+    }
+}
+
+impl PushError {
+    /// Whether sending the push again may succeed.
+    fn is_transient(&self) -> bool {
+        match self {
+            PushError::Encode => false,
+            PushError::Request(_) | PushError::Timeout => true,
+            PushError::Status(status) => *status == 429 || *status >= 500,
+        }
+    }
+}
+
+impl std::error::Error for PushError {}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    /// Answer the requests of one connection each with the next of
+    /// `statuses`, closing the connection after each answer.
+    async fn answer(listener: TcpListener, statuses: &[u16]) {
+        for status in statuses {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                let mut buf = [0; 1024];
+                let read = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..read]);
+            }
+            let response = format!(
+                "HTTP/1.1 {status} Status\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+        }
+    }
+
+    fn request(listener: &TcpListener) -> Request<Full<Bytes>> {
+        Request::builder()
+            .uri(format!("http://{}/", listener.local_addr().unwrap()))
+            .body(Full::default())
+            .unwrap()
+    }
+
+    fn counts(client: &PushClient) -> String {
+        let mut registry = Registry::default();
+        client.register(&mut registry);
+        let mut text = String::new();
+        prometheus_client::encoding::text::encode(&mut text, &registry).unwrap();
+        text.lines()
+            .filter(|line| !line.starts_with('#'))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max_with_jitter() {
+        let retry = RetryPolicy::new()
+            .backoff(Duration::from_millis(100), Duration::from_millis(300))
+            .jitter(0.5);
+        for (retry_number, max) in [(1, 100), (2, 200), (3, 300), (10, 300)] {
+            let backoff = retry.backoff_before(retry_number);
+            let max = Duration::from_millis(max);
+            assert!(backoff <= max && backoff >= max / 2, "{backoff:?}");
+        }
+        let retry = retry.jitter(0.0);
+        assert_eq!(retry.backoff_before(2), Duration::from_millis(200));
+        assert_eq!(RetryPolicy::new().max_attempts(0).max_attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn retries_transient_failures() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let request = request(&listener);
+        let answered = tokio::spawn(async move { answer(listener, &[503, 429, 204]).await });
+        let client = PushClient::new().retry(
+            RetryPolicy::new()
+                .max_attempts(3)
+                .backoff(Duration::from_millis(1), Duration::from_millis(1)),
+        );
+
+        client.send(request, Duration::from_secs(5)).await.unwrap();
+        answered.await.unwrap();
+        assert_eq!(
+            counts(&client),
+            "push_requests_total 3 push_retries_total 2 push_failures_total 0"
+        );
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_permanent_failures() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let request = request(&listener);
+        let answered = tokio::spawn(async move { answer(listener, &[400]).await });
+        let failures = Arc::new(Mutex::new(Vec::new()));
+        let client = PushClient::new().retry(RetryPolicy::new().max_attempts(3).on_failure({
+            let failures = failures.clone();
+            move |err| failures.lock().unwrap().push(err.to_string())
+        }));
+
+        let err = client
+            .send(request, Duration::from_secs(5))
+            .await
+            .unwrap_err();
+        answered.await.unwrap();
+        assert!(matches!(err, PushError::Status(400)));
+        assert_eq!(*failures.lock().unwrap(), ["push failed with status 400"]);
+        assert_eq!(
+            counts(&client),
+            "push_requests_total 1 push_retries_total 0 push_failures_total 1"
+        );
+    }
+
+    #[cfg(any(feature = "pushgateway", feature = "remote-write"))]
+    #[test]
+    fn add_labels_renames_or_honors_existing_labels() {
+        use prometheus_client::metrics::MetricType;
+
+        use crate::samples::Sample;
+
+        let families = || {
+            vec![MetricFamily {
+                name: "requests".to_string(),
+                help: "Requests".to_string(),
+                unit: None,
+                metric_type: MetricType::Counter,
+                samples: vec![Sample {
+                    name: "requests_total".to_string(),
+                    labels: vec![("instance".to_string(), "pod-1".to_string())],
+                    value: 1.0,
+                }],
+            }]
+        };
+        let labels = [
+            ("job".to_string(), "batch".to_string()),
+            ("instance".to_string(), "worker-1".to_string()),
+        ];
+        let labeled = |honor_labels| {
+            let mut families = families();
+            add_labels(&mut families, &labels, honor_labels);
+            families.remove(0).samples.remove(0).labels
+        };
+        let pairs = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            labeled(false),
+            pairs(&[
+                ("exported_instance", "pod-1"),
+                ("job", "batch"),
+                ("instance", "worker-1"),
+            ])
+        );
+        assert_eq!(
+            labeled(true),
+            pairs(&[("instance", "pod-1"), ("job", "batch")])
+        );
+    }
+}
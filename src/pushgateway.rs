@@ -0,0 +1,215 @@
+//! Push a [`Registry`] to a Prometheus
+//! [Pushgateway](https://github.com/prometheus/pushgateway).
+//!
+//! Enabled with the `pushgateway` feature. Useful for short lived jobs that
+//! finish before they could ever be scraped.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use http::{header::CONTENT_TYPE, Method, Request};
+use http_body_util::Full;
+use prometheus_client::registry::Registry;
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+
+pub use crate::push::{PushError, RetryPolicy};
+use crate::{
+    base64,
+    push::{add_labels, PushClient},
+    samples::{collect, encode_text},
+    TEXT_CONTENT_TYPE,
+};
+
+/// Pushes a [`Registry`] to a Pushgateway grouping key.
+///
+/// ## Example
+///
+/// ```no_run
+/// # use std::{sync::{Arc, Mutex}, time::Duration};
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let handle = tokio::runtime::Handle::current();
+/// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// tokio_prometheus_client::register(runtime_monitor, registry.sub_registry_with_prefix("tokio"));
+/// let registry = Arc::new(Mutex::new(registry));
+///
+/// let pushgateway = tokio_prometheus_client::pushgateway::Pushgateway::new(
+///     "http://pushgateway:9091",
+///     "batch",
+/// )
+/// .instance("worker-1")
+/// .grouping_label("shard", "3")
+/// .interval(Duration::from_secs(10));
+/// let push_loop = pushgateway.clone().spawn(registry.clone());
+///
+/// // ... run the batch job ...
+///
+/// push_loop.abort();
+/// // Push the final values before exiting.
+/// pushgateway.push(&registry).await.unwrap();
+/// # });
+/// ```
+#[derive(Clone, Debug)]
+pub struct Pushgateway {
+    url: String,
+    job: String,
+    grouping: Vec<(String, String)>,
+    honor_labels: bool,
+    interval: Duration,
+    timeout: Duration,
+    client: PushClient,
+}
+
+impl Pushgateway {
+    /// Create a [`Pushgateway`] pushing to the `job` group of the Pushgateway
+    /// at `url`, e.g. `http://pushgateway:9091`.
+    ///
+    /// Pushes happen every 15 seconds, each with a timeout of 5 seconds.
+    pub fn new(url: impl Into<String>, job: impl Into<String>) -> Self {
+        Self {
+            url: url.into().trim_end_matches('/').to_string(),
+            job: job.into(),
+            grouping: Vec::new(),
+            honor_labels: false,
+            interval: Duration::from_secs(15),
+            timeout: Duration::from_secs(5),
+            client: PushClient::new(),
+        }
+    }
+
+    /// Set the `instance` grouping label.
+    pub fn instance(self, instance: impl Into<String>) -> Self {
+        self.grouping_label("instance", instance)
+    }
+
+    /// Add a grouping label.
+    pub fn grouping_label(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.grouping.push((name.into(), value.into()));
+        self
+    }
+
+    /// Whether labels of the pushed samples named like the job or a grouping
+    /// label keep their values, disabled by default.
+    ///
+    /// By default such labels are renamed to `exported_<name>` before
+    /// pushing, like Prometheus does for scrapes without `honor_labels`, so
+    /// every sample carries the labels of its group. Replicas of a batch job
+    /// should push to groups with distinct labels, e.g. with
+    /// [`instance`](Self::instance), or they overwrite each other's metrics.
+    pub fn honor_labels(mut self, honor_labels: bool) -> Self {
+        self.honor_labels = honor_labels;
+        self
+    }
+
+    /// Retry failed pushes according to `retry`, never retried by default.
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.client = self.client.retry(retry);
+        self
+    }
+
+    /// Register counters of the requests, retries and failures of the
+    /// pushes with `registry`.
+    pub fn register_telemetry(&self, registry: &mut Registry) {
+        self.client.register(registry);
+    }
+
+    /// Set the interval between pushes of the background task.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Set the timeout of a single push.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// URL of the grouping key pushes are sent to.
+    pub fn grouping_url(&self) -> String {
+        let mut url = format!("{}/metrics", self.url);
+        for (name, value) in std::iter::once(("job", self.job.as_str()))
+            .chain(self.grouping.iter().map(|(n, v)| (n.as_str(), v.as_str())))
+        {
+            push_path_segment(&mut url, name, value);
+        }
+        url
+    }
+
+    /// Encode `registry` and replace the metrics of the grouping key with it.
+    pub async fn push(&self, registry: &Mutex<Registry>) -> Result<(), PushError> {
+        let mut families = collect(&registry.lock().expect("should be able to lock registry"))
+            .map_err(|_| PushError::Encode)?;
+        if !self.honor_labels {
+            let mut grouping = vec![("job".to_string(), self.job.clone())];
+            grouping.extend(self.grouping.iter().cloned());
+            add_labels(&mut families, &grouping, false);
+        }
+        let mut body = String::new();
+        encode_text(&mut body, &families).map_err(|_| PushError::Encode)?;
+
+        let request = Request::builder()
+            .method(Method::PUT)
+            .uri(self.grouping_url())
+            .header(CONTENT_TYPE, TEXT_CONTENT_TYPE)
+            .body(Full::new(Bytes::from(body)))
+            .map_err(|err| PushError::Request(err.to_string()))?;
+        self.client.send(request, self.timeout).await
+    }
+
+    /// Spawn a task pushing `registry` every interval.
+    ///
+    /// Failed pushes are logged and retried on the next interval.
+    pub fn spawn(self, registry: Arc<Mutex<Registry>>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                if let Err(err) = self.push(&registry).await {
+                    tracing::warn!(%err, url = self.grouping_url(), "failed to push metrics");
+                }
+            }
+        })
+    }
+}
+
+/// Append `/{name}/{value}` to `url`, base64 encoding values that are not
+/// safe to use in a path segment as described in the Pushgateway docs.
+fn push_path_segment(url: &mut String, name: &str, value: &str) {
+    let safe = !value.is_empty()
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~'));
+    if safe {
+        url.push_str(&format!("/{name}/{value}"));
+    } else if value.is_empty() {
+        // The Pushgateway represents empty values as a single padding char.
+        url.push_str(&format!("/{name}@base64/="));
+    } else {
+        let value = base64::encode_url_safe(value.as_bytes());
+        url.push_str(&format!("/{name}@base64/{value}"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grouping_url_encodes_unsafe_values() {
+        let pushgateway = Pushgateway::new("http://pushgateway:9091/", "batch")
+            .instance("worker-1")
+            .grouping_label("path", "/var/tmp")
+            .grouping_label("shard", "");
+        assert_eq!(
+            pushgateway.grouping_url(),
+            "http://pushgateway:9091/metrics/job/batch/instance/worker-1\
+             /path@base64/L3Zhci90bXA/shard@base64/="
+        );
+    }
+}
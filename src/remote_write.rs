@@ -0,0 +1,384 @@
+//! Push a [`Registry`] to a Prometheus
+//! [remote write](https://prometheus.io/docs/concepts/remote_write_spec/)
+//! endpoint such as Mimir, Thanos or Grafana Cloud.
+//!
+//! Enabled with the `remote-write` feature.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use http::{
+    header::{AUTHORIZATION, CONTENT_ENCODING, CONTENT_TYPE},
+    HeaderName, HeaderValue, Method, Request,
+};
+use http_body_util::Full;
+use prometheus_client::registry::Registry;
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+
+pub use crate::push::{PushError, RetryPolicy};
+use crate::{
+    base64,
+    protobuf::{encode_bytes, encode_double, encode_uint},
+    push::{add_labels, PushClient},
+    samples::{collect, MetricFamily},
+};
+
+/// Writes a [`Registry`] to a remote write endpoint.
+///
+/// ## Example
+///
+/// ```no_run
+/// # use std::{sync::{Arc, Mutex}, time::Duration};
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let handle = tokio::runtime::Handle::current();
+/// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// tokio_prometheus_client::register(runtime_monitor, registry.sub_registry_with_prefix("tokio"));
+///
+/// tokio_prometheus_client::remote_write::RemoteWrite::new("https://mimir.example.com/api/v1/push")
+///     .basic_auth("tenant", "secret")
+///     .job("batch")
+///     .instance("worker-1")
+///     .interval(Duration::from_secs(30))
+///     .spawn(Arc::new(Mutex::new(registry)));
+/// # });
+/// ```
+#[derive(Clone, Debug)]
+pub struct RemoteWrite {
+    url: String,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    labels: Vec<(String, String)>,
+    honor_labels: bool,
+    interval: Duration,
+    timeout: Duration,
+    client: PushClient,
+}
+
+impl RemoteWrite {
+    /// Create a [`RemoteWrite`] sending to `url`.
+    ///
+    /// Writes happen every 15 seconds, each with a timeout of 5 seconds.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            headers: Vec::new(),
+            labels: Vec::new(),
+            honor_labels: false,
+            interval: Duration::from_secs(15),
+            timeout: Duration::from_secs(5),
+            client: PushClient::new(),
+        }
+    }
+
+    /// Add a header to every request, e.g. `X-Scope-OrgID`.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `name` or `value` are not valid header names and values.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((
+            HeaderName::try_from(name).expect("header name should be valid"),
+            HeaderValue::try_from(value).expect("header value should be valid"),
+        ));
+        self
+    }
+
+    /// Authenticate with HTTP basic auth.
+    pub fn basic_auth(self, username: &str, password: &str) -> Self {
+        let credentials = base64::encode(format!("{username}:{password}").as_bytes());
+        self.header(AUTHORIZATION.as_str(), &format!("Basic {credentials}"))
+    }
+
+    /// Authenticate with a bearer token.
+    pub fn bearer_token(self, token: &str) -> Self {
+        self.header(AUTHORIZATION.as_str(), &format!("Bearer {token}"))
+    }
+
+    /// Set the `job` label of every series.
+    pub fn job(self, job: impl Into<String>) -> Self {
+        self.label("job", job)
+    }
+
+    /// Set the `instance` label of every series.
+    pub fn instance(self, instance: impl Into<String>) -> Self {
+        self.label("instance", instance)
+    }
+
+    /// Add a label to every series, e.g. to tell replicas apart that would
+    /// otherwise write the same series.
+    pub fn label(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.labels.push((name.into(), value.into()));
+        self
+    }
+
+    /// Whether labels of the samples named like an added label keep their
+    /// values, disabled by default.
+    ///
+    /// By default such labels are renamed to `exported_<name>`, like
+    /// Prometheus does for scrapes without `honor_labels`.
+    pub fn honor_labels(mut self, honor_labels: bool) -> Self {
+        self.honor_labels = honor_labels;
+        self
+    }
+
+    /// Retry failed writes according to `retry`, never retried by default.
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.client = self.client.retry(retry);
+        self
+    }
+
+    /// Register counters of the requests, retries and failures of the
+    /// writes with `registry`.
+    pub fn register_telemetry(&self, registry: &mut Registry) {
+        self.client.register(registry);
+    }
+
+    /// Set the interval between writes of the background task.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Set the timeout of a single write.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Encode `registry` and write all of its samples.
+    pub async fn write(&self, registry: &Mutex<Registry>) -> Result<(), PushError> {
+        let mut families = collect(&registry.lock().expect("should be able to lock registry"))
+            .map_err(|_| PushError::Encode)?;
+        add_labels(&mut families, &self.labels, self.honor_labels);
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64;
+        let body = snap::raw::Encoder::new()
+            .compress_vec(&write_request(&families, timestamp))
+            .map_err(|_| PushError::Encode)?;
+
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri(&self.url)
+            .header(CONTENT_TYPE, "application/x-protobuf")
+            .header(CONTENT_ENCODING, "snappy")
+            .header("X-Prometheus-Remote-Write-Version", "0.1.0");
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        let request = request
+            .body(Full::new(Bytes::from(body)))
+            .map_err(|err| PushError::Request(err.to_string()))?;
+        self.client.send(request, self.timeout).await
+    }
+
+    /// Spawn a task writing `registry` every interval.
+    ///
+    /// Failed writes are logged and the samples are dropped.
+    pub fn spawn(self, registry: Arc<Mutex<Registry>>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                if let Err(err) = self.write(&registry).await {
+                    tracing::warn!(%err, url = self.url, "failed to remote write metrics");
+                }
+            }
+        })
+    }
+}
+
+/// Encode a `prometheus.WriteRequest` protobuf message with one time series
+/// per sample.
+fn write_request(families: &[MetricFamily], timestamp: i64) -> Vec<u8> {
+    let mut request = Vec::new();
+    let mut series = Vec::new();
+    let mut message = Vec::new();
+    for sample in families.iter().flat_map(|family| &family.samples) {
+        let mut labels: Vec<(&str, &str)> = sample
+            .labels
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        labels.push(("__name__", &sample.name));
+        labels.sort_unstable();
+
+        series.clear();
+        for (name, value) in labels {
+            // Label
+            message.clear();
+            encode_bytes(&mut message, 1, name.as_bytes());
+            encode_bytes(&mut message, 2, value.as_bytes());
+            encode_bytes(&mut series, 1, &message);
+        }
+        // Sample
+        message.clear();
+        encode_double(&mut message, 1, sample.value);
+        encode_uint(&mut message, 2, timestamp as u64);
+        encode_bytes(&mut series, 2, &message);
+
+        // TimeSeries
+        encode_bytes(&mut request, 1, &series);
+    }
+    request
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus_client::metrics::{counter::Counter, family::Family, gauge::Gauge};
+    use prost::Message;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    /// `prometheus.WriteRequest` of the remote write protocol.
+    #[derive(Clone, PartialEq, Message)]
+    struct WriteRequest {
+        #[prost(message, repeated, tag = "1")]
+        timeseries: Vec<TimeSeries>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    struct TimeSeries {
+        #[prost(message, repeated, tag = "1")]
+        labels: Vec<Label>,
+        #[prost(message, repeated, tag = "2")]
+        samples: Vec<Sample>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    struct Label {
+        #[prost(string, tag = "1")]
+        name: String,
+        #[prost(string, tag = "2")]
+        value: String,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    struct Sample {
+        #[prost(double, tag = "1")]
+        value: f64,
+        #[prost(int64, tag = "2")]
+        timestamp: i64,
+    }
+
+    /// Accept a single request, answer it with `204 No Content` and return
+    /// its headers and body.
+    async fn receive(listener: tokio::net::TcpListener) -> (String, Vec<u8>) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let headers_end = loop {
+            let mut buf = [0; 4096];
+            let read = stream.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..read]);
+            if let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+                break end + 4;
+            }
+        };
+        let headers = String::from_utf8(request[..headers_end].to_vec()).unwrap();
+        let length: usize = headers
+            .lines()
+            .find_map(|line| {
+                line.to_ascii_lowercase()
+                    .strip_prefix("content-length: ")
+                    .map(str::to_string)
+            })
+            .expect("request should have a content length")
+            .parse()
+            .unwrap();
+        while request.len() < headers_end + length {
+            let mut buf = [0; 4096];
+            let read = stream.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..read]);
+        }
+        stream
+            .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+            .await
+            .unwrap();
+        (headers, request.split_off(headers_end))
+    }
+
+    fn series(series: &TimeSeries) -> (Vec<(&str, &str)>, f64) {
+        let labels = series
+            .labels
+            .iter()
+            .map(|label| (label.name.as_str(), label.value.as_str()))
+            .collect();
+        (labels, series.samples[0].value)
+    }
+
+    #[tokio::test]
+    async fn write_request_decodes() {
+        let mut registry = Registry::default();
+        let requests = Family::<Vec<(String, String)>, Counter>::default();
+        requests
+            .get_or_create(&vec![
+                ("method".into(), "GET".into()),
+                ("instance".into(), "pod-1".into()),
+            ])
+            .inc_by(3);
+        registry.register("requests", "Handled requests", requests);
+        let workers = Gauge::<i64>::default();
+        workers.set(4);
+        registry.register("workers", "Worker threads", workers);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/api/v1/push", listener.local_addr().unwrap());
+        let received = tokio::spawn(receive(listener));
+        RemoteWrite::new(url)
+            .job("batch")
+            .instance("worker-1")
+            .write(&Mutex::new(registry))
+            .await
+            .unwrap();
+
+        let (headers, body) = received.await.unwrap();
+        let headers = headers.to_ascii_lowercase();
+        assert!(
+            headers.contains("content-encoding: snappy\r\n"),
+            "{headers}"
+        );
+        assert!(
+            headers.contains("x-prometheus-remote-write-version: 0.1.0\r\n"),
+            "{headers}"
+        );
+        let body = snap::raw::Decoder::new().decompress_vec(&body).unwrap();
+        let request = WriteRequest::decode(body.as_slice()).unwrap();
+
+        let [requests, workers] = request.timeseries.as_slice() else {
+            panic!("request should have two series: {request:?}");
+        };
+        assert_eq!(
+            series(requests),
+            (
+                vec![
+                    ("__name__", "requests_total"),
+                    ("exported_instance", "pod-1"),
+                    ("instance", "worker-1"),
+                    ("job", "batch"),
+                    ("method", "GET"),
+                ],
+                3.0,
+            ),
+        );
+        assert_eq!(
+            series(workers),
+            (
+                vec![
+                    ("__name__", "workers"),
+                    ("instance", "worker-1"),
+                    ("job", "batch"),
+                ],
+                4.0,
+            ),
+        );
+        assert_eq!(requests.samples[0].timestamp, workers.samples[0].timestamp);
+        assert!(requests.samples[0].timestamp > 0);
+    }
+}
@@ -0,0 +1,394 @@
+//! Prometheus rules for the runtime metrics.
+//!
+//! The rules are generated from the names the collector exports, so they
+//! match whatever version of this crate is deployed. Write them to a rules
+//! file loaded by Prometheus, e.g. from a build script or a `--rules` flag
+//! of the service.
+
+use std::{fmt::Write, time::Duration};
+
+use crate::names::runtime_samples;
+
+/// A starter set of alerting rules for the runtime metrics.
+///
+/// * `TokioRuntimeSaturated`: the workers are busy more than
+///   [`busy_ratio`](Self::busy_ratio) of the time.
+/// * `TokioRuntimeSchedulingBacklog`: more than
+///   [`queue_depth`](Self::queue_depth) tasks wait in the run queues, so
+///   tasks see a high scheduling delay.
+/// * `TokioRuntimeSlowPolls`: the 0.99 quantile of the mean poll duration
+///   exceeds [`slow_poll`](Self::slow_poll). Needs
+///   [`RuntimeCollectorBuilder::mean_poll_duration_quantiles`](crate::RuntimeCollectorBuilder::mean_poll_duration_quantiles).
+/// * `TokioRuntimeMetricsStale`: sampling the runtime failed. Needs
+///   [`ErrorPolicy::StaleLast`](crate::ErrorPolicy::StaleLast).
+///
+/// `tokio_metrics` does not expose the blocking pool, so there is no rule
+/// for its backlog.
+///
+/// ## Example
+///
+/// ```
+/// let mut yaml = String::new();
+/// tokio_prometheus_client::rules::AlertRules::new("tokio")
+///     .busy_ratio(0.8)
+///     .encode(&mut yaml)
+///     .unwrap();
+/// assert!(yaml.contains(
+///     "expr: \"rate(tokio_total_busy_duration_seconds_total[5m]) / tokio_workers_count > 0.8\"\n"
+/// ));
+/// ```
+#[derive(Clone, Debug)]
+pub struct AlertRules {
+    prefix: String,
+    window: Duration,
+    pending: Duration,
+    busy_ratio: f64,
+    queue_depth: u64,
+    slow_poll: Duration,
+}
+
+impl AlertRules {
+    /// Create [`AlertRules`] for a collector registered with `prefix`, e.g.
+    /// with `registry.sub_registry_with_prefix(prefix)`, or `""` for none.
+    ///
+    /// Rates are computed over 5 minutes and alerts fire once pending for 10
+    /// minutes.
+    pub fn new(prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+            window: Duration::from_secs(5 * 60),
+            pending: Duration::from_secs(10 * 60),
+            busy_ratio: 0.9,
+            queue_depth: 1000,
+            slow_poll: Duration::from_millis(10),
+        }
+    }
+
+    /// Set the range rates are computed over.
+    pub fn window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Set how long a condition has to hold before the alert fires.
+    pub fn pending(mut self, pending: Duration) -> Self {
+        self.pending = pending;
+        self
+    }
+
+    /// Set the fraction of the time the workers may be busy, 0.9 by
+    /// default.
+    pub fn busy_ratio(mut self, busy_ratio: f64) -> Self {
+        self.busy_ratio = busy_ratio;
+        self
+    }
+
+    /// Set the number of tasks that may wait in the run queues, 1000 by
+    /// default.
+    pub fn queue_depth(mut self, queue_depth: u64) -> Self {
+        self.queue_depth = queue_depth;
+        self
+    }
+
+    /// Set the mean poll duration that counts as slow, 10ms by default.
+    pub fn slow_poll(mut self, slow_poll: Duration) -> Self {
+        self.slow_poll = slow_poll;
+        self
+    }
+
+    /// Encode the rules as a Prometheus rules file in YAML.
+    pub fn encode(&self, writer: &mut impl Write) -> std::fmt::Result {
+        let name = |samples: &str| prefixed(&self.prefix, samples);
+        let window = duration(self.window);
+        let rules = [
+            Alert {
+                name: "TokioRuntimeSaturated",
+                expr: format!(
+                    "rate({}[{window}]) / {} > {}",
+                    name(runtime_samples!("total_busy_duration")),
+                    name(runtime_samples!("workers_count")),
+                    self.busy_ratio,
+                ),
+                summary: "Tokio workers are busy most of the time",
+            },
+            Alert {
+                name: "TokioRuntimeSchedulingBacklog",
+                expr: format!(
+                    "{} + {} > {}",
+                    name(runtime_samples!("injection_queue_depth")),
+                    name(runtime_samples!("total_local_queue_depth")),
+                    self.queue_depth,
+                ),
+                summary: "Tokio tasks wait in the run queues to be scheduled",
+            },
+            Alert {
+                name: "TokioRuntimeSlowPolls",
+                expr: format!(
+                    "{}{{quantile=\"0.99\"}} > {}",
+                    name(runtime_samples!("mean_poll_duration")),
+                    self.slow_poll.as_secs_f64(),
+                ),
+                summary: "Tokio task polls take long, delaying other tasks",
+            },
+            Alert {
+                name: "TokioRuntimeMetricsStale",
+                expr: format!("{} == 1", name(runtime_samples!("stale"))),
+                summary: "Sampling the Tokio runtime metrics fails",
+            },
+        ];
+
+        writeln!(writer, "groups:")?;
+        writeln!(writer, "  - name: tokio-runtime-alerts")?;
+        writeln!(writer, "    rules:")?;
+        for rule in rules {
+            writeln!(writer, "      - alert: {}", rule.name)?;
+            writeln!(writer, "        expr: {}", quoted(&rule.expr))?;
+            writeln!(writer, "        for: {}", duration(self.pending))?;
+            writeln!(writer, "        labels:")?;
+            writeln!(writer, "          severity: warning")?;
+            writeln!(writer, "        annotations:")?;
+            writeln!(writer, "          summary: {}", quoted(rule.summary))?;
+        }
+        Ok(())
+    }
+}
+
+/// Recording rules for the ratios derived from the runtime metrics, for
+/// computing them in Prometheus rather than in dashboards.
+///
+/// The ratios are recorded over the rate window, e.g. 5 minutes as
+/// `tokio:busy_ratio:rate5m`, following the `level:metric:operations`
+/// naming convention with the prefix as level:
+///
+/// * `busy_ratio`: the fraction of the time the workers were busy.
+/// * `polls_per_park`: the number of task polls between workers parking.
+/// * `busy_seconds_per_poll`: the mean busy duration per task poll.
+///
+/// `tokio_metrics` does not expose the poll count histogram to the
+/// collector, so there is no slow poll ratio.
+///
+/// ## Example
+///
+/// ```
+/// let mut yaml = String::new();
+/// tokio_prometheus_client::rules::RecordingRules::new("tokio")
+///     .encode(&mut yaml)
+///     .unwrap();
+/// assert!(yaml.contains("record: \"tokio:busy_ratio:rate5m\"\n"));
+/// ```
+#[derive(Clone, Debug)]
+pub struct RecordingRules {
+    prefix: String,
+    window: Duration,
+}
+
+impl RecordingRules {
+    /// Create [`RecordingRules`] for a collector registered with `prefix`,
+    /// e.g. with `registry.sub_registry_with_prefix(prefix)`, or `""` for
+    /// none, recorded at the `tokio` level then.
+    ///
+    /// Rates are computed over 5 minutes.
+    pub fn new(prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+            window: Duration::from_secs(5 * 60),
+        }
+    }
+
+    /// Set the range rates are computed over.
+    pub fn window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Encode the rules as a Prometheus rules file in YAML.
+    pub fn encode(&self, writer: &mut impl Write) -> std::fmt::Result {
+        let name = |samples: &str| prefixed(&self.prefix, samples);
+        let window = duration(self.window);
+        let rate = |samples: &str| format!("rate({}[{window}])", name(samples));
+        let busy = rate(runtime_samples!("total_busy_duration"));
+        let polls = rate(runtime_samples!("total_polls_count"));
+        let rules = [
+            (
+                "busy_ratio",
+                format!("{busy} / {}", name(runtime_samples!("workers_count"))),
+            ),
+            (
+                "polls_per_park",
+                format!("{polls} / {}", rate(runtime_samples!("total_park_count"))),
+            ),
+            ("busy_seconds_per_poll", format!("{busy} / {polls}")),
+        ];
+
+        let level = if self.prefix.is_empty() {
+            "tokio"
+        } else {
+            &self.prefix
+        };
+        writeln!(writer, "groups:")?;
+        writeln!(writer, "  - name: tokio-runtime-ratios")?;
+        writeln!(writer, "    rules:")?;
+        for (metric, expr) in rules {
+            let record = format!("{level}:{metric}:rate{window}");
+            writeln!(writer, "      - record: {}", quoted(&record))?;
+            writeln!(writer, "        expr: {}", quoted(&expr))?;
+        }
+        Ok(())
+    }
+}
+
+struct Alert {
+    name: &'static str,
+    expr: String,
+    summary: &'static str,
+}
+
+/// The name of `samples` as registered with `prefix`.
+fn prefixed(prefix: &str, samples: &str) -> String {
+    if prefix.is_empty() {
+        samples.to_string()
+    } else {
+        format!("{prefix}_{samples}")
+    }
+}
+
+/// A Prometheus duration, e.g. `5m` or `90s`.
+fn duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if duration.subsec_nanos() != 0 {
+        format!("{}ms", duration.as_millis())
+    } else if seconds != 0 && seconds.is_multiple_of(3600) {
+        format!("{}h", seconds / 3600)
+    } else if seconds != 0 && seconds.is_multiple_of(60) {
+        format!("{}m", seconds / 60)
+    } else {
+        format!("{seconds}s")
+    }
+}
+
+/// `value` as a double quoted YAML string.
+fn quoted(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use prometheus_client::registry::Registry;
+
+    use super::*;
+    use crate::{ErrorPolicy, RuntimeCollectorBuilder};
+
+    /// The names of the samples the collector with every family the rules
+    /// use exports with `prefix`.
+    fn exported(prefix: &str) -> Vec<String> {
+        let mut registry = Registry::default();
+        let builder = RuntimeCollectorBuilder::noop()
+            .mean_poll_duration_quantiles(Duration::from_secs(60))
+            .error_policy(ErrorPolicy::StaleLast);
+        if prefix.is_empty() {
+            builder.register(&mut registry);
+        } else {
+            builder.register(registry.sub_registry_with_prefix(prefix));
+        }
+        let mut text = String::new();
+        prometheus_client::encoding::text::encode(&mut text, &registry).unwrap();
+        text.lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| line.split(['{', ' ']).next().unwrap().to_string())
+            .collect()
+    }
+
+    /// The `key: "value"` values of `yaml`, unquoted.
+    fn values<'a>(yaml: &'a str, key: &str) -> Vec<&'a str> {
+        yaml.lines()
+            .filter_map(|line| {
+                let line = line.trim_start().trim_start_matches("- ");
+                line.strip_prefix(key)?.strip_prefix(": ")
+            })
+            .map(|value| value.trim_matches('"'))
+            .collect()
+    }
+
+    /// The series `expr` selects, leaving out functions, label matchers,
+    /// ranges and numbers.
+    fn selected(expr: &str) -> Vec<&str> {
+        let mut series = Vec::new();
+        let mut rest = expr;
+        while let Some(start) = rest.find(|c: char| c.is_ascii_alphabetic() || "_:{[".contains(c)) {
+            rest = &rest[start..];
+            if let Some(close) = rest
+                .strip_prefix('{')
+                .map(|_| '}')
+                .or(rest.strip_prefix('[').map(|_| ']'))
+            {
+                rest = &rest[rest.find(close).unwrap() + 1..];
+                continue;
+            }
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || "_:".contains(c)))
+                .unwrap_or(rest.len());
+            if !rest[end..].starts_with('(') {
+                series.push(&rest[..end]);
+            }
+            rest = &rest[end..];
+        }
+        series
+    }
+
+    #[test]
+    fn selected_series_of_exprs() {
+        assert_eq!(
+            selected("rate(a_total[5m]) / b{quantile=\"0.99\"} > 0.8"),
+            ["a_total", "b"]
+        );
+    }
+
+    #[test]
+    fn alert_rules_select_exported_series() {
+        for prefix in ["tokio", ""] {
+            let mut yaml = String::new();
+            AlertRules::new(prefix).encode(&mut yaml).unwrap();
+            let exported = exported(prefix);
+            let exprs = values(&yaml, "expr");
+            assert_eq!(exprs.len(), values(&yaml, "alert").len(), "{yaml}");
+            for expr in exprs {
+                let series = selected(expr);
+                assert!(!series.is_empty(), "{expr}");
+                for series in series {
+                    assert!(
+                        exported.iter().any(|name| name == series),
+                        "{series} of {expr}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn recording_rules_select_exported_series() {
+        for prefix in ["tokio", ""] {
+            let mut yaml = String::new();
+            RecordingRules::new(prefix)
+                .window(Duration::from_secs(60))
+                .encode(&mut yaml)
+                .unwrap();
+            let exported = exported(prefix);
+            let exprs = values(&yaml, "expr");
+            assert_eq!(exprs.len(), values(&yaml, "record").len(), "{yaml}");
+            for expr in exprs {
+                for series in selected(expr) {
+                    assert!(
+                        exported.iter().any(|name| name == series),
+                        "{series} of {expr}"
+                    );
+                }
+            }
+            assert!(
+                values(&yaml, "record").contains(&"tokio:busy_ratio:rate1m"),
+                "{yaml}"
+            );
+        }
+    }
+}
@@ -0,0 +1,454 @@
+//! Structured view of the samples exported by a [`Registry`].
+//!
+//! `prometheus-client` only exposes a [`Registry`] through its encoders, this
+//! module parses the OpenMetrics text exposition back into metric families so
+//! the values can be forwarded to systems that do not scrape Prometheus
+//! endpoints.
+
+use std::{fmt::Write, time::Duration};
+
+use prometheus_client::{encoding::text::encode_registry, metrics::MetricType, registry::Registry};
+
+/// A metric family and all of its samples.
+#[derive(Clone, Debug)]
+pub struct MetricFamily {
+    /// Name of the family, including prefix and unit suffix.
+    pub name: String,
+    /// Help text of the family.
+    pub help: String,
+    /// Unit of the family, if any.
+    pub unit: Option<String>,
+    /// Type of the family.
+    pub metric_type: MetricType,
+    /// Samples of the family in exposition order.
+    pub samples: Vec<Sample>,
+}
+
+/// A single sample, i.e. one line of the text exposition.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    /// Name of the sample, including type specific suffixes like `_total`
+    /// or `_bucket`.
+    pub name: String,
+    /// Label pairs of the sample in exposition order.
+    pub labels: Vec<(String, String)>,
+    /// Value of the sample.
+    pub value: f64,
+}
+
+/// Encode `registry` and return its metric families.
+///
+/// Note that encoding a registry samples any registered collectors, exactly
+/// like a scrape does.
+///
+/// ## Example
+///
+/// ```
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let handle = tokio::runtime::Handle::current();
+/// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// tokio_prometheus_client::register(runtime_monitor, registry.sub_registry_with_prefix("tokio"));
+///
+/// let families = tokio_prometheus_client::samples::collect(&registry).unwrap();
+/// assert_eq!(families[0].name, "tokio_workers_count");
+/// # });
+/// ```
+pub fn collect(registry: &Registry) -> Result<Vec<MetricFamily>, std::fmt::Error> {
+    let mut text = String::new();
+    encode_registry(&mut text, registry)?;
+    Ok(parse(&text))
+}
+
+/// Encode `registry` in the OpenMetrics text format, in a stable order.
+///
+/// `prometheus-client` encodes the metrics of a registry, then its
+/// collectors and then its sub-registries, each in the order they were
+/// registered. Here families are sorted by name and the series of each
+/// family by their labels, so the output does not depend on how the metrics
+/// were registered and scrapes can be diffed. The buckets of a histogram
+/// keep their order.
+///
+/// ## Example
+///
+/// ```
+/// # use prometheus_client::metrics::counter::Counter;
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// registry.register("requests", "Handled requests", Counter::<u64>::default());
+/// registry.register("errors", "Failed requests", Counter::<u64>::default());
+///
+/// let mut text = String::new();
+/// tokio_prometheus_client::samples::encode_sorted(&mut text, &registry).unwrap();
+/// assert!(text.starts_with("# HELP errors Failed requests.\n"));
+/// ```
+pub fn encode_sorted(writer: &mut impl Write, registry: &Registry) -> std::fmt::Result {
+    let mut text = String::new();
+    encode_registry(&mut text, registry)?;
+
+    // The lines of each family, starting with its `# HELP` line.
+    let mut families: Vec<(&str, Vec<&str>)> = Vec::new();
+    for line in text.lines() {
+        if let Some(rest) = line.strip_prefix("# HELP ") {
+            let name = rest.split_once(' ').map_or(rest, |(name, _)| name);
+            families.push((name, Vec::new()));
+        }
+        if let Some((_, lines)) = families.last_mut() {
+            lines.push(line);
+        }
+    }
+    families.sort_by_key(|(name, _)| *name);
+
+    for (_, lines) in &mut families {
+        let samples = lines
+            .iter()
+            .position(|line| !line.starts_with('#'))
+            .unwrap_or(lines.len());
+        // Stable, so bucket, sum and count samples keep their order.
+        lines[samples..].sort_by_cached_key(|line| {
+            parse_sample(line).map(|sample| {
+                sample
+                    .labels
+                    .into_iter()
+                    .filter(|(key, _)| key != "le")
+                    .collect::<Vec<_>>()
+            })
+        });
+        for line in lines {
+            writeln!(writer, "{line}")?;
+        }
+    }
+    writeln!(writer, "# EOF")
+}
+
+/// Parse an OpenMetrics text exposition as produced by `prometheus-client`.
+///
+/// Lines that cannot be parsed are skipped.
+pub fn parse(text: &str) -> Vec<MetricFamily> {
+    let mut families: Vec<MetricFamily> = Vec::new();
+    for line in text.lines() {
+        if let Some(rest) = line.strip_prefix("# HELP ") {
+            let (name, help) = rest.split_once(' ').unwrap_or((rest, ""));
+            families.push(MetricFamily {
+                name: name.to_string(),
+                help: help.to_string(),
+                unit: None,
+                metric_type: MetricType::Unknown,
+                samples: Vec::new(),
+            });
+        } else if let Some(rest) = line.strip_prefix("# TYPE ") {
+            if let (Some(family), Some((_, metric_type))) =
+                (families.last_mut(), rest.split_once(' '))
+            {
+                family.metric_type = match metric_type {
+                    "counter" => MetricType::Counter,
+                    "gauge" => MetricType::Gauge,
+                    "histogram" => MetricType::Histogram,
+                    "info" => MetricType::Info,
+                    _ => MetricType::Unknown,
+                };
+            }
+        } else if let Some(rest) = line.strip_prefix("# UNIT ") {
+            if let (Some(family), Some((_, unit))) = (families.last_mut(), rest.split_once(' ')) {
+                family.unit = Some(unit.to_string());
+            }
+        } else if line.starts_with('#') || line.is_empty() {
+            continue;
+        } else if let (Some(family), Some(sample)) = (families.last_mut(), parse_sample(line)) {
+            family.samples.push(sample);
+        }
+    }
+    families
+}
+
+fn parse_sample(line: &str) -> Option<Sample> {
+    let (name, labels, rest) = match line.find(['{', ' ']) {
+        Some(start) if line[start..].starts_with('{') => {
+            let (labels, rest) = parse_labels(&line[start + 1..])?;
+            (&line[..start], labels, rest)
+        }
+        Some(start) => (&line[..start], Vec::new(), &line[start..]),
+        None => return None,
+    };
+    // Any timestamp and exemplar follow the value.
+    let value = rest.split_whitespace().next()?;
+    let value = match value {
+        "+Inf" => f64::INFINITY,
+        "-Inf" => f64::NEG_INFINITY,
+        value => value.parse().ok()?,
+    };
+    Some(Sample {
+        name: name.to_string(),
+        labels,
+        value,
+    })
+}
+
+/// Parse the label pairs following the `{` of a sample, returning them and
+/// the rest of the line after the closing `}`.
+fn parse_labels(mut text: &str) -> Option<(Vec<(String, String)>, &str)> {
+    let mut labels = Vec::new();
+    loop {
+        text = text.trim_start_matches(',');
+        if let Some(rest) = text.strip_prefix('}') {
+            return Some((labels, rest));
+        }
+        let (key, rest) = text.split_once("=\"")?;
+        let mut value = String::new();
+        let mut chars = rest.char_indices();
+        let end = loop {
+            match chars.next()? {
+                (_, '\\') => match chars.next()?.1 {
+                    'n' => value.push('\n'),
+                    c => value.push(c),
+                },
+                (i, '"') => break i,
+                (_, c) => value.push(c),
+            }
+        };
+        labels.push((key.to_string(), value));
+        text = &rest[end + 1..];
+    }
+}
+
+/// Encode `families` in the classic Prometheus text format (version 0.0.4).
+///
+/// Some consumers, e.g. the Pushgateway, do not accept OpenMetrics. Counter
+/// families are named after their `_total` samples, info families become
+/// gauges and unknown families become untyped.
+pub fn encode_text(writer: &mut impl Write, families: &[MetricFamily]) -> std::fmt::Result {
+    encode_text_at(writer, families, None)
+}
+
+/// Encode `families` like [`encode_text`], stamping every sample with
+/// `timestamp` since the Unix epoch, if any.
+pub(crate) fn encode_text_at(
+    writer: &mut impl Write,
+    families: &[MetricFamily],
+    timestamp: Option<Duration>,
+) -> std::fmt::Result {
+    let timestamp = timestamp.map(|timestamp| timestamp.as_millis().to_string());
+    for family in families {
+        let (name, metric_type) = match family.metric_type {
+            MetricType::Counter => (format!("{}_total", family.name), "counter"),
+            MetricType::Gauge => (family.name.clone(), "gauge"),
+            MetricType::Histogram => (family.name.clone(), "histogram"),
+            MetricType::Info => (format!("{}_info", family.name), "gauge"),
+            MetricType::Unknown => (family.name.clone(), "untyped"),
+        };
+        writeln!(writer, "# HELP {name} {}", family.help)?;
+        writeln!(writer, "# TYPE {name} {metric_type}")?;
+        for sample in &family.samples {
+            encode_sample(writer, sample, timestamp.as_deref())?;
+        }
+    }
+    Ok(())
+}
+
+/// Encode `families` in the OpenMetrics text format, without the final
+/// `# EOF`, stamping every sample with `timestamp` since the Unix epoch.
+///
+/// Exemplars are not kept by [`parse`], so they are not encoded.
+#[cfg(feature = "tower")]
+pub(crate) fn encode_openmetrics_at(
+    writer: &mut impl Write,
+    families: &[MetricFamily],
+    timestamp: Duration,
+) -> std::fmt::Result {
+    let timestamp = format!("{}.{:03}", timestamp.as_secs(), timestamp.subsec_millis());
+    for family in families {
+        let metric_type = match family.metric_type {
+            MetricType::Counter => "counter",
+            MetricType::Gauge => "gauge",
+            MetricType::Histogram => "histogram",
+            MetricType::Info => "info",
+            MetricType::Unknown => "unknown",
+        };
+        writeln!(writer, "# HELP {} {}", family.name, family.help)?;
+        writeln!(writer, "# TYPE {} {metric_type}", family.name)?;
+        if let Some(unit) = &family.unit {
+            writeln!(writer, "# UNIT {} {unit}", family.name)?;
+        }
+        for sample in &family.samples {
+            encode_sample(writer, sample, Some(&timestamp))?;
+        }
+    }
+    Ok(())
+}
+
+/// Encode the line of `sample`, with `timestamp` if any.
+fn encode_sample(
+    writer: &mut impl Write,
+    sample: &Sample,
+    timestamp: Option<&str>,
+) -> std::fmt::Result {
+    writer.write_str(&sample.name)?;
+    if !sample.labels.is_empty() {
+        writer.write_char('{')?;
+        for (i, (key, value)) in sample.labels.iter().enumerate() {
+            if i > 0 {
+                writer.write_char(',')?;
+            }
+            write!(writer, "{key}=\"")?;
+            for c in value.chars() {
+                match c {
+                    '\\' => writer.write_str("\\\\")?,
+                    '"' => writer.write_str("\\\"")?,
+                    '\n' => writer.write_str("\\n")?,
+                    c => writer.write_char(c)?,
+                }
+            }
+            writer.write_char('"')?;
+        }
+        writer.write_char('}')?;
+    }
+    write!(writer, " {}", format_value(sample.value))?;
+    if let Some(timestamp) = timestamp {
+        write!(writer, " {timestamp}")?;
+    }
+    writer.write_char('\n')
+}
+
+/// The samples of one histogram in a histogram family.
+#[cfg(any(feature = "otlp", feature = "prometheus", feature = "statsd"))]
+pub(crate) struct HistogramPoint<'a> {
+    pub(crate) labels: Vec<&'a (String, String)>,
+    pub(crate) sum: f64,
+    pub(crate) count: f64,
+    /// Upper bound and cumulative count of each bucket.
+    pub(crate) buckets: Vec<(f64, f64)>,
+}
+
+/// Group the samples of a histogram family by their labels, except `le`.
+#[cfg(any(feature = "otlp", feature = "prometheus", feature = "statsd"))]
+pub(crate) fn histogram_points(samples: &[Sample]) -> Vec<HistogramPoint<'_>> {
+    let mut points: Vec<HistogramPoint<'_>> = Vec::new();
+    for sample in samples {
+        let labels: Vec<_> = sample.labels.iter().filter(|(k, _)| k != "le").collect();
+        let index = match points.iter().position(|p| p.labels == labels) {
+            Some(index) => index,
+            None => {
+                points.push(HistogramPoint {
+                    labels,
+                    sum: 0.0,
+                    count: 0.0,
+                    buckets: Vec::new(),
+                });
+                points.len() - 1
+            }
+        };
+        let point = &mut points[index];
+        if sample.name.ends_with("_sum") {
+            point.sum = sample.value;
+        } else if sample.name.ends_with("_count") {
+            point.count = sample.value;
+        } else if sample.name.ends_with("_bucket") {
+            let bound = sample
+                .labels
+                .iter()
+                .find(|(k, _)| k == "le")
+                .and_then(|(_, v)| match v.as_str() {
+                    "+Inf" => Some(f64::INFINITY),
+                    v => v.parse().ok(),
+                });
+            if let Some(bound) = bound {
+                point.buckets.push((bound, sample.value));
+            }
+        }
+    }
+    points
+}
+
+/// Format `value` the way the text formats spell it.
+pub(crate) fn format_value(value: f64) -> String {
+    if value == f64::INFINITY {
+        "+Inf".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus_client::metrics::{counter::Counter, family::Family};
+
+    use super::*;
+
+    #[test]
+    fn parses_escaped_label_values() {
+        let sample = parse_sample(r#"requests_total{path="/a # b",quote="\"\\\n"} 3"#).unwrap();
+        assert_eq!(sample.name, "requests_total");
+        assert_eq!(
+            sample.labels,
+            [
+                ("path".to_string(), "/a # b".to_string()),
+                ("quote".to_string(), "\"\\\n".to_string()),
+            ],
+        );
+        assert_eq!(sample.value, 3.0);
+    }
+
+    #[test]
+    fn parses_timestamps_and_exemplars() {
+        let sample =
+            parse_sample(r#"latency_bucket{le="+Inf"} 7 1700000000.000 # {trace_id="a}"} 0.5"#)
+                .unwrap();
+        assert_eq!(sample.labels, [("le".to_string(), "+Inf".to_string())]);
+        assert_eq!(sample.value, 7.0);
+        let sample = parse_sample("workers 4 # {} 1").unwrap();
+        assert!(sample.labels.is_empty());
+        assert_eq!(sample.value, 4.0);
+        assert_eq!(parse_sample("workers"), None);
+        assert_eq!(parse_sample(r#"workers{a="b" 4"#), None);
+    }
+
+    fn requests() -> Vec<MetricFamily> {
+        let mut registry = Registry::default();
+        let requests = Family::<Vec<(String, String)>, Counter>::default();
+        requests
+            .get_or_create(&vec![("path".into(), "/a # b".into())])
+            .inc_by(2);
+        registry.register("requests", "Handled requests", requests);
+        collect(&registry).unwrap()
+    }
+
+    #[cfg(feature = "tower")]
+    #[test]
+    fn openmetrics_round_trips_with_timestamps() {
+        let families = requests();
+        let mut text = String::new();
+        encode_openmetrics_at(
+            &mut text,
+            &families,
+            Duration::from_millis(1_700_000_000_123),
+        )
+        .unwrap();
+        assert_eq!(
+            text,
+            "# HELP requests Handled requests.\n\
+             # TYPE requests counter\n\
+             requests_total{path=\"/a # b\"} 2 1700000000.123\n",
+        );
+        let parsed = parse(&text);
+        assert_eq!(parsed[0].samples, families[0].samples);
+    }
+
+    #[test]
+    fn text_stamps_samples() {
+        let mut text = String::new();
+        encode_text_at(
+            &mut text,
+            &requests(),
+            Some(Duration::from_millis(1_700_000_000_123)),
+        )
+        .unwrap();
+        assert_eq!(
+            text,
+            "# HELP requests_total Handled requests.\n\
+             # TYPE requests_total counter\n\
+             requests_total{path=\"/a # b\"} 2 1700000000123\n",
+        );
+    }
+}
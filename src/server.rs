@@ -0,0 +1,1191 @@
+//! Minimal HTTP server exposing a
+//! [`Registry`](prometheus_client::registry::Registry) on `/metrics`.
+//!
+//! Enabled with the `server` feature. The `serve_metrics` functions cover the
+//! common cases, [`Server`] adds graceful shutdown and readiness reporting.
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    fmt,
+    future::Future,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use http_body_util::Full;
+use hyper::{
+    body::{Bytes, Incoming},
+    header::{RETRY_AFTER, USER_AGENT},
+    server::conn::http1,
+    service::service_fn,
+    Request, Response, StatusCode,
+};
+use hyper_util::{
+    rt::{TokioIo, TokioTimer},
+    server::graceful::{GracefulShutdown, Watcher},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    sync::{oneshot, watch, OwnedSemaphorePermit, Semaphore},
+    task::JoinHandle,
+};
+
+use crate::tower::{status, MetricsService};
+
+mod config;
+#[cfg(feature = "tls")]
+mod tls;
+
+#[cfg(feature = "tls")]
+pub use config::TlsFiles;
+pub use config::{AuthConfig, ExporterConfig};
+#[cfg(feature = "tls")]
+pub use tls::TlsConfig;
+
+/// Serve `metrics` on `http://{addr}/metrics`.
+///
+/// `metrics` is either an `Arc<Mutex<Registry>>` or a [`MetricsService`]
+/// configuring how requests are handled, e.g. to require authentication.
+/// The registry is encoded on every request, so metrics registered after the
+/// server has started are exported as well. The returned future only
+/// completes if the listener fails.
+///
+/// ## Example
+///
+/// ```no_run
+/// # use std::sync::{Arc, Mutex};
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let handle = tokio::runtime::Handle::current();
+/// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// tokio_prometheus_client::register(runtime_monitor, registry.sub_registry_with_prefix("tokio"));
+///
+/// let addr = "0.0.0.0:9090".parse().unwrap();
+/// let metrics = tokio_prometheus_client::tower::MetricsService::new(Arc::new(Mutex::new(registry)))
+///     .bearer_token("secret");
+/// tokio_prometheus_client::server::serve_metrics(addr, metrics)
+///     .await
+///     .unwrap();
+/// # });
+/// ```
+pub async fn serve_metrics(addr: SocketAddr, metrics: impl Into<MetricsService>) -> io::Result<()> {
+    Server::bind(addr, metrics).serve().await
+}
+
+/// Serve `metrics` on `https://{addr}/metrics`.
+///
+/// Otherwise this behaves like [`serve_metrics`].
+///
+/// ## Example
+///
+/// ```no_run
+/// # use std::sync::{Arc, Mutex};
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let handle = tokio::runtime::Handle::current();
+/// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// tokio_prometheus_client::register(runtime_monitor, registry.sub_registry_with_prefix("tokio"));
+///
+/// let tls = tokio_prometheus_client::server::TlsConfig::from_pem_files(
+///     "/etc/metrics/tls.crt",
+///     "/etc/metrics/tls.key",
+/// )
+/// .unwrap();
+/// let addr = "0.0.0.0:9090".parse().unwrap();
+/// tokio_prometheus_client::server::serve_metrics_tls(addr, Arc::new(Mutex::new(registry)), tls)
+///     .await
+///     .unwrap();
+/// # });
+/// ```
+#[cfg(feature = "tls")]
+pub async fn serve_metrics_tls(
+    addr: SocketAddr,
+    metrics: impl Into<MetricsService>,
+    tls: TlsConfig,
+) -> io::Result<()> {
+    Server::bind(addr, metrics).tls(tls).serve().await
+}
+
+/// Serve `metrics` on `/metrics` of the Unix domain socket at `path`.
+///
+/// A socket file left behind at `path`, e.g. by a crashed instance, is
+/// replaced, and the socket file is removed again on shutdown. Otherwise
+/// this behaves like [`serve_metrics`].
+///
+/// ## Example
+///
+/// ```no_run
+/// # use std::sync::{Arc, Mutex};
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let handle = tokio::runtime::Handle::current();
+/// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// tokio_prometheus_client::register(runtime_monitor, registry.sub_registry_with_prefix("tokio"));
+///
+/// tokio_prometheus_client::server::serve_metrics_unix(
+///     "/run/my-service/metrics.sock",
+///     Arc::new(Mutex::new(registry)),
+/// )
+/// .await
+/// .unwrap();
+/// # });
+/// ```
+#[cfg(unix)]
+pub async fn serve_metrics_unix(
+    path: impl AsRef<std::path::Path>,
+    metrics: impl Into<MetricsService>,
+) -> io::Result<()> {
+    Server::bind_unix(path, metrics).serve().await
+}
+
+/// The built-in metrics server, with control over its lifecycle.
+///
+/// On shutdown the server stops accepting connections and waits for
+/// in-flight scrapes to be answered instead of dropping them.
+///
+/// ## Example
+///
+/// ```no_run
+/// # use std::sync::{Arc, Mutex};
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let handle = tokio::runtime::Handle::current();
+/// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// tokio_prometheus_client::register(runtime_monitor, registry.sub_registry_with_prefix("tokio"));
+///
+/// let addr = "0.0.0.0:9090".parse().unwrap();
+/// let mut server =
+///     tokio_prometheus_client::server::Server::bind(addr, Arc::new(Mutex::new(registry))).spawn();
+/// assert!(server.wait_serving().await);
+///
+/// // ... run the application ...
+///
+/// server.shutdown().await.unwrap();
+/// # });
+/// ```
+#[derive(Debug)]
+pub struct Server {
+    bind: Bind,
+    endpoint: Endpoint,
+    max_in_flight: Option<usize>,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsConfig>,
+}
+
+/// What connections are served with.
+#[derive(Debug)]
+struct Endpoint {
+    metrics: MetricsService,
+    path: String,
+    request_timeout: Option<Duration>,
+    scrapes: Option<Arc<Semaphore>>,
+    scrape_timeout: Option<Duration>,
+    rate_limit: Option<RateLimit>,
+    access_log: Option<AccessLogger>,
+    #[cfg(feature = "health")]
+    health: Option<crate::health::HealthCheck>,
+}
+
+/// The last scrapes of each client, see [`Server::min_scrape_interval`].
+#[derive(Debug)]
+struct RateLimit {
+    interval: Duration,
+    /// When each client scraped last, pruned once the interval elapsed.
+    scraped_at: Mutex<HashMap<String, Instant>>,
+}
+
+impl RateLimit {
+    fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            scraped_at: Mutex::default(),
+        }
+    }
+
+    /// Record a scrape of `client`, or return how long it has to wait
+    /// before it may scrape again.
+    fn check(&self, client: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut scraped_at = self
+            .scraped_at
+            .lock()
+            .expect("should be able to lock scrape rate limit");
+        scraped_at.retain(|_, scraped_at| now.duration_since(*scraped_at) < self.interval);
+        match scraped_at.get(client) {
+            Some(last) => Err(self.interval - now.duration_since(*last)),
+            None => {
+                scraped_at.insert(client.to_string(), now);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A request handled by the built-in server, see [`Server::access_log`].
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct AccessLog<'a> {
+    /// Address of the client, the socket address for TCP connections.
+    pub peer: &'a str,
+    /// Path of the request.
+    pub path: &'a str,
+    /// `User-Agent` header of the request, if any and valid.
+    pub user_agent: Option<&'a str>,
+    /// Time it took to answer the request, including encoding the metrics.
+    pub duration: Duration,
+    /// Status of the response.
+    pub status: StatusCode,
+    /// Length of the response body, after compression.
+    pub bytes: usize,
+}
+
+/// The callback of [`Server::access_log`].
+#[derive(Clone)]
+struct AccessLogger(Arc<dyn Fn(&AccessLog<'_>) + Send + Sync>);
+
+impl fmt::Debug for AccessLogger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AccessLogger").finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug)]
+enum Bind {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+}
+
+impl Server {
+    /// Create a [`Server`] serving `metrics` on `/metrics` of `addr`.
+    pub fn bind(addr: SocketAddr, metrics: impl Into<MetricsService>) -> Self {
+        Self::new(Bind::Tcp(addr), metrics.into())
+    }
+
+    /// Create a [`Server`] serving `metrics` on `/metrics` of the Unix domain
+    /// socket at `path`.
+    ///
+    /// A socket file left behind at `path` is replaced, other files are not.
+    /// The socket file is removed once the server shut down.
+    #[cfg(unix)]
+    pub fn bind_unix(
+        path: impl AsRef<std::path::Path>,
+        metrics: impl Into<MetricsService>,
+    ) -> Self {
+        Self::new(Bind::Unix(path.as_ref().to_path_buf()), metrics.into())
+    }
+
+    /// Create a [`Server`] as described by `config`.
+    ///
+    /// Fails if the TLS files of `config` cannot be loaded.
+    pub fn from_config(
+        config: ExporterConfig,
+        metrics: impl Into<MetricsService>,
+    ) -> io::Result<Self> {
+        let metrics = metrics.into();
+        #[cfg(feature = "gzip")]
+        let metrics = metrics.gzip(config.compression);
+        let metrics = match &config.auth {
+            Some(AuthConfig::Basic { username, password }) => {
+                metrics.basic_auth(username, password)
+            }
+            Some(AuthConfig::Bearer { token }) => metrics.bearer_token(token),
+            None => metrics,
+        };
+        let mut server = Self::new(Bind::Tcp(config.bind), metrics).path(config.path);
+        server.endpoint.request_timeout = config.request_timeout;
+        server.endpoint.scrape_timeout = config.scrape_timeout;
+        server.endpoint.rate_limit = config.min_scrape_interval.map(RateLimit::new);
+        server.max_in_flight = config.max_in_flight;
+        #[cfg(feature = "tls")]
+        if let Some(files) = config.tls {
+            let tls = TlsConfig::from_pem_files(files.cert, files.key)?;
+            server.tls = Some(match files.client_ca {
+                Some(client_ca) => tls.client_ca_pem_file(client_ca)?,
+                None => tls,
+            });
+        }
+        Ok(match config.max_concurrent_scrapes {
+            Some(max) => server.max_concurrent_scrapes(max),
+            None => server,
+        })
+    }
+
+    fn new(bind: Bind, metrics: MetricsService) -> Self {
+        let config = ExporterConfig::default();
+        Self {
+            bind,
+            endpoint: Endpoint {
+                metrics,
+                path: config.path,
+                request_timeout: config.request_timeout,
+                scrapes: None,
+                scrape_timeout: config.scrape_timeout,
+                rate_limit: None,
+                access_log: None,
+                #[cfg(feature = "health")]
+                health: None,
+            },
+            max_in_flight: config.max_in_flight,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+
+    /// Serve metrics on `path` instead of `/metrics`.
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.endpoint.path = path.into();
+        self
+    }
+
+    /// Set the time a client has to complete the TLS handshake and to send
+    /// its request, 30 seconds by default.
+    ///
+    /// Connections of slower clients are closed.
+    pub fn request_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.endpoint.request_timeout = timeout;
+        self
+    }
+
+    /// Serve at most `max` connections at the same time.
+    ///
+    /// Further connections are accepted once a served one is closed.
+    pub fn max_in_flight(mut self, max: usize) -> Self {
+        self.max_in_flight = Some(max);
+        self
+    }
+
+    /// Encode at most `max` scrapes at the same time.
+    ///
+    /// Further scrapes are answered with `503 Service Unavailable`.
+    pub fn max_concurrent_scrapes(mut self, max: usize) -> Self {
+        self.endpoint.scrapes = Some(Arc::new(Semaphore::new(max)));
+        self
+    }
+
+    /// Answer scrapes that take longer than `timeout` to encode with
+    /// `503 Service Unavailable`, 10 seconds by default.
+    ///
+    /// Encoding then runs on the blocking thread pool. A timed out encode
+    /// keeps counting towards [`Server::max_concurrent_scrapes`] until it
+    /// completes.
+    pub fn scrape_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.endpoint.scrape_timeout = timeout;
+        self
+    }
+
+    /// Answer scrapes with `429 Too Many Requests` while less than
+    /// `interval` passed since the last scrape of the same client.
+    ///
+    /// Clients are told by their IP address, all clients of a Unix domain
+    /// socket share the limit. Rejected and unauthenticated scrapes do not
+    /// count towards the limit, and rejected ones carry a `Retry-After`
+    /// header.
+    pub fn min_scrape_interval(mut self, interval: Duration) -> Self {
+        self.endpoint.rate_limit = Some(RateLimit::new(interval));
+        self
+    }
+
+    /// Call `log` with every request answered, e.g. to audit scrapers
+    /// without a proxy in front of the server.
+    ///
+    /// `log` is called on the task serving the connection after the
+    /// response is ready, so it should not block.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use std::sync::{Arc, Mutex};
+    /// let addr = "0.0.0.0:9090".parse().unwrap();
+    /// let registry = Arc::new(Mutex::new(prometheus_client::registry::Registry::default()));
+    /// let server = tokio_prometheus_client::server::Server::bind(addr, registry).access_log(|log| {
+    ///     tracing::info!(
+    ///         peer = log.peer,
+    ///         user_agent = log.user_agent,
+    ///         status = log.status.as_u16(),
+    ///         duration = ?log.duration,
+    ///         bytes = log.bytes,
+    ///         "metrics scraped",
+    ///     );
+    /// });
+    /// ```
+    pub fn access_log(mut self, log: impl Fn(&AccessLog<'_>) + Send + Sync + 'static) -> Self {
+        self.endpoint.access_log = Some(AccessLogger(Arc::new(log)));
+        self
+    }
+
+    /// Answer `/healthz` and `/readyz` with the liveness and readiness of
+    /// `health`.
+    ///
+    /// Unhealthy endpoints are answered with `503 Service Unavailable` and
+    /// the reason.
+    ///
+    /// The health endpoints are answered before authentication and rate
+    /// limiting, so probes like the ones of Kubernetes work without
+    /// credentials, even when the metrics require them. They only reveal
+    /// whether the runtime is live and ready, and why not.
+    #[cfg(feature = "health")]
+    pub fn health(mut self, health: crate::health::HealthCheck) -> Self {
+        self.endpoint.health = Some(health);
+        self
+    }
+
+    /// Serve over TLS.
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Serve until the listener fails.
+    pub async fn serve(self) -> io::Result<()> {
+        self.serve_with_shutdown(std::future::pending()).await
+    }
+
+    /// Serve until `shutdown` completes, e.g.
+    /// `CancellationToken::cancelled_owned()`.
+    ///
+    /// The returned future completes once all in-flight requests have been
+    /// answered.
+    pub async fn serve_with_shutdown(self, shutdown: impl Future<Output = ()>) -> io::Result<()> {
+        self.run(shutdown, None).await
+    }
+
+    /// Spawn a task serving until the returned handle is shut down or
+    /// dropped.
+    pub fn spawn(self) -> ServerHandle {
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        let (serving, serving_rx) = watch::channel(false);
+        let task = tokio::spawn(async move {
+            let shutdown = async {
+                let _ = shutdown_rx.await;
+            };
+            self.run(shutdown, Some(serving)).await
+        });
+        ServerHandle {
+            shutdown,
+            serving: serving_rx,
+            task,
+        }
+    }
+
+    async fn run(
+        self,
+        shutdown: impl Future<Output = ()>,
+        serving: Option<watch::Sender<bool>>,
+    ) -> io::Result<()> {
+        #[cfg(feature = "tls")]
+        let acceptor = match self.tls {
+            Some(tls) => Some(tokio_rustls::TlsAcceptor::from(tls.into_server_config()?)),
+            None => None,
+        };
+        let listener = Listener::bind(&self.bind).await?;
+        if let Some(serving) = &serving {
+            serving.send_replace(true);
+        }
+
+        let mut handshakes = Handshakes {
+            #[cfg(feature = "tls")]
+            acceptor,
+            #[cfg(feature = "tls")]
+            timeout: self.endpoint.request_timeout,
+            #[cfg(feature = "tls")]
+            tasks: tokio::task::JoinSet::new(),
+        };
+        let endpoint = Arc::new(self.endpoint);
+        let in_flight = self.max_in_flight.map(|max| Arc::new(Semaphore::new(max)));
+        let graceful = GracefulShutdown::new();
+        tokio::pin!(shutdown);
+        let result = loop {
+            let accept = async {
+                let permit = match &in_flight {
+                    Some(in_flight) => Some(
+                        in_flight
+                            .clone()
+                            .acquire_owned()
+                            .await
+                            .expect("semaphore should not be closed"),
+                    ),
+                    None => None,
+                };
+                listener
+                    .accept()
+                    .await
+                    .map(|(stream, peer)| (stream, Arc::new(peer), permit))
+            };
+            // Connections only hold up the shutdown once they completed the
+            // TLS handshake, clients stalling it are dropped on shutdown.
+            let (stream, peer, permit) = tokio::select! {
+                accepted = accept => match accepted {
+                    Ok(accepted) => match handshakes.start(accepted) {
+                        Some(accepted) => accepted,
+                        None => continue,
+                    },
+                    Err(err) => break Err(err),
+                },
+                accepted = handshakes.next() => accepted,
+                () = &mut shutdown => break Ok(()),
+            };
+            let connection = Connection {
+                peer,
+                endpoint: endpoint.clone(),
+                watcher: graceful.watcher(),
+                _permit: permit,
+            };
+            match stream {
+                Stream::Tcp(stream) => {
+                    tokio::spawn(connection.serve(stream));
+                }
+                #[cfg(feature = "tls")]
+                Stream::Tls(stream) => {
+                    tokio::spawn(connection.serve(stream));
+                }
+                #[cfg(unix)]
+                Stream::Unix(stream) => {
+                    tokio::spawn(connection.serve(stream));
+                }
+            }
+        };
+
+        drop(listener);
+        #[cfg(feature = "tls")]
+        drop(handshakes);
+        if let Some(serving) = &serving {
+            serving.send_replace(false);
+        }
+        graceful.shutdown().await;
+        result
+    }
+}
+
+/// Handle to a [`Server`] running in a task.
+///
+/// Dropping the handle shuts the server down.
+#[derive(Debug)]
+pub struct ServerHandle {
+    shutdown: oneshot::Sender<()>,
+    serving: watch::Receiver<bool>,
+    task: JoinHandle<io::Result<()>>,
+}
+
+impl ServerHandle {
+    /// Whether the server is accepting connections.
+    pub fn is_serving(&self) -> bool {
+        *self.serving.borrow()
+    }
+
+    /// Wait until the server is accepting connections.
+    ///
+    /// Returns `false` if the server stopped before, e.g. because `addr`
+    /// could not be bound. [`ServerHandle::shutdown`] returns the error.
+    pub async fn wait_serving(&mut self) -> bool {
+        self.serving.wait_for(|serving| *serving).await.is_ok()
+    }
+
+    /// Stop accepting connections and wait until all in-flight requests have
+    /// been answered.
+    pub async fn shutdown(self) -> io::Result<()> {
+        let _ = self.shutdown.send(());
+        match self.task.await {
+            Ok(result) => result,
+            Err(err) => Err(io::Error::other(err)),
+        }
+    }
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix {
+        listener: tokio::net::UnixListener,
+        /// The socket file, removed when the listener is dropped.
+        path: std::path::PathBuf,
+    },
+}
+
+enum Stream {
+    Tcp(tokio::net::TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<tokio_rustls::server::TlsStream<tokio::net::TcpStream>>),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixStream),
+}
+
+impl Listener {
+    async fn bind(bind: &Bind) -> io::Result<Self> {
+        Ok(match bind {
+            Bind::Tcp(addr) => Self::Tcp(TcpListener::bind(addr).await?),
+            #[cfg(unix)]
+            Bind::Unix(path) => {
+                use std::os::unix::fs::FileTypeExt;
+
+                // Only sockets are replaced, so a wrong path does not delete
+                // a regular file.
+                if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+                    std::fs::remove_file(path)?;
+                }
+                Self::Unix {
+                    listener: tokio::net::UnixListener::bind(path)?,
+                    path: path.clone(),
+                }
+            }
+        })
+    }
+
+    async fn accept(&self) -> io::Result<(Stream, Peer)> {
+        Ok(match self {
+            Self::Tcp(listener) => {
+                let (stream, peer) = listener.accept().await?;
+                let peer = Peer {
+                    address: peer.to_string(),
+                    client: peer.ip().to_string(),
+                };
+                (Stream::Tcp(stream), peer)
+            }
+            #[cfg(unix)]
+            Self::Unix { listener, .. } => {
+                let (stream, peer) = listener.accept().await?;
+                let peer = Peer {
+                    address: format!("{peer:?}"),
+                    client: "unix".to_string(),
+                };
+                (Stream::Unix(stream), peer)
+            }
+        })
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Self::Unix { path, .. } = self {
+            if let Err(err) = std::fs::remove_file(&*path) {
+                tracing::debug!(path = %path.display(), %err, "failed to remove metrics socket");
+            }
+        }
+    }
+}
+
+/// An accepted connection, with the permit it holds if in-flight
+/// connections are limited.
+type Accepted = (Stream, Arc<Peer>, Option<OwnedSemaphorePermit>);
+
+/// The TLS handshakes of accepted connections in progress.
+struct Handshakes {
+    #[cfg(feature = "tls")]
+    acceptor: Option<tokio_rustls::TlsAcceptor>,
+    /// Time a client has to complete the handshake.
+    #[cfg(feature = "tls")]
+    timeout: Option<Duration>,
+    #[cfg(feature = "tls")]
+    tasks: tokio::task::JoinSet<Option<Accepted>>,
+}
+
+impl Handshakes {
+    /// Start the TLS handshake of `accepted` if serving over TLS, or return
+    /// it to be served right away.
+    fn start(&mut self, accepted: Accepted) -> Option<Accepted> {
+        #[cfg(feature = "tls")]
+        let accepted = match (accepted, &self.acceptor) {
+            ((Stream::Tcp(stream), peer, permit), Some(acceptor)) => {
+                let handshake = acceptor.accept(stream);
+                let timeout = self.timeout;
+                self.tasks.spawn(async move {
+                    let result = match timeout {
+                        Some(timeout) => tokio::time::timeout(timeout, handshake)
+                            .await
+                            .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into())),
+                        None => handshake.await,
+                    };
+                    match result {
+                        Ok(stream) => Some((Stream::Tls(Box::new(stream)), peer, permit)),
+                        Err(err) => {
+                            tracing::debug!(peer = &*peer.address, %err, "metrics TLS handshake failed");
+                            None
+                        }
+                    }
+                });
+                return None;
+            }
+            (accepted, _) => accepted,
+        };
+        Some(accepted)
+    }
+
+    /// Wait for a connection to complete its TLS handshake.
+    async fn next(&mut self) -> Accepted {
+        #[cfg(feature = "tls")]
+        while let Some(handshake) = self.tasks.join_next().await {
+            if let Ok(Some(accepted)) = handshake {
+                return accepted;
+            }
+        }
+        std::future::pending().await
+    }
+}
+
+/// The client of a connection.
+struct Peer {
+    /// Address of the connection.
+    address: String,
+    /// Client the connection counts towards in rate limits.
+    client: String,
+}
+
+/// An accepted connection.
+struct Connection {
+    peer: Arc<Peer>,
+    endpoint: Arc<Endpoint>,
+    watcher: Watcher,
+    /// Held while the connection is served if in-flight connections are
+    /// limited.
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Connection {
+    async fn serve<S>(self, stream: S)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let endpoint = self.endpoint.clone();
+        let peer = self.peer.clone();
+        let service = service_fn(move |request| {
+            let endpoint = endpoint.clone();
+            let peer = peer.clone();
+            async move { Ok::<_, Infallible>(endpoint.handle(&peer, request).await) }
+        });
+        let connection = http1::Builder::new()
+            .timer(TokioTimer::new())
+            .header_read_timeout(self.endpoint.request_timeout)
+            .serve_connection(TokioIo::new(stream), service);
+        if let Err(err) = self.watcher.watch(connection).await {
+            tracing::debug!(peer = self.peer.address, %err, "metrics connection failed");
+        }
+    }
+}
+
+impl Endpoint {
+    async fn handle(
+        self: Arc<Self>,
+        peer: &Peer,
+        request: Request<Incoming>,
+    ) -> Response<Full<Bytes>> {
+        let Some(access_log) = self.access_log.clone() else {
+            return self.respond(peer, request).await.map(Full::new);
+        };
+        let started = Instant::now();
+        let path = request.uri().path().to_string();
+        let user_agent = request
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|user_agent| user_agent.to_str().ok())
+            .map(str::to_string);
+        let response = self.respond(peer, request).await;
+        (access_log.0)(&AccessLog {
+            peer: &peer.address,
+            path: &path,
+            user_agent: user_agent.as_deref(),
+            duration: started.elapsed(),
+            status: response.status(),
+            bytes: response.body().len(),
+        });
+        response.map(Full::new)
+    }
+
+    async fn respond(self: Arc<Self>, peer: &Peer, request: Request<Incoming>) -> Response<Bytes> {
+        #[cfg(feature = "health")]
+        if let Some(health) = &self.health {
+            let result = match request.uri().path() {
+                "/healthz" if health.is_live() => Some(Ok(())),
+                "/healthz" => Some(Err("runtime has not been sampled recently".to_string())),
+                "/readyz" => Some(health.readiness()),
+                _ => None,
+            };
+            if let Some(result) = result {
+                let (status, body) = match result {
+                    Ok(()) => (StatusCode::OK, "ok\n".to_string()),
+                    Err(reason) => (StatusCode::SERVICE_UNAVAILABLE, format!("{reason}\n")),
+                };
+                return Response::builder()
+                    .status(status)
+                    .body(Bytes::from(body))
+                    .expect("response should be valid");
+            }
+        }
+        if request.uri().path() != self.path {
+            return status(StatusCode::NOT_FOUND);
+        }
+        // Authenticated first, so unauthenticated requests cannot use up the
+        // rate limit of a client.
+        if let Some(response) = self.metrics.unauthorized(&request) {
+            return response;
+        }
+        if let Some(Err(wait)) = self
+            .rate_limit
+            .as_ref()
+            .map(|limit| limit.check(&peer.client))
+        {
+            tracing::debug!(client = peer.client, "metrics scraped too often");
+            let mut response = status(StatusCode::TOO_MANY_REQUESTS);
+            let seconds = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+            response.headers_mut().insert(RETRY_AFTER, seconds.into());
+            return response;
+        }
+        let permit = match &self.scrapes {
+            Some(scrapes) => match scrapes.clone().try_acquire_owned() {
+                Ok(permit) => Some(permit),
+                Err(_) => {
+                    tracing::warn!("too many concurrent metrics scrapes");
+                    return status(StatusCode::SERVICE_UNAVAILABLE);
+                }
+            },
+            None => None,
+        };
+
+        match self.scrape_timeout {
+            Some(timeout) => {
+                let endpoint = self.clone();
+                let scrape = tokio::task::spawn_blocking(move || {
+                    let _permit = permit;
+                    endpoint.metrics.respond(&request)
+                });
+                match tokio::time::timeout(timeout, scrape).await {
+                    Ok(Ok(response)) => response,
+                    Ok(Err(err)) => {
+                        tracing::error!(%err, "failed to encode metrics");
+                        status(StatusCode::INTERNAL_SERVER_ERROR)
+                    }
+                    Err(_) => {
+                        tracing::warn!(?timeout, "metrics scrape timed out");
+                        status(StatusCode::SERVICE_UNAVAILABLE)
+                    }
+                }
+            }
+            None => self.metrics.respond(&request),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A free port on the loopback interface.
+    fn free_addr() -> SocketAddr {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .expect("should be able to bind a free port")
+    }
+
+    fn registry() -> Arc<Mutex<prometheus_client::registry::Registry>> {
+        Arc::default()
+    }
+
+    /// Send a `GET` of `path` with `headers` and return the response.
+    async fn get(addr: SocketAddr, path: &str, headers: &str) -> String {
+        let client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        send(client, path, headers).await
+    }
+
+    /// Send a `GET` of `path` with `headers` over `client` and return the
+    /// response.
+    async fn send(
+        mut client: impl AsyncRead + AsyncWrite + Unpin,
+        path: &str,
+        headers: &str,
+    ) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let request =
+            format!("GET {path} HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n{headers}\r\n");
+        client.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn serves_plain_connections() {
+        let addr = free_addr();
+        let mut server = Server::bind(addr, registry()).spawn();
+        assert!(server.wait_serving().await);
+
+        let response = get(addr, "/metrics", "").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        let response = get(addr, "/metrics", "accept: application/openmetrics-text\r\n").await;
+        assert!(response.ends_with("# EOF\n"), "{response}");
+        assert!(get(addr, "/", "")
+            .await
+            .starts_with("HTTP/1.1 404 Not Found\r\n"));
+        server.shutdown().await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn serves_unix_sockets_and_removes_them() {
+        let path = std::env::temp_dir().join("tokio-prometheus-client-server-test.sock");
+        // A socket left behind by a previous instance is replaced.
+        drop(std::os::unix::net::UnixListener::bind(&path));
+        assert!(path.exists());
+
+        for _ in 0..2 {
+            let mut server = Server::bind_unix(&path, registry()).spawn();
+            assert!(server.wait_serving().await);
+            let client = tokio::net::UnixStream::connect(&path).await.unwrap();
+            let response = send(client, "/metrics", "").await;
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+            server.shutdown().await.unwrap();
+            assert!(!path.exists());
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_sockets_do_not_replace_other_files() {
+        let path = std::env::temp_dir().join("tokio-prometheus-client-server-test.file");
+        std::fs::write(&path, "data").unwrap();
+        let server = Server::bind_unix(&path, registry()).spawn();
+        assert!(server.shutdown().await.is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "data");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn from_config_requires_auth() {
+        let addr = free_addr();
+        let config = ExporterConfig {
+            bind: addr,
+            auth: Some(AuthConfig::Bearer {
+                token: "secret".to_string(),
+            }),
+            ..Default::default()
+        };
+        let mut server = Server::from_config(config, registry()).unwrap().spawn();
+        assert!(server.wait_serving().await);
+
+        let response = get(addr, "/metrics", "").await;
+        assert!(
+            response.starts_with("HTTP/1.1 401 Unauthorized\r\n"),
+            "{response}"
+        );
+        let response = get(addr, "/metrics", "authorization: Bearer secret\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        server.shutdown().await.unwrap();
+    }
+
+    #[cfg(feature = "health")]
+    #[tokio::test]
+    async fn health_endpoints_skip_auth() {
+        let addr = free_addr();
+        let runtime_monitor =
+            tokio_metrics::RuntimeMonitor::new(&tokio::runtime::Handle::current());
+        let health = crate::health::RuntimeHealth::new(&runtime_monitor).spawn();
+        for _ in 0..100 {
+            if health.readiness().is_ok() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let metrics = MetricsService::new(registry()).bearer_token("secret");
+        let mut server = Server::bind(addr, metrics).health(health).spawn();
+        assert!(server.wait_serving().await);
+
+        for path in ["/healthz", "/readyz"] {
+            let response = get(addr, path, "").await;
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        }
+        let response = get(addr, "/metrics", "").await;
+        assert!(
+            response.starts_with("HTTP/1.1 401 Unauthorized\r\n"),
+            "{response}"
+        );
+        server.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rate_limits_authenticated_scrapes() {
+        let addr = free_addr();
+        let metrics = MetricsService::new(registry()).bearer_token("secret");
+        let mut server = Server::bind(addr, metrics)
+            .min_scrape_interval(Duration::from_secs(60))
+            .spawn();
+        assert!(server.wait_serving().await);
+
+        let response = get(addr, "/metrics", "").await;
+        assert!(
+            response.starts_with("HTTP/1.1 401 Unauthorized\r\n"),
+            "{response}"
+        );
+        let authorization = "authorization: Bearer secret\r\n";
+        let response = get(addr, "/metrics", authorization).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        let response = get(addr, "/metrics", authorization).await;
+        assert!(
+            response.starts_with("HTTP/1.1 429 Too Many Requests\r\n"),
+            "{response}"
+        );
+        assert!(response.contains("retry-after: 60\r\n"), "{response}");
+        server.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn slow_scrapes_time_out_and_hold_their_permit() {
+        /// Sleeps for a second on every collection.
+        #[derive(Debug)]
+        struct Slow;
+
+        impl prometheus_client::collector::Collector for Slow {
+            fn encode(
+                &self,
+                _: prometheus_client::encoding::DescriptorEncoder,
+            ) -> Result<(), std::fmt::Error> {
+                std::thread::sleep(Duration::from_secs(1));
+                Ok(())
+            }
+        }
+
+        let addr = free_addr();
+        let registry = registry();
+        registry.lock().unwrap().register_collector(Box::new(Slow));
+        let mut server = Server::bind(addr, registry)
+            .max_concurrent_scrapes(1)
+            .scrape_timeout(Some(Duration::from_millis(100)))
+            .spawn();
+        assert!(server.wait_serving().await);
+
+        let started = Instant::now();
+        let unavailable = "HTTP/1.1 503 Service Unavailable\r\n";
+        let response = get(addr, "/metrics", "").await;
+        assert!(response.starts_with(unavailable), "{response}");
+        assert!(started.elapsed() < Duration::from_millis(900));
+        // The timed out encode still holds the only permit.
+        let response = get(addr, "/metrics", "").await;
+        assert!(response.starts_with(unavailable), "{response}");
+        assert!(started.elapsed() < Duration::from_millis(900));
+        server.shutdown().await.unwrap();
+    }
+
+    #[test]
+    fn rate_limit_expires() {
+        let limit = RateLimit::new(Duration::from_millis(50));
+        assert_eq!(limit.check("a"), Ok(()));
+        assert!(limit.check("a").is_err());
+        assert_eq!(limit.check("b"), Ok(()));
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(limit.check("a"), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn logs_handled_requests() {
+        let addr = free_addr();
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        let mut server = Server::bind(addr, registry())
+            .access_log({
+                let logs = logs.clone();
+                move |log| {
+                    logs.lock().unwrap().push((
+                        log.path.to_string(),
+                        log.user_agent.map(str::to_string),
+                        log.status,
+                        log.bytes,
+                        log.peer.to_string(),
+                    ))
+                }
+            })
+            .spawn();
+        assert!(server.wait_serving().await);
+
+        let response = get(addr, "/metrics", "user-agent: Prometheus/3.0\r\n").await;
+        get(addr, "/missing", "").await;
+        server.shutdown().await.unwrap();
+
+        let logs = logs.lock().unwrap();
+        let [(path, user_agent, status, bytes, peer), missing] = logs.as_slice() else {
+            panic!("both requests should be logged: {logs:?}");
+        };
+        assert_eq!(
+            (path.as_str(), user_agent.as_deref(), *status),
+            ("/metrics", Some("Prometheus/3.0"), StatusCode::OK)
+        );
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        assert_eq!(*bytes, body.len());
+        assert!(peer.starts_with("127.0.0.1:"), "{peer}");
+        assert_eq!(
+            (missing.0.as_str(), &missing.1, missing.2),
+            ("/missing", &None, StatusCode::NOT_FOUND)
+        );
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn from_config_loads_tls_files() {
+        let testdata = concat!(env!("CARGO_MANIFEST_DIR"), "/src/server/testdata");
+        let config = |cert: &str| ExporterConfig {
+            tls: Some(TlsFiles {
+                cert: format!("{testdata}/{cert}").into(),
+                key: format!("{testdata}/key.pem").into(),
+                client_ca: None,
+            }),
+            ..Default::default()
+        };
+        let server = Server::from_config(config("cert.pem"), registry()).unwrap();
+        assert!(server.tls.is_some());
+        assert!(Server::from_config(config("missing.pem"), registry()).is_err());
+    }
+
+    #[cfg(feature = "tls")]
+    fn tls() -> TlsConfig {
+        let testdata = concat!(env!("CARGO_MANIFEST_DIR"), "/src/server/testdata");
+        TlsConfig::from_pem_files(
+            format!("{testdata}/cert.pem"),
+            format!("{testdata}/key.pem"),
+        )
+        .expect("test certificate should load")
+    }
+
+    #[cfg(feature = "tls")]
+    #[tokio::test]
+    async fn stalled_tls_handshake_times_out() {
+        use tokio::io::AsyncReadExt;
+
+        let addr = free_addr();
+        let mut server = Server::bind(addr, registry())
+            .tls(tls())
+            .request_timeout(Some(Duration::from_millis(50)))
+            .spawn();
+        assert!(server.wait_serving().await);
+
+        let mut client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let read = tokio::time::timeout(Duration::from_secs(5), client.read(&mut [0; 1]))
+            .await
+            .expect("server should close the connection");
+        assert_eq!(read.unwrap(), 0);
+        server.shutdown().await.unwrap();
+    }
+
+    #[cfg(feature = "tls")]
+    #[tokio::test]
+    async fn stalled_tls_handshake_does_not_block_shutdown() {
+        let addr = free_addr();
+        let mut server = Server::bind(addr, registry())
+            .tls(tls())
+            .request_timeout(None)
+            .spawn();
+        assert!(server.wait_serving().await);
+
+        let _client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        tokio::time::timeout(Duration::from_secs(5), server.shutdown())
+            .await
+            .expect("shutdown should not wait for the handshake")
+            .unwrap();
+    }
+}
@@ -0,0 +1,206 @@
+//! Configuration of the built-in server.
+
+#[cfg(feature = "tls")]
+use std::path::PathBuf;
+use std::{net::SocketAddr, time::Duration};
+
+/// Configuration of the built-in server, e.g. read from an application
+/// config file.
+///
+/// With the `serde` feature the configuration can be deserialized, durations
+/// are given in seconds and missing fields take their default values.
+///
+/// ## Example
+///
+/// ```no_run
+/// # use std::sync::{Arc, Mutex};
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let handle = tokio::runtime::Handle::current();
+/// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// tokio_prometheus_client::register(runtime_monitor, registry.sub_registry_with_prefix("tokio"));
+///
+/// let config = tokio_prometheus_client::server::ExporterConfig {
+///     bind: "127.0.0.1:9090".parse().unwrap(),
+///     max_in_flight: Some(4),
+///     ..Default::default()
+/// };
+/// tokio_prometheus_client::server::Server::from_config(config, Arc::new(Mutex::new(registry)))
+///     .unwrap()
+///     .serve()
+///     .await
+///     .unwrap();
+/// # });
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct ExporterConfig {
+    /// Address to listen on, `0.0.0.0:9090` by default.
+    pub bind: SocketAddr,
+    /// Path metrics are served on, `/metrics` by default.
+    pub path: String,
+    /// Time a client has to send its request, 30 seconds by default.
+    #[cfg_attr(feature = "serde", serde(with = "seconds"))]
+    pub request_timeout: Option<Duration>,
+    /// Maximum number of connections served at the same time, unlimited by
+    /// default.
+    pub max_in_flight: Option<usize>,
+    /// Maximum number of scrapes encoded at the same time, unlimited by
+    /// default. Further scrapes are answered with `503 Service Unavailable`.
+    pub max_concurrent_scrapes: Option<usize>,
+    /// Time a scrape may take to encode before it is answered with
+    /// `503 Service Unavailable`, 10 seconds by default.
+    #[cfg_attr(feature = "serde", serde(with = "seconds"))]
+    pub scrape_timeout: Option<Duration>,
+    /// Minimum time between scrapes of the same client, unlimited by
+    /// default. Further scrapes are answered with `429 Too Many Requests`.
+    #[cfg_attr(feature = "serde", serde(with = "seconds"))]
+    pub min_scrape_interval: Option<Duration>,
+    /// Compress responses when the client accepts it, enabled by default.
+    ///
+    /// Only has an effect with the `gzip` feature.
+    pub compression: bool,
+    /// Credentials scrapes have to authenticate with, none by default.
+    pub auth: Option<AuthConfig>,
+    /// Serve over TLS with these files, plain HTTP by default.
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsFiles>,
+}
+
+/// Credentials of [`ExporterConfig::auth`].
+///
+/// Deserialized from a map with a single `basic` or `bearer` key.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case", deny_unknown_fields)
+)]
+pub enum AuthConfig {
+    /// HTTP basic auth, see
+    /// [`MetricsService::basic_auth`](crate::tower::MetricsService::basic_auth).
+    Basic {
+        /// The expected username.
+        username: String,
+        /// The expected password.
+        password: String,
+    },
+    /// A bearer token, see
+    /// [`MetricsService::bearer_token`](crate::tower::MetricsService::bearer_token).
+    Bearer {
+        /// The expected token.
+        token: String,
+    },
+}
+
+/// PEM files of [`ExporterConfig::tls`], see
+/// [`TlsConfig`](super::TlsConfig).
+#[cfg(feature = "tls")]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(deny_unknown_fields)
+)]
+pub struct TlsFiles {
+    /// The certificate chain.
+    pub cert: PathBuf,
+    /// The private key.
+    pub key: PathBuf,
+    /// CA certificates client certificates have to be signed by, if clients
+    /// have to present one.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub client_ca: Option<PathBuf>,
+}
+
+impl Default for ExporterConfig {
+    fn default() -> Self {
+        Self {
+            bind: SocketAddr::from(([0, 0, 0, 0], 9090)),
+            path: "/metrics".to_string(),
+            request_timeout: Some(Duration::from_secs(30)),
+            max_in_flight: None,
+            max_concurrent_scrapes: None,
+            scrape_timeout: Some(Duration::from_secs(10)),
+            min_scrape_interval: None,
+            compression: true,
+            auth: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+mod seconds {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer};
+
+    pub(super) fn deserialize<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Option::<f64>::deserialize(deserializer)?
+            .map(|secs| Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deserializes_defaults() {
+        let config: ExporterConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(config, ExporterConfig::default());
+    }
+
+    #[test]
+    fn deserializes_auth() {
+        let config: ExporterConfig = serde_json::from_str(
+            r#"{"auth": {"basic": {"username": "prometheus", "password": "secret"}}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.auth,
+            Some(AuthConfig::Basic {
+                username: "prometheus".to_string(),
+                password: "secret".to_string(),
+            }),
+        );
+        let config: ExporterConfig = serde_json::from_str(
+            r#"{"auth": {"bearer": {"token": "secret"}}, "scrape_timeout": 2.5}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.auth,
+            Some(AuthConfig::Bearer {
+                token: "secret".to_string()
+            }),
+        );
+        assert_eq!(config.scrape_timeout, Some(Duration::from_millis(2500)));
+        assert!(serde_json::from_str::<ExporterConfig>(r#"{"auth": {"digest": {}}}"#).is_err());
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn deserializes_tls() {
+        let config: ExporterConfig =
+            serde_json::from_str(r#"{"tls": {"cert": "tls.crt", "key": "tls.key"}}"#).unwrap();
+        assert_eq!(
+            config.tls,
+            Some(TlsFiles {
+                cert: "tls.crt".into(),
+                key: "tls.key".into(),
+                client_ca: None,
+            }),
+        );
+    }
+}
@@ -0,0 +1,77 @@
+//! TLS configuration of the built-in server.
+
+use std::{io, path::Path, sync::Arc};
+
+use tokio_rustls::rustls::{
+    crypto::ring::default_provider,
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+    RootCertStore, ServerConfig,
+};
+
+/// TLS configuration for [`serve_metrics_tls`](super::serve_metrics_tls).
+///
+/// ## Example
+///
+/// ```no_run
+/// use tokio_prometheus_client::server::TlsConfig;
+///
+/// let tls = TlsConfig::from_pem_files("/etc/metrics/tls.crt", "/etc/metrics/tls.key")?
+///     // Only accept scrapers presenting a certificate signed by this CA.
+///     .client_ca_pem_file("/etc/metrics/ca.crt")?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct TlsConfig {
+    certs: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+    client_roots: Option<RootCertStore>,
+}
+
+impl TlsConfig {
+    /// Load the certificate chain and private key from PEM files.
+    pub fn from_pem_files(cert: impl AsRef<Path>, key: impl AsRef<Path>) -> io::Result<Self> {
+        let certs = CertificateDer::pem_file_iter(cert)
+            .map_err(io::Error::other)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(io::Error::other)?;
+        let key = PrivateKeyDer::from_pem_file(key).map_err(io::Error::other)?;
+        Ok(Self {
+            certs,
+            key,
+            client_roots: None,
+        })
+    }
+
+    /// Require clients to present a certificate signed by one of the CA
+    /// certificates in the PEM file at `path`.
+    pub fn client_ca_pem_file(mut self, path: impl AsRef<Path>) -> io::Result<Self> {
+        let roots = self.client_roots.get_or_insert_with(RootCertStore::empty);
+        for cert in CertificateDer::pem_file_iter(path).map_err(io::Error::other)? {
+            roots
+                .add(cert.map_err(io::Error::other)?)
+                .map_err(io::Error::other)?;
+        }
+        Ok(self)
+    }
+
+    pub(super) fn into_server_config(self) -> io::Result<Arc<ServerConfig>> {
+        let provider = Arc::new(default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(io::Error::other)?;
+        let builder = match self.client_roots {
+            Some(roots) => builder.with_client_cert_verifier(
+                WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                    .build()
+                    .map_err(io::Error::other)?,
+            ),
+            None => builder.with_no_client_auth(),
+        };
+        let mut config = builder
+            .with_single_cert(self.certs, self.key)
+            .map_err(io::Error::other)?;
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(Arc::new(config))
+    }
+}
@@ -0,0 +1,69 @@
+//! Signals of the health of a runtime during an interval, shared by the
+//! `health` gauge and the readiness checks of the `health` feature.
+
+use std::time::Duration;
+
+use tokio_metrics::RuntimeMetrics;
+
+/// The values thresholds on the health of a runtime are compared with.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Signals {
+    /// Mean duration of the task polls.
+    pub(crate) mean_poll_duration: Duration,
+    /// Tasks waiting in the injection queue at the end of the interval.
+    pub(crate) injection_queue_depth: usize,
+    /// Tasks waiting in the local queues of all workers at the end of the
+    /// interval.
+    pub(crate) local_queue_depth: usize,
+    /// Times per second tasks were forced to yield after exhausting their
+    /// budget.
+    pub(crate) forced_yields_per_second: f64,
+    /// Fraction of the time, between 0 and 1, the workers were busy.
+    pub(crate) busy_ratio: f64,
+}
+
+impl Signals {
+    /// The signals of the runtime during `interval`.
+    pub(crate) fn new(interval: &RuntimeMetrics) -> Self {
+        let elapsed = interval.elapsed.as_secs_f64();
+        let capacity = elapsed * interval.workers_count as f64;
+        let ratio = |value: f64, total: f64| if total > 0.0 { value / total } else { 0.0 };
+        Self {
+            mean_poll_duration: interval.mean_poll_duration,
+            injection_queue_depth: interval.injection_queue_depth,
+            local_queue_depth: interval.total_local_queue_depth,
+            forced_yields_per_second: ratio(interval.budget_forced_yield_count as f64, elapsed),
+            busy_ratio: ratio(interval.total_busy_duration.as_secs_f64(), capacity),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rates_and_ratios_of_interval() {
+        let mut interval = RuntimeMetrics::default();
+        interval.workers_count = 4;
+        interval.elapsed = Duration::from_secs(2);
+        interval.total_busy_duration = Duration::from_secs(6);
+        interval.budget_forced_yield_count = 10;
+        interval.injection_queue_depth = 3;
+        interval.total_local_queue_depth = 7;
+        interval.mean_poll_duration = Duration::from_millis(5);
+        let signals = Signals::new(&interval);
+        assert_eq!(signals.busy_ratio, 0.75);
+        assert_eq!(signals.forced_yields_per_second, 5.0);
+        assert_eq!(signals.injection_queue_depth, 3);
+        assert_eq!(signals.local_queue_depth, 7);
+        assert_eq!(signals.mean_poll_duration, Duration::from_millis(5));
+    }
+
+    #[test]
+    fn empty_interval_has_no_load() {
+        let signals = Signals::new(&RuntimeMetrics::default());
+        assert_eq!(signals.busy_ratio, 0.0);
+        assert_eq!(signals.forced_yields_per_second, 0.0);
+    }
+}
@@ -0,0 +1,459 @@
+//! Emit a [`Registry`] as [statsd](https://github.com/statsd/statsd) or
+//! [DogStatsD](https://docs.datadoghq.com/developers/dogstatsd/) packets.
+//!
+//! Enabled with the `statsd` feature.
+
+use std::{
+    borrow::Borrow,
+    collections::HashMap,
+    fmt::Write,
+    io,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use prometheus_client::{metrics::MetricType, registry::Registry};
+use tokio::{
+    net::{ToSocketAddrs, UdpSocket},
+    task::JoinHandle,
+    time::MissedTickBehavior,
+};
+
+use crate::samples::{collect, format_value, histogram_points, HistogramPoint};
+
+/// Maximum payload of a single datagram, small enough to avoid IP
+/// fragmentation on common networks.
+const MAX_PACKET_SIZE: usize = 1432;
+
+/// Emits a [`Registry`] as statsd packets.
+///
+/// Gauges and info metrics are sent as gauges. Counters are sent as counts
+/// of the increase since the previous emission. Histograms are sent as the
+/// observations since the previous emission, as DogStatsD distributions or
+/// statsd timers in the unit of the histogram. The exact values are not
+/// known, so each observation is sent as the upper bound of its bucket, or
+/// the largest finite bound for the `+Inf` bucket, with a sample rate
+/// counting all observations of the bucket in a single line.
+///
+/// Labels are sent as tags in DogStatsD mode, otherwise their values are
+/// appended to the metric name separated by dots.
+///
+/// ## Example
+///
+/// ```no_run
+/// # use std::{sync::{Arc, Mutex}, time::Duration};
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let handle = tokio::runtime::Handle::current();
+/// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// tokio_prometheus_client::register(runtime_monitor, registry.sub_registry_with_prefix("tokio"));
+///
+/// tokio_prometheus_client::statsd::Statsd::connect_udp("127.0.0.1:8125")
+///     .await
+///     .unwrap()
+///     .dogstatsd(true)
+///     .interval(Duration::from_secs(10))
+///     .spawn(Arc::new(Mutex::new(registry)));
+/// # });
+/// ```
+#[derive(Debug)]
+pub struct Statsd {
+    socket: Socket,
+    dogstatsd: bool,
+    prefix: String,
+    interval: Duration,
+    previous: HashMap<String, f64>,
+    /// Previous observations per bucket of histograms.
+    previous_buckets: HashMap<String, Vec<f64>>,
+}
+
+#[derive(Debug)]
+enum Socket {
+    Udp(UdpSocket),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixDatagram),
+}
+
+impl Statsd {
+    /// Create a [`Statsd`] sending UDP packets to `addr`.
+    ///
+    /// Emissions happen every 10 seconds.
+    pub async fn connect_udp(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        socket.connect(addr).await?;
+        Ok(Self::new(Socket::Udp(socket)))
+    }
+
+    /// Create a [`Statsd`] sending datagrams to the Unix domain socket at
+    /// `path`, as supported by the Datadog agent.
+    ///
+    /// Emissions happen every 10 seconds.
+    #[cfg(unix)]
+    pub fn connect_unix(path: impl AsRef<std::path::Path>) -> io::Result<Self> {
+        let socket = tokio::net::UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(Self::new(Socket::Unix(socket)))
+    }
+
+    fn new(socket: Socket) -> Self {
+        Self {
+            socket,
+            dogstatsd: false,
+            prefix: String::new(),
+            interval: Duration::from_secs(10),
+            previous: HashMap::new(),
+            previous_buckets: HashMap::new(),
+        }
+    }
+
+    /// Send labels as DogStatsD tags.
+    pub fn dogstatsd(mut self, dogstatsd: bool) -> Self {
+        self.dogstatsd = dogstatsd;
+        self
+    }
+
+    /// Prefix every metric name, e.g. with `myapp.`.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Set the interval between emissions of the background task.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Encode `registry` and send all of its samples.
+    ///
+    /// The first emission establishes the baseline for counters and
+    /// histograms and sends their full value. The baseline only moves once
+    /// all packets were sent, so a failed emission is repeated in full by
+    /// the next one.
+    pub async fn emit(&mut self, registry: &Mutex<Registry>) -> io::Result<()> {
+        let families = collect(&registry.lock().expect("should be able to lock registry"))
+            .map_err(|_| io::Error::other("failed to encode metrics"))?;
+
+        let mut lines = Vec::new();
+        let mut previous = Vec::new();
+        let mut previous_buckets = Vec::new();
+        for family in &families {
+            if matches!(family.metric_type, MetricType::Histogram) {
+                for point in histogram_points(&family.samples) {
+                    let (key, observations) =
+                        self.histogram_lines(&family.name, &point, &mut lines);
+                    previous_buckets.push((key, observations));
+                }
+                continue;
+            }
+            for sample in &family.samples {
+                let line = match family.metric_type {
+                    MetricType::Counter => {
+                        if sample.name.ends_with("_created") {
+                            continue;
+                        }
+                        let key = format!("{}{:?}", sample.name, sample.labels);
+                        let last = self.previous.get(&key).copied().unwrap_or(0.0);
+                        previous.push((key, sample.value));
+                        // Counters only go down when they were reset.
+                        let delta = if sample.value >= last {
+                            sample.value - last
+                        } else {
+                            sample.value
+                        };
+                        self.line(&sample.name, &sample.labels, delta, "c", None)
+                    }
+                    _ => self.line(&sample.name, &sample.labels, sample.value, "g", None),
+                };
+                lines.push(line);
+            }
+        }
+
+        let mut packet = String::new();
+        for line in lines {
+            if !packet.is_empty() && packet.len() + line.len() + 1 > MAX_PACKET_SIZE {
+                self.send(&packet).await?;
+                packet.clear();
+            }
+            if !packet.is_empty() {
+                packet.push('\n');
+            }
+            packet.push_str(&line);
+        }
+        if !packet.is_empty() {
+            self.send(&packet).await?;
+        }
+        self.previous.extend(previous);
+        self.previous_buckets.extend(previous_buckets);
+        Ok(())
+    }
+
+    /// Spawn a task emitting `registry` every interval.
+    ///
+    /// Failed emissions are logged.
+    pub fn spawn(mut self, registry: Arc<Mutex<Registry>>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                if let Err(err) = self.emit(&registry).await {
+                    tracing::warn!(%err, "failed to emit statsd metrics");
+                }
+            }
+        })
+    }
+
+    /// Add a line for the observations of each bucket of `point` since the
+    /// previous emission to `lines`, returning the key and observations per
+    /// bucket of the baseline of the next emission.
+    fn histogram_lines(
+        &self,
+        name: &str,
+        point: &HistogramPoint<'_>,
+        lines: &mut Vec<String>,
+    ) -> (String, Vec<f64>) {
+        let mut cumulative = 0.0;
+        let observations: Vec<f64> = point
+            .buckets
+            .iter()
+            .map(|(_, count)| {
+                let observations = (count - cumulative).max(0.0);
+                cumulative = *count;
+                observations
+            })
+            .collect();
+        let key = format!("{name}{:?}", point.labels);
+        let baseline = observations.clone();
+        let observations = match self.previous_buckets.get(&key) {
+            // Buckets only lose observations when the histogram was reset.
+            Some(previous)
+                if previous.len() == observations.len()
+                    && observations
+                        .iter()
+                        .zip(previous)
+                        .all(|(now, then)| now >= then) =>
+            {
+                observations
+                    .iter()
+                    .zip(previous)
+                    .map(|(now, then)| now - then)
+                    .collect()
+            }
+            _ => observations,
+        };
+
+        let statsd_type = if self.dogstatsd { "d" } else { "ms" };
+        let mut largest_finite = 0.0;
+        for ((bound, _), observations) in point.buckets.iter().zip(observations) {
+            let value = if bound.is_finite() {
+                largest_finite = *bound;
+                *bound
+            } else {
+                largest_finite
+            };
+            if observations >= 1.0 {
+                let line = self.line(
+                    name,
+                    &point.labels,
+                    value,
+                    statsd_type,
+                    Some(1.0 / observations),
+                );
+                lines.push(line);
+            }
+        }
+        (key, baseline)
+    }
+
+    fn line<L: Borrow<(String, String)>>(
+        &self,
+        name: &str,
+        labels: &[L],
+        value: f64,
+        statsd_type: &str,
+        sample_rate: Option<f64>,
+    ) -> String {
+        let mut line = self.prefix.clone();
+        line.push_str(name);
+        if !self.dogstatsd {
+            for label in labels {
+                line.push('.');
+                line.push_str(&sanitize(&label.borrow().1));
+            }
+        }
+        let _ = write!(line, ":{}|{statsd_type}", format_value(value));
+        if let Some(sample_rate) = sample_rate {
+            let _ = write!(line, "|@{sample_rate}");
+        }
+        if self.dogstatsd && !labels.is_empty() {
+            line.push_str("|#");
+            for (i, label) in labels.iter().enumerate() {
+                if i > 0 {
+                    line.push(',');
+                }
+                let (key, label) = label.borrow();
+                let _ = write!(line, "{key}:{}", sanitize(label));
+            }
+        }
+        line
+    }
+
+    async fn send(&self, packet: &str) -> io::Result<()> {
+        match &self.socket {
+            Socket::Udp(socket) => socket.send(packet.as_bytes()).await?,
+            #[cfg(unix)]
+            Socket::Unix(socket) => socket.send(packet.as_bytes()).await?,
+        };
+        Ok(())
+    }
+}
+
+/// Replace characters with a meaning in the statsd line format.
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            ':' | '|' | '@' | ',' | '#' | '\n' | ' ' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus_client::metrics::{counter::Counter, family::Family, histogram::Histogram};
+
+    use super::*;
+
+    type Labels = Vec<(String, String)>;
+
+    async fn statsd(dogstatsd: bool) -> (Statsd, UdpSocket) {
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let statsd = Statsd::connect_udp(receiver.local_addr().unwrap())
+            .await
+            .unwrap()
+            .dogstatsd(dogstatsd);
+        (statsd, receiver)
+    }
+
+    async fn emitted(
+        statsd: &mut Statsd,
+        receiver: &UdpSocket,
+        registry: &Mutex<Registry>,
+    ) -> Vec<String> {
+        statsd.emit(registry).await.unwrap();
+        let mut buffer = [0; MAX_PACKET_SIZE];
+        let len = receiver.recv(&mut buffer).await.unwrap();
+        let mut lines: Vec<String> = std::str::from_utf8(&buffer[..len])
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+        lines.sort();
+        lines
+    }
+
+    #[tokio::test]
+    async fn counters_send_increases_and_resets() {
+        let counter = Family::<Labels, Counter>::default();
+        let mut registry = Registry::default();
+        registry.register("requests", "Requests", counter.clone());
+        let registry = Mutex::new(registry);
+        let labels = vec![("path".to_owned(), "a:b|c".to_owned())];
+        let (mut statsd, receiver) = statsd(true).await;
+
+        counter.get_or_create(&labels).inc_by(5);
+        assert_eq!(
+            emitted(&mut statsd, &receiver, &registry).await,
+            ["requests_total:5|c|#path:a_b_c"]
+        );
+        counter.get_or_create(&labels).inc_by(2);
+        assert_eq!(
+            emitted(&mut statsd, &receiver, &registry).await,
+            ["requests_total:2|c|#path:a_b_c"]
+        );
+        counter.clear();
+        counter.get_or_create(&labels).inc();
+        assert_eq!(
+            emitted(&mut statsd, &receiver, &registry).await,
+            ["requests_total:1|c|#path:a_b_c"]
+        );
+    }
+
+    #[tokio::test]
+    async fn histograms_send_observations_per_bucket() {
+        let histogram = Histogram::new([0.1, 1.0].into_iter());
+        let mut registry = Registry::default();
+        registry.register("latency", "Latency", histogram.clone());
+        let registry = Mutex::new(registry);
+        let (mut statsd, receiver) = statsd(true).await;
+
+        histogram.observe(0.05);
+        histogram.observe(0.5);
+        histogram.observe(0.5);
+        histogram.observe(5.0);
+        assert_eq!(
+            emitted(&mut statsd, &receiver, &registry).await,
+            ["latency:0.1|d|@1", "latency:1|d|@0.5", "latency:1|d|@1"]
+        );
+        histogram.observe(0.05);
+        assert_eq!(
+            emitted(&mut statsd, &receiver, &registry).await,
+            ["latency:0.1|d|@1"]
+        );
+    }
+
+    #[tokio::test]
+    async fn statsd_appends_labels_to_names() {
+        let histogram =
+            Family::<Labels, Histogram>::new_with_constructor(|| Histogram::new([1.0].into_iter()));
+        let mut registry = Registry::default();
+        registry.register("latency", "Latency", histogram.clone());
+        let registry = Mutex::new(registry);
+        let (mut statsd, receiver) = statsd(false).await;
+
+        let labels = vec![("method".to_owned(), "get".to_owned())];
+        histogram.get_or_create(&labels).observe(0.5);
+        histogram.get_or_create(&labels).observe(0.5);
+        assert_eq!(
+            emitted(&mut statsd, &receiver, &registry).await,
+            ["latency.get:1|ms|@0.5"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn failed_emissions_keep_the_baseline() {
+        let counter = Counter::<u64>::default();
+        let histogram = Histogram::new([1.0].into_iter());
+        let mut registry = Registry::default();
+        registry.register("requests", "Requests", counter.clone());
+        registry.register("latency", "Latency", histogram.clone());
+        let registry = Mutex::new(registry);
+
+        let path = std::env::temp_dir().join("tokio-prometheus-client-statsd-failed.sock");
+        let _ = std::fs::remove_file(&path);
+        let gone = tokio::net::UnixDatagram::bind(&path).unwrap();
+        let mut statsd = Statsd::connect_unix(&path).unwrap().dogstatsd(true);
+        drop(gone);
+        std::fs::remove_file(&path).unwrap();
+
+        counter.inc_by(5);
+        histogram.observe(0.5);
+        statsd.emit(&registry).await.unwrap_err();
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket
+            .connect(receiver.local_addr().unwrap())
+            .await
+            .unwrap();
+        statsd.socket = Socket::Udp(socket);
+        assert_eq!(
+            emitted(&mut statsd, &receiver, &registry).await,
+            ["latency:1|d|@1", "requests_total:5|c"]
+        );
+    }
+}
@@ -0,0 +1,244 @@
+//! Expose poll durations as quantiles instead of histogram buckets.
+//!
+//! Enabled with the `summary` feature. A handful of quantile series is much
+//! cheaper to store than a bucketed histogram for every runtime.
+
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use prometheus_client::{
+    collector::Collector,
+    encoding::{DescriptorEncoder, EncodeMetric},
+    metrics::{gauge::ConstGauge, MetricType},
+    registry::{Registry, Unit},
+};
+use tokio::runtime::Handle;
+use tokio_metrics::{RuntimeIntervals, RuntimeMonitor};
+
+/// Estimates quantiles of task poll durations over a sliding window.
+///
+/// The durations are taken from the poll time histogram of the runtime,
+/// which has to be enabled with
+/// [`Builder::enable_metrics_poll_time_histogram`](tokio::runtime::Builder::enable_metrics_poll_time_histogram).
+/// Quantiles are interpolated within the buckets of that histogram, so its
+/// resolution bounds their precision. The runtime does not record
+/// scheduling durations, so only poll durations are available.
+///
+/// Exposes `poll_time_seconds` gauges labeled with the `quantile`, `NaN`
+/// while no task was polled within the window. As the buckets carry no sum,
+/// there are no `_sum` and `_count` series.
+///
+/// ## Example
+///
+/// ```
+/// # use std::time::Duration;
+/// let rt = tokio::runtime::Builder::new_multi_thread()
+///     .enable_metrics_poll_time_histogram()
+///     .build()
+///     .unwrap();
+///
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// tokio_prometheus_client::summary::PollTimeSummary::new(rt.handle())
+///     .quantiles(&[0.5, 0.99])
+///     .window(Duration::from_secs(300))
+///     .register(registry.sub_registry_with_prefix("tokio"));
+/// ```
+#[derive(Debug)]
+pub struct PollTimeSummary {
+    handle: Handle,
+    quantiles: Vec<f64>,
+    window: Duration,
+}
+
+impl PollTimeSummary {
+    /// Create a [`PollTimeSummary`] of the runtime of `handle` estimating
+    /// the 0.5, 0.9 and 0.99 quantiles over the last 10 minutes.
+    pub fn new(handle: &Handle) -> Self {
+        Self {
+            handle: handle.clone(),
+            quantiles: vec![0.5, 0.9, 0.99],
+            window: Duration::from_secs(600),
+        }
+    }
+
+    /// Set the quantiles to estimate, between 0 and 1.
+    pub fn quantiles(mut self, quantiles: &[f64]) -> Self {
+        self.quantiles = quantiles.to_vec();
+        self
+    }
+
+    /// Set the window of polls the quantiles are estimated over.
+    pub fn window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Register the collector of the quantiles with `registry`.
+    pub fn register(self, registry: &mut Registry) {
+        let metrics = self.handle.metrics();
+        let bounds: Vec<_> = (0..metrics.poll_time_histogram_num_buckets())
+            .map(|bucket| {
+                let range = metrics.poll_time_histogram_bucket_range(bucket);
+                (range.start.as_secs_f64(), range.end.as_secs_f64())
+            })
+            .collect();
+        registry.register_collector(Box::new(SummaryCollector {
+            counts: Mutex::new(vec![0; bounds.len()]),
+            quantiles: self
+                .quantiles
+                .into_iter()
+                .map(|quantile| (quantile, quantile.to_string()))
+                .collect(),
+            window: self.window,
+            bounds,
+            state: Mutex::new(State {
+                intervals: RuntimeMonitor::new(&self.handle).intervals(),
+                samples: VecDeque::new(),
+            }),
+        }))
+    }
+}
+
+/// Collects the quantiles of a [`PollTimeSummary`].
+#[derive(Debug)]
+struct SummaryCollector {
+    /// The quantiles with their preformatted labels.
+    quantiles: Vec<(f64, String)>,
+    window: Duration,
+    /// Lower and upper bound of each bucket, in seconds.
+    bounds: Vec<(f64, f64)>,
+    state: Mutex<State>,
+    /// Bucket counts summed over the window, reused between collections.
+    counts: Mutex<Vec<u64>>,
+}
+
+#[derive(Debug)]
+struct State {
+    intervals: RuntimeIntervals,
+    /// Bucket counts of the intervals within the window.
+    samples: VecDeque<(Instant, Vec<u64>)>,
+}
+
+impl Collector for SummaryCollector {
+    fn encode(&self, mut encoder: DescriptorEncoder) -> Result<(), std::fmt::Error> {
+        let mut counts = self.counts.lock().expect("should be able to lock counts");
+        counts.fill(0);
+        {
+            let mut state = self.state.lock().expect("should be able to lock state");
+            let interval = state
+                .intervals
+                .next()
+                .expect("should always be another interval");
+            let now = Instant::now();
+            state
+                .samples
+                .push_back((now, interval.poll_count_histogram));
+            while let Some((sampled_at, _)) = state.samples.front() {
+                if now.duration_since(*sampled_at) <= self.window {
+                    break;
+                }
+                state.samples.pop_front();
+            }
+            for (_, sample) in &state.samples {
+                for (count, sampled) in counts.iter_mut().zip(sample) {
+                    *count = count.saturating_add(*sampled);
+                }
+            }
+        }
+
+        let mut family = encoder.encode_descriptor(
+            "poll_time",
+            "Estimated quantiles of task poll durations",
+            Some(&Unit::Seconds),
+            MetricType::Gauge,
+        )?;
+        for (quantile, label) in &self.quantiles {
+            let value = self.estimate(&counts, *quantile);
+            let labels = [("quantile", label.as_str())];
+            ConstGauge::new(value).encode(family.encode_family(&labels)?)?;
+        }
+        Ok(())
+    }
+}
+
+impl SummaryCollector {
+    /// Estimate `quantile` from bucket `counts`, interpolating linearly
+    /// within the bucket it falls into.
+    fn estimate(&self, counts: &[u64], quantile: f64) -> f64 {
+        // Saturated like the counts, rather than overflowing.
+        let total = counts
+            .iter()
+            .fold(0u64, |total, count| total.saturating_add(*count));
+        if total == 0 {
+            return f64::NAN;
+        }
+        let rank = quantile.clamp(0.0, 1.0) * total as f64;
+        let mut below = 0.0;
+        let last = self.bounds.len() - 1;
+        for (bucket, (count, (start, end))) in counts.iter().zip(&self.bounds).enumerate() {
+            let count = *count as f64;
+            if count > 0.0 && below + count >= rank {
+                // The last bucket is unbounded.
+                if bucket == last {
+                    return *start;
+                }
+                return start + (end - start) * ((rank - below) / count);
+            }
+            below += count;
+        }
+        self.bounds.last().map_or(f64::NAN, |(start, _)| *start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A collector of a runtime with buckets of 1ms up to 3ms and an
+    /// unbounded one.
+    fn collector(runtime: &tokio::runtime::Runtime) -> SummaryCollector {
+        SummaryCollector {
+            quantiles: Vec::new(),
+            window: Duration::from_secs(60),
+            bounds: vec![(0.0, 0.001), (0.001, 0.002), (0.002, 0.003), (0.003, 1e9)],
+            state: Mutex::new(State {
+                intervals: RuntimeMonitor::new(runtime.handle()).intervals(),
+                samples: VecDeque::new(),
+            }),
+            counts: Mutex::new(vec![0; 4]),
+        }
+    }
+
+    #[test]
+    fn estimate_interpolates_within_buckets() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let collector = collector(&runtime);
+        assert!(collector.estimate(&[0; 4], 0.5).is_nan());
+
+        let counts = [10, 0, 30, 0];
+        assert_eq!(collector.estimate(&counts, 0.0), 0.0);
+        assert_eq!(collector.estimate(&counts, 0.125), 0.0005);
+        assert_eq!(collector.estimate(&counts, 0.25), 0.001);
+        assert!((collector.estimate(&counts, 0.625) - 0.0025).abs() < 1e-12);
+        assert_eq!(collector.estimate(&counts, 1.0), 0.003);
+        assert_eq!(collector.estimate(&counts, 2.0), 0.003);
+        // The unbounded bucket has no upper bound to interpolate to.
+        assert_eq!(collector.estimate(&[0, 0, 0, 5], 0.5), 0.003);
+    }
+
+    #[test]
+    fn estimate_of_saturated_counts() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let collector = collector(&runtime);
+        let counts = [u64::MAX, u64::MAX, 0, 0];
+        assert_eq!(collector.estimate(&counts, 0.5), 0.0005);
+        assert_eq!(collector.estimate(&counts, 1.0), 0.001);
+    }
+}
@@ -0,0 +1,179 @@
+//! Instrumented synchronization primitives of `tokio::sync`.
+//!
+//! Enabled with the `sync` feature. The runtime metrics show that tasks
+//! wait, but not on what. The primitives created by a [`SyncMonitor`]
+//! export how they are used, labeled with the name they were created with,
+//! e.g. the semaphores guarding the pools where backpressure builds up.
+
+use std::sync::{Arc, Mutex, Weak};
+
+use prometheus_client::{collector::Collector, encoding::DescriptorEncoder, registry::Registry};
+
+use crate::{buckets::BucketPreset, primitives::live};
+
+mod broadcast;
+mod lock;
+mod mpsc;
+mod notify;
+mod semaphore;
+mod watch;
+
+pub use broadcast::{MonitoredBroadcastReceiver, MonitoredBroadcastSender};
+pub use lock::{
+    MonitoredMutex, MonitoredMutexGuard, MonitoredRwLock, MonitoredRwLockReadGuard,
+    MonitoredRwLockWriteGuard,
+};
+pub use mpsc::{MonitoredReceiver, MonitoredSender};
+pub use notify::MonitoredNotify;
+pub use semaphore::{MonitoredSemaphore, MonitoredSemaphorePermit, OwnedMonitoredSemaphorePermit};
+pub use watch::{MonitoredWatchReceiver, MonitoredWatchSender};
+
+/// Creates instrumented synchronization primitives and collects their
+/// metrics.
+///
+/// Each primitive is exported with a label of its kind set to its name,
+/// names should be unique per kind. The series of a primitive are removed
+/// once all of its handles are dropped.
+///
+/// Semaphores, created with [`SyncMonitor::semaphore`], expose:
+///
+/// * `semaphore_available_permits`: the number of permits available.
+/// * `semaphore_acquired_permits`: the number of permits currently held.
+/// * `semaphore_acquires`: the number of times permits were acquired.
+/// * `semaphore_waiters`: the number of tasks waiting for permits.
+/// * `semaphore_acquire_wait_seconds`: a histogram of the time tasks waited
+///   for permits.
+///
+/// Bounded mpsc channels, created with [`SyncMonitor::channel`], expose:
+///
+/// * `channel_depth`: the number of values waiting in the channel.
+/// * `channel_capacity`: the number of values the channel can hold.
+/// * `channel_sends`: the number of values sent.
+/// * `channel_receives`: the number of values received.
+/// * `channel_blocked_sends`: the number of sends that found the channel
+///   full and waited for a free slot.
+/// * `channel_blocked_send_wait_seconds`: a histogram of the time those
+///   sends waited. A deep channel with short waits keeps up, long waits
+///   mean its producers are stalled.
+///
+/// Broadcast channels, created with [`SyncMonitor::broadcast`], expose:
+///
+/// * `broadcast_receivers`: the number of receivers.
+/// * `broadcast_sends`: the number of values sent.
+/// * `broadcast_lags`: the number of times receivers lagged behind and
+///   missed values, i.e. got [`RecvError::Lagged`](tokio::sync::broadcast::error::RecvError::Lagged).
+/// * `broadcast_lagged_values`: the number of values receivers missed.
+///
+/// Watch channels, created with [`SyncMonitor::watch`], expose:
+///
+/// * `watch_receivers`: the number of receivers. One that only grows, e.g.
+///   of a configuration channel, points to leaked clones.
+/// * `watch_updates`: the number of times the value was updated.
+///
+/// Mutexes and read-write locks, created with [`SyncMonitor::mutex`] and
+/// [`SyncMonitor::rw_lock`], expose:
+///
+/// * `lock_acquires`: the number of times the lock was acquired.
+/// * `lock_wait_seconds`: a histogram of the time tasks waited to acquire
+///   the lock.
+/// * `lock_hold_seconds`: a histogram of the time the lock was held.
+///
+/// Notifies, created with [`SyncMonitor::notify`], expose:
+///
+/// * `notify_one_calls`: the number of calls to notify one waiting task.
+/// * `notify_waiters_calls`: the number of calls to notify all waiting
+///   tasks.
+/// * `notify_waiters`: the number of tasks waiting for a notification.
+/// * `notify_wait_seconds`: a histogram of the time tasks waited for a
+///   notification.
+///
+/// ## Example
+///
+/// ```
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// let monitor = tokio_prometheus_client::sync::SyncMonitor::new();
+/// monitor.register(&mut registry);
+///
+/// let pool = monitor.semaphore("db_pool", 10);
+/// let permit = pool.acquire().await.unwrap();
+///
+/// let mut text = String::new();
+/// prometheus_client::encoding::text::encode(&mut text, &registry).unwrap();
+/// assert!(text.contains("semaphore_acquired_permits{semaphore=\"db_pool\"} 1\n"));
+///
+/// let (sender, mut receiver) = monitor.channel("jobs", 100);
+/// sender.send("job").await.unwrap();
+/// assert_eq!(receiver.recv().await, Some("job"));
+/// # drop(permit);
+/// # });
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct SyncMonitor {
+    buckets: BucketPreset,
+    shared: Arc<Shared>,
+}
+
+/// The primitives of a [`SyncMonitor`], pruned on every collection.
+#[derive(Debug, Default)]
+struct Shared {
+    broadcasts: Mutex<Vec<Weak<broadcast::State>>>,
+    channels: Mutex<Vec<Weak<mpsc::State>>>,
+    locks: Mutex<Vec<Weak<lock::State>>>,
+    notifies: Mutex<Vec<Weak<notify::State>>>,
+    semaphores: Mutex<Vec<Weak<semaphore::State>>>,
+    watches: Mutex<Vec<Weak<watch::State>>>,
+}
+
+impl SyncMonitor {
+    /// Create a [`SyncMonitor`] with [`BucketPreset::LatencyCoarse`] wait
+    /// and hold time histograms.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the buckets of the wait and hold time histograms of the
+    /// primitives created from then on.
+    pub fn buckets(mut self, buckets: BucketPreset) -> Self {
+        self.buckets = buckets;
+        self
+    }
+
+    /// Register the collector of the primitives with `registry`.
+    pub fn register(&self, registry: &mut Registry) {
+        registry.register_collector(Box::new(SyncCollector {
+            shared: self.shared.clone(),
+        }));
+    }
+}
+
+/// Collects the primitives of a [`SyncMonitor`].
+#[derive(Debug)]
+struct SyncCollector {
+    shared: Arc<Shared>,
+}
+
+impl Collector for SyncCollector {
+    fn encode(&self, mut encoder: DescriptorEncoder) -> Result<(), std::fmt::Error> {
+        broadcast::encode(&live(&self.shared.broadcasts), &mut encoder)?;
+        lock::encode(&live(&self.shared.locks), &mut encoder)?;
+        mpsc::encode(&live(&self.shared.channels), &mut encoder)?;
+        notify::encode(&live(&self.shared.notifies), &mut encoder)?;
+        semaphore::encode(&live(&self.shared.semaphores), &mut encoder)?;
+        watch::encode(&live(&self.shared.watches), &mut encoder)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl SyncMonitor {
+    /// The text exposition of the primitives.
+    fn encoded(&self) -> String {
+        let mut registry = Registry::default();
+        self.register(&mut registry);
+        let mut text = String::new();
+        prometheus_client::encoding::text::encode(&mut text, &registry).unwrap();
+        text
+    }
+}
@@ -0,0 +1,240 @@
+//! Broadcast channels exporting how far their receivers lag.
+
+use std::sync::{
+    atomic::{AtomicI64, AtomicU64, Ordering},
+    Arc,
+};
+
+use prometheus_client::encoding::DescriptorEncoder;
+use tokio::sync::broadcast::{
+    self,
+    error::{RecvError, SendError, TryRecvError},
+};
+
+use crate::primitives::{encode_counters, encode_gauges, label_value, track, Primitive};
+
+use super::SyncMonitor;
+
+impl SyncMonitor {
+    /// Create a broadcast channel named `name` holding `capacity` values,
+    /// see [`broadcast::channel`].
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn broadcast<T: Clone>(
+        &self,
+        name: impl Into<String>,
+        capacity: usize,
+    ) -> (MonitoredBroadcastSender<T>, MonitoredBroadcastReceiver<T>) {
+        let (sender, receiver) = broadcast::channel(capacity);
+        let state = State {
+            name: label_value(name),
+            sends: AtomicU64::new(0),
+            receivers: AtomicI64::new(0),
+            lags: AtomicU64::new(0),
+            lagged: AtomicU64::new(0),
+        };
+        let state = track(&self.shared.broadcasts, state);
+        (
+            MonitoredBroadcastSender {
+                sender,
+                state: state.clone(),
+            },
+            MonitoredBroadcastReceiver::new(receiver, state),
+        )
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct State {
+    name: String,
+    sends: AtomicU64,
+    receivers: AtomicI64,
+    /// The number of times receivers lagged behind.
+    lags: AtomicU64,
+    /// The number of values receivers skipped while lagging behind.
+    lagged: AtomicU64,
+}
+
+/// The sending half of a channel created with [`SyncMonitor::broadcast`].
+#[derive(Debug)]
+pub struct MonitoredBroadcastSender<T> {
+    sender: broadcast::Sender<T>,
+    state: Arc<State>,
+}
+
+impl<T> Clone for MonitoredBroadcastSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<T> MonitoredBroadcastSender<T> {
+    /// Send `value` to all receivers, see [`broadcast::Sender::send`].
+    pub fn send(&self, value: T) -> Result<usize, SendError<T>> {
+        let receivers = self.sender.send(value)?;
+        self.state.sends.fetch_add(1, Ordering::Relaxed);
+        Ok(receivers)
+    }
+
+    /// Create a receiver of the values sent from now on, see
+    /// [`broadcast::Sender::subscribe`].
+    pub fn subscribe(&self) -> MonitoredBroadcastReceiver<T> {
+        MonitoredBroadcastReceiver::new(self.sender.subscribe(), self.state.clone())
+    }
+
+    /// The number of receivers, see [`broadcast::Sender::receiver_count`].
+    pub fn receiver_count(&self) -> usize {
+        self.sender.receiver_count()
+    }
+
+    /// The number of values not yet received by all receivers, see
+    /// [`broadcast::Sender::len`].
+    pub fn len(&self) -> usize {
+        self.sender.len()
+    }
+
+    /// Whether all receivers received all values, see
+    /// [`broadcast::Sender::is_empty`].
+    pub fn is_empty(&self) -> bool {
+        self.sender.is_empty()
+    }
+}
+
+/// A receiving half of a channel created with [`SyncMonitor::broadcast`].
+#[derive(Debug)]
+pub struct MonitoredBroadcastReceiver<T> {
+    receiver: broadcast::Receiver<T>,
+    state: Arc<State>,
+}
+
+impl<T> MonitoredBroadcastReceiver<T> {
+    fn new(receiver: broadcast::Receiver<T>, state: Arc<State>) -> Self {
+        state.receivers.fetch_add(1, Ordering::Relaxed);
+        Self { receiver, state }
+    }
+
+    /// Count a receive that lagged behind by `skipped` values.
+    fn lagged(&self, skipped: u64) {
+        self.state.lags.fetch_add(1, Ordering::Relaxed);
+        self.state.lagged.fetch_add(skipped, Ordering::Relaxed);
+    }
+}
+
+impl<T: Clone> MonitoredBroadcastReceiver<T> {
+    /// Receive the next value, see [`broadcast::Receiver::recv`].
+    pub async fn recv(&mut self) -> Result<T, RecvError> {
+        let value = self.receiver.recv().await;
+        if let Err(RecvError::Lagged(skipped)) = value {
+            self.lagged(skipped);
+        }
+        value
+    }
+
+    /// Receive the next value if there is one, see
+    /// [`broadcast::Receiver::try_recv`].
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let value = self.receiver.try_recv();
+        if let Err(TryRecvError::Lagged(skipped)) = value {
+            self.lagged(skipped);
+        }
+        value
+    }
+
+    /// Create a receiver of the values sent from now on, see
+    /// [`broadcast::Receiver::resubscribe`].
+    pub fn resubscribe(&self) -> Self {
+        Self::new(self.receiver.resubscribe(), self.state.clone())
+    }
+}
+
+impl<T> Drop for MonitoredBroadcastReceiver<T> {
+    fn drop(&mut self) {
+        self.state.receivers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Primitive for State {
+    const LABEL: &'static str = "broadcast";
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Encode the metrics of `broadcasts`.
+pub(super) fn encode(
+    broadcasts: &[Arc<State>],
+    encoder: &mut DescriptorEncoder,
+) -> Result<(), std::fmt::Error> {
+    encode_gauges(
+        encoder,
+        broadcasts,
+        "broadcast_receivers",
+        "The number of receivers of the broadcast channel",
+        |broadcast| broadcast.receivers.load(Ordering::Relaxed),
+    )?;
+    encode_counters(
+        encoder,
+        broadcasts,
+        "broadcast_sends",
+        "The number of values sent to the broadcast channel",
+        |broadcast| &broadcast.sends,
+    )?;
+    encode_counters(
+        encoder,
+        broadcasts,
+        "broadcast_lags",
+        "The number of times receivers of the broadcast channel lagged behind",
+        |broadcast| &broadcast.lags,
+    )?;
+    encode_counters(
+        encoder,
+        broadcasts,
+        "broadcast_lagged_values",
+        "The number of values receivers of the broadcast channel skipped while lagging behind",
+        |broadcast| &broadcast.lagged,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_sends_receivers_and_lags() {
+        let monitor = SyncMonitor::new();
+        let (sender, mut receiver) = monitor.broadcast("events", 2);
+        let mut resubscribed = receiver.resubscribe();
+        for value in 0..5 {
+            sender.send(value).unwrap();
+        }
+        assert!(matches!(receiver.try_recv(), Err(TryRecvError::Lagged(3))));
+        assert_eq!(receiver.try_recv(), Ok(3));
+        assert!(matches!(
+            resubscribed.try_recv(),
+            Err(TryRecvError::Lagged(3))
+        ));
+
+        let text = monitor.encoded();
+        for expected in [
+            "broadcast_receivers{broadcast=\"events\"} 2\n",
+            "broadcast_sends_total{broadcast=\"events\"} 5\n",
+            "broadcast_lags_total{broadcast=\"events\"} 2\n",
+            "broadcast_lagged_values_total{broadcast=\"events\"} 6\n",
+        ] {
+            assert!(text.contains(expected), "{text}");
+        }
+
+        drop(resubscribed);
+        assert!(monitor
+            .encoded()
+            .contains("broadcast_receivers{broadcast=\"events\"} 1\n"));
+        drop((sender, receiver));
+        assert!(!monitor.encoded().contains("events"));
+    }
+}
@@ -0,0 +1,326 @@
+//! Mutexes and read-write locks exporting their contention.
+
+use std::{
+    future::Future,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use prometheus_client::{encoding::DescriptorEncoder, metrics::histogram::Histogram};
+use tokio::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError};
+
+use crate::primitives::{encode_counters, encode_histograms, label_value, track, Primitive};
+
+use super::SyncMonitor;
+
+impl SyncMonitor {
+    /// Create a [`MonitoredMutex`] named `name` holding `value`.
+    pub fn mutex<T>(&self, name: impl Into<String>, value: T) -> MonitoredMutex<T> {
+        MonitoredMutex {
+            lock: Mutex::new(value),
+            state: self.lock_state(name),
+        }
+    }
+
+    /// Create a [`MonitoredRwLock`] named `name` holding `value`.
+    pub fn rw_lock<T>(&self, name: impl Into<String>, value: T) -> MonitoredRwLock<T> {
+        MonitoredRwLock {
+            lock: RwLock::new(value),
+            state: self.lock_state(name),
+        }
+    }
+
+    fn lock_state(&self, name: impl Into<String>) -> Arc<State> {
+        let histogram = || Histogram::new(self.buckets.buckets().iter().copied());
+        let state = State {
+            name: label_value(name),
+            acquires: AtomicU64::new(0),
+            wait: histogram(),
+            hold: histogram(),
+        };
+        track(&self.shared.locks, state)
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct State {
+    name: String,
+    acquires: AtomicU64,
+    wait: Histogram,
+    hold: Histogram,
+}
+
+impl State {
+    /// Wait for `lock` to acquire the lock.
+    async fn wait<G>(&self, lock: impl Future<Output = G>) -> Held<'_, G> {
+        let started = Instant::now();
+        let guard = lock.await;
+        let acquired_at = Instant::now();
+        self.wait
+            .observe(acquired_at.duration_since(started).as_secs_f64());
+        self.acquires.fetch_add(1, Ordering::Relaxed);
+        Held {
+            guard,
+            state: self,
+            acquired_at,
+        }
+    }
+
+    /// Count an acquire of `guard` without waiting.
+    fn acquired<G>(&self, guard: G) -> Held<'_, G> {
+        self.wait.observe(0.0);
+        self.acquires.fetch_add(1, Ordering::Relaxed);
+        Held {
+            guard,
+            state: self,
+            acquired_at: Instant::now(),
+        }
+    }
+}
+
+/// A guard of a lock, observing the time it was held when dropped.
+#[derive(Debug)]
+struct Held<'a, G> {
+    guard: G,
+    state: &'a State,
+    acquired_at: Instant,
+}
+
+impl<G> Drop for Held<'_, G> {
+    fn drop(&mut self) {
+        self.state
+            .hold
+            .observe(self.acquired_at.elapsed().as_secs_f64());
+    }
+}
+
+/// A [`Mutex`] exporting its contention, see [`SyncMonitor::mutex`].
+#[derive(Debug)]
+pub struct MonitoredMutex<T> {
+    lock: Mutex<T>,
+    state: Arc<State>,
+}
+
+impl<T> MonitoredMutex<T> {
+    /// Lock the mutex, see [`Mutex::lock`].
+    pub async fn lock(&self) -> MonitoredMutexGuard<'_, T> {
+        MonitoredMutexGuard(self.state.wait(self.lock.lock()).await)
+    }
+
+    /// Lock the mutex if it is not locked, see [`Mutex::try_lock`].
+    pub fn try_lock(&self) -> Result<MonitoredMutexGuard<'_, T>, TryLockError> {
+        let guard = self.lock.try_lock()?;
+        Ok(MonitoredMutexGuard(self.state.acquired(guard)))
+    }
+
+    /// The value, see [`Mutex::get_mut`].
+    pub fn get_mut(&mut self) -> &mut T {
+        self.lock.get_mut()
+    }
+
+    /// Consume the mutex, returning the value, see [`Mutex::into_inner`].
+    pub fn into_inner(self) -> T {
+        self.lock.into_inner()
+    }
+}
+
+/// A guard of a [`MonitoredMutex`], unlocking it when dropped.
+#[derive(Debug)]
+#[must_use]
+pub struct MonitoredMutexGuard<'a, T>(Held<'a, MutexGuard<'a, T>>);
+
+impl<T> Deref for MonitoredMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0.guard
+    }
+}
+
+impl<T> DerefMut for MonitoredMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0.guard
+    }
+}
+
+/// A [`RwLock`] exporting its contention, see [`SyncMonitor::rw_lock`].
+///
+/// Reads and writes are counted together.
+#[derive(Debug)]
+pub struct MonitoredRwLock<T> {
+    lock: RwLock<T>,
+    state: Arc<State>,
+}
+
+impl<T> MonitoredRwLock<T> {
+    /// Lock for reading, see [`RwLock::read`].
+    pub async fn read(&self) -> MonitoredRwLockReadGuard<'_, T> {
+        MonitoredRwLockReadGuard(self.state.wait(self.lock.read()).await)
+    }
+
+    /// Lock for reading if not locked for writing, see
+    /// [`RwLock::try_read`].
+    pub fn try_read(&self) -> Result<MonitoredRwLockReadGuard<'_, T>, TryLockError> {
+        let guard = self.lock.try_read()?;
+        Ok(MonitoredRwLockReadGuard(self.state.acquired(guard)))
+    }
+
+    /// Lock for writing, see [`RwLock::write`].
+    pub async fn write(&self) -> MonitoredRwLockWriteGuard<'_, T> {
+        MonitoredRwLockWriteGuard(self.state.wait(self.lock.write()).await)
+    }
+
+    /// Lock for writing if not locked, see [`RwLock::try_write`].
+    pub fn try_write(&self) -> Result<MonitoredRwLockWriteGuard<'_, T>, TryLockError> {
+        let guard = self.lock.try_write()?;
+        Ok(MonitoredRwLockWriteGuard(self.state.acquired(guard)))
+    }
+
+    /// The value, see [`RwLock::get_mut`].
+    pub fn get_mut(&mut self) -> &mut T {
+        self.lock.get_mut()
+    }
+
+    /// Consume the lock, returning the value, see [`RwLock::into_inner`].
+    pub fn into_inner(self) -> T {
+        self.lock.into_inner()
+    }
+}
+
+/// A read guard of a [`MonitoredRwLock`], unlocking it when dropped.
+#[derive(Debug)]
+#[must_use]
+pub struct MonitoredRwLockReadGuard<'a, T>(Held<'a, RwLockReadGuard<'a, T>>);
+
+impl<T> Deref for MonitoredRwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0.guard
+    }
+}
+
+/// A write guard of a [`MonitoredRwLock`], unlocking it when dropped.
+#[derive(Debug)]
+#[must_use]
+pub struct MonitoredRwLockWriteGuard<'a, T>(Held<'a, RwLockWriteGuard<'a, T>>);
+
+impl<T> Deref for MonitoredRwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0.guard
+    }
+}
+
+impl<T> DerefMut for MonitoredRwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0.guard
+    }
+}
+
+impl Primitive for State {
+    const LABEL: &'static str = "lock";
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Encode the metrics of `locks`.
+pub(super) fn encode(
+    locks: &[Arc<State>],
+    encoder: &mut DescriptorEncoder,
+) -> Result<(), std::fmt::Error> {
+    encode_counters(
+        encoder,
+        locks,
+        "lock_acquires",
+        "The number of times the lock was acquired",
+        |lock| &lock.acquires,
+    )?;
+    encode_histograms(
+        encoder,
+        locks,
+        "lock_wait",
+        "The time tasks waited to acquire the lock",
+        |lock| &lock.wait,
+    )?;
+    encode_histograms(
+        encoder,
+        locks,
+        "lock_hold",
+        "The time the lock was held",
+        |lock| &lock.hold,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn sample(text: &str, name: &str) -> f64 {
+        text.lines()
+            .find_map(|line| line.strip_prefix(&format!("{name} ")))
+            .unwrap_or_else(|| panic!("{name} should be exported: {text}"))
+            .parse()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn mutex_observes_wait_and_hold_times() {
+        let monitor = SyncMonitor::new();
+        let mutex = Arc::new(monitor.mutex("state", 0));
+
+        let mut guard = mutex.lock().await;
+        *guard += 1;
+        assert!(mutex.try_lock().is_err());
+        let waiting = tokio::spawn({
+            let mutex = mutex.clone();
+            async move { *mutex.lock().await += 1 }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        drop(guard);
+        waiting.await.unwrap();
+        assert_eq!(*mutex.try_lock().unwrap(), 2);
+
+        let text = monitor.encoded();
+        assert_eq!(sample(&text, "lock_acquires_total{lock=\"state\"}"), 3.0);
+        assert_eq!(
+            sample(&text, "lock_wait_seconds_count{lock=\"state\"}"),
+            3.0
+        );
+        assert!(sample(&text, "lock_wait_seconds_sum{lock=\"state\"}") >= 0.01);
+        assert_eq!(
+            sample(&text, "lock_hold_seconds_count{lock=\"state\"}"),
+            3.0
+        );
+        assert!(sample(&text, "lock_hold_seconds_sum{lock=\"state\"}") >= 0.02);
+    }
+
+    #[tokio::test]
+    async fn rw_lock_counts_reads_and_writes() {
+        let monitor = SyncMonitor::new();
+        let lock = monitor.rw_lock("cache", 0);
+
+        let read = lock.read().await;
+        let other_read = lock.try_read().unwrap();
+        assert!(lock.try_write().is_err());
+        drop((read, other_read));
+        *lock.write().await += 1;
+        assert_eq!(*lock.try_write().unwrap(), 1);
+
+        let text = monitor.encoded();
+        assert_eq!(sample(&text, "lock_acquires_total{lock=\"cache\"}"), 4.0);
+        assert_eq!(
+            sample(&text, "lock_hold_seconds_count{lock=\"cache\"}"),
+            4.0
+        );
+    }
+}
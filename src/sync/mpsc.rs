@@ -0,0 +1,267 @@
+//! Bounded mpsc channels exporting their depth.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use prometheus_client::{encoding::DescriptorEncoder, metrics::histogram::Histogram};
+use tokio::sync::mpsc::{
+    self,
+    error::{SendError, TryRecvError, TrySendError},
+};
+
+use crate::primitives::{
+    encode_counters, encode_gauges, encode_histograms, label_value, track, Primitive,
+};
+
+use super::SyncMonitor;
+
+impl SyncMonitor {
+    /// Create a bounded mpsc channel named `name` with `capacity` slots,
+    /// see [`mpsc::channel`].
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn channel<T>(
+        &self,
+        name: impl Into<String>,
+        capacity: usize,
+    ) -> (MonitoredSender<T>, MonitoredReceiver<T>) {
+        let (sender, receiver) = mpsc::channel(capacity);
+        let state = State {
+            name: label_value(name),
+            capacity,
+            sends: AtomicU64::new(0),
+            receives: AtomicU64::new(0),
+            blocked_sends: AtomicU64::new(0),
+            blocked_send_wait: Histogram::new(self.buckets.buckets().iter().copied()),
+        };
+        let state = track(&self.shared.channels, state);
+        (
+            MonitoredSender {
+                sender,
+                state: state.clone(),
+            },
+            MonitoredReceiver { receiver, state },
+        )
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct State {
+    name: String,
+    capacity: usize,
+    sends: AtomicU64,
+    receives: AtomicU64,
+    /// The number of sends that found the channel full.
+    blocked_sends: AtomicU64,
+    /// The time blocked sends waited for a free slot.
+    blocked_send_wait: Histogram,
+}
+
+/// The sending half of a channel created with [`SyncMonitor::channel`].
+#[derive(Debug)]
+pub struct MonitoredSender<T> {
+    sender: mpsc::Sender<T>,
+    state: Arc<State>,
+}
+
+impl<T> Clone for MonitoredSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<T> MonitoredSender<T> {
+    /// Send `value`, waiting for a free slot if the channel is full, see
+    /// [`mpsc::Sender::send`].
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let value = match self.sender.try_send(value) {
+            Ok(()) => {
+                self.state.sends.fetch_add(1, Ordering::Relaxed);
+                return Ok(());
+            }
+            Err(TrySendError::Closed(value)) => return Err(SendError(value)),
+            Err(TrySendError::Full(value)) => value,
+        };
+        self.state.blocked_sends.fetch_add(1, Ordering::Relaxed);
+        let started = Instant::now();
+        let sent = self.sender.send(value).await;
+        self.state
+            .blocked_send_wait
+            .observe(started.elapsed().as_secs_f64());
+        sent?;
+        self.state.sends.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Send `value` if there is a free slot, see
+    /// [`mpsc::Sender::try_send`].
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        self.sender.try_send(value)?;
+        self.state.sends.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Whether the receiver was dropped or closed, see
+    /// [`mpsc::Sender::is_closed`].
+    pub fn is_closed(&self) -> bool {
+        self.sender.is_closed()
+    }
+
+    /// The number of free slots, see [`mpsc::Sender::capacity`].
+    pub fn capacity(&self) -> usize {
+        self.sender.capacity()
+    }
+
+    /// The number of slots, see [`mpsc::Sender::max_capacity`].
+    pub fn max_capacity(&self) -> usize {
+        self.sender.max_capacity()
+    }
+}
+
+/// The receiving half of a channel created with [`SyncMonitor::channel`].
+#[derive(Debug)]
+pub struct MonitoredReceiver<T> {
+    receiver: mpsc::Receiver<T>,
+    state: Arc<State>,
+}
+
+impl<T> MonitoredReceiver<T> {
+    /// Receive the next value, see [`mpsc::Receiver::recv`].
+    pub async fn recv(&mut self) -> Option<T> {
+        let value = self.receiver.recv().await?;
+        self.state.receives.fetch_add(1, Ordering::Relaxed);
+        Some(value)
+    }
+
+    /// Receive the next value if there is one, see
+    /// [`mpsc::Receiver::try_recv`].
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let value = self.receiver.try_recv()?;
+        self.state.receives.fetch_add(1, Ordering::Relaxed);
+        Ok(value)
+    }
+
+    /// Close the channel for sending, see [`mpsc::Receiver::close`].
+    pub fn close(&mut self) {
+        self.receiver.close();
+    }
+}
+
+impl Primitive for State {
+    const LABEL: &'static str = "channel";
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Encode the metrics of `channels`.
+pub(super) fn encode(
+    channels: &[Arc<State>],
+    encoder: &mut DescriptorEncoder,
+) -> Result<(), std::fmt::Error> {
+    encode_gauges(
+        encoder,
+        channels,
+        "channel_depth",
+        "The number of values waiting in the channel",
+        |channel| {
+            // Received values were sent before, load them first.
+            let receives = channel.receives.load(Ordering::Relaxed);
+            let sends = channel.sends.load(Ordering::Relaxed);
+            sends.saturating_sub(receives) as i64
+        },
+    )?;
+    encode_gauges(
+        encoder,
+        channels,
+        "channel_capacity",
+        "The number of values the channel can hold",
+        |channel| channel.capacity as i64,
+    )?;
+    encode_counters(
+        encoder,
+        channels,
+        "channel_sends",
+        "The number of values sent to the channel",
+        |channel| &channel.sends,
+    )?;
+    encode_counters(
+        encoder,
+        channels,
+        "channel_receives",
+        "The number of values received from the channel",
+        |channel| &channel.receives,
+    )?;
+    encode_counters(
+        encoder,
+        channels,
+        "channel_blocked_sends",
+        "The number of sends that waited for the channel to have a free slot",
+        |channel| &channel.blocked_sends,
+    )?;
+    encode_histograms(
+        encoder,
+        channels,
+        "channel_blocked_send_wait",
+        "The time sends waited for the channel to have a free slot",
+        |channel| &channel.blocked_send_wait,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn tracks_depth_and_blocked_sends() {
+        let monitor = SyncMonitor::new();
+        let (sender, mut receiver) = monitor.channel("jobs", 1);
+        sender.send(1).await.unwrap();
+        assert!(matches!(sender.try_send(2), Err(TrySendError::Full(2))));
+        let blocked = tokio::spawn({
+            let sender = sender.clone();
+            async move { sender.send(2).await }
+        });
+        tokio::task::yield_now().await;
+
+        let text = monitor.encoded();
+        for expected in [
+            "channel_depth{channel=\"jobs\"} 1\n",
+            "channel_capacity{channel=\"jobs\"} 1\n",
+            "channel_sends_total{channel=\"jobs\"} 1\n",
+            "channel_blocked_sends_total{channel=\"jobs\"} 1\n",
+            "channel_blocked_send_wait_seconds_count{channel=\"jobs\"} 0\n",
+        ] {
+            assert!(text.contains(expected), "{text}");
+        }
+
+        assert_eq!(receiver.recv().await, Some(1));
+        blocked.await.unwrap().unwrap();
+        assert_eq!(receiver.try_recv(), Ok(2));
+        let text = monitor.encoded();
+        for expected in [
+            "channel_depth{channel=\"jobs\"} 0\n",
+            "channel_receives_total{channel=\"jobs\"} 2\n",
+            "channel_blocked_send_wait_seconds_count{channel=\"jobs\"} 1\n",
+        ] {
+            assert!(text.contains(expected), "{text}");
+        }
+
+        receiver.close();
+        assert!(sender.send(3).await.is_err());
+        assert!(monitor
+            .encoded()
+            .contains("channel_sends_total{channel=\"jobs\"} 2\n"));
+    }
+}
@@ -0,0 +1,172 @@
+//! Notifies exporting their notifications and how long tasks wait for them.
+
+use std::{
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use prometheus_client::{encoding::DescriptorEncoder, metrics::histogram::Histogram};
+use tokio::sync::Notify;
+
+use crate::primitives::{
+    encode_counters, encode_gauges, encode_histograms, label_value, track, Primitive, Waiting,
+};
+
+use super::SyncMonitor;
+
+impl SyncMonitor {
+    /// Create a [`MonitoredNotify`] named `name`.
+    pub fn notify(&self, name: impl Into<String>) -> MonitoredNotify {
+        let state = State {
+            name: label_value(name),
+            notify: Notify::new(),
+            notify_one: AtomicU64::new(0),
+            notify_waiters: AtomicU64::new(0),
+            waiters: AtomicI64::new(0),
+            wait: Histogram::new(self.buckets.buckets().iter().copied()),
+        };
+        MonitoredNotify {
+            state: track(&self.shared.notifies, state),
+        }
+    }
+}
+
+/// A [`Notify`] exporting its notifications and how long tasks wait for
+/// them, see [`SyncMonitor::notify`].
+///
+/// Clones share the notify.
+#[derive(Clone, Debug)]
+pub struct MonitoredNotify {
+    state: Arc<State>,
+}
+
+#[derive(Debug)]
+pub(super) struct State {
+    name: String,
+    notify: Notify,
+    /// The number of calls to [`Notify::notify_one`].
+    notify_one: AtomicU64,
+    /// The number of calls to [`Notify::notify_waiters`].
+    notify_waiters: AtomicU64,
+    waiters: AtomicI64,
+    wait: Histogram,
+}
+
+impl MonitoredNotify {
+    /// The notify, e.g. to pass it to APIs taking a [`Notify`] or to
+    /// enable a [`Notified`](tokio::sync::futures::Notified) before
+    /// waiting.
+    ///
+    /// Notifications and waits through it directly are not counted.
+    pub fn inner(&self) -> &Notify {
+        &self.state.notify
+    }
+
+    /// Wait for a notification, see [`Notify::notified`].
+    pub async fn notified(&self) {
+        let _waiting = Waiting::new(&self.state.waiters);
+        let started = Instant::now();
+        self.state.notify.notified().await;
+        self.state.wait.observe(started.elapsed().as_secs_f64());
+    }
+
+    /// Notify a waiting task, or the next one to wait, see
+    /// [`Notify::notify_one`].
+    pub fn notify_one(&self) {
+        self.state.notify_one.fetch_add(1, Ordering::Relaxed);
+        self.state.notify.notify_one();
+    }
+
+    /// Notify all waiting tasks, see [`Notify::notify_waiters`].
+    pub fn notify_waiters(&self) {
+        self.state.notify_waiters.fetch_add(1, Ordering::Relaxed);
+        self.state.notify.notify_waiters();
+    }
+}
+
+impl Primitive for State {
+    const LABEL: &'static str = "notify";
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Encode the metrics of `notifies`.
+pub(super) fn encode(
+    notifies: &[Arc<State>],
+    encoder: &mut DescriptorEncoder,
+) -> Result<(), std::fmt::Error> {
+    encode_counters(
+        encoder,
+        notifies,
+        "notify_one_calls",
+        "The number of times a waiting task was notified",
+        |notify| &notify.notify_one,
+    )?;
+    encode_counters(
+        encoder,
+        notifies,
+        "notify_waiters_calls",
+        "The number of times all waiting tasks were notified",
+        |notify| &notify.notify_waiters,
+    )?;
+    encode_gauges(
+        encoder,
+        notifies,
+        "notify_waiters",
+        "The number of tasks waiting for a notification",
+        |notify| notify.waiters.load(Ordering::Relaxed),
+    )?;
+    encode_histograms(
+        encoder,
+        notifies,
+        "notify_wait",
+        "The time tasks waited for a notification",
+        |notify| &notify.wait,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn tracks_waiters_and_notifications() {
+        let monitor = SyncMonitor::new();
+        let notify = monitor.notify("shutdown");
+        let waiters: Vec<_> = (0..2)
+            .map(|_| {
+                let notify = notify.clone();
+                tokio::spawn(async move { notify.notified().await })
+            })
+            .collect();
+        tokio::task::yield_now().await;
+
+        let text = monitor.encoded();
+        assert!(
+            text.contains("notify_waiters{notify=\"shutdown\"} 2\n"),
+            "{text}"
+        );
+        notify.notify_waiters();
+        for waiter in waiters {
+            waiter.await.unwrap();
+        }
+        // Stores a permit for the next waiter.
+        notify.notify_one();
+        notify.notified().await;
+
+        let text = monitor.encoded();
+        for expected in [
+            "notify_one_calls_total{notify=\"shutdown\"} 1\n",
+            "notify_waiters_calls_total{notify=\"shutdown\"} 1\n",
+            "notify_waiters{notify=\"shutdown\"} 0\n",
+            "notify_wait_seconds_count{notify=\"shutdown\"} 3\n",
+        ] {
+            assert!(text.contains(expected), "{text}");
+        }
+    }
+}
@@ -0,0 +1,354 @@
+//! Semaphores exporting the use of their permits.
+
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use prometheus_client::{encoding::DescriptorEncoder, metrics::histogram::Histogram};
+use tokio::sync::{
+    AcquireError, OwnedSemaphorePermit, Semaphore, SemaphorePermit, TryAcquireError,
+};
+
+use crate::primitives::{
+    encode_counters, encode_gauges, encode_histograms, label_value, track, Primitive, Waiting,
+};
+
+use super::SyncMonitor;
+
+impl SyncMonitor {
+    /// Create a [`MonitoredSemaphore`] named `name` with `permits` permits.
+    pub fn semaphore(&self, name: impl Into<String>, permits: usize) -> MonitoredSemaphore {
+        let state = State {
+            name: label_value(name),
+            semaphore: Arc::new(Semaphore::new(permits)),
+            acquired: AtomicI64::new(0),
+            acquires: AtomicU64::new(0),
+            waiters: AtomicI64::new(0),
+            wait: Histogram::new(self.buckets.buckets().iter().copied()),
+        };
+        MonitoredSemaphore {
+            state: track(&self.shared.semaphores, state),
+        }
+    }
+}
+
+/// A [`Semaphore`] exporting the use of its permits, see
+/// [`SyncMonitor::semaphore`].
+///
+/// Clones share the semaphore.
+#[derive(Clone, Debug)]
+pub struct MonitoredSemaphore {
+    state: Arc<State>,
+}
+
+#[derive(Debug)]
+pub(super) struct State {
+    name: String,
+    semaphore: Arc<Semaphore>,
+    /// The number of permits held through the permits of the wrapper.
+    acquired: AtomicI64,
+    acquires: AtomicU64,
+    waiters: AtomicI64,
+    wait: Histogram,
+}
+
+impl State {
+    /// Count `permits` acquired.
+    fn acquired(&self, permits: u32) {
+        self.acquires.fetch_add(1, Ordering::Relaxed);
+        self.acquired
+            .fetch_add(i64::from(permits), Ordering::Relaxed);
+    }
+
+    /// Count `permits` released.
+    fn released(&self, permits: u32) {
+        self.acquired
+            .fetch_sub(i64::from(permits), Ordering::Relaxed);
+    }
+}
+
+impl MonitoredSemaphore {
+    /// The semaphore, e.g. to pass it to APIs taking a [`Semaphore`].
+    ///
+    /// Permits acquired from it directly are not counted.
+    pub fn inner(&self) -> &Arc<Semaphore> {
+        &self.state.semaphore
+    }
+
+    /// The number of permits available, see [`Semaphore::available_permits`].
+    pub fn available_permits(&self) -> usize {
+        self.state.semaphore.available_permits()
+    }
+
+    /// Add `permits` permits, see [`Semaphore::add_permits`].
+    pub fn add_permits(&self, permits: usize) {
+        self.state.semaphore.add_permits(permits);
+    }
+
+    /// Close the semaphore, see [`Semaphore::close`].
+    pub fn close(&self) {
+        self.state.semaphore.close();
+    }
+
+    /// Whether the semaphore is closed, see [`Semaphore::is_closed`].
+    pub fn is_closed(&self) -> bool {
+        self.state.semaphore.is_closed()
+    }
+
+    /// Acquire a permit, see [`Semaphore::acquire`].
+    pub async fn acquire(&self) -> Result<MonitoredSemaphorePermit<'_>, AcquireError> {
+        self.acquire_many(1).await
+    }
+
+    /// Acquire `permits` permits, see [`Semaphore::acquire_many`].
+    pub async fn acquire_many(
+        &self,
+        permits: u32,
+    ) -> Result<MonitoredSemaphorePermit<'_>, AcquireError> {
+        let permit = self
+            .wait(permits, self.state.semaphore.acquire_many(permits))
+            .await?;
+        Ok(MonitoredSemaphorePermit {
+            _permit: permit,
+            state: &self.state,
+            permits,
+        })
+    }
+
+    /// Acquire a permit without waiting, see [`Semaphore::try_acquire`].
+    pub fn try_acquire(&self) -> Result<MonitoredSemaphorePermit<'_>, TryAcquireError> {
+        let permit = self.state.semaphore.try_acquire()?;
+        self.state.acquired(1);
+        Ok(MonitoredSemaphorePermit {
+            _permit: permit,
+            state: &self.state,
+            permits: 1,
+        })
+    }
+
+    /// Acquire an owned permit, see [`Semaphore::acquire_owned`].
+    pub async fn acquire_owned(&self) -> Result<OwnedMonitoredSemaphorePermit, AcquireError> {
+        self.acquire_many_owned(1).await
+    }
+
+    /// Acquire `permits` owned permits, see
+    /// [`Semaphore::acquire_many_owned`].
+    pub async fn acquire_many_owned(
+        &self,
+        permits: u32,
+    ) -> Result<OwnedMonitoredSemaphorePermit, AcquireError> {
+        let acquire = self.state.semaphore.clone().acquire_many_owned(permits);
+        let permit = self.wait(permits, acquire).await?;
+        Ok(OwnedMonitoredSemaphorePermit {
+            _permit: permit,
+            state: self.state.clone(),
+            permits,
+        })
+    }
+
+    /// Wait for `acquire` to acquire `permits` permits.
+    async fn wait<P>(
+        &self,
+        permits: u32,
+        acquire: impl Future<Output = Result<P, AcquireError>>,
+    ) -> Result<P, AcquireError> {
+        let _waiting = Waiting::new(&self.state.waiters);
+        let started = Instant::now();
+        let permit = acquire.await?;
+        self.state.wait.observe(started.elapsed().as_secs_f64());
+        self.state.acquired(permits);
+        Ok(permit)
+    }
+}
+
+/// A permit of a [`MonitoredSemaphore`], released when dropped.
+#[derive(Debug)]
+#[must_use]
+pub struct MonitoredSemaphorePermit<'a> {
+    _permit: SemaphorePermit<'a>,
+    state: &'a State,
+    permits: u32,
+}
+
+impl Drop for MonitoredSemaphorePermit<'_> {
+    fn drop(&mut self) {
+        self.state.released(self.permits);
+    }
+}
+
+/// An owned permit of a [`MonitoredSemaphore`], released when dropped.
+#[derive(Debug)]
+#[must_use]
+pub struct OwnedMonitoredSemaphorePermit {
+    _permit: OwnedSemaphorePermit,
+    state: Arc<State>,
+    permits: u32,
+}
+
+impl Drop for OwnedMonitoredSemaphorePermit {
+    fn drop(&mut self) {
+        self.state.released(self.permits);
+    }
+}
+
+impl Primitive for State {
+    const LABEL: &'static str = "semaphore";
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Encode the metrics of `semaphores`.
+pub(super) fn encode(
+    semaphores: &[Arc<State>],
+    encoder: &mut DescriptorEncoder,
+) -> Result<(), std::fmt::Error> {
+    encode_gauges(
+        encoder,
+        semaphores,
+        "semaphore_available_permits",
+        "The number of permits available from the semaphore",
+        |semaphore| semaphore.semaphore.available_permits() as i64,
+    )?;
+    encode_gauges(
+        encoder,
+        semaphores,
+        "semaphore_acquired_permits",
+        "The number of permits of the semaphore currently held",
+        |semaphore| semaphore.acquired.load(Ordering::Relaxed),
+    )?;
+    encode_gauges(
+        encoder,
+        semaphores,
+        "semaphore_waiters",
+        "The number of tasks waiting for permits of the semaphore",
+        |semaphore| semaphore.waiters.load(Ordering::Relaxed),
+    )?;
+    encode_counters(
+        encoder,
+        semaphores,
+        "semaphore_acquires",
+        "The number of times permits of the semaphore were acquired",
+        |semaphore| &semaphore.acquires,
+    )?;
+    encode_histograms(
+        encoder,
+        semaphores,
+        "semaphore_acquire_wait",
+        "The time tasks waited for permits of the semaphore",
+        |semaphore| &semaphore.wait,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn sample(text: &str, name: &str) -> f64 {
+        text.lines()
+            .find_map(|line| line.strip_prefix(&format!("{name} ")))
+            .unwrap_or_else(|| panic!("{name} should be exported: {text}"))
+            .parse()
+            .unwrap()
+    }
+
+    /// The available, acquired and acquires of the `pool` semaphore.
+    fn permits(monitor: &SyncMonitor) -> [f64; 3] {
+        let text = monitor.encoded();
+        [
+            sample(&text, "semaphore_available_permits{semaphore=\"pool\"}"),
+            sample(&text, "semaphore_acquired_permits{semaphore=\"pool\"}"),
+            sample(&text, "semaphore_acquires_total{semaphore=\"pool\"}"),
+        ]
+    }
+
+    #[tokio::test]
+    async fn semaphore_observes_waiters_and_wait_times() {
+        let monitor = SyncMonitor::new();
+        let semaphore = monitor.semaphore("pool", 1);
+
+        let permit = semaphore.acquire().await.unwrap();
+        let waiting = tokio::spawn({
+            let semaphore = semaphore.clone();
+            async move {
+                let _permit = semaphore.acquire().await.unwrap();
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let text = monitor.encoded();
+        assert_eq!(sample(&text, "semaphore_waiters{semaphore=\"pool\"}"), 1.0);
+        drop(permit);
+        waiting.await.unwrap();
+
+        let text = monitor.encoded();
+        assert_eq!(sample(&text, "semaphore_waiters{semaphore=\"pool\"}"), 0.0);
+        assert_eq!(
+            sample(
+                &text,
+                "semaphore_acquire_wait_seconds_count{semaphore=\"pool\"}"
+            ),
+            2.0
+        );
+        assert!(
+            sample(
+                &text,
+                "semaphore_acquire_wait_seconds_sum{semaphore=\"pool\"}"
+            ) >= 0.01
+        );
+        assert_eq!(permits(&monitor), [1.0, 0.0, 2.0]);
+    }
+
+    #[tokio::test]
+    async fn semaphore_counts_acquired_permits() {
+        let monitor = SyncMonitor::new();
+        let semaphore = monitor.semaphore("pool", 3);
+
+        let many = semaphore.acquire_many(2).await.unwrap();
+        assert_eq!(permits(&monitor), [1.0, 2.0, 1.0]);
+        let one = semaphore.try_acquire().unwrap();
+        assert_eq!(permits(&monitor), [0.0, 3.0, 2.0]);
+        // Failed acquires are not counted.
+        assert!(semaphore.try_acquire().is_err());
+        assert_eq!(permits(&monitor), [0.0, 3.0, 2.0]);
+
+        drop(many);
+        assert_eq!(permits(&monitor), [2.0, 1.0, 2.0]);
+        drop(one);
+        assert_eq!(permits(&monitor), [3.0, 0.0, 2.0]);
+        // Only acquires that could wait are observed.
+        let text = monitor.encoded();
+        assert_eq!(
+            sample(
+                &text,
+                "semaphore_acquire_wait_seconds_count{semaphore=\"pool\"}"
+            ),
+            1.0
+        );
+    }
+
+    #[tokio::test]
+    async fn semaphore_counts_owned_permits() {
+        let monitor = SyncMonitor::new();
+        let semaphore = monitor.semaphore("pool", 3);
+
+        let one = semaphore.acquire_owned().await.unwrap();
+        let many = semaphore.acquire_many_owned(2).await.unwrap();
+        assert_eq!(permits(&monitor), [0.0, 3.0, 2.0]);
+
+        // Owned permits outlive the borrow of the semaphore.
+        drop(semaphore);
+        tokio::spawn(async move { drop(many) }).await.unwrap();
+        assert_eq!(permits(&monitor), [2.0, 1.0, 2.0]);
+        // The series are removed with the last permit.
+        drop(one);
+        assert!(!monitor.encoded().contains("semaphore=\"pool\""));
+    }
+}
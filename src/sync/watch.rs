@@ -0,0 +1,229 @@
+//! Watch channels exporting their receivers and updates.
+
+use std::sync::{
+    atomic::{AtomicI64, AtomicU64, Ordering},
+    Arc,
+};
+
+use prometheus_client::encoding::DescriptorEncoder;
+use tokio::sync::watch::{
+    self,
+    error::{RecvError, SendError},
+    Ref,
+};
+
+use crate::primitives::{encode_counters, encode_gauges, label_value, track, Primitive};
+
+use super::SyncMonitor;
+
+impl SyncMonitor {
+    /// Create a watch channel named `name` holding `init`, see
+    /// [`watch::channel`].
+    pub fn watch<T>(
+        &self,
+        name: impl Into<String>,
+        init: T,
+    ) -> (MonitoredWatchSender<T>, MonitoredWatchReceiver<T>) {
+        let (sender, receiver) = watch::channel(init);
+        let state = State {
+            name: label_value(name),
+            receivers: AtomicI64::new(0),
+            updates: AtomicU64::new(0),
+        };
+        let state = track(&self.shared.watches, state);
+        (
+            MonitoredWatchSender {
+                sender,
+                state: state.clone(),
+            },
+            MonitoredWatchReceiver::new(receiver, state),
+        )
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct State {
+    name: String,
+    receivers: AtomicI64,
+    updates: AtomicU64,
+}
+
+/// The sending half of a channel created with [`SyncMonitor::watch`].
+#[derive(Debug)]
+pub struct MonitoredWatchSender<T> {
+    sender: watch::Sender<T>,
+    state: Arc<State>,
+}
+
+impl<T> MonitoredWatchSender<T> {
+    /// Update the value if there are receivers, see
+    /// [`watch::Sender::send`].
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        self.sender.send(value)?;
+        self.updated();
+        Ok(())
+    }
+
+    /// Update the value, returning the previous one, see
+    /// [`watch::Sender::send_replace`].
+    pub fn send_replace(&self, value: T) -> T {
+        let previous = self.sender.send_replace(value);
+        self.updated();
+        previous
+    }
+
+    /// Modify the value in place, see [`watch::Sender::send_modify`].
+    pub fn send_modify(&self, modify: impl FnOnce(&mut T)) {
+        self.sender.send_modify(modify);
+        self.updated();
+    }
+
+    /// Modify the value in place, notifying receivers if `modify` returns
+    /// `true`, see [`watch::Sender::send_if_modified`].
+    pub fn send_if_modified(&self, modify: impl FnOnce(&mut T) -> bool) -> bool {
+        let modified = self.sender.send_if_modified(modify);
+        if modified {
+            self.updated();
+        }
+        modified
+    }
+
+    /// Borrow the value, see [`watch::Sender::borrow`].
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.sender.borrow()
+    }
+
+    /// Create a receiver, see [`watch::Sender::subscribe`].
+    pub fn subscribe(&self) -> MonitoredWatchReceiver<T> {
+        MonitoredWatchReceiver::new(self.sender.subscribe(), self.state.clone())
+    }
+
+    /// The number of receivers, see [`watch::Sender::receiver_count`].
+    pub fn receiver_count(&self) -> usize {
+        self.sender.receiver_count()
+    }
+
+    /// Whether all receivers were dropped, see [`watch::Sender::is_closed`].
+    pub fn is_closed(&self) -> bool {
+        self.sender.is_closed()
+    }
+
+    fn updated(&self) {
+        self.state.updates.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// A receiving half of a channel created with [`SyncMonitor::watch`].
+#[derive(Debug)]
+pub struct MonitoredWatchReceiver<T> {
+    receiver: watch::Receiver<T>,
+    state: Arc<State>,
+}
+
+impl<T> MonitoredWatchReceiver<T> {
+    fn new(receiver: watch::Receiver<T>, state: Arc<State>) -> Self {
+        state.receivers.fetch_add(1, Ordering::Relaxed);
+        Self { receiver, state }
+    }
+
+    /// Borrow the latest value, see [`watch::Receiver::borrow`].
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.receiver.borrow()
+    }
+
+    /// Borrow the latest value and mark it seen, see
+    /// [`watch::Receiver::borrow_and_update`].
+    pub fn borrow_and_update(&mut self) -> Ref<'_, T> {
+        self.receiver.borrow_and_update()
+    }
+
+    /// Whether the value changed since it was last seen, see
+    /// [`watch::Receiver::has_changed`].
+    pub fn has_changed(&self) -> Result<bool, RecvError> {
+        self.receiver.has_changed()
+    }
+
+    /// Wait for the value to change, see [`watch::Receiver::changed`].
+    pub async fn changed(&mut self) -> Result<(), RecvError> {
+        self.receiver.changed().await
+    }
+}
+
+impl<T> Clone for MonitoredWatchReceiver<T> {
+    fn clone(&self) -> Self {
+        Self::new(self.receiver.clone(), self.state.clone())
+    }
+}
+
+impl<T> Drop for MonitoredWatchReceiver<T> {
+    fn drop(&mut self) {
+        self.state.receivers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Primitive for State {
+    const LABEL: &'static str = "watch";
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Encode the metrics of `watches`.
+pub(super) fn encode(
+    watches: &[Arc<State>],
+    encoder: &mut DescriptorEncoder,
+) -> Result<(), std::fmt::Error> {
+    encode_gauges(
+        encoder,
+        watches,
+        "watch_receivers",
+        "The number of receivers of the watch channel",
+        |watch| watch.receivers.load(Ordering::Relaxed),
+    )?;
+    encode_counters(
+        encoder,
+        watches,
+        "watch_updates",
+        "The number of times the value of the watch channel was updated",
+        |watch| &watch.updates,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_receivers_and_updates() {
+        let monitor = SyncMonitor::new();
+        let (sender, mut receiver) = monitor.watch("config", 0);
+        let cloned = receiver.clone();
+        let subscribed = sender.subscribe();
+
+        sender.send(1).unwrap();
+        assert_eq!(sender.send_replace(2), 1);
+        sender.send_modify(|value| *value += 1);
+        assert!(!sender.send_if_modified(|_| false));
+        assert!(sender.send_if_modified(|value| {
+            *value += 1;
+            true
+        }));
+        assert_eq!(*receiver.borrow_and_update(), 4);
+
+        let text = monitor.encoded();
+        assert!(
+            text.contains("watch_receivers{watch=\"config\"} 3\n"),
+            "{text}"
+        );
+        assert!(
+            text.contains("watch_updates_total{watch=\"config\"} 4\n"),
+            "{text}"
+        );
+
+        drop((cloned, subscribed));
+        assert!(monitor
+            .encoded()
+            .contains("watch_receivers{watch=\"config\"} 1\n"));
+    }
+}
@@ -0,0 +1,161 @@
+//! Helpers to test the exposition of a registry against golden output.
+//!
+//! Enabled with the `test-util` feature. Combined with
+//! [`RuntimeCollectorBuilder::from_intervals`](crate::RuntimeCollectorBuilder::from_intervals)
+//! the values are known in advance, otherwise values can be left out with
+//! `*`.
+
+use prometheus_client::registry::Registry;
+
+/// Assert that `registry` encodes in the text format as `expected`.
+///
+/// Lines are compared after trimming, and `# HELP` lines are left out on
+/// both sides, so the help texts can change without breaking tests. A value
+/// of `*` in `expected` matches any value, for volatile samples like
+/// counters of a live runtime or creation times. On a mismatch it panics
+/// with a diff of the expected and actual lines.
+///
+/// ## Example
+///
+/// ```
+/// use tokio_prometheus_client::test_util::assert_encodes;
+///
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// tokio_prometheus_client::RuntimeCollectorBuilder::from_intervals([])
+///     .created(true)
+///     .register(registry.sub_registry_with_prefix("tokio"));
+///
+/// assert_encodes(
+///     &registry,
+///     r#"
+///     ## TYPE tokio_workers_count gauge
+///     tokio_workers_count 0
+///     ## TYPE tokio_total_park_count counter
+///     tokio_total_park_count_total 0
+///     ## TYPE tokio_total_park_count_created gauge
+///     tokio_total_park_count_created *
+///     ## TYPE tokio_total_noop_count counter
+///     tokio_total_noop_count_total 0
+///     ## TYPE tokio_total_noop_count_created gauge
+///     tokio_total_noop_count_created *
+///     ## TYPE tokio_total_steal_count counter
+///     tokio_total_steal_count_total 0
+///     ## TYPE tokio_total_steal_count_created gauge
+///     tokio_total_steal_count_created *
+///     ## TYPE tokio_total_steal_operations counter
+///     tokio_total_steal_operations_total 0
+///     ## TYPE tokio_total_steal_operations_created gauge
+///     tokio_total_steal_operations_created *
+///     ## TYPE tokio_num_remote_schedules counter
+///     tokio_num_remote_schedules_total 0
+///     ## TYPE tokio_num_remote_schedules_created gauge
+///     tokio_num_remote_schedules_created *
+///     ## TYPE tokio_total_local_schedule_count counter
+///     tokio_total_local_schedule_count_total 0
+///     ## TYPE tokio_total_local_schedule_count_created gauge
+///     tokio_total_local_schedule_count_created *
+///     ## TYPE tokio_total_overflow_count counter
+///     tokio_total_overflow_count_total 0
+///     ## TYPE tokio_total_overflow_count_created gauge
+///     tokio_total_overflow_count_created *
+///     ## TYPE tokio_total_polls_count counter
+///     tokio_total_polls_count_total 0
+///     ## TYPE tokio_total_polls_count_created gauge
+///     tokio_total_polls_count_created *
+///     ## TYPE tokio_total_busy_duration_seconds counter
+///     ## UNIT tokio_total_busy_duration_seconds seconds
+///     tokio_total_busy_duration_seconds_total 0.0
+///     ## TYPE tokio_total_busy_duration_seconds_created gauge
+///     tokio_total_busy_duration_seconds_created *
+///     ## TYPE tokio_injection_queue_depth gauge
+///     tokio_injection_queue_depth 0
+///     ## TYPE tokio_total_local_queue_depth gauge
+///     tokio_total_local_queue_depth 0
+///     ## TYPE tokio_budget_forced_yield_count counter
+///     tokio_budget_forced_yield_count_total 0
+///     ## TYPE tokio_budget_forced_yield_count_created gauge
+///     tokio_budget_forced_yield_count_created *
+///     ## TYPE tokio_io_driver_ready_count counter
+///     tokio_io_driver_ready_count_total 0
+///     ## TYPE tokio_io_driver_ready_count_created gauge
+///     tokio_io_driver_ready_count_created *
+///     ## TYPE tokio_counter_anomalies counter
+///     tokio_counter_anomalies_total 0
+///     ## TYPE tokio_counter_saturations counter
+///     tokio_counter_saturations_total 0
+///     ## EOF
+///     "#,
+/// );
+/// ```
+#[track_caller]
+pub fn assert_encodes(registry: &Registry, expected: &str) {
+    let mut actual = String::new();
+    prometheus_client::encoding::text::encode(&mut actual, registry)
+        .expect("should be able to encode registry");
+    let actual = lines(&actual);
+    let expected = lines(expected);
+    if actual.len() == expected.len()
+        && actual
+            .iter()
+            .zip(&expected)
+            .all(|(actual, expected)| matches(actual, expected))
+    {
+        return;
+    }
+    panic!(
+        "registry does not encode as expected (- expected, + actual):\n{}",
+        diff(&expected, &actual)
+    );
+}
+
+/// The trimmed, non-empty lines of `text` except `# HELP` lines.
+fn lines(text: &str) -> Vec<&str> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("# HELP "))
+        .collect()
+}
+
+fn matches(actual: &str, expected: &str) -> bool {
+    match (expected.strip_suffix(" *"), actual.rsplit_once(' ')) {
+        (Some(expected), Some((actual, _))) => expected == actual,
+        _ => expected == actual,
+    }
+}
+
+/// A line diff of `expected` and `actual`, from their longest common
+/// subsequence.
+fn diff(expected: &[&str], actual: &[&str]) -> String {
+    // common[i][j]: length of the longest common subsequence of
+    // expected[i..] and actual[j..].
+    let mut common = vec![vec![0; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            common[i][j] = if matches(actual[j], expected[i]) {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        let line = if i < expected.len() && j < actual.len() && matches(actual[j], expected[i]) {
+            i += 1;
+            j += 1;
+            format!("  {}", actual[j - 1])
+        } else if j < actual.len() && (i == expected.len() || common[i][j + 1] >= common[i + 1][j])
+        {
+            j += 1;
+            format!("+ {}", actual[j - 1])
+        } else {
+            i += 1;
+            format!("- {}", expected[i - 1])
+        };
+        diff.push_str(&line);
+        diff.push('\n');
+    }
+    diff
+}
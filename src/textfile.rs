@@ -0,0 +1,181 @@
+//! Write a [`Registry`] to a file for the
+//! [node_exporter textfile collector](https://github.com/prometheus/node_exporter#textfile-collector).
+//!
+//! Enabled with the `textfile` feature. Useful for processes that cannot
+//! expose any network endpoint.
+
+use std::{
+    ffi::OsString,
+    io::{self, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use prometheus_client::registry::Registry;
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+
+use crate::samples::{collect, encode_text};
+
+/// Periodically writes a [`Registry`] to a `.prom` file.
+///
+/// The file is written in the classic text format the textfile collector
+/// expects. Every write goes to a temporary file in the same directory which
+/// is then renamed over the target, so node_exporter never reads a partial
+/// file.
+///
+/// ## Example
+///
+/// ```no_run
+/// # use std::{sync::{Arc, Mutex}, time::Duration};
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let handle = tokio::runtime::Handle::current();
+/// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// tokio_prometheus_client::register(runtime_monitor, registry.sub_registry_with_prefix("tokio"));
+///
+/// tokio_prometheus_client::textfile::Textfile::new("/var/lib/node_exporter/my_service.prom")
+///     .interval(Duration::from_secs(30))
+///     .spawn(Arc::new(Mutex::new(registry)));
+/// # });
+/// ```
+#[derive(Clone, Debug)]
+pub struct Textfile {
+    path: PathBuf,
+    interval: Duration,
+}
+
+impl Textfile {
+    /// Create a [`Textfile`] writing to `path`, which should end in `.prom`.
+    ///
+    /// Writes happen every 15 seconds.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            interval: Duration::from_secs(15),
+        }
+    }
+
+    /// Set the interval between writes of the background task.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Encode `registry` and atomically replace the file with it.
+    ///
+    /// This performs blocking file system operations.
+    pub fn write(&self, registry: &Mutex<Registry>) -> io::Result<()> {
+        let families = collect(&registry.lock().expect("should be able to lock registry"))
+            .map_err(|_| io::Error::other("failed to encode metrics"))?;
+        let mut body = String::new();
+        encode_text(&mut body, &families)
+            .map_err(|_| io::Error::other("failed to encode metrics"))?;
+
+        // The textfile collector only reads files ending in `.prom`, so the
+        // temporary file is never picked up.
+        let mut temporary = OsString::from(self.path.as_os_str());
+        temporary.push(".tmp");
+        let temporary = PathBuf::from(temporary);
+        let mut file = std::fs::File::create(&temporary)?;
+        file.write_all(body.as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&temporary, &self.path)
+    }
+
+    /// Spawn a task writing `registry` every interval.
+    ///
+    /// Failed writes are logged.
+    pub fn spawn(self, registry: Arc<Mutex<Registry>>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let textfile = Arc::new(self);
+            let mut interval = tokio::time::interval(textfile.interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let (textfile, registry) = (textfile.clone(), registry.clone());
+                let written = tokio::task::spawn_blocking(move || textfile.write(&registry))
+                    .await
+                    .unwrap_or_else(|err| Err(io::Error::other(err)));
+                if let Err(err) = written {
+                    tracing::warn!(%err, "failed to write metrics textfile");
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus_client::metrics::counter::Counter;
+
+    use super::*;
+
+    /// A fresh directory for the files of the test called `name`.
+    fn directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("tokio-prometheus-client-{name}"));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn registry() -> Arc<Mutex<Registry>> {
+        let counter = Counter::<u64>::default();
+        counter.inc_by(3);
+        let mut registry = Registry::default();
+        registry.register("requests", "Handled requests", counter);
+        Arc::new(Mutex::new(registry))
+    }
+
+    #[test]
+    fn writes_classic_text_format() {
+        let directory = directory("textfile-write");
+        let path = directory.join("service.prom");
+        Textfile::new(&path).write(&registry()).unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            text,
+            "# HELP requests_total Handled requests.\n\
+             # TYPE requests_total counter\n\
+             requests_total 3\n"
+        );
+        let files: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(files, ["service.prom"]);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn write_fails_without_directory() {
+        let path = std::env::temp_dir()
+            .join("tokio-prometheus-client-textfile-missing")
+            .join("service.prom");
+        let err = Textfile::new(&path).write(&registry()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[tokio::test]
+    async fn spawned_task_writes_the_file() {
+        let directory = directory("textfile-spawn");
+        let path = directory.join("service.prom");
+        let task = Textfile::new(&path)
+            .interval(Duration::from_millis(10))
+            .spawn(registry());
+        // The first write happens right away.
+        for _ in 0..100 {
+            if path.exists() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        task.abort();
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .contains("requests_total 3\n"));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
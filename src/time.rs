@@ -0,0 +1,131 @@
+//! Instrumented utilities of `tokio::time`.
+//!
+//! Enabled with the `time` feature. Periodic jobs running late and storms
+//! of timeouts are often the first symptoms of a busy or blocked runtime.
+//! The utilities created by a [`TimeMonitor`] export how late they run or
+//! how often they expire, labeled with the name they were created with,
+//! next to the runtime metrics explaining why.
+
+use std::sync::{Arc, Mutex, Weak};
+
+use prometheus_client::{collector::Collector, encoding::DescriptorEncoder, registry::Registry};
+
+use crate::{
+    buckets::BucketPreset,
+    primitives::{all, live},
+};
+
+mod interval;
+mod timeout;
+
+pub use interval::MonitoredInterval;
+
+/// Creates instrumented timers and collects their metrics.
+///
+/// Each timer is exported with a label of its kind set to its name, names
+/// should be unique per kind. The series of a timer are removed once all
+/// of its handles are dropped, except for timeouts.
+///
+/// Intervals, created with [`TimeMonitor::interval`], expose:
+///
+/// * `interval_ticks`: the number of ticks.
+/// * `interval_late_ticks`: the number of ticks that completed a period
+///   or more after they were due.
+/// * `interval_missed_ticks`: the number of periods skipped without a
+///   tick, see [`MissedTickBehavior`](tokio::time::MissedTickBehavior).
+/// * `interval_tick_processing_seconds`: a histogram of the time from a
+///   tick to waiting for the next one, i.e. processing it.
+///
+/// Timeouts, run with [`TimeMonitor::timeout`], expose:
+///
+/// * `timeout_completions`: the number of futures that completed in time.
+/// * `timeout_expirations`: the number of futures that timed out. A storm
+///   of them next to growing scheduling delays points to the runtime, not
+///   to what was waited for.
+/// * `timeout_expired_after_seconds`: a histogram of the time from starting
+///   futures to their timeout expiring, the timeout plus how late the
+///   timer fired.
+///
+/// ## Example
+///
+/// ```
+/// # use std::time::Duration;
+/// # let rt = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+/// # rt.block_on(async {
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// let monitor = tokio_prometheus_client::time::TimeMonitor::new();
+/// monitor.register(&mut registry);
+///
+/// let mut interval = monitor.interval("flush", Duration::from_millis(10));
+/// for _ in 0..3 {
+///     interval.tick().await;
+/// }
+///
+/// let mut text = String::new();
+/// prometheus_client::encoding::text::encode(&mut text, &registry).unwrap();
+/// assert!(text.contains("interval_ticks_total{interval=\"flush\"} 3\n"));
+///
+/// let reply = monitor.timeout("rpc", Duration::from_millis(10), std::future::pending::<()>());
+/// assert!(reply.await.is_err());
+/// # });
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct TimeMonitor {
+    buckets: BucketPreset,
+    shared: Arc<Shared>,
+}
+
+/// The timers of a [`TimeMonitor`], pruned on every collection.
+#[derive(Debug, Default)]
+struct Shared {
+    intervals: Mutex<Vec<Weak<interval::State>>>,
+    timeouts: Mutex<Vec<Arc<timeout::State>>>,
+}
+
+impl TimeMonitor {
+    /// Create a [`TimeMonitor`] with [`BucketPreset::LatencyCoarse`]
+    /// histograms.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the buckets of the histograms of the timers created from then
+    /// on.
+    pub fn buckets(mut self, buckets: BucketPreset) -> Self {
+        self.buckets = buckets;
+        self
+    }
+
+    /// Register the collector of the timers with `registry`.
+    pub fn register(&self, registry: &mut Registry) {
+        registry.register_collector(Box::new(TimeCollector {
+            shared: self.shared.clone(),
+        }));
+    }
+}
+
+/// Collects the timers of a [`TimeMonitor`].
+#[derive(Debug)]
+struct TimeCollector {
+    shared: Arc<Shared>,
+}
+
+impl Collector for TimeCollector {
+    fn encode(&self, mut encoder: DescriptorEncoder) -> Result<(), std::fmt::Error> {
+        interval::encode(&live(&self.shared.intervals), &mut encoder)?;
+        timeout::encode(&all(&self.shared.timeouts), &mut encoder)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl TimeMonitor {
+    /// The text exposition of the timers.
+    fn encoded(&self) -> String {
+        let mut registry = Registry::default();
+        self.register(&mut registry);
+        let mut text = String::new();
+        prometheus_client::encoding::text::encode(&mut text, &registry).unwrap();
+        text
+    }
+}
@@ -0,0 +1,272 @@
+//! Intervals exporting their late and missed ticks.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use prometheus_client::{encoding::DescriptorEncoder, metrics::histogram::Histogram};
+use tokio::time::{self, Instant, Interval, MissedTickBehavior};
+
+use crate::primitives::{encode_counters, encode_histograms, label_value, track, Primitive};
+
+use super::TimeMonitor;
+
+impl TimeMonitor {
+    /// Create a [`MonitoredInterval`] named `name` ticking every `period`,
+    /// first immediately, see [`time::interval`].
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `period` is zero.
+    pub fn interval(&self, name: impl Into<String>, period: Duration) -> MonitoredInterval {
+        self.interval_at(name, Instant::now(), period)
+    }
+
+    /// Create a [`MonitoredInterval`] named `name` ticking every `period`,
+    /// first at `start`, see [`time::interval_at`].
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `period` is zero.
+    pub fn interval_at(
+        &self,
+        name: impl Into<String>,
+        start: Instant,
+        period: Duration,
+    ) -> MonitoredInterval {
+        let state = State {
+            name: label_value(name),
+            ticks: AtomicU64::new(0),
+            late: AtomicU64::new(0),
+            missed: AtomicU64::new(0),
+            processing: Histogram::new(self.buckets.buckets().iter().copied()),
+        };
+        MonitoredInterval {
+            interval: time::interval_at(start, period),
+            state: track(&self.shared.intervals, state),
+            last_tick: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct State {
+    name: String,
+    ticks: AtomicU64,
+    /// The number of ticks completed a period or more after they were due.
+    late: AtomicU64,
+    /// The number of periods skipped without a tick.
+    missed: AtomicU64,
+    processing: Histogram,
+}
+
+/// An [`Interval`] exporting its late and missed ticks, see
+/// [`TimeMonitor::interval`].
+#[derive(Debug)]
+pub struct MonitoredInterval {
+    interval: Interval,
+    state: Arc<State>,
+    /// When the last tick was due and when it completed.
+    last_tick: Option<(Instant, Instant)>,
+}
+
+impl MonitoredInterval {
+    /// Wait for the next tick, returning when it was due, see
+    /// [`Interval::tick`].
+    pub async fn tick(&mut self) -> Instant {
+        if let Some((_, completed)) = self.last_tick {
+            self.state
+                .processing
+                .observe(completed.elapsed().as_secs_f64());
+        }
+        let due = self.interval.tick().await;
+        let completed = Instant::now();
+        let period = self.interval.period();
+        self.state.ticks.fetch_add(1, Ordering::Relaxed);
+        if completed.duration_since(due) >= period {
+            self.state.late.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some((last_due, _)) = self.last_tick {
+            let periods = due.duration_since(last_due).as_nanos() / period.as_nanos();
+            let missed = periods.saturating_sub(1).try_into().unwrap_or(u64::MAX);
+            self.state.missed.fetch_add(missed, Ordering::Relaxed);
+        }
+        self.last_tick = Some((due, completed));
+        due
+    }
+
+    /// Reset the interval to tick a period from now, see
+    /// [`Interval::reset`].
+    ///
+    /// The periods skipped by resetting are not counted as missed, nor is
+    /// the time until the next tick counted as processing.
+    pub fn reset(&mut self) {
+        self.interval.reset();
+        self.last_tick = None;
+    }
+
+    /// The period of the interval, see [`Interval::period`].
+    pub fn period(&self) -> Duration {
+        self.interval.period()
+    }
+
+    /// What happens when ticks are missed, see
+    /// [`Interval::missed_tick_behavior`].
+    pub fn missed_tick_behavior(&self) -> MissedTickBehavior {
+        self.interval.missed_tick_behavior()
+    }
+
+    /// Set what happens when ticks are missed, see
+    /// [`Interval::set_missed_tick_behavior`].
+    pub fn set_missed_tick_behavior(&mut self, behavior: MissedTickBehavior) {
+        self.interval.set_missed_tick_behavior(behavior);
+    }
+}
+
+impl Primitive for State {
+    const LABEL: &'static str = "interval";
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Encode the metrics of `intervals`.
+pub(super) fn encode(
+    intervals: &[Arc<State>],
+    encoder: &mut DescriptorEncoder,
+) -> Result<(), std::fmt::Error> {
+    encode_counters(
+        encoder,
+        intervals,
+        "interval_ticks",
+        "The number of ticks of the interval",
+        |interval| &interval.ticks,
+    )?;
+    encode_counters(
+        encoder,
+        intervals,
+        "interval_late_ticks",
+        "The number of ticks of the interval completed a period or more after they were due",
+        |interval| &interval.late,
+    )?;
+    encode_counters(
+        encoder,
+        intervals,
+        "interval_missed_ticks",
+        "The number of periods the interval skipped without a tick",
+        |interval| &interval.missed,
+    )?;
+    encode_histograms(
+        encoder,
+        intervals,
+        "interval_tick_processing",
+        "The time from a tick of the interval to waiting for the next one",
+        |interval| &interval.processing,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(text: &str, name: &str) -> f64 {
+        text.lines()
+            .find_map(|line| line.strip_prefix(&format!("{name} ")))
+            .unwrap_or_else(|| panic!("{name} should be exported: {text}"))
+            .parse()
+            .unwrap()
+    }
+
+    /// The ticks, late ticks and missed periods of the `tick` interval.
+    fn counts(monitor: &TimeMonitor) -> [f64; 3] {
+        let text = monitor.encoded();
+        [
+            sample(&text, "interval_ticks_total{interval=\"tick\"}"),
+            sample(&text, "interval_late_ticks_total{interval=\"tick\"}"),
+            sample(&text, "interval_missed_ticks_total{interval=\"tick\"}"),
+        ]
+    }
+
+    /// Create an interval ticking every 10ms with `behavior`, tick it once
+    /// and process the tick for 35ms, missing 3 ticks.
+    async fn interval(monitor: &TimeMonitor, behavior: MissedTickBehavior) -> MonitoredInterval {
+        let mut interval = monitor.interval("tick", Duration::from_millis(10));
+        interval.set_missed_tick_behavior(behavior);
+        interval.tick().await;
+        time::sleep(Duration::from_millis(35)).await;
+        interval
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn burst_catches_up_on_late_ticks() {
+        let monitor = TimeMonitor::new();
+        let mut interval = interval(&monitor, MissedTickBehavior::Burst).await;
+        let start = interval.last_tick.unwrap().0;
+
+        // The ticks due at 10ms and 20ms complete a period or more late,
+        // the one due at 30ms less than a period late.
+        for due in [10, 20, 30, 40] {
+            assert_eq!(interval.tick().await - start, Duration::from_millis(due));
+        }
+        assert_eq!(counts(&monitor), [5.0, 2.0, 0.0]);
+
+        let text = monitor.encoded();
+        assert_eq!(
+            sample(
+                &text,
+                "interval_tick_processing_seconds_count{interval=\"tick\"}"
+            ),
+            4.0
+        );
+        assert_eq!(
+            sample(
+                &text,
+                "interval_tick_processing_seconds_sum{interval=\"tick\"}"
+            ),
+            0.035
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn delay_misses_the_periods_it_delays_by() {
+        let monitor = TimeMonitor::new();
+        let mut interval = interval(&monitor, MissedTickBehavior::Delay).await;
+        let start = interval.last_tick.unwrap().0;
+
+        // The late tick due at 10ms delays the next one to a period after
+        // it completed, missing the periods up to 45ms in between.
+        for due in [10, 45, 55] {
+            assert_eq!(interval.tick().await - start, Duration::from_millis(due));
+        }
+        assert_eq!(counts(&monitor), [4.0, 1.0, 2.0]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn skip_misses_the_skipped_periods() {
+        let monitor = TimeMonitor::new();
+        let mut interval = interval(&monitor, MissedTickBehavior::Skip).await;
+        let start = interval.last_tick.unwrap().0;
+
+        // The late tick due at 10ms skips the ones due at 20ms and 30ms.
+        for due in [10, 40, 50] {
+            assert_eq!(interval.tick().await - start, Duration::from_millis(due));
+        }
+        assert_eq!(counts(&monitor), [4.0, 1.0, 2.0]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reset_does_not_miss_ticks() {
+        let monitor = TimeMonitor::new();
+        let mut interval = interval(&monitor, MissedTickBehavior::Skip).await;
+        let start = interval.last_tick.unwrap().0;
+
+        interval.reset();
+        assert_eq!(interval.tick().await - start, Duration::from_millis(45));
+        assert_eq!(counts(&monitor), [2.0, 0.0, 0.0]);
+    }
+}
@@ -0,0 +1,153 @@
+//! Timeouts exporting how often they expire.
+
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use prometheus_client::{encoding::DescriptorEncoder, metrics::histogram::Histogram};
+use tokio::time::{self, error::Elapsed, Instant};
+
+use crate::primitives::{encode_counters, encode_histograms, named, Primitive};
+
+use super::TimeMonitor;
+
+impl TimeMonitor {
+    /// Run `future` with a timeout of `duration`, counted in the timeout
+    /// named `name`, see [`time::timeout`].
+    ///
+    /// Unlike the other timers, the timeouts of a name, e.g. of a call
+    /// site, are counted together and kept for the lifetime of the
+    /// monitor.
+    pub fn timeout<F: Future>(
+        &self,
+        name: impl Into<String>,
+        duration: Duration,
+        future: F,
+    ) -> impl Future<Output = Result<F::Output, Elapsed>> {
+        let state = self.timeout_state(name);
+        async move {
+            let started = Instant::now();
+            let output = time::timeout(duration, future).await;
+            match output {
+                Ok(_) => state.completed.fetch_add(1, Ordering::Relaxed),
+                Err(_) => {
+                    state.expired_after.observe(started.elapsed().as_secs_f64());
+                    state.expired.fetch_add(1, Ordering::Relaxed)
+                }
+            };
+            output
+        }
+    }
+
+    /// The state of the timeout `name`, created on first use.
+    fn timeout_state(&self, name: impl Into<String>) -> Arc<State> {
+        named(&self.shared.timeouts, name, |name| State {
+            name,
+            completed: AtomicU64::new(0),
+            expired: AtomicU64::new(0),
+            expired_after: Histogram::new(self.buckets.buckets().iter().copied()),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct State {
+    name: String,
+    completed: AtomicU64,
+    expired: AtomicU64,
+    /// The time from starting to expiring, the timeout plus how late the
+    /// timer fired.
+    expired_after: Histogram,
+}
+
+impl Primitive for State {
+    const LABEL: &'static str = "timeout";
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Encode the metrics of `timeouts`.
+pub(super) fn encode(
+    timeouts: &[Arc<State>],
+    encoder: &mut DescriptorEncoder,
+) -> Result<(), std::fmt::Error> {
+    encode_counters(
+        encoder,
+        timeouts,
+        "timeout_completions",
+        "The number of futures that completed before the timeout",
+        |timeout| &timeout.completed,
+    )?;
+    encode_counters(
+        encoder,
+        timeouts,
+        "timeout_expirations",
+        "The number of futures that did not complete before the timeout",
+        |timeout| &timeout.expired,
+    )?;
+    encode_histograms(
+        encoder,
+        timeouts,
+        "timeout_expired_after",
+        "The time from starting futures to their timeout expiring",
+        |timeout| &timeout.expired_after,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(text: &str, name: &str) -> f64 {
+        text.lines()
+            .find_map(|line| line.strip_prefix(&format!("{name} ")))
+            .unwrap_or_else(|| panic!("{name} should be exported: {text}"))
+            .parse()
+            .unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn timeouts_count_completions_and_expirations() {
+        let monitor = TimeMonitor::new();
+        let completed = monitor.timeout(
+            "rpc",
+            Duration::from_millis(20),
+            time::sleep(Duration::from_millis(10)),
+        );
+        assert!(completed.await.is_ok());
+        let expired = monitor.timeout(
+            "rpc",
+            Duration::from_millis(20),
+            std::future::pending::<()>(),
+        );
+        assert!(expired.await.is_err());
+
+        let text = monitor.encoded();
+        assert_eq!(
+            sample(&text, "timeout_completions_total{timeout=\"rpc\"}"),
+            1.0
+        );
+        assert_eq!(
+            sample(&text, "timeout_expirations_total{timeout=\"rpc\"}"),
+            1.0
+        );
+        assert_eq!(
+            sample(
+                &text,
+                "timeout_expired_after_seconds_count{timeout=\"rpc\"}"
+            ),
+            1.0
+        );
+        assert_eq!(
+            sample(&text, "timeout_expired_after_seconds_sum{timeout=\"rpc\"}"),
+            0.02
+        );
+    }
+}
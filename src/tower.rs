@@ -0,0 +1,666 @@
+//! Framework agnostic [`tower_service::Service`] serving a [`Registry`].
+//!
+//! Enabled with the `tower` feature. The framework specific integrations are
+//! thin wrappers around [`MetricsService`].
+
+use std::{
+    convert::Infallible,
+    future::{ready, Ready},
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::UNIX_EPOCH,
+};
+
+use bytes::Bytes;
+#[cfg(feature = "gzip")]
+use http::header::{ACCEPT_ENCODING, CONTENT_ENCODING};
+use http::{
+    header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, VARY, WWW_AUTHENTICATE},
+    HeaderValue, Method, Request, Response, StatusCode,
+};
+use http_body_util::Full;
+use prometheus_client::{
+    encoding::text::{encode_eof, encode_registry},
+    registry::Registry,
+};
+use tower_service::Service;
+
+use crate::{base64, samples, SampleClock, OPENMETRICS_CONTENT_TYPE, TEXT_CONTENT_TYPE};
+
+/// A [`Service`] responding to every request with the encoded metrics of a
+/// [`Registry`].
+///
+/// The service does not look at the request path, mount it on the path
+/// metrics should be served on. Metrics are served in the OpenMetrics text
+/// format to clients whose `Accept` header asks for it, like Prometheus, and
+/// in the classic Prometheus text format otherwise, e.g. to older scrapers,
+/// agents and curl. With the `gzip` feature responses are
+/// compressed if the client sends a matching `Accept-Encoding` header.
+/// Requests can be required to authenticate, see
+/// [`MetricsService::basic_auth`] and [`MetricsService::bearer_token`].
+/// Further registries can be served from the same endpoint with
+/// [`MetricsService::registry`], or [`MetricsService::named_registry`] to
+/// let requests select them.
+///
+/// ## Example
+///
+/// ```
+/// # use std::sync::{Arc, Mutex};
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let handle = tokio::runtime::Handle::current();
+/// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// tokio_prometheus_client::register(runtime_monitor, registry.sub_registry_with_prefix("tokio"));
+///
+/// let service = tokio_prometheus_client::tower::MetricsService::new(Arc::new(Mutex::new(registry)));
+/// let response = service.respond(&http::Request::get("/metrics").body(()).unwrap());
+/// assert_eq!(response.status(), http::StatusCode::OK);
+/// assert_eq!(
+///     response.headers()[http::header::CONTENT_TYPE],
+///     tokio_prometheus_client::TEXT_CONTENT_TYPE,
+/// );
+/// assert!(!std::str::from_utf8(response.body()).unwrap().contains("# EOF"));
+///
+/// let request = http::Request::get("/metrics")
+///     .header(http::header::ACCEPT, "application/openmetrics-text; version=1.0.0")
+///     .body(())
+///     .unwrap();
+/// let response = service.respond(&request);
+/// assert_eq!(
+///     response.headers()[http::header::CONTENT_TYPE],
+///     tokio_prometheus_client::OPENMETRICS_CONTENT_TYPE,
+/// );
+/// # });
+/// ```
+#[derive(Clone, Debug)]
+pub struct MetricsService {
+    registries: Vec<Served>,
+    format: Format,
+    #[cfg(feature = "gzip")]
+    gzip: bool,
+    auth: Option<Arc<Auth>>,
+}
+
+/// A registry served by a [`MetricsService`].
+#[derive(Clone, Debug)]
+struct Served {
+    /// The name requests select the registry by, if any.
+    name: Option<String>,
+    registry: Arc<Mutex<Registry>>,
+    /// The clock the samples of the registry are stamped with, if any.
+    clock: Option<SampleClock>,
+}
+
+impl Served {
+    fn new(name: Option<String>, registry: Arc<Mutex<Registry>>) -> Self {
+        Self {
+            name,
+            registry,
+            clock: None,
+        }
+    }
+
+    /// Encode the registry, in the classic text format rather than
+    /// OpenMetrics if `text`, stamping its samples with the time of the last
+    /// sample, if any.
+    fn encode(&self, body: &mut String, text: bool) -> std::fmt::Result {
+        let registry = self
+            .registry
+            .lock()
+            .expect("should be able to lock registry");
+        let sampled_at = self
+            .clock
+            .as_ref()
+            .and_then(SampleClock::last_sample)
+            .map(|sampled_at| sampled_at.duration_since(UNIX_EPOCH).unwrap_or_default());
+        match (text, sampled_at) {
+            (true, sampled_at) => {
+                samples::encode_text_at(body, &samples::collect(&registry)?, sampled_at)
+            }
+            (false, Some(sampled_at)) => {
+                samples::encode_openmetrics_at(body, &samples::collect(&registry)?, sampled_at)
+            }
+            (false, None) => encode_registry(body, &registry),
+        }
+    }
+}
+
+/// Credentials requests have to present in their `Authorization` header.
+#[derive(Debug)]
+struct Auth {
+    scheme: &'static str,
+    credentials: String,
+}
+
+impl Auth {
+    fn verify<B>(&self, request: &Request<B>) -> bool {
+        let Some((scheme, credentials)) = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split_once(' '))
+        else {
+            return false;
+        };
+        // Compare in constant time to not leak the credentials through
+        // response timings.
+        scheme.eq_ignore_ascii_case(self.scheme)
+            && credentials.len() == self.credentials.len()
+            && credentials
+                .bytes()
+                .zip(self.credentials.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+/// Format a [`MetricsService`] responds with.
+#[derive(Clone, Copy, Debug)]
+enum Format {
+    OpenMetrics,
+    #[cfg(feature = "json")]
+    Json,
+}
+
+impl MetricsService {
+    /// Create a [`MetricsService`] encoding `registry` on every request.
+    pub fn new(registry: Arc<Mutex<Registry>>) -> Self {
+        Self {
+            registries: vec![Served::new(None, registry)],
+            format: Format::OpenMetrics,
+            #[cfg(feature = "gzip")]
+            gzip: true,
+            auth: None,
+        }
+    }
+
+    /// Create a [`MetricsService`] rendering `registry` as JSON on every
+    /// request, see [`crate::json::encode`].
+    #[cfg(feature = "json")]
+    pub fn json(registry: Arc<Mutex<Registry>>) -> Self {
+        Self {
+            registries: vec![Served::new(None, registry)],
+            format: Format::Json,
+            #[cfg(feature = "gzip")]
+            gzip: true,
+            auth: None,
+        }
+    }
+
+    /// Additionally serve `registry`, e.g. one owned by another crate.
+    ///
+    /// Registries are encoded one after the other into a single response, in
+    /// the order they were added. Their metric names should not overlap.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use std::sync::{Arc, Mutex};
+    /// # let rt = tokio::runtime::Runtime::new().unwrap();
+    /// # rt.block_on(async {
+    /// let handle = tokio::runtime::Handle::current();
+    /// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+    /// let mut tokio_registry = prometheus_client::registry::Registry::with_prefix("tokio");
+    /// tokio_prometheus_client::register(runtime_monitor, &mut tokio_registry);
+    ///
+    /// let mut app_registry = prometheus_client::registry::Registry::default();
+    /// let requests = prometheus_client::metrics::counter::Counter::<u64>::default();
+    /// app_registry.register("requests", "Handled requests", requests);
+    ///
+    /// let service = tokio_prometheus_client::tower::MetricsService::new(Arc::new(Mutex::new(app_registry)))
+    ///     .registry(Arc::new(Mutex::new(tokio_registry)));
+    /// let response = service.respond(&http::Request::get("/metrics").body(()).unwrap());
+    /// let body = std::str::from_utf8(response.body()).unwrap();
+    /// assert!(body.contains("requests_total 0"));
+    /// assert!(body.contains("tokio_workers_count"));
+    /// # });
+    /// ```
+    pub fn registry(mut self, registry: Arc<Mutex<Registry>>) -> Self {
+        self.registries.push(Served::new(None, registry));
+        self
+    }
+
+    /// Additionally serve `registry` as `name`, selectable with `collect[]`
+    /// query parameters.
+    ///
+    /// Like with the node_exporter, requests with `collect[]` parameters,
+    /// e.g. `/metrics?collect[]=runtime&collect[]=tasks`, are only answered
+    /// with the named registries they list, so heavy registries can be
+    /// scraped less often by a second job. Requests without them are
+    /// answered with all registries, requests listing unknown names are
+    /// rejected with `400 Bad Request`. Names are compared as given in the
+    /// query, they should not need percent-encoding.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use std::sync::{Arc, Mutex};
+    /// let mut runtime = prometheus_client::registry::Registry::with_prefix("tokio");
+    /// tokio_prometheus_client::RuntimeCollectorBuilder::noop().register(&mut runtime);
+    /// let mut app_registry = prometheus_client::registry::Registry::default();
+    /// let requests = prometheus_client::metrics::counter::Counter::<u64>::default();
+    /// app_registry.register("requests", "Handled requests", requests);
+    ///
+    /// let service = tokio_prometheus_client::tower::MetricsService::new(Arc::new(Mutex::new(app_registry)))
+    ///     .named_registry("runtime", Arc::new(Mutex::new(runtime)));
+    /// let request = http::Request::get("/metrics?collect[]=runtime").body(()).unwrap();
+    /// let response = service.respond(&request);
+    /// let body = std::str::from_utf8(response.body()).unwrap();
+    /// assert!(body.contains("tokio_workers_count"));
+    /// assert!(!body.contains("requests_total"));
+    /// ```
+    pub fn named_registry(
+        mut self,
+        name: impl Into<String>,
+        registry: Arc<Mutex<Registry>>,
+    ) -> Self {
+        self.registries
+            .push(Served::new(Some(name.into()), registry));
+        self
+    }
+
+    /// Additionally serve `registry`, stamping its samples with the time of
+    /// the last sample of `clock`.
+    ///
+    /// Scrapes of a collector built with
+    /// [`RuntimeCollectorBuilder::build_with_sampler`](crate::RuntimeCollectorBuilder::build_with_sampler)
+    /// export the values of the last sample. With the time of that sample,
+    /// scrapes that are slow, batched or lag behind the sampler do not shift
+    /// the data in time. `registry` should only hold the collector of the
+    /// sampler. Samples are not stamped before the first sample, nor in
+    /// JSON. Stamped registries are encoded from their
+    /// [parsed samples](crate::samples::collect), without exemplars.
+    ///
+    /// Prometheus drops samples that are older than its head block, so the
+    /// sampler should keep sampling while the collector is scraped, e.g. not
+    /// be [frozen](crate::Pause::Freeze) for long.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use std::sync::{Arc, Mutex};
+    /// # let rt = tokio::runtime::Runtime::new().unwrap();
+    /// # rt.block_on(async {
+    /// let handle = tokio::runtime::Handle::current();
+    /// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+    /// let (collector, mut sampler) =
+    ///     tokio_prometheus_client::RuntimeCollectorBuilder::new(runtime_monitor)
+    ///         .build_with_sampler();
+    /// let mut runtime = prometheus_client::registry::Registry::with_prefix("tokio");
+    /// runtime.register_collector(collector);
+    /// sampler.sample();
+    ///
+    /// let app_registry = prometheus_client::registry::Registry::default();
+    /// let service = tokio_prometheus_client::tower::MetricsService::new(Arc::new(Mutex::new(app_registry)))
+    ///     .timestamped_registry(Arc::new(Mutex::new(runtime)), sampler.clock());
+    /// let response = service.respond(&http::Request::get("/metrics").body(()).unwrap());
+    /// let body = std::str::from_utf8(response.body()).unwrap();
+    /// let workers = body.lines().find(|line| line.starts_with("tokio_workers_count")).unwrap();
+    /// assert_eq!(workers.split(' ').count(), 3);
+    /// # });
+    /// ```
+    pub fn timestamped_registry(
+        mut self,
+        registry: Arc<Mutex<Registry>>,
+        clock: SampleClock,
+    ) -> Self {
+        self.registries.push(Served {
+            name: None,
+            registry,
+            clock: Some(clock),
+        });
+        self
+    }
+
+    /// Compress responses with gzip when the client accepts it, enabled by
+    /// default.
+    #[cfg(feature = "gzip")]
+    pub fn gzip(mut self, enabled: bool) -> Self {
+        self.gzip = enabled;
+        self
+    }
+
+    /// Require requests to authenticate with HTTP basic auth.
+    pub fn basic_auth(mut self, username: &str, password: &str) -> Self {
+        self.auth = Some(Arc::new(Auth {
+            scheme: "Basic",
+            credentials: base64::encode(format!("{username}:{password}").as_bytes()),
+        }));
+        self
+    }
+
+    /// Require requests to authenticate with a bearer token.
+    pub fn bearer_token(mut self, token: &str) -> Self {
+        self.auth = Some(Arc::new(Auth {
+            scheme: "Bearer",
+            credentials: token.to_string(),
+        }));
+        self
+    }
+
+    /// Build the response to `request`.
+    ///
+    /// This is the synchronous core of the service, for use by integrations
+    /// that do not speak [`Service`].
+    pub fn respond<B>(&self, request: &Request<B>) -> Response<Bytes> {
+        if let Some(response) = self.unauthorized(request) {
+            return response;
+        }
+        if request.method() != Method::GET && request.method() != Method::HEAD {
+            return status(StatusCode::METHOD_NOT_ALLOWED);
+        }
+
+        let Some(selected) = self.select(request) else {
+            return status(StatusCode::BAD_REQUEST);
+        };
+        match self.encode(&selected, prefers_text(request)) {
+            #[cfg(feature = "gzip")]
+            Ok((content_type, body)) if self.gzip && accepts_gzip(request) => Response::builder()
+                .header(CONTENT_TYPE, content_type)
+                .header(CONTENT_ENCODING, "gzip")
+                .header(VARY, "accept, accept-encoding")
+                .body(gzip(body.as_bytes()))
+                .expect("response should be valid"),
+            Ok((content_type, body)) => Response::builder()
+                .header(CONTENT_TYPE, content_type)
+                .header(VARY, "accept")
+                .body(Bytes::from(body))
+                .expect("response should be valid"),
+            Err(err) => {
+                tracing::error!(%err, "failed to encode metrics");
+                status(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+
+    /// The response rejecting `request`, unless it authenticates as
+    /// required.
+    pub(crate) fn unauthorized<B>(&self, request: &Request<B>) -> Option<Response<Bytes>> {
+        let auth = self.auth.as_ref()?;
+        if auth.verify(request) {
+            return None;
+        }
+        let mut response = status(StatusCode::UNAUTHORIZED);
+        let challenge = HeaderValue::from_str(&format!("{} realm=\"metrics\"", auth.scheme))
+            .expect("challenge should be a valid header value");
+        response.headers_mut().insert(WWW_AUTHENTICATE, challenge);
+        Some(response)
+    }
+
+    /// The registries `request` selects, or `None` if it selects unknown
+    /// ones.
+    fn select<B>(&self, request: &Request<B>) -> Option<Vec<&Served>> {
+        let collect: Vec<&str> = request
+            .uri()
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .filter_map(|param| param.split_once('='))
+            .filter(|(key, _)| *key == "collect[]" || key.eq_ignore_ascii_case("collect%5B%5D"))
+            .map(|(_, name)| name)
+            .collect();
+        if collect.is_empty() {
+            return Some(self.registries.iter().collect());
+        }
+        let known = |name: &&str| {
+            self.registries
+                .iter()
+                .any(|served| served.name.as_deref() == Some(*name))
+        };
+        if let Some(unknown) = collect.iter().find(|name| !known(name)) {
+            tracing::debug!(collector = unknown, "unknown collector requested");
+            return None;
+        }
+        Some(
+            self.registries
+                .iter()
+                .filter(|served| {
+                    served
+                        .name
+                        .as_deref()
+                        .is_some_and(|name| collect.contains(&name))
+                })
+                .collect(),
+        )
+    }
+
+    /// Encode `registries`, in the classic text format rather than
+    /// OpenMetrics if `text`.
+    fn encode(
+        &self,
+        registries: &[&Served],
+        text: bool,
+    ) -> Result<(&'static str, String), std::fmt::Error> {
+        match self.format {
+            Format::OpenMetrics if text => {
+                let mut body = String::new();
+                for served in registries {
+                    served.encode(&mut body, true)?;
+                }
+                Ok((TEXT_CONTENT_TYPE, body))
+            }
+            Format::OpenMetrics => {
+                let mut body = String::new();
+                for served in registries {
+                    served.encode(&mut body, false)?;
+                }
+                encode_eof(&mut body)?;
+                Ok((OPENMETRICS_CONTENT_TYPE, body))
+            }
+            #[cfg(feature = "json")]
+            Format::Json => {
+                let mut families = Vec::new();
+                for served in registries {
+                    let registry = served
+                        .registry
+                        .lock()
+                        .expect("should be able to lock registry");
+                    families.extend(samples::collect(&registry)?);
+                }
+                let body = crate::json::to_value(&families).to_string();
+                Ok((crate::json::JSON_CONTENT_TYPE, body))
+            }
+        }
+    }
+}
+
+impl From<Arc<Mutex<Registry>>> for MetricsService {
+    fn from(registry: Arc<Mutex<Registry>>) -> Self {
+        Self::new(registry)
+    }
+}
+
+impl<B> Service<Request<B>> for MetricsService {
+    type Response = Response<Full<Bytes>>;
+    type Error = Infallible;
+    type Future = Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        ready(Ok(self.respond(&request).map(Full::new)))
+    }
+}
+
+/// Whether the `Accept` header of `request` prefers the classic text format
+/// over OpenMetrics.
+///
+/// Requests that do not explicitly accept OpenMetrics are answered with the
+/// classic text format.
+fn prefers_text<B>(request: &Request<B>) -> bool {
+    let mut openmetrics = None::<f32>;
+    let mut text = None::<f32>;
+    for range in request
+        .headers()
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+    {
+        let mut params = range.split(';').map(str::trim);
+        let media_type = params.next().unwrap_or_default();
+        let q = params
+            .find_map(|param| param.strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        let preference = if media_type.eq_ignore_ascii_case("application/openmetrics-text") {
+            &mut openmetrics
+        } else if media_type.eq_ignore_ascii_case("text/plain") {
+            &mut text
+        } else {
+            continue;
+        };
+        *preference = Some(preference.map_or(q, |preference| preference.max(q)));
+    }
+    match (openmetrics, text) {
+        (Some(openmetrics), Some(text)) => text > openmetrics,
+        (Some(openmetrics), None) => openmetrics <= 0.0,
+        (None, _) => true,
+    }
+}
+
+/// Whether the `Accept-Encoding` header of `request` allows gzip.
+#[cfg(feature = "gzip")]
+fn accepts_gzip<B>(request: &Request<B>) -> bool {
+    request
+        .headers()
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| {
+            let mut params = coding.split(';').map(str::trim);
+            let name = params.next().unwrap_or_default();
+            let rejected = params.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            (name.eq_ignore_ascii_case("gzip") || name == "*") && !rejected
+        })
+}
+
+#[cfg(feature = "gzip")]
+fn gzip(body: &[u8]) -> Bytes {
+    use std::io::Write;
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+    encoder
+        .write_all(body)
+        .expect("writing to a Vec should not fail");
+    Bytes::from(encoder.finish().expect("writing to a Vec should not fail"))
+}
+
+pub(crate) fn status(status: StatusCode) -> Response<Bytes> {
+    Response::builder()
+        .status(status)
+        .body(Bytes::new())
+        .expect("response should be valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accepting(accept: &[&str]) -> Request<()> {
+        let mut request = Request::get("/metrics");
+        for accept in accept {
+            request = request.header(ACCEPT, *accept);
+        }
+        request.body(()).unwrap()
+    }
+
+    #[test]
+    fn prefers_text_without_openmetrics() {
+        assert!(prefers_text(&accepting(&[])));
+        assert!(prefers_text(&accepting(&["*/*"])));
+        assert!(prefers_text(&accepting(&["text/plain; version=0.0.4"])));
+        assert!(prefers_text(&accepting(&[
+            "application/openmetrics-text; q=0, text/plain"
+        ])));
+    }
+
+    #[test]
+    fn prefers_openmetrics_when_accepted() {
+        // As sent by Prometheus.
+        assert!(!prefers_text(&accepting(&[
+            "application/openmetrics-text;version=1.0.0;q=0.5,application/openmetrics-text;version=0.0.1;q=0.4,text/plain;version=0.0.4;q=0.3,*/*;q=0.2"
+        ])));
+        assert!(!prefers_text(&accepting(&["application/openmetrics-text"])));
+        assert!(!prefers_text(&accepting(&[
+            "text/plain",
+            "Application/OpenMetrics-Text"
+        ])));
+        assert!(prefers_text(&accepting(&[
+            "application/openmetrics-text; q=0.2, text/plain; q=0.8"
+        ])));
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn accepts_gzip_unless_rejected() {
+        let encoding = |encodings: &[&str]| {
+            let mut request = Request::get("/metrics");
+            for encoding in encodings {
+                request = request.header(ACCEPT_ENCODING, *encoding);
+            }
+            accepts_gzip(&request.body(()).unwrap())
+        };
+        assert!(!encoding(&[]));
+        assert!(!encoding(&["br"]));
+        assert!(encoding(&["GZIP"]));
+        assert!(encoding(&["br", "deflate, gzip;q=0.5"]));
+        assert!(encoding(&["*"]));
+        assert!(!encoding(&["gzip;q=0"]));
+        assert!(!encoding(&["identity, gzip; q=0.0"]));
+    }
+
+    #[test]
+    fn serves_text_by_default() {
+        let service = MetricsService::new(Arc::default());
+        let response = service.respond(&accepting(&[]));
+        assert_eq!(response.headers()[CONTENT_TYPE], TEXT_CONTENT_TYPE);
+        let response = service.respond(&accepting(&["application/openmetrics-text"]));
+        assert_eq!(response.headers()[CONTENT_TYPE], OPENMETRICS_CONTENT_TYPE);
+        assert!(response.body().ends_with(b"# EOF\n"));
+    }
+
+    #[test]
+    fn stamps_samples_with_labels_containing_comments() {
+        use prometheus_client::metrics::{family::Family, gauge::Gauge};
+
+        let mut registry = Registry::default();
+        let tasks = Family::<Vec<(String, String)>, Gauge>::default();
+        tasks
+            .get_or_create(&vec![("name".into(), "worker # 1".into())])
+            .set(3);
+        registry.register("tasks", "Running tasks", tasks);
+        let clock = SampleClock::default();
+        clock.tick();
+        let at = clock
+            .last_sample()
+            .unwrap()
+            .duration_since(UNIX_EPOCH)
+            .unwrap();
+        let service = MetricsService::new(Arc::default())
+            .timestamped_registry(Arc::new(Mutex::new(registry)), clock);
+
+        let response = service.respond(&accepting(&["application/openmetrics-text"]));
+        let body = std::str::from_utf8(response.body()).unwrap();
+        let stamped = format!(
+            "\ntasks{{name=\"worker # 1\"}} 3 {}.{:03}\n# EOF\n",
+            at.as_secs(),
+            at.subsec_millis()
+        );
+        assert!(body.ends_with(&stamped), "{body}");
+
+        let response = service.respond(&accepting(&[]));
+        let body = std::str::from_utf8(response.body()).unwrap();
+        let stamped = format!("\ntasks{{name=\"worker # 1\"}} 3 {}\n", at.as_millis());
+        assert!(body.ends_with(&stamped), "{body}");
+    }
+}
@@ -0,0 +1,147 @@
+//! Instrumented utilities of `tokio_util`.
+//!
+//! Enabled with the `util` feature. The utilities created by a
+//! [`UtilMonitor`] export how they are used, labeled with the name they
+//! were created with, e.g. the task trackers services shut down
+//! gracefully with.
+
+use std::sync::{Arc, Mutex, Weak};
+
+use prometheus_client::{collector::Collector, encoding::DescriptorEncoder, registry::Registry};
+
+use crate::{buckets::BucketPreset, primitives::live};
+
+mod cancellation;
+mod delay_queue;
+mod task_tracker;
+
+pub use cancellation::MonitoredCancellationToken;
+pub use delay_queue::MonitoredDelayQueue;
+pub use task_tracker::MonitoredTaskTracker;
+
+/// Creates instrumented utilities and collects their metrics.
+///
+/// Each utility is exported with a label of its kind set to its name,
+/// names should be unique per kind. The series of a utility are removed
+/// once all of its handles are dropped.
+///
+/// Task trackers, created with [`UtilMonitor::task_tracker`], expose:
+///
+/// * `task_tracker_tasks`: the number of tracked tasks that did not exit
+///   yet.
+/// * `task_tracker_tracked`: the number of tasks tracked.
+/// * `task_tracker_completed_tasks`, `task_tracker_panicked_tasks` and
+///   `task_tracker_cancelled_tasks`: the number of tracked tasks that
+///   completed, panicked or were dropped before completing, e.g. aborted.
+///   Alert on panics here instead of on an easily missed log line.
+/// * `task_tracker_closed`: 1 if the tracker was closed, 0 otherwise.
+/// * `task_tracker_waiters`: the number of tasks waiting for the tracker
+///   to be closed and empty. One that stays up with tasks left points to a
+///   shutdown stuck on them.
+///
+/// Cancellation tokens, registered with [`UtilMonitor::cancellation_token`],
+/// expose:
+///
+/// * `cancellation_token_cancelled`: 1 if the token was cancelled, 0
+///   otherwise. A subsystem whose token stays at 0 during a shutdown was
+///   not reached by it.
+/// * `cancellation_token_children`: the number of child tokens created.
+///
+/// Delay queues, created with [`UtilMonitor::delay_queue`], expose:
+///
+/// * `delay_queue_length`: the number of values waiting in the queue.
+/// * `delay_queue_inserts`: the number of values inserted.
+/// * `delay_queue_expirations`: the number of values that expired.
+/// * `delay_queue_expiration_lateness_seconds`: a histogram of the time
+///   from the deadlines of values to yielding them. A growing one points
+///   to a backlog or to a runtime too busy to poll the queue.
+///
+/// ## Example
+///
+/// ```
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// let monitor = tokio_prometheus_client::util::UtilMonitor::new();
+/// monitor.register(&mut registry);
+///
+/// let tracker = monitor.task_tracker("connections");
+/// tracker.spawn(async {});
+/// let _ = tracker.spawn(async { panic!("bad connection") }).await;
+/// tracker.close();
+/// tracker.wait().await;
+///
+/// let mut text = String::new();
+/// prometheus_client::encoding::text::encode(&mut text, &registry).unwrap();
+/// assert!(text.contains("task_tracker_tracked_total{task_tracker=\"connections\"} 2\n"));
+/// assert!(text.contains("task_tracker_panicked_tasks_total{task_tracker=\"connections\"} 1\n"));
+/// assert!(text.contains("task_tracker_tasks{task_tracker=\"connections\"} 0\n"));
+///
+/// let shutdown = monitor.cancellation_token("shutdown", Default::default());
+/// let workers = monitor.cancellation_token("workers", shutdown.child_token());
+/// shutdown.cancel();
+/// assert!(workers.is_cancelled());
+/// # });
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct UtilMonitor {
+    buckets: BucketPreset,
+    shared: Arc<Shared>,
+}
+
+/// The utilities of a [`UtilMonitor`], pruned on every collection.
+#[derive(Debug, Default)]
+struct Shared {
+    cancellation_tokens: Mutex<Vec<Weak<cancellation::State>>>,
+    delay_queues: Mutex<Vec<Weak<delay_queue::State>>>,
+    task_trackers: Mutex<Vec<Weak<task_tracker::State>>>,
+}
+
+impl UtilMonitor {
+    /// Create a [`UtilMonitor`] with [`BucketPreset::LatencyCoarse`]
+    /// histograms.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the buckets of the histograms of the utilities created from
+    /// then on.
+    pub fn buckets(mut self, buckets: BucketPreset) -> Self {
+        self.buckets = buckets;
+        self
+    }
+
+    /// Register the collector of the utilities with `registry`.
+    pub fn register(&self, registry: &mut Registry) {
+        registry.register_collector(Box::new(UtilCollector {
+            shared: self.shared.clone(),
+        }));
+    }
+}
+
+/// Collects the utilities of a [`UtilMonitor`].
+#[derive(Debug)]
+struct UtilCollector {
+    shared: Arc<Shared>,
+}
+
+impl Collector for UtilCollector {
+    fn encode(&self, mut encoder: DescriptorEncoder) -> Result<(), std::fmt::Error> {
+        cancellation::encode(&live(&self.shared.cancellation_tokens), &mut encoder)?;
+        delay_queue::encode(&live(&self.shared.delay_queues), &mut encoder)?;
+        task_tracker::encode(&live(&self.shared.task_trackers), &mut encoder)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl UtilMonitor {
+    /// The text exposition of the utilities.
+    fn encoded(&self) -> String {
+        let mut registry = Registry::default();
+        self.register(&mut registry);
+        let mut text = String::new();
+        prometheus_client::encoding::text::encode(&mut text, &registry).unwrap();
+        text
+    }
+}
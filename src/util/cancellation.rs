@@ -0,0 +1,162 @@
+//! Cancellation tokens exporting whether they were cancelled.
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use prometheus_client::encoding::DescriptorEncoder;
+use tokio_util::sync::{CancellationToken, DropGuard, WaitForCancellationFuture};
+
+use crate::primitives::{encode_counters, encode_gauges, label_value, track, Primitive};
+
+use super::UtilMonitor;
+
+impl UtilMonitor {
+    /// Monitor `token` as a [`MonitoredCancellationToken`] named `name`.
+    ///
+    /// Register the child tokens of subsystems too, created with
+    /// [`MonitoredCancellationToken::child_token`], to see which ones were
+    /// not cancelled during a shutdown.
+    pub fn cancellation_token(
+        &self,
+        name: impl Into<String>,
+        token: CancellationToken,
+    ) -> MonitoredCancellationToken {
+        let state = State {
+            name: label_value(name),
+            token,
+            children: AtomicU64::new(0),
+        };
+        MonitoredCancellationToken {
+            state: track(&self.shared.cancellation_tokens, state),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct State {
+    name: String,
+    token: CancellationToken,
+    children: AtomicU64,
+}
+
+/// A [`CancellationToken`] exporting whether it was cancelled, see
+/// [`UtilMonitor::cancellation_token`].
+#[derive(Clone, Debug)]
+pub struct MonitoredCancellationToken {
+    state: Arc<State>,
+}
+
+impl MonitoredCancellationToken {
+    /// The token, e.g. to pass it to APIs taking a [`CancellationToken`].
+    ///
+    /// Child tokens created from it directly are not counted.
+    pub fn inner(&self) -> &CancellationToken {
+        &self.state.token
+    }
+
+    /// Create a child token, cancelled with this one, see
+    /// [`CancellationToken::child_token`].
+    pub fn child_token(&self) -> CancellationToken {
+        self.state.children.fetch_add(1, Ordering::Relaxed);
+        self.state.token.child_token()
+    }
+
+    /// Cancel the token and its children, see
+    /// [`CancellationToken::cancel`].
+    pub fn cancel(&self) {
+        self.state.token.cancel();
+    }
+
+    /// Whether the token was cancelled, see
+    /// [`CancellationToken::is_cancelled`].
+    pub fn is_cancelled(&self) -> bool {
+        self.state.token.is_cancelled()
+    }
+
+    /// Wait until the token is cancelled, see
+    /// [`CancellationToken::cancelled`].
+    pub fn cancelled(&self) -> WaitForCancellationFuture<'_> {
+        self.state.token.cancelled()
+    }
+
+    /// Cancel the token when the returned guard is dropped, see
+    /// [`CancellationToken::drop_guard`].
+    pub fn drop_guard(&self) -> DropGuard {
+        self.state.token.clone().drop_guard()
+    }
+}
+
+impl Primitive for State {
+    const LABEL: &'static str = "cancellation_token";
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Encode the metrics of `tokens`.
+pub(super) fn encode(
+    tokens: &[Arc<State>],
+    encoder: &mut DescriptorEncoder,
+) -> Result<(), std::fmt::Error> {
+    encode_gauges(
+        encoder,
+        tokens,
+        "cancellation_token_cancelled",
+        "Whether the cancellation token was cancelled",
+        |token| token.token.is_cancelled().into(),
+    )?;
+    encode_counters(
+        encoder,
+        tokens,
+        "cancellation_token_children",
+        "The number of child tokens created from the cancellation token",
+        |token| &token.children,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_cancellation_and_children() {
+        let monitor = UtilMonitor::new();
+        let token = monitor.cancellation_token("shutdown", CancellationToken::new());
+        let children = [token.child_token(), token.child_token()];
+        // Children created from the inner token are not counted.
+        let _uncounted = token.inner().child_token();
+
+        let text = monitor.encoded();
+        for expected in [
+            "cancellation_token_cancelled{cancellation_token=\"shutdown\"} 0\n",
+            "cancellation_token_children_total{cancellation_token=\"shutdown\"} 2\n",
+        ] {
+            assert!(text.contains(expected), "{text}");
+        }
+
+        token.cancel();
+        assert!(children.iter().all(CancellationToken::is_cancelled));
+        let text = monitor.encoded();
+        assert!(
+            text.contains("cancellation_token_cancelled{cancellation_token=\"shutdown\"} 1\n"),
+            "{text}"
+        );
+    }
+
+    #[test]
+    fn drop_guards_cancel_tokens() {
+        let monitor = UtilMonitor::new();
+        let token = monitor.cancellation_token("worker", CancellationToken::new());
+        drop(token.drop_guard());
+
+        assert!(token.is_cancelled());
+        let text = monitor.encoded();
+        assert!(
+            text.contains("cancellation_token_cancelled{cancellation_token=\"worker\"} 1\n"),
+            "{text}"
+        );
+    }
+}
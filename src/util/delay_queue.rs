@@ -0,0 +1,245 @@
+//! Delay queues exporting their backlog and how late values expire.
+
+use std::{
+    future,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use prometheus_client::{encoding::DescriptorEncoder, metrics::histogram::Histogram};
+use tokio::time::Instant;
+use tokio_util::time::{
+    delay_queue::{Expired, Key},
+    DelayQueue,
+};
+
+use crate::primitives::{
+    encode_counters, encode_gauges, encode_histograms, label_value, track, Primitive,
+};
+
+use super::UtilMonitor;
+
+impl UtilMonitor {
+    /// Create a [`MonitoredDelayQueue`] named `name`.
+    pub fn delay_queue<T>(&self, name: impl Into<String>) -> MonitoredDelayQueue<T> {
+        let state = State {
+            name: label_value(name),
+            length: AtomicI64::new(0),
+            inserts: AtomicU64::new(0),
+            expirations: AtomicU64::new(0),
+            lateness: Histogram::new(self.buckets.buckets().iter().copied()),
+        };
+        MonitoredDelayQueue {
+            queue: DelayQueue::new(),
+            state: track(&self.shared.delay_queues, state),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct State {
+    name: String,
+    length: AtomicI64,
+    inserts: AtomicU64,
+    expirations: AtomicU64,
+    /// The time from the deadlines of values to yielding them.
+    lateness: Histogram,
+}
+
+/// A [`DelayQueue`] exporting its backlog and how late values expire, see
+/// [`UtilMonitor::delay_queue`].
+#[derive(Debug)]
+pub struct MonitoredDelayQueue<T> {
+    queue: DelayQueue<T>,
+    state: Arc<State>,
+}
+
+impl<T> MonitoredDelayQueue<T> {
+    /// Insert `value` to expire after `timeout`, see
+    /// [`DelayQueue::insert`].
+    #[track_caller]
+    pub fn insert(&mut self, value: T, timeout: Duration) -> Key {
+        let key = self.queue.insert(value, timeout);
+        self.inserted();
+        key
+    }
+
+    /// Insert `value` to expire at `when`, see [`DelayQueue::insert_at`].
+    #[track_caller]
+    pub fn insert_at(&mut self, value: T, when: Instant) -> Key {
+        let key = self.queue.insert_at(value, when);
+        self.inserted();
+        key
+    }
+
+    /// Poll for the next expired value, see [`DelayQueue::poll_expired`].
+    pub fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<Option<Expired<T>>> {
+        let expired = self.queue.poll_expired(cx);
+        if let Poll::Ready(Some(expired)) = &expired {
+            self.state
+                .lateness
+                .observe(expired.deadline().elapsed().as_secs_f64());
+            self.state.expirations.fetch_add(1, Ordering::Relaxed);
+            self.updated();
+        }
+        expired
+    }
+
+    /// Wait for the next expired value, `None` once the queue is empty.
+    pub async fn next(&mut self) -> Option<Expired<T>> {
+        future::poll_fn(|cx| self.poll_expired(cx)).await
+    }
+
+    /// Remove the value of `key`, see [`DelayQueue::remove`].
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `key` is not in the queue.
+    #[track_caller]
+    pub fn remove(&mut self, key: &Key) -> Expired<T> {
+        let removed = self.queue.remove(key);
+        self.updated();
+        removed
+    }
+
+    /// Remove the value of `key` if it is in the queue, see
+    /// [`DelayQueue::try_remove`].
+    pub fn try_remove(&mut self, key: &Key) -> Option<Expired<T>> {
+        let removed = self.queue.try_remove(key);
+        self.updated();
+        removed
+    }
+
+    /// Expire the value of `key` after `timeout` instead, see
+    /// [`DelayQueue::reset`].
+    #[track_caller]
+    pub fn reset(&mut self, key: &Key, timeout: Duration) {
+        self.queue.reset(key, timeout);
+    }
+
+    /// Expire the value of `key` at `when` instead, see
+    /// [`DelayQueue::reset_at`].
+    #[track_caller]
+    pub fn reset_at(&mut self, key: &Key, when: Instant) {
+        self.queue.reset_at(key, when);
+    }
+
+    /// When the value of `key` expires, see [`DelayQueue::deadline`].
+    #[track_caller]
+    pub fn deadline(&self, key: &Key) -> Instant {
+        self.queue.deadline(key)
+    }
+
+    /// The key of the value expiring next, see [`DelayQueue::peek`].
+    pub fn peek(&self) -> Option<Key> {
+        self.queue.peek()
+    }
+
+    /// Remove all values, see [`DelayQueue::clear`].
+    pub fn clear(&mut self) {
+        self.queue.clear();
+        self.updated();
+    }
+
+    /// The number of values, see [`DelayQueue::len`].
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Whether there are no values, see [`DelayQueue::is_empty`].
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    fn inserted(&self) {
+        self.state.inserts.fetch_add(1, Ordering::Relaxed);
+        self.updated();
+    }
+
+    /// Export the length after it changed.
+    fn updated(&self) {
+        let length = self.queue.len().try_into().unwrap_or(i64::MAX);
+        self.state.length.store(length, Ordering::Relaxed);
+    }
+}
+
+impl Primitive for State {
+    const LABEL: &'static str = "delay_queue";
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Encode the metrics of `queues`.
+pub(super) fn encode(
+    queues: &[Arc<State>],
+    encoder: &mut DescriptorEncoder,
+) -> Result<(), std::fmt::Error> {
+    encode_gauges(
+        encoder,
+        queues,
+        "delay_queue_length",
+        "The number of values waiting in the delay queue",
+        |queue| queue.length.load(Ordering::Relaxed),
+    )?;
+    encode_counters(
+        encoder,
+        queues,
+        "delay_queue_inserts",
+        "The number of values inserted into the delay queue",
+        |queue| &queue.inserts,
+    )?;
+    encode_counters(
+        encoder,
+        queues,
+        "delay_queue_expirations",
+        "The number of values that expired from the delay queue",
+        |queue| &queue.expirations,
+    )?;
+    encode_histograms(
+        encoder,
+        queues,
+        "delay_queue_expiration_lateness",
+        "The time from the deadlines of values to yielding them from the delay queue",
+        |queue| &queue.lateness,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn tracks_length_and_expirations() {
+        let monitor = UtilMonitor::new();
+        let mut queue = monitor.delay_queue("timeouts");
+        queue.insert("soon", Duration::from_millis(1));
+        let removed = queue.insert("removed", Duration::from_secs(60));
+        queue.insert_at("later", Instant::now() + Duration::from_secs(60));
+        assert_eq!(queue.remove(&removed).into_inner(), "removed");
+        assert!(queue.try_remove(&removed).is_none());
+
+        let expired = queue.next().await.unwrap();
+        assert_eq!(expired.into_inner(), "soon");
+
+        let text = monitor.encoded();
+        for expected in [
+            "delay_queue_length{delay_queue=\"timeouts\"} 1\n",
+            "delay_queue_inserts_total{delay_queue=\"timeouts\"} 3\n",
+            "delay_queue_expirations_total{delay_queue=\"timeouts\"} 1\n",
+            "delay_queue_expiration_lateness_seconds_count{delay_queue=\"timeouts\"} 1\n",
+        ] {
+            assert!(text.contains(expected), "{text}");
+        }
+
+        queue.clear();
+        assert!(monitor
+            .encoded()
+            .contains("delay_queue_length{delay_queue=\"timeouts\"} 0\n"));
+    }
+}
@@ -0,0 +1,253 @@
+//! Task trackers exporting their tasks and shutdown state.
+
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use prometheus_client::encoding::DescriptorEncoder;
+use tokio::{runtime::Handle, task::JoinHandle};
+use tokio_util::task::{task_tracker::TrackedFuture, TaskTracker};
+
+use crate::primitives::{encode_counters, encode_gauges, label_value, track, Primitive, Waiting};
+
+use super::UtilMonitor;
+
+impl UtilMonitor {
+    /// Create a [`MonitoredTaskTracker`] named `name`.
+    pub fn task_tracker(&self, name: impl Into<String>) -> MonitoredTaskTracker {
+        let state = State {
+            name: label_value(name),
+            tracker: TaskTracker::new(),
+            tracked: AtomicU64::new(0),
+            completed: AtomicU64::new(0),
+            panicked: AtomicU64::new(0),
+            cancelled: AtomicU64::new(0),
+            waiters: AtomicI64::new(0),
+        };
+        MonitoredTaskTracker {
+            state: track(&self.shared.task_trackers, state),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct State {
+    name: String,
+    tracker: TaskTracker,
+    tracked: AtomicU64,
+    completed: AtomicU64,
+    panicked: AtomicU64,
+    /// The number of tasks dropped before completing, e.g. aborted.
+    cancelled: AtomicU64,
+    waiters: AtomicI64,
+}
+
+/// Counts the outcome of a tracked task when dropped.
+struct Outcome {
+    state: Arc<State>,
+    completed: bool,
+}
+
+impl Outcome {
+    /// Run `task`, counting its outcome.
+    async fn run<F: Future>(mut self, task: F) -> F::Output {
+        let output = task.await;
+        self.completed = true;
+        output
+    }
+}
+
+impl Drop for Outcome {
+    fn drop(&mut self) {
+        let outcome = if self.completed {
+            &self.state.completed
+        } else if thread::panicking() {
+            &self.state.panicked
+        } else {
+            &self.state.cancelled
+        };
+        outcome.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// A [`TaskTracker`] exporting its tasks and shutdown state, see
+/// [`UtilMonitor::task_tracker`].
+#[derive(Clone, Debug)]
+pub struct MonitoredTaskTracker {
+    state: Arc<State>,
+}
+
+impl MonitoredTaskTracker {
+    /// The tracker, e.g. to pass it to APIs taking a [`TaskTracker`].
+    ///
+    /// Tasks tracked by it directly are not counted in
+    /// `task_tracker_tracked`.
+    pub fn inner(&self) -> &TaskTracker {
+        &self.state.tracker
+    }
+
+    /// Spawn `task` on the current runtime and track it, see
+    /// [`TaskTracker::spawn`].
+    #[track_caller]
+    pub fn spawn<F>(&self, task: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.state.tracker.spawn(self.track(task))
+    }
+
+    /// Spawn `task` on the runtime of `handle` and track it, see
+    /// [`TaskTracker::spawn_on`].
+    #[track_caller]
+    pub fn spawn_on<F>(&self, task: F, handle: &Handle) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.state.tracker.spawn_on(self.track(task), handle)
+    }
+
+    /// Spawn the blocking `task` and track it, see
+    /// [`TaskTracker::spawn_blocking`].
+    #[track_caller]
+    pub fn spawn_blocking<F, T>(&self, task: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let outcome = self.outcome();
+        self.state.tracker.spawn_blocking(move || {
+            let mut outcome = outcome;
+            let output = task();
+            outcome.completed = true;
+            output
+        })
+    }
+
+    /// Track `future` until it completes or is dropped, see
+    /// [`TaskTracker::track_future`].
+    pub fn track_future<F: Future>(
+        &self,
+        future: F,
+    ) -> TrackedFuture<impl Future<Output = F::Output>> {
+        self.state.tracker.track_future(self.track(future))
+    }
+
+    /// Close the tracker, returning whether it was open, see
+    /// [`TaskTracker::close`].
+    pub fn close(&self) -> bool {
+        self.state.tracker.close()
+    }
+
+    /// Reopen the tracker, returning whether it was closed, see
+    /// [`TaskTracker::reopen`].
+    pub fn reopen(&self) -> bool {
+        self.state.tracker.reopen()
+    }
+
+    /// Whether the tracker is closed, see [`TaskTracker::is_closed`].
+    pub fn is_closed(&self) -> bool {
+        self.state.tracker.is_closed()
+    }
+
+    /// The number of tracked tasks, see [`TaskTracker::len`].
+    pub fn len(&self) -> usize {
+        self.state.tracker.len()
+    }
+
+    /// Whether there are no tracked tasks, see [`TaskTracker::is_empty`].
+    pub fn is_empty(&self) -> bool {
+        self.state.tracker.is_empty()
+    }
+
+    /// Wait until the tracker is closed and empty, see
+    /// [`TaskTracker::wait`].
+    pub async fn wait(&self) {
+        let _waiting = Waiting::new(&self.state.waiters);
+        self.state.tracker.wait().await;
+    }
+
+    /// Count `task` as tracked, and its outcome once dropped.
+    fn track<F: Future>(&self, task: F) -> impl Future<Output = F::Output> {
+        self.outcome().run(task)
+    }
+
+    /// Count a task as tracked, returning the guard counting its outcome.
+    fn outcome(&self) -> Outcome {
+        self.state.tracked.fetch_add(1, Ordering::Relaxed);
+        Outcome {
+            state: self.state.clone(),
+            completed: false,
+        }
+    }
+}
+
+impl Primitive for State {
+    const LABEL: &'static str = "task_tracker";
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Encode the metrics of `trackers`.
+pub(super) fn encode(
+    trackers: &[Arc<State>],
+    encoder: &mut DescriptorEncoder,
+) -> Result<(), std::fmt::Error> {
+    encode_gauges(
+        encoder,
+        trackers,
+        "task_tracker_tasks",
+        "The number of tracked tasks that did not exit yet",
+        |tracker| tracker.tracker.len() as i64,
+    )?;
+    encode_counters(
+        encoder,
+        trackers,
+        "task_tracker_tracked",
+        "The number of tasks tracked by the task tracker",
+        |tracker| &tracker.tracked,
+    )?;
+    encode_counters(
+        encoder,
+        trackers,
+        "task_tracker_completed_tasks",
+        "The number of tracked tasks that completed",
+        |tracker| &tracker.completed,
+    )?;
+    encode_counters(
+        encoder,
+        trackers,
+        "task_tracker_panicked_tasks",
+        "The number of tracked tasks that panicked",
+        |tracker| &tracker.panicked,
+    )?;
+    encode_counters(
+        encoder,
+        trackers,
+        "task_tracker_cancelled_tasks",
+        "The number of tracked tasks dropped before completing, e.g. aborted",
+        |tracker| &tracker.cancelled,
+    )?;
+    encode_gauges(
+        encoder,
+        trackers,
+        "task_tracker_closed",
+        "Whether the task tracker was closed",
+        |tracker| tracker.tracker.is_closed().into(),
+    )?;
+    encode_gauges(
+        encoder,
+        trackers,
+        "task_tracker_waiters",
+        "The number of tasks waiting for the task tracker to be closed and empty",
+        |tracker| tracker.waiters.load(Ordering::Relaxed),
+    )
+}
@@ -0,0 +1,102 @@
+//! [warp](https://docs.rs/warp) integration.
+//!
+//! Enabled with the `warp` feature.
+
+use warp::{
+    http::{HeaderMap, Method, Request, Uri},
+    path::FullPath,
+    reply::{Reply, Response},
+    Filter, Rejection,
+};
+
+use crate::tower::MetricsService;
+
+/// Create a [`Filter`] serving `metrics` on `GET /metrics`.
+///
+/// `metrics` is either an `Arc<Mutex<Registry>>` or a [`MetricsService`].
+///
+/// ## Example
+///
+/// ```
+/// # use std::sync::{Arc, Mutex};
+/// # use warp::Filter;
+/// # let rt = tokio::runtime::Runtime::new().unwrap();
+/// # rt.block_on(async {
+/// let handle = tokio::runtime::Handle::current();
+/// let runtime_monitor = tokio_metrics::RuntimeMonitor::new(&handle);
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// tokio_prometheus_client::register(runtime_monitor, registry.sub_registry_with_prefix("tokio"));
+///
+/// let routes = tokio_prometheus_client::warp::metrics_filter(Arc::new(Mutex::new(registry)))
+///     .or(warp::path("hello").map(|| "hello"));
+/// # });
+/// ```
+pub fn metrics_filter(
+    metrics: impl Into<MetricsService>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    let metrics: MetricsService = metrics.into();
+    warp::path("metrics")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::method())
+        .and(warp::path::full())
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .and(warp::header::headers_cloned())
+        .map(
+            move |method: Method, path: FullPath, query: String, headers: HeaderMap| {
+                let mut request = Request::new(());
+                *request.method_mut() = method;
+                *request.uri_mut() = match query.as_str() {
+                    "" => path.as_str().parse(),
+                    query => format!("{}?{query}", path.as_str()).parse(),
+                }
+                .unwrap_or_else(|_| Uri::from_static("/"));
+                *request.headers_mut() = headers;
+                metrics.respond(&request).into_response()
+            },
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use prometheus_client::{metrics::counter::Counter, registry::Registry};
+
+    use super::*;
+
+    fn registry(name: &str) -> Arc<Mutex<Registry>> {
+        let mut registry = Registry::default();
+        registry.register(name, "A counter", Counter::<u64>::default());
+        Arc::new(Mutex::new(registry))
+    }
+
+    #[tokio::test]
+    async fn selects_registries_by_query() {
+        let metrics = MetricsService::new(registry("default"))
+            .named_registry("a", registry("a"))
+            .named_registry("b", registry("b"));
+        let filter = metrics_filter(metrics);
+
+        let response = warp::test::request()
+            .path("/metrics?collect[]=a")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 200);
+        let body = std::str::from_utf8(response.body()).unwrap();
+        assert!(body.contains("\na_total 0\n"), "{body}");
+        assert!(!body.contains("b_total"), "{body}");
+        assert!(!body.contains("default_total"), "{body}");
+
+        let response = warp::test::request().path("/metrics").reply(&filter).await;
+        let body = std::str::from_utf8(response.body()).unwrap();
+        assert!(body.contains("default_total 0"), "{body}");
+        assert!(body.contains("b_total 0"), "{body}");
+
+        let response = warp::test::request()
+            .path("/metrics?collect[]=unknown")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 400);
+    }
+}
@@ -0,0 +1,242 @@
+//! Detect a stalled runtime by timing how long a probe task waits for its
+//! first poll.
+//!
+//! Enabled with the `watchdog` feature. Sampling the runtime metrics needs
+//! the runtime to make progress, or at least a scrape to reach it, while a
+//! wedged runtime is the case where they matter most. The watchdog probes
+//! from a thread of its own, so it measures the stall while it lasts.
+
+use std::{
+    sync::{mpsc, Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+
+use prometheus_client::{
+    collector::Collector,
+    encoding::{DescriptorEncoder, EncodeMetric},
+    metrics::{gauge::ConstGauge, histogram::Histogram},
+    registry::{Registry, Unit},
+};
+use tokio::runtime::Handle;
+
+use crate::buckets::BucketPreset;
+
+/// Periodically spawns a trivial task on a runtime and measures the time to
+/// its first poll.
+///
+/// Exposes:
+///
+/// * `watchdog_poll_delay_seconds`: a histogram of the delays of the
+///   probes.
+/// * `watchdog_last_poll_delay_seconds`: the delay of the last probe, or
+///   how long the pending probe has waited so far if that is longer.
+/// * `watchdog_stalled`: 1 while a probe has waited longer than the stall
+///   threshold, 0 otherwise.
+///
+/// ## Example
+///
+/// ```
+/// # use std::time::Duration;
+/// let rt = tokio::runtime::Runtime::new().unwrap();
+///
+/// let mut registry = prometheus_client::registry::Registry::default();
+/// tokio_prometheus_client::watchdog::Watchdog::new(rt.handle())
+///     .interval(Duration::from_secs(1))
+///     .stall_threshold(Duration::from_millis(100))
+///     .register(registry.sub_registry_with_prefix("tokio"))
+///     .unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Watchdog {
+    handle: Handle,
+    interval: Duration,
+    stall_threshold: Duration,
+    buckets: BucketPreset,
+}
+
+impl Watchdog {
+    /// Create a [`Watchdog`] probing the runtime of `handle` every second,
+    /// considering it stalled once a probe waits for a second.
+    pub fn new(handle: &Handle) -> Self {
+        Self {
+            handle: handle.clone(),
+            interval: Duration::from_secs(1),
+            stall_threshold: Duration::from_secs(1),
+            buckets: BucketPreset::LatencyFine,
+        }
+    }
+
+    /// Set the interval between probes.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Set how long a probe may wait for its first poll before the runtime
+    /// is considered stalled.
+    pub fn stall_threshold(mut self, stall_threshold: Duration) -> Self {
+        self.stall_threshold = stall_threshold;
+        self
+    }
+
+    /// Set the buckets of the delay histogram,
+    /// [`BucketPreset::LatencyFine`] by default.
+    pub fn buckets(mut self, buckets: BucketPreset) -> Self {
+        self.buckets = buckets;
+        self
+    }
+
+    /// Register the collector of the probes with `registry` and spawn the
+    /// thread probing the runtime.
+    ///
+    /// The thread stops once the collector is dropped or the runtime shut
+    /// down.
+    pub fn register(self, registry: &mut Registry) -> std::io::Result<()> {
+        let state = Arc::new(Mutex::new(State {
+            histogram: Histogram::new(self.buckets.buckets().iter().copied()),
+            last_delay: Duration::ZERO,
+            pending: None,
+        }));
+        let probes = Arc::downgrade(&state);
+        let interval = self.interval;
+        let handle = self.handle;
+        std::thread::Builder::new()
+            .name("tokio-metrics-watchdog".to_string())
+            .spawn(move || probe(&handle, interval, &probes))?;
+        registry.register_collector(Box::new(WatchdogCollector {
+            stall_threshold: self.stall_threshold,
+            state,
+        }));
+        Ok(())
+    }
+}
+
+/// Probe the runtime of `handle` every `interval` until `state` is dropped.
+fn probe(handle: &Handle, interval: Duration, state: &Weak<Mutex<State>>) {
+    loop {
+        std::thread::sleep(interval);
+        let Some(strong) = state.upgrade() else {
+            return;
+        };
+        let spawned_at = Instant::now();
+        strong
+            .lock()
+            .expect("should be able to lock watchdog")
+            .pending = Some(spawned_at);
+        drop(strong);
+
+        let (polled, first_poll) = mpsc::channel();
+        handle.spawn(async move {
+            let _ = polled.send(Instant::now());
+        });
+        let polled_at = loop {
+            match first_poll.recv_timeout(interval) {
+                Ok(polled_at) => break polled_at,
+                // Still waiting, unless nobody is left to collect the delay.
+                Err(mpsc::RecvTimeoutError::Timeout) if state.strong_count() > 0 => {}
+                // Or the runtime shut down and dropped the probe.
+                Err(_) => return,
+            }
+        };
+
+        let Some(state) = state.upgrade() else {
+            return;
+        };
+        let mut state = state.lock().expect("should be able to lock watchdog");
+        let delay = polled_at.duration_since(spawned_at);
+        state.histogram.observe(delay.as_secs_f64());
+        state.last_delay = delay;
+        state.pending = None;
+    }
+}
+
+/// Collects the probes of a [`Watchdog`].
+#[derive(Debug)]
+struct WatchdogCollector {
+    stall_threshold: Duration,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug)]
+struct State {
+    histogram: Histogram,
+    last_delay: Duration,
+    /// When the probe waiting for its first poll was spawned.
+    pending: Option<Instant>,
+}
+
+impl Collector for WatchdogCollector {
+    fn encode(&self, mut encoder: DescriptorEncoder) -> Result<(), std::fmt::Error> {
+        let state = self.state.lock().expect("should be able to lock watchdog");
+        let waiting = state
+            .pending
+            .map_or(Duration::ZERO, |spawned_at| spawned_at.elapsed());
+
+        state.histogram.encode(encoder.encode_descriptor(
+            "watchdog_poll_delay",
+            "The time probe tasks waited for their first poll",
+            Some(&Unit::Seconds),
+            state.histogram.metric_type(),
+        )?)?;
+
+        let last_delay = ConstGauge::new(state.last_delay.max(waiting).as_secs_f64());
+        last_delay.encode(encoder.encode_descriptor(
+            "watchdog_last_poll_delay",
+            "The time the last probe task waited for its first poll, or the pending one so far",
+            Some(&Unit::Seconds),
+            last_delay.metric_type(),
+        )?)?;
+
+        let stalled = ConstGauge::new(i64::from(waiting > self.stall_threshold));
+        stalled.encode(encoder.encode_descriptor(
+            "watchdog_stalled",
+            "Whether a probe task has waited longer than the stall threshold for its first poll",
+            None,
+            stalled.metric_type(),
+        )?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gauge(registry: &Registry, name: &str) -> f64 {
+        let mut text = String::new();
+        prometheus_client::encoding::text::encode(&mut text, registry).unwrap();
+        text.lines()
+            .find_map(|line| line.strip_prefix(&format!("{name} ")))
+            .unwrap()
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn detects_stalled_runtime() {
+        // Tasks of a current thread runtime are only polled while blocking
+        // on it.
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let mut registry = Registry::default();
+        Watchdog::new(runtime.handle())
+            .interval(Duration::from_millis(10))
+            .stall_threshold(Duration::from_millis(100))
+            .register(registry.sub_registry_with_prefix("tokio"))
+            .unwrap();
+
+        std::thread::sleep(Duration::from_millis(300));
+        assert_eq!(gauge(&registry, "tokio_watchdog_stalled"), 1.0);
+        assert!(gauge(&registry, "tokio_watchdog_last_poll_delay_seconds") > 0.1);
+        assert_eq!(
+            gauge(&registry, "tokio_watchdog_poll_delay_seconds_count"),
+            0.0
+        );
+
+        runtime.block_on(async { tokio::time::sleep(Duration::from_millis(100)).await });
+        assert_eq!(gauge(&registry, "tokio_watchdog_stalled"), 0.0);
+        assert!(gauge(&registry, "tokio_watchdog_poll_delay_seconds_count") > 1.0);
+    }
+}